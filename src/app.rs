@@ -14,6 +14,7 @@
 //! - ui_helpers.rs: UI & Logging Helpers
 
 use makepad_widgets::*;
+use clash_chain_patcher::merger::{ApplyPlan, AutoGroupType, BalanceStrategy};
 use clash_chain_patcher::patcher::{RuleGroup, RuleMatchType, CustomRule, CustomRuleSet};
 use clash_chain_patcher::state::ProxyState;
 use std::collections::VecDeque;
@@ -350,12 +351,102 @@ live_design! {
                         filter_input = <TextInput> {
                             width: Fill,
                             height: 24,
-                            empty_text: "empty=all, comma separated"
+                            empty_text: "empty=all, e.g. hk, jp, !test, type:ss, limit:20"
                             draw_text: {color: #ffffff, text_style: {font_size: 10.0}}
                             draw_bg: {color: #555555}
                         }
                     }
 
+                    // Chain-Auto group
+                    <View> {
+                        width: Fill,
+                        height: Fit,
+                        padding: 8,
+                        flow: Down,
+                        spacing: 4,
+                        show_bg: true,
+                        draw_bg: {color: #333333}
+
+                        <View> {
+                            width: Fill,
+                            height: Fit,
+                            flow: Right,
+                            spacing: 4,
+                            align: {y: 0.5},
+
+                            <Label> {
+                                width: 40,
+                                text: "Auto"
+                                draw_text: {color: #ffffff, text_style: {font_size: 10.0}}
+                            }
+                            auto_type_btn = <Button> {
+                                width: 100, height: 24, text: "url-test",
+                                draw_text: {color: #aaccff, text_style: {font_size: 9.0}}
+                                draw_bg: { fn pixel(self) -> vec4 { return mix(#3a3a3a, #555555, self.hover); } }
+                            }
+                            auto_url_input = <TextInput> {
+                                width: Fill,
+                                height: 24,
+                                empty_text: "http://www.gstatic.com/generate_204"
+                                draw_text: {color: #ffffff, text_style: {font_size: 10.0}}
+                                draw_bg: {color: #555555}
+                            }
+                            auto_interval_input = <TextInput> {
+                                width: 60,
+                                height: 24,
+                                empty_text: "300 s"
+                                draw_text: {color: #ffffff, text_style: {font_size: 10.0}}
+                                draw_bg: {color: #555555}
+                            }
+                            auto_tolerance_input = <TextInput> {
+                                width: 60,
+                                height: 24,
+                                empty_text: "50 ms"
+                                draw_text: {color: #ffffff, text_style: {font_size: 10.0}}
+                                draw_bg: {color: #555555}
+                            }
+                        }
+
+                        <View> {
+                            width: Fill,
+                            height: Fit,
+                            flow: Right,
+                            spacing: 4,
+                            align: {y: 0.5},
+
+                            <View> { width: 40, height: Fit }
+                            auto_strategy_btn = <Button> {
+                                width: 130, height: 24, text: "consistent-hashing",
+                                draw_text: {color: #aaccff, text_style: {font_size: 8.5}}
+                                draw_bg: { fn pixel(self) -> vec4 { return mix(#3a3a3a, #555555, self.hover); } }
+                            }
+                            auto_lazy_btn = <Button> {
+                                width: 70, height: 24, text: "lazy: off",
+                                draw_text: {color: #aaccff, text_style: {font_size: 9.0}}
+                                draw_bg: { fn pixel(self) -> vec4 { return mix(#3a3a3a, #555555, self.hover); } }
+                            }
+                            region_groups_btn = <Button> {
+                                width: 90, height: 24, text: "regions: off",
+                                draw_text: {color: #aaccff, text_style: {font_size: 9.0}}
+                                draw_bg: { fn pixel(self) -> vec4 { return mix(#3a3a3a, #555555, self.hover); } }
+                            }
+                            auto_timeout_input = <TextInput> {
+                                width: 80,
+                                height: 24,
+                                empty_text: "timeout ms"
+                                draw_text: {color: #ffffff, text_style: {font_size: 10.0}}
+                                draw_bg: {color: #555555}
+                            }
+                            auto_extra_input = <TextInput> {
+                                width: Fill,
+                                height: 24,
+                                empty_text: "extra groups, e.g. Chain-Fallback:fallback; Chain-LB:load-balance"
+                                draw_text: {color: #ffffff, text_style: {font_size: 10.0}}
+                                draw_bg: {color: #555555}
+                            }
+                        }
+                    }
+
                     // Rules Rewrite (collapsible)
                     <View> {
                         width: Fill,
@@ -853,6 +944,25 @@ live_design! {
                                 draw_text: {color: #ffffff, text_style: {font_size: 10.0}}
                             }
 
+                            best_proxy_btn = <Button> {
+                                text: "Best"
+                                draw_text: {color: #ffffff, text_style: {font_size: 10.0}}
+                            }
+
+                            best_fresh_btn = <Button> {
+                                width: 70, height: 24, text: "fresh: off",
+                                draw_text: {color: #aaccff, text_style: {font_size: 9.0}}
+                                draw_bg: { fn pixel(self) -> vec4 { return mix(#3a3a3a, #555555, self.hover); } }
+                            }
+
+                            best_tag_input = <TextInput> {
+                                width: 60,
+                                height: 24,
+                                empty_text: "tag"
+                                draw_text: {color: #ffffff, text_style: {font_size: 9.0}}
+                                draw_bg: {color: #555555}
+                            }
+
                             auto_check_btn = <Button> {
                                 width: Fit,
                                 height: Fit,
@@ -898,6 +1008,35 @@ live_design! {
                             }
                         }
 
+                        // Passphrase prompt (shown when the config secrets are locked)
+                        unlock_panel = <View> {
+                            visible: false,
+                            width: Fill,
+                            height: Fit,
+                            flow: Right,
+                            spacing: 6,
+                            align: {y: 0.5},
+
+                            <Label> {
+                                text: "Config passphrase"
+                                draw_text: {color: #ffcc66, text_style: {font_size: 10.0}}
+                            }
+
+                            unlock_passphrase_input = <TextInput> {
+                                width: 200,
+                                height: 24,
+                                is_password: true,
+                                empty_text: "passphrase"
+                                draw_text: {color: #ffffff, text_style: {font_size: 10.0}}
+                                draw_bg: {color: #555555}
+                            }
+
+                            unlock_btn = <Button> {
+                                text: "Unlock"
+                                draw_text: {color: #ffffff, text_style: {font_size: 10.0}}
+                            }
+                        }
+
                         // Proxy Slots (10 pre-allocated)
                         <ScrollYView> {
                             width: Fill,
@@ -1097,6 +1236,21 @@ live_design! {
                             draw_text: {color: #ffffff}
                         }
 
+                        unapply_btn = <Button> {
+                            text: "Remove chains"
+                            draw_text: {color: #ffffff}
+                        }
+
+                        backups_btn = <Button> {
+                            text: "Backups"
+                            draw_text: {color: #ffffff}
+                        }
+
+                        restore_btn = <Button> {
+                            text: "Restore"
+                            draw_text: {color: #ffffff}
+                        }
+
                         download_btn = <Button> {
                             text: "Save"
                             draw_text: {color: #ffffff}
@@ -1175,6 +1329,8 @@ pub struct ApplyResult {
     pub success: bool,
     pub message: String,
     pub details: Vec<String>,
+    /// Plan that was applied (kept for watcher re-applies)
+    pub plan: Option<ApplyPlan>,
 }
 
 /// Maximum number of log lines to retain
@@ -1230,6 +1386,8 @@ pub struct AppState {
     pub recent_files: Vec<String>,
     pub show_file_history: bool,
     pub auto_checking: bool,
+    /// Health-check the candidates before picking the best proxy
+    pub best_fresh_check: bool,
     pub auto_check_interval: u64,
     pub health_check_rx: Option<std::sync::mpsc::Receiver<(String, clash_chain_patcher::health::ProxyValidationResult)>>,
     #[allow(dead_code)]
@@ -1253,6 +1411,11 @@ pub struct AppState {
     pub available_targets: Vec<String>,
     pub show_preset_list: bool,
     pub custom_rule_presets: Vec<CustomRuleSet>,
+    // Chain-Auto group (type None = no Chain-Auto)
+    pub auto_group_type: Option<AutoGroupType>,
+    pub auto_strategy: BalanceStrategy,
+    pub auto_lazy: bool,
+    pub region_groups: bool,
 }
 
 impl Default for AppState {
@@ -1269,6 +1432,7 @@ impl Default for AppState {
             recent_files: Vec::new(),
             show_file_history: false,
             auto_checking: false,
+            best_fresh_check: false,
             auto_check_interval: 5,
             health_check_rx: None,
             auto_check_handle: None,
@@ -1288,6 +1452,10 @@ impl Default for AppState {
             available_targets: vec!["DIRECT".to_string(), "Chain-Selector".to_string(), "Chain-Auto".to_string()],
             show_preset_list: false,
             custom_rule_presets: Vec::new(),
+            auto_group_type: Some(AutoGroupType::UrlTest),
+            auto_strategy: BalanceStrategy::ConsistentHashing,
+            auto_lazy: false,
+            region_groups: false,
         }
     }
 }
@@ -1395,6 +1563,18 @@ impl MatchEvent for App {
         if self.ui.button(id!(toggle_custom_rules_btn)).clicked(actions) {
             self.toggle_custom_rules_panel(cx);
         }
+        if self.ui.button(id!(auto_type_btn)).clicked(actions) {
+            self.cycle_auto_group_type(cx);
+        }
+        if self.ui.button(id!(auto_strategy_btn)).clicked(actions) {
+            self.cycle_auto_strategy(cx);
+        }
+        if self.ui.button(id!(auto_lazy_btn)).clicked(actions) {
+            self.toggle_auto_lazy(cx);
+        }
+        if self.ui.button(id!(region_groups_btn)).clicked(actions) {
+            self.toggle_region_groups(cx);
+        }
         if self.ui.button(id!(custom_rule_type_btn)).clicked(actions) {
             self.cycle_custom_rule_type(cx);
         }
@@ -1465,6 +1645,15 @@ impl MatchEvent for App {
         if self.ui.button(id!(apply_btn)).clicked(actions) {
             self.apply_patch(cx);
         }
+        if self.ui.button(id!(unapply_btn)).clicked(actions) {
+            self.remove_chains(cx);
+        }
+        if self.ui.button(id!(backups_btn)).clicked(actions) {
+            self.list_backups(cx);
+        }
+        if self.ui.button(id!(restore_btn)).clicked(actions) {
+            self.restore_backup(cx);
+        }
         if self.ui.button(id!(download_btn)).clicked(actions) {
             self.save_output(cx);
         }
@@ -1474,6 +1663,15 @@ impl MatchEvent for App {
         if self.ui.button(id!(check_all_proxies_btn)).clicked(actions) {
             self.check_all_proxies(cx);
         }
+        if self.ui.button(id!(best_proxy_btn)).clicked(actions) {
+            self.load_best_proxy_to_form(cx);
+        }
+        if self.ui.button(id!(unlock_btn)).clicked(actions) {
+            self.unlock_proxy_state(cx);
+        }
+        if self.ui.button(id!(best_fresh_btn)).clicked(actions) {
+            self.toggle_best_fresh_check(cx);
+        }
         if self.ui.button(id!(auto_check_btn)).clicked(actions) {
             self.toggle_auto_health_check(cx);
         }
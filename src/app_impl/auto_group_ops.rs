@@ -0,0 +1,105 @@
+//! Chain-Auto Group Operations
+//!
+//! Methods for:
+//! - Cycling the Chain-Auto type and load-balance strategy
+//! - Toggling lazy health checks and per-region groups
+//! - Building the auto groups for the preview/apply flow
+
+use makepad_widgets::*;
+use clash_chain_patcher::config::ConfigManager;
+use clash_chain_patcher::merger::{AutoGroup, AutoGroupType, BalanceStrategy, RegionTable};
+use crate::app::App;
+
+impl App {
+    /// Cycle the type button: url-test → fallback → load-balance → off
+    pub(crate) fn cycle_auto_group_type(&mut self, cx: &mut Cx) {
+        self.state.auto_group_type = match self.state.auto_group_type {
+            Some(AutoGroupType::UrlTest) => Some(AutoGroupType::Fallback),
+            Some(AutoGroupType::Fallback) => Some(AutoGroupType::LoadBalance),
+            Some(AutoGroupType::LoadBalance) => None,
+            None => Some(AutoGroupType::UrlTest),
+        };
+        let label = self.state.auto_group_type.map_or("off", |t| t.as_str());
+        self.ui.button(id!(auto_type_btn)).set_text(cx, label);
+        self.ui.redraw(cx);
+    }
+
+    /// Cycle the load-balance strategy button
+    pub(crate) fn cycle_auto_strategy(&mut self, cx: &mut Cx) {
+        self.state.auto_strategy = match self.state.auto_strategy {
+            BalanceStrategy::ConsistentHashing => BalanceStrategy::RoundRobin,
+            BalanceStrategy::RoundRobin => BalanceStrategy::ConsistentHashing,
+        };
+        self.ui.button(id!(auto_strategy_btn)).set_text(cx, self.state.auto_strategy.as_str());
+        self.ui.redraw(cx);
+    }
+
+    /// Toggle lazy health checks
+    pub(crate) fn toggle_auto_lazy(&mut self, cx: &mut Cx) {
+        self.state.auto_lazy = !self.state.auto_lazy;
+        let label = if self.state.auto_lazy { "lazy: on" } else { "lazy: off" };
+        self.ui.button(id!(auto_lazy_btn)).set_text(cx, label);
+        self.ui.redraw(cx);
+    }
+
+    /// Toggle the `Chain-<CODE>` region groups
+    pub(crate) fn toggle_region_groups(&mut self, cx: &mut Cx) {
+        self.state.region_groups = !self.state.region_groups;
+        let label = if self.state.region_groups { "regions: on" } else { "regions: off" };
+        self.ui.button(id!(region_groups_btn)).set_text(cx, label);
+        self.ui.redraw(cx);
+    }
+
+    /// Region table for the merger, when region groups are on
+    pub(crate) fn region_table(&self) -> Option<RegionTable> {
+        self.state.region_groups.then(ConfigManager::region_settings)
+    }
+
+    /// Chain-Auto from the Auto row, followed by the `;` separated extra
+    /// groups; logs the error and returns `None` when a field is invalid
+    pub(crate) fn build_auto_groups(&mut self, cx: &mut Cx) -> Option<Vec<AutoGroup>> {
+        match self.read_auto_groups() {
+            Ok(groups) => Some(groups),
+            Err(e) => {
+                self.clear_logs(cx);
+                self.add_log(cx, &format!("✗ Auto group: {:#}", e));
+                self.set_status(cx, "Error");
+                self.update_log_display(cx);
+                self.ui.redraw(cx);
+                None
+            }
+        }
+    }
+
+    fn read_auto_groups(&self) -> anyhow::Result<Vec<AutoGroup>> {
+        let mut groups = Vec::new();
+        if let Some(kind) = self.state.auto_group_type {
+            let mut auto = AutoGroup::new("Chain-Auto", kind);
+            for (key, input) in [
+                ("url", id!(auto_url_input)),
+                ("interval", id!(auto_interval_input)),
+                ("timeout", id!(auto_timeout_input)),
+            ] {
+                let value = self.ui.text_input(input).text();
+                if !value.trim().is_empty() {
+                    auto.set(key, &value)?;
+                }
+            }
+            // Tolerance and strategy only apply to one type each
+            let tolerance = self.ui.text_input(id!(auto_tolerance_input)).text();
+            if kind == AutoGroupType::UrlTest && !tolerance.trim().is_empty() {
+                auto.set("tolerance", &tolerance)?;
+            }
+            if kind == AutoGroupType::LoadBalance {
+                auto.strategy = Some(self.state.auto_strategy);
+            }
+            auto.lazy = self.state.auto_lazy.then_some(true);
+            groups.push(auto);
+        }
+        let extra = self.ui.text_input(id!(auto_extra_input)).text();
+        for spec in extra.split(';').filter(|s| !s.trim().is_empty()) {
+            groups.push(AutoGroup::parse(spec.trim())?);
+        }
+        Ok(groups)
+    }
+}
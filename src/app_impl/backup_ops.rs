@@ -0,0 +1,120 @@
+//! Backup Operations
+//!
+//! Methods for:
+//! - Listing the backup history of the selected Clash config
+//! - Restoring the most recent backup that differs from the current file
+
+use makepad_widgets::*;
+use clash_chain_patcher::backup::BackupStore;
+use clash_chain_patcher::config::ConfigManager;
+use std::path::PathBuf;
+use crate::app::App;
+
+impl App {
+    /// Show the backup history of the selected config in the log
+    pub(crate) fn list_backups(&mut self, cx: &mut Cx) {
+        self.clear_logs(cx);
+        let Some(config_path) = self.selected_config_path() else {
+            self.add_log(cx, "✗ Select Clash config file first");
+            self.set_status(cx, "No config");
+            self.update_log_display(cx);
+            self.ui.redraw(cx);
+            return;
+        };
+
+        let store = BackupStore::new(ConfigManager::backup_settings());
+        match store.list(&config_path) {
+            Ok(entries) if entries.is_empty() => {
+                self.add_log(cx, "No backups yet (one is taken before every apply)");
+                self.set_status(cx, "No backups");
+            }
+            Ok(entries) => {
+                if let Ok(dir) = store.dir_for(&config_path) {
+                    self.add_log(cx, &format!("Backups in {}:", dir.display()));
+                }
+                for (i, entry) in entries.iter().enumerate() {
+                    let created = entry.created_at.with_timezone(&chrono::Local);
+                    self.add_log(cx, &format!(
+                        "{:>2}. {}  {} bytes",
+                        i + 1, created.format("%Y-%m-%d %H:%M:%S"), entry.size
+                    ));
+                }
+                self.add_log(cx, "");
+                self.add_log(cx, "Click Restore to roll back to the latest different version");
+                self.set_status(cx, &format!("{} backups", entries.len()));
+            }
+            Err(e) => {
+                self.add_log(cx, &format!("✗ Failed to list backups: {:#}", e));
+                self.set_status(cx, "Failed");
+            }
+        }
+
+        self.update_log_display(cx);
+        self.ui.redraw(cx);
+    }
+
+    /// Restore the newest backup whose content differs from the current file
+    pub(crate) fn restore_backup(&mut self, cx: &mut Cx) {
+        if self.state.is_applying {
+            self.add_log(cx, "⚠ Apply is already in progress");
+            self.update_log_display(cx);
+            self.ui.redraw(cx);
+            return;
+        }
+
+        self.clear_logs(cx);
+        let Some(config_path) = self.selected_config_path() else {
+            self.add_log(cx, "✗ Select Clash config file first");
+            self.set_status(cx, "No config");
+            self.update_log_display(cx);
+            self.ui.redraw(cx);
+            return;
+        };
+
+        let store = BackupStore::new(ConfigManager::backup_settings());
+        let current = std::fs::read_to_string(&config_path).ok();
+        let target = store.list(&config_path).map(|entries| {
+            entries.into_iter().find(|e| store.read(e).ok() != current)
+        });
+
+        match target {
+            Ok(Some(entry)) => match store.restore(&config_path, &entry) {
+                Ok(_) => {
+                    let created = entry.created_at.with_timezone(&chrono::Local);
+                    self.add_log(cx, &format!("✓ Restored backup from {}", created.format("%Y-%m-%d %H:%M:%S")));
+                    self.add_log(cx, "  The replaced version was backed up too");
+
+                    // Reload so the rules panels reflect the restored config
+                    if let Ok(content) = std::fs::read_to_string(&config_path) {
+                        self.state.config_content = Some(content);
+                        self.refresh_rule_groups(cx);
+                        self.refresh_available_targets(cx);
+                    }
+                    self.set_status(cx, "Restored");
+                }
+                Err(e) => {
+                    self.add_log(cx, &format!("✗ Restore failed: {:#}", e));
+                    self.set_status(cx, "Failed");
+                }
+            },
+            Ok(None) => {
+                self.add_log(cx, "No earlier version to restore");
+                self.set_status(cx, "Nothing to restore");
+            }
+            Err(e) => {
+                self.add_log(cx, &format!("✗ Failed to list backups: {:#}", e));
+                self.set_status(cx, "Failed");
+            }
+        }
+
+        self.update_log_display(cx);
+        self.ui.redraw(cx);
+    }
+
+    /// Path of the Clash config selected in the GUI
+    fn selected_config_path(&self) -> Option<PathBuf> {
+        self.state.proxy_state.as_ref()
+            .and_then(|state| state.clash_config_path())
+            .map(|p| p.to_path_buf())
+    }
+}
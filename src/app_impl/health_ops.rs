@@ -11,6 +11,9 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use crate::app::App;
 
+/// Proxy to check: id, host, port, username, password
+pub(crate) type CheckTarget = (String, String, u16, Option<String>, Option<String>);
+
 impl App {
     /// Check health of all enabled proxies
     pub(crate) fn check_all_proxies(&mut self, cx: &mut Cx) {
@@ -21,7 +24,7 @@ impl App {
         }
 
         // Get proxies to check
-        let proxies_info: Vec<(String, String, u16, Option<String>, Option<String>)> = {
+        let proxies_info: Vec<CheckTarget> = {
             if let Some(state) = &self.state.proxy_state {
                 state.list_upstreams()
                     .into_iter()
@@ -46,6 +49,16 @@ impl App {
             return;
         }
 
+        self.check_upstreams(cx, &proxies_info);
+
+        self.clear_logs(cx);
+        self.add_log(cx, "✓ Health check completed");
+        self.refresh_proxy_list_display(cx);
+        self.ui.redraw(cx);
+    }
+
+    /// Check the given proxies one by one and store the results in the pool
+    pub(crate) fn check_upstreams(&mut self, cx: &mut Cx, proxies_info: &[CheckTarget]) {
         self.state.checking = true;
         self.clear_logs(cx);
         self.add_log(cx, &format!("Checking {} proxies...", proxies_info.len()));
@@ -88,10 +101,6 @@ impl App {
         }
 
         self.state.checking = false;
-        self.clear_logs(cx);
-        self.add_log(cx, "✓ Health check completed");
-        self.refresh_proxy_list_display(cx);
-        self.ui.redraw(cx);
     }
 
     /// Toggle auto health check on/off
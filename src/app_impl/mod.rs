@@ -3,6 +3,8 @@
 //! These modules contain impl App blocks for different functional areas,
 //! keeping the main app.rs smaller and more organized.
 
+pub mod auto_group_ops;
+pub mod backup_ops;
 pub mod custom_rules_ops;
 pub mod file_ops;
 pub mod health_ops;
@@ -1,52 +1,86 @@
 //! Patch Operations (Preview & Apply)
 //!
 //! Methods for:
-//! - Preview patch changes
+//! - Preview patch changes as a unified diff
 //! - Apply patch to Clash config
+//! - Remove chains from Clash config
 //! - Handle apply results
 
 use makepad_widgets::*;
-use clash_chain_patcher::patcher::{self, PatchOptions};
-use clash_chain_patcher::merger::MergerConfig;
+use clash_chain_patcher::config::ConfigManager;
+use clash_chain_patcher::merger::{ApplyPlan, MergerConfig, NodeFilter};
 use crate::app::{App, ApplyResult};
 
 impl App {
-    /// Get patch options from form
-    pub(crate) fn get_options_from_form(&self) -> PatchOptions {
-        let filter_str = self.ui.text_input(id!(filter_input)).text();
-        let filter_keywords: Vec<String> = if filter_str.is_empty() {
-            vec![]
-        } else {
-            filter_str.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect()
-        };
-        PatchOptions { filter_keywords }
-    }
-
-    /// Preview the patch without applying
+    /// Preview the patch as a unified diff without applying
     pub(crate) fn preview_patch(&mut self, cx: &mut Cx) {
         self.clear_logs(cx);
-        let config = match &self.state.config_content {
-            Some(c) => c.clone(),
+        // Prefer the file on disk: it may have changed since it was loaded
+        let on_disk = self.state.proxy_state.as_ref()
+            .and_then(|state| state.clash_config_path())
+            .and_then(|path| std::fs::read_to_string(path).ok());
+        let config = match on_disk.or_else(|| self.state.config_content.clone()) {
+            Some(c) => c,
             None => { self.add_log(cx, "Select file first"); self.update_log_display(cx); self.ui.redraw(cx); return; }
         };
         let proxy = match self.get_proxy_from_form() {
             Some(p) => p,
             None => { self.add_log(cx, "Fill proxy info"); self.update_log_display(cx); self.ui.redraw(cx); return; }
         };
-        let opts = self.get_options_from_form();
-        let result = patcher::preview_patch(&config, &proxy, &opts);
-        for log in &result.logs { self.add_log(cx, log); }
-        if result.success {
-            self.add_log(cx, "");
-            for name in &result.relay_names {
-                self.add_log(cx, &format!("  {}", name));
+        let Some(node_filter) = self.build_node_filter(cx) else { return };
+        let Some(auto_groups) = self.build_auto_groups(cx) else { return };
+
+        let plan = ApplyPlan::new(MergerConfig {
+            proxy_host: proxy.host.clone(),
+            proxy_port: proxy.port,
+            proxy_username: proxy.username.clone().filter(|s| !s.is_empty()),
+            proxy_password: proxy.password.clone().filter(|s| !s.is_empty()),
+            node_filter,
+            auto_groups,
+            region_groups: self.region_table(),
+            ..MergerConfig::default()
+        })
+        .with_rule_rewrites(self.build_rule_replacements())
+        .with_custom_rules(self.build_custom_rules());
+
+        let name = self.state.config_filename.clone().unwrap_or_else(|| "config.yaml".to_string());
+        match plan.diff(&config, &name) {
+            Ok((diff, planned)) => {
+                if diff.is_empty() {
+                    self.add_log(cx, "No changes");
+                }
+                for line in diff.lines() { self.add_log(cx, line); }
+                for w in &planned.merge.warnings {
+                    self.add_log(cx, &format!("⚠ {}", w));
+                }
+                self.set_status(cx, "Preview OK");
+            }
+            Err(e) => {
+                self.add_log(cx, &format!("✗ Preview failed: {}", e));
+                self.set_status(cx, "Failed");
             }
-            self.set_status(cx, "Preview OK");
-        } else { self.set_status(cx, "Failed"); }
+        }
         self.update_log_display(cx);
         self.ui.redraw(cx);
     }
 
+    /// Node filter from the Filter box plus the saved ignore patterns; logs
+    /// the error and returns `None` when the box cannot be parsed
+    fn build_node_filter(&mut self, cx: &mut Cx) -> Option<NodeFilter> {
+        let spec = self.ui.text_input(id!(filter_input)).text();
+        match NodeFilter::parse(&spec) {
+            Ok(filter) => Some(NodeFilter { ignore: ConfigManager::ignore_settings(), ..filter }),
+            Err(e) => {
+                self.clear_logs(cx);
+                self.add_log(cx, &format!("✗ Filter: {:#}", e));
+                self.set_status(cx, "Error");
+                self.update_log_display(cx);
+                self.ui.redraw(cx);
+                None
+            }
+        }
+    }
+
     /// Apply patch to Clash config (async, non-blocking)
     pub(crate) fn apply_patch(&mut self, cx: &mut Cx) {
         // Check if already applying
@@ -108,6 +142,9 @@ impl App {
             return;
         };
 
+        let Some(node_filter) = self.build_node_filter(cx) else { return };
+        let Some(auto_groups) = self.build_auto_groups(cx) else { return };
+
         // Create channel for result
         let (tx, rx) = std::sync::mpsc::channel();
         self.state.apply_result_rx = Some(rx);
@@ -121,25 +158,27 @@ impl App {
         self.set_status(cx, "Applying...");
         self.update_log_display(cx);
 
-        // Build rule replacements from UI state
-        let rule_replacements = self.build_rule_replacements();
-        let has_rule_rewrites = !rule_replacements.is_empty();
-
-        // Build custom rules from UI state
-        let custom_rules = self.build_custom_rules();
-        let has_custom_rules = !custom_rules.is_empty();
-
-        // Clone data for thread
-        let proxy_host_clone = proxy_host.clone();
-        let proxy_username_clone = proxy_username.clone().filter(|s| !s.is_empty());
-        let proxy_password_clone = proxy_password.clone().filter(|s| !s.is_empty());
-
-        if has_rule_rewrites {
-            self.add_log(cx, &format!("  Rules rewrite: {} groups to replace", rule_replacements.len()));
+        // Merge, rules rewrite and custom rules are written in one step
+        let plan = ApplyPlan::new(MergerConfig {
+            proxy_host: proxy_host.clone(),
+            proxy_port,
+            proxy_username: proxy_username.clone().filter(|s| !s.is_empty()),
+            proxy_password: proxy_password.clone().filter(|s| !s.is_empty()),
+            node_filter,
+            auto_groups,
+            region_groups: self.region_table(),
+            ..MergerConfig::default()
+        })
+        .with_rule_rewrites(self.build_rule_replacements())
+        .with_custom_rules(self.build_custom_rules())
+        .with_backups(ConfigManager::backup_settings());
+
+        if !plan.rule_rewrites().is_empty() {
+            self.add_log(cx, &format!("  Rules rewrite: {} groups to replace", plan.rule_rewrites().len()));
             self.update_log_display(cx);
         }
-        if has_custom_rules {
-            self.add_log(cx, &format!("  Custom rules: {} to inject", custom_rules.len()));
+        if !plan.custom_rules().is_empty() {
+            self.add_log(cx, &format!("  Custom rules: {} to inject", plan.custom_rules().len()));
             self.update_log_display(cx);
         }
 
@@ -148,27 +187,12 @@ impl App {
             use clash_chain_patcher::bridge::MergerBridge;
 
             let result = (|| -> Result<ApplyResult, String> {
-                // Create MergerConfig with actual proxy from pool
-                let merger_config = MergerConfig {
-                    proxy_name: "Local-Chain-Proxy".to_string(),
-                    proxy_host: proxy_host_clone.clone(),
-                    proxy_port,
-                    proxy_username: proxy_username_clone.clone(),
-                    proxy_password: proxy_password_clone.clone(),
-                    create_backup: true,
-                    insert_at_beginning: true,
-                    chain_suffix: "-Chain".to_string(),
-                };
-
-                // Create MergerBridge with custom config
-                let merger = MergerBridge::with_config(merger_config);
-
-                // Execute merge
-                match merger.merge(&config_path) {
-                    Ok(merge_result) => {
+                match MergerBridge::apply_plan(&plan, &config_path) {
+                    Ok(planned) => {
+                        let merge_result = &planned.merge;
                         let mut details = Vec::new();
-                        details.push(format!("SOCKS5 Proxy: {}:{}", proxy_host_clone, proxy_port));
-                        if let Some(ref username) = proxy_username_clone {
+                        details.push(format!("SOCKS5 Proxy: {}:{}", proxy_host, proxy_port));
+                        if let Some(ref username) = plan.merger_config().proxy_username {
                             details.push(format!("  User: {}", username));
                         }
                         details.push("".to_string());
@@ -176,58 +200,26 @@ impl App {
                         details.push(format!("Chain relays created: {}", merge_result.chains_created));
                         details.push(format!("Groups updated: {}", merge_result.groups_updated));
 
-                        if let Some(backup_path) = merge_result.backup_path {
+                        if let Some(ref backup_path) = merge_result.backup_path {
                             details.push(format!("Backup: {}", backup_path.display()));
                         }
 
-                        // Apply rules rewrite if any (text-based, format-preserving)
-                        if !rule_replacements.is_empty() {
-                            match std::fs::read_to_string(&config_path) {
-                                Ok(content) => {
-                                    let (output, count) = clash_chain_patcher::patcher::rewrite_rules_text(&content, &rule_replacements);
-                                    if count > 0 {
-                                        if let Err(e) = std::fs::write(&config_path, output) {
-                                            details.push(format!("✗ Rules rewrite write failed: {}", e));
-                                        } else {
-                                            details.push(format!("Rules rewritten: {} rules updated", count));
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    details.push(format!("✗ Rules rewrite read failed: {}", e));
-                                }
-                            }
+                        if planned.rules_rewritten > 0 {
+                            details.push(format!("Rules rewritten: {} rules updated", planned.rules_rewritten));
                         }
 
-                        // Inject custom rules if any (Step 3)
-                        if !custom_rules.is_empty() {
-                            // Log each custom rule
-                            for r in &custom_rules {
-                                if r.enabled {
-                                    details.push(format!("  + {},{},{}", r.match_type.clash_prefix(), r.domain, r.target_group));
-                                }
-                            }
-                            match std::fs::read_to_string(&config_path) {
-                                Ok(content) => {
-                                    let (output, count) = clash_chain_patcher::patcher::inject_custom_rules_text(&content, &custom_rules);
-                                    if count > 0 {
-                                        if let Err(e) = std::fs::write(&config_path, output) {
-                                            details.push(format!("✗ Custom rules write failed: {}", e));
-                                        } else {
-                                            details.push(format!("Custom rules injected: {} rules", count));
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    details.push(format!("✗ Custom rules read failed: {}", e));
-                                }
-                            }
+                        for r in plan.custom_rules().iter().filter(|r| r.enabled) {
+                            details.push(format!("  + {},{},{}", r.match_type.clash_prefix(), r.domain, r.target_group));
+                        }
+                        if planned.rules_injected > 0 {
+                            details.push(format!("Custom rules injected: {} rules", planned.rules_injected));
                         }
 
                         Ok(ApplyResult {
                             success: true,
                             message: "✓ Configuration applied successfully".to_string(),
                             details,
+                            plan: Some(plan),
                         })
                     }
                     Err(e) => {
@@ -235,6 +227,7 @@ impl App {
                             success: false,
                             message: format!("✗ Apply failed: {}", e),
                             details: vec![],
+                            plan: None,
                         })
                     }
                 }
@@ -244,6 +237,7 @@ impl App {
                 success: false,
                 message: format!("✗ Error: {}", e),
                 details: vec![],
+                plan: None,
             });
 
             let _ = tx.send(apply_result);
@@ -252,6 +246,64 @@ impl App {
         self.ui.redraw(cx);
     }
 
+    /// Remove all chain proxies, chain groups and rule changes from the config
+    pub(crate) fn remove_chains(&mut self, cx: &mut Cx) {
+        if self.state.is_applying {
+            self.add_log(cx, "⚠ Apply is already in progress");
+            self.update_log_display(cx);
+            self.ui.redraw(cx);
+            return;
+        }
+
+        self.clear_logs(cx);
+        let config_path = self.state.proxy_state.as_ref()
+            .and_then(|state| state.clash_config_path())
+            .map(|p| p.to_path_buf());
+        let Some(config_path) = config_path else {
+            self.add_log(cx, "✗ Select Clash config file first");
+            self.set_status(cx, "No config");
+            self.update_log_display(cx);
+            self.ui.redraw(cx);
+            return;
+        };
+
+        use clash_chain_patcher::bridge::MergerBridge;
+        match MergerBridge::new().with_backups(ConfigManager::backup_settings()).unapply(&config_path) {
+            Ok(result) if result.is_empty() => {
+                self.add_log(cx, "No chain artifacts found");
+                self.set_status(cx, "Nothing to remove");
+            }
+            Ok(result) => {
+                self.add_log(cx, "✓ Chains removed");
+                self.add_log(cx, &format!("Proxies removed: {}", result.proxies_removed));
+                self.add_log(cx, &format!("Groups removed: {}", result.groups_removed));
+                self.add_log(cx, &format!("Rules restored: {}", result.rules_restored));
+                self.add_log(cx, &format!("Custom rules removed: {}", result.rules_removed));
+                if let Some(backup_path) = result.backup_path {
+                    self.add_log(cx, &format!("Backup: {}", backup_path.display()));
+                }
+                for w in &result.warnings {
+                    self.add_log(cx, &format!("⚠ {}", w));
+                }
+
+                // Reload so the rules panels reflect the restored groups
+                if let Ok(content) = std::fs::read_to_string(&config_path) {
+                    self.state.config_content = Some(content);
+                    self.refresh_rule_groups(cx);
+                    self.refresh_available_targets(cx);
+                }
+                self.set_status(cx, "Removed");
+            }
+            Err(e) => {
+                self.add_log(cx, &format!("✗ Remove failed: {}", e));
+                self.set_status(cx, "Failed");
+            }
+        }
+
+        self.update_log_display(cx);
+        self.ui.redraw(cx);
+    }
+
     /// Handle apply result from background thread
     pub(crate) fn handle_apply_result(&mut self, cx: &mut Cx, result: ApplyResult) {
         eprintln!("DEBUG: Apply completed: success={}", result.success);
@@ -264,6 +316,9 @@ impl App {
         }
 
         if result.success {
+            if let (Some(plan), Some(state)) = (result.plan, self.state.proxy_state.as_mut()) {
+                state.set_last_apply_plan(plan);
+            }
             self.add_log(cx, "");
             self.add_log(cx, "Next steps:");
             self.add_log(cx, "1. Refresh Clash configuration");
@@ -3,14 +3,17 @@
 //! Methods for:
 //! - Adding/removing proxies from the pool
 //! - Loading proxy info to form
+//! - Picking the best healthy proxy from the pool
 //! - Parsing proxy strings
 //! - Refreshing proxy list display
 
 use makepad_widgets::*;
 use clash_chain_patcher::patcher::{self, Socks5Proxy};
-use clash_chain_patcher::config::UpstreamProxy;
+use clash_chain_patcher::config::{ConfigManager, UpstreamProxy};
+use clash_chain_patcher::health::UpstreamSelector;
 use clash_chain_patcher::proxy::config::UpstreamConfig;
 use clash_chain_patcher::state::ProxyState;
+use std::time::SystemTime;
 use crate::app::App;
 
 impl App {
@@ -58,9 +61,29 @@ impl App {
     }
 
     /// Initialize proxy state on startup
+    ///
+    /// A passphrase-protected config stays locked until the passphrase is
+    /// entered (see [`Self::unlock_proxy_state`]).
     pub(crate) fn init_proxy_state(&mut self, cx: &mut Cx) {
+        if ConfigManager::passphrase_required() {
+            self.ui.view(id!(unlock_panel)).set_visible(cx, true);
+            self.add_log(cx, "Config secrets are passphrase-protected");
+            self.add_log(cx, "  Enter the passphrase and click Unlock");
+            self.ui.redraw(cx);
+            return;
+        }
+
+        self.load_proxy_state(cx, None);
+    }
+
+    /// Create the proxy state, unlocking the config with `passphrase` if given
+    fn load_proxy_state(&mut self, cx: &mut Cx, passphrase: Option<&str>) {
         let mut state = ProxyState::new();
-        if let Err(e) = state.initialize() {
+        let result = match passphrase {
+            Some(passphrase) => state.initialize_with_passphrase(passphrase),
+            None => state.initialize(),
+        };
+        if let Err(e) = result {
             self.add_log(cx, &format!("ProxyState init error: {}", e));
             return;
         }
@@ -94,6 +117,27 @@ impl App {
         }
     }
 
+    /// Initialize proxy state with the passphrase from the unlock prompt
+    pub(crate) fn unlock_proxy_state(&mut self, cx: &mut Cx) {
+        let passphrase = self.ui.text_input(id!(unlock_passphrase_input)).text();
+        if passphrase.is_empty() {
+            self.add_log(cx, "✗ Enter the config passphrase first");
+            self.ui.redraw(cx);
+            return;
+        }
+        self.ui.text_input(id!(unlock_passphrase_input)).set_text(cx, "");
+
+        self.clear_logs(cx);
+        self.load_proxy_state(cx, Some(&passphrase));
+        if self.state.proxy_state.is_none() {
+            self.add_log(cx, "✗ Config is still locked");
+            self.ui.redraw(cx);
+            return;
+        }
+        self.ui.view(id!(unlock_panel)).set_visible(cx, false);
+        self.ui.redraw(cx);
+    }
+
     /// Add proxy from form to pool
     pub(crate) fn add_proxy_to_pool(&mut self, cx: &mut Cx) {
         // Get proxy from form
@@ -137,6 +181,7 @@ impl App {
             },
             enabled: true,
             health: Default::default(),
+            tags: Vec::new(),
         };
 
         // Add to pool
@@ -218,6 +263,96 @@ impl App {
         }
     }
 
+    /// Toggle health-checking the candidates before picking the best proxy
+    pub(crate) fn toggle_best_fresh_check(&mut self, cx: &mut Cx) {
+        self.state.best_fresh_check = !self.state.best_fresh_check;
+        let label = if self.state.best_fresh_check { "fresh: on" } else { "fresh: off" };
+        self.ui.button(id!(best_fresh_btn)).set_text(cx, label);
+        self.ui.redraw(cx);
+    }
+
+    /// Load the best healthy pool proxy (latency, failure rate, freshness) to
+    /// form, like `ccp apply --from-pool`: optionally only proxies with the
+    /// tag, optionally checked first, and never a stale or unhealthy one
+    pub(crate) fn load_best_proxy_to_form(&mut self, cx: &mut Cx) {
+        if self.state.checking {
+            self.add_log(cx, "Check in progress...");
+            self.ui.redraw(cx);
+            return;
+        }
+
+        let tag = self.ui.text_input(id!(best_tag_input)).text().trim().to_string();
+        let selector = match tag.as_str() {
+            "" => UpstreamSelector::new(),
+            t => UpstreamSelector::new().tag(t),
+        };
+        let with_tag = if tag.is_empty() { String::new() } else { format!(" with tag '{}'", tag) };
+
+        let candidates: Vec<_> = match &self.state.proxy_state {
+            Some(state) => selector.candidates(&state.list_upstreams())
+                .into_iter()
+                .map(|p| (
+                    p.id.clone(),
+                    p.config.host.clone(),
+                    p.config.port,
+                    p.config.username.clone(),
+                    p.config.password.clone(),
+                ))
+                .collect(),
+            None => return,
+        };
+        if candidates.is_empty() {
+            self.clear_logs(cx);
+            self.add_log(cx, &format!("✗ No enabled proxies in pool{}", with_tag));
+            self.ui.redraw(cx);
+            return;
+        }
+
+        if self.state.best_fresh_check {
+            self.check_upstreams(cx, &candidates);
+            self.refresh_proxy_list_display(cx);
+        }
+
+        let proxies = match &self.state.proxy_state {
+            Some(state) => state.list_upstreams(),
+            None => return,
+        };
+        let Some(best) = selector.select(&proxies) else {
+            self.clear_logs(cx);
+            self.add_log(cx, &format!("✗ No healthy proxy in pool{}", with_tag));
+            if !self.state.best_fresh_check {
+                self.add_log(cx, "  Turn fresh on or run Check All first");
+            }
+            self.ui.redraw(cx);
+            return;
+        };
+        if selector.is_stale(best, SystemTime::now()) {
+            self.clear_logs(cx);
+            self.add_log(cx, &format!("✗ Best proxy {} was not checked recently", best.name));
+            self.add_log(cx, "  Turn fresh on or run Check All first");
+            self.ui.redraw(cx);
+            return;
+        }
+
+        self.ui.text_input(id!(host_input)).set_text(cx, &best.config.host);
+        self.ui.text_input(id!(port_input)).set_text(cx, &best.config.port.to_string());
+        self.ui.text_input(id!(username_input))
+            .set_text(cx, best.config.username.as_deref().unwrap_or(""));
+        self.ui.text_input(id!(password_input))
+            .set_text(cx, best.config.password.as_deref().unwrap_or(""));
+
+        self.clear_logs(cx);
+        self.add_log(cx, &format!(
+            "✓ Loaded best proxy: {} ({}ms)",
+            best.name,
+            best.health.latency_ms.unwrap_or_default()
+        ));
+        if let Some(exit_ip) = &best.health.exit_ip {
+            self.add_log(cx, &format!("   Exit IP: {}", exit_ip));
+        }
+        self.ui.redraw(cx);
+    }
+
     /// Delete proxy from a specific slot
     pub(crate) fn delete_proxy_by_slot(&mut self, cx: &mut Cx, slot_index: usize) {
         if let Some(state) = &mut self.state.proxy_state {
@@ -71,13 +71,13 @@ impl App {
         let download_dir = dirs::download_dir().unwrap_or_else(|| std::path::PathBuf::from("."));
         let output_path = download_dir.join(&output_filename);
 
-        match std::fs::write(&output_path, content) {
+        match clash_chain_patcher::fsutil::write_atomic(&output_path, content) {
             Ok(_) => {
                 self.add_log(cx, &format!("Saved: {}", output_path.display()));
                 self.set_status(cx, "Saved");
             }
             Err(e) => {
-                self.add_log(cx, &format!("Save failed: {}", e));
+                self.add_log(cx, &format!("Save failed: {:#}", e));
                 self.set_status(cx, "Save failed");
             }
         }
@@ -0,0 +1,8 @@
+//! Backup history module
+//!
+//! Keeps timestamped, deduplicated copies of a Clash config before every
+//! write, with retention by count and age, and restores them on demand.
+
+pub mod store;
+
+pub use store::{BackupEntry, BackupStore};
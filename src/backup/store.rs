@@ -0,0 +1,353 @@
+//! Versioned backup store
+//!
+//! Each backup is a copy of the config named `<UTC timestamp>-<hash>.<ext>`,
+//! with the config's own extension (`yaml` when it has none), kept in
+//! `.ccp-backups/<file name>/` next to the config (or under the configured
+//! backup directory). Content that is already stored is not
+//! copied again; old versions are pruned by count and age, but the newest
+//! backup is never removed.
+
+use anyhow::{Context, Result};
+use blake2::{Blake2s256, Digest};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::config::BackupConfig;
+use crate::fsutil;
+
+/// Directory created next to the config when no backup dir is configured
+pub const DEFAULT_DIR_NAME: &str = ".ccp-backups";
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+const HASH_LEN: usize = 12;
+
+/// One stored version of a config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupEntry {
+    /// `<timestamp>-<hash>`, also the file stem
+    pub id: String,
+    pub path: PathBuf,
+    pub created_at: DateTime<Utc>,
+    /// Truncated BLAKE2s hash of the content (hex)
+    pub hash: String,
+    pub size: u64,
+}
+
+/// Backup history for Clash config files
+#[derive(Debug, Clone, Default)]
+pub struct BackupStore {
+    config: BackupConfig,
+}
+
+impl BackupStore {
+    pub fn new(config: BackupConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &BackupConfig {
+        &self.config
+    }
+
+    /// Directory holding the backups of `config_path`
+    pub fn dir_for(&self, config_path: &Path) -> Result<PathBuf> {
+        let file_name = config_path
+            .file_name()
+            .with_context(|| format!("Not a file path: {}", config_path.display()))?
+            .to_string_lossy()
+            .to_string();
+
+        match &self.config.dir {
+            Some(dir) => {
+                // Shared directory: keep configs with the same file name apart
+                let absolute = std::path::absolute(config_path)
+                    .with_context(|| format!("Failed to resolve: {}", config_path.display()))?;
+                let path_hash = content_hash(absolute.to_string_lossy().as_bytes());
+                Ok(PathBuf::from(dir).join(format!("{}-{}", file_name, &path_hash[..8])))
+            }
+            None => {
+                let parent = config_path.parent().unwrap_or_else(|| Path::new(""));
+                Ok(parent.join(DEFAULT_DIR_NAME).join(file_name))
+            }
+        }
+    }
+
+    /// Snapshot the current content of `config_path`
+    ///
+    /// When the same content is already stored, that backup is moved to the
+    /// top of the history instead of being copied again.
+    pub fn backup(&self, config_path: &Path) -> Result<BackupEntry> {
+        let content = fs::read(config_path)
+            .with_context(|| format!("Failed to read: {}", config_path.display()))?;
+        let hash = content_hash(&content);
+        let entries = self.list(config_path)?;
+
+        if let Some(newest) = entries.first().filter(|e| e.hash == hash) {
+            return Ok(newest.clone());
+        }
+
+        let dir = self.dir_for(config_path)?;
+        // Keep the history strictly ordered even within one millisecond
+        let mut now = Utc::now();
+        if let Some(newest) = entries.first() {
+            now = now.max(newest.created_at + Duration::milliseconds(1));
+        }
+        let id = format!("{}-{}", now.format(TIMESTAMP_FORMAT), hash);
+        let extension = config_path.extension().and_then(|e| e.to_str()).unwrap_or("yaml");
+        let path = dir.join(format!("{}.{}", id, extension));
+
+        if let Some(existing) = entries.iter().find(|e| e.hash == hash) {
+            fs::rename(&existing.path, &path)
+                .with_context(|| format!("Failed to update backup: {}", existing.path.display()))?;
+        } else {
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create backup directory: {}", dir.display()))?;
+            fsutil::write_atomic(&path, &content)?;
+            // Backups hold the same credentials as the config itself
+            if let Ok(meta) = fs::metadata(config_path) {
+                let _ = fs::set_permissions(&path, meta.permissions());
+            }
+        }
+        info!("Backup: {}", path.display());
+
+        if let Err(e) = self.prune(config_path) {
+            warn!("Failed to prune old backups: {:#}", e);
+        }
+
+        Self::parse_entry(&path).context("Failed to read new backup")
+    }
+
+    /// Backups of `config_path`, newest first
+    pub fn list(&self, config_path: &Path) -> Result<Vec<BackupEntry>> {
+        let dir = self.dir_for(config_path)?;
+        let read_dir = match fs::read_dir(&dir) {
+            Ok(rd) => rd,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read: {}", dir.display()))
+            }
+        };
+
+        let mut entries: Vec<BackupEntry> = read_dir
+            .flatten()
+            .filter_map(|e| Self::parse_entry(&e.path()))
+            .collect();
+        entries.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| b.id.cmp(&a.id)));
+        Ok(entries)
+    }
+
+    /// Look up a backup by number from `list` (1 = newest), ID or unique ID prefix
+    ///
+    /// `None` selects the newest backup.
+    pub fn find(&self, config_path: &Path, key: Option<&str>) -> Result<BackupEntry> {
+        let entries = self.list(config_path)?;
+        if entries.is_empty() {
+            anyhow::bail!("No backups found for {}", config_path.display());
+        }
+
+        let Some(key) = key.map(str::trim) else {
+            return Ok(entries[0].clone());
+        };
+
+        // IDs start with a 17-digit timestamp, so short numbers are indexes
+        if key.len() <= 4 && !key.is_empty() && key.chars().all(|c| c.is_ascii_digit()) {
+            let index: usize = key.parse()?;
+            return index
+                .checked_sub(1)
+                .and_then(|i| entries.get(i))
+                .cloned()
+                .with_context(|| format!("No backup #{} ({} available)", index, entries.len()));
+        }
+
+        let matches: Vec<&BackupEntry> = entries.iter().filter(|e| e.id.starts_with(key)).collect();
+        match matches.as_slice() {
+            [entry] => Ok((*entry).clone()),
+            [] => anyhow::bail!("Backup not found: {}", key),
+            _ => anyhow::bail!("Ambiguous backup ID prefix: {} ({} matches)", key, matches.len()),
+        }
+    }
+
+    /// Content of a backup
+    pub fn read(&self, entry: &BackupEntry) -> Result<String> {
+        fs::read_to_string(&entry.path)
+            .with_context(|| format!("Failed to read backup: {}", entry.path.display()))
+    }
+
+    /// Write a backup over `config_path`
+    ///
+    /// The current content is backed up first, so a restore can itself be
+    /// undone. Returns that snapshot (if the config existed).
+    pub fn restore(&self, config_path: &Path, entry: &BackupEntry) -> Result<Option<BackupEntry>> {
+        let content = self.read(entry)?;
+        let snapshot = if config_path.exists() {
+            Some(self.backup(config_path)?)
+        } else {
+            None
+        };
+
+        fsutil::write_atomic(config_path, &content)?;
+        info!("Restored {} from {}", config_path.display(), entry.id);
+        Ok(snapshot)
+    }
+
+    /// Apply the retention policy; returns the number of backups removed
+    pub fn prune(&self, config_path: &Path) -> Result<usize> {
+        let entries = self.list(config_path)?;
+        let cutoff = (self.config.max_age_days > 0)
+            .then(|| Utc::now() - Duration::days(self.config.max_age_days as i64));
+
+        let mut removed = 0;
+        for (i, entry) in entries.iter().enumerate().skip(1) {
+            let over_count = self.config.keep > 0 && i >= self.config.keep;
+            let too_old = cutoff.is_some_and(|c| entry.created_at < c);
+            if over_count || too_old {
+                fs::remove_file(&entry.path)
+                    .with_context(|| format!("Failed to remove: {}", entry.path.display()))?;
+                info!("Removed old backup: {}", entry.id);
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Parse `<timestamp>-<hash>.<ext>`; other files are ignored
+    fn parse_entry(path: &Path) -> Option<BackupEntry> {
+        // The timestamp has a dot of its own, so the extension is required
+        path.extension()?;
+        let id = path.file_stem()?.to_str()?.to_string();
+        let (timestamp, hash) = id.rsplit_once('-')?;
+        let created_at = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?.and_utc();
+        let size = fs::metadata(path).ok()?.len();
+
+        Some(BackupEntry {
+            hash: hash.to_string(),
+            id,
+            path: path.to_path_buf(),
+            created_at,
+            size,
+        })
+    }
+}
+
+/// Short hex content hash used for dedup and file names
+fn content_hash(content: &[u8]) -> String {
+    Blake2s256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>()[..HASH_LEN]
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn setup(content: &str) -> (TempDir, PathBuf) {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(&config_path, content).unwrap();
+        (temp_dir, config_path)
+    }
+
+    #[test]
+    fn test_backup_dedup_and_order() {
+        let (temp_dir, config_path) = setup("a: 1\n");
+        let store = BackupStore::default();
+
+        let first = store.backup(&config_path).unwrap();
+        assert_eq!(store.backup(&config_path).unwrap(), first);
+        assert!(first.path.starts_with(temp_dir.path().join(".ccp-backups").join("config.yaml")));
+
+        fs::write(&config_path, "a: 2\n").unwrap();
+        let second = store.backup(&config_path).unwrap();
+        assert_ne!(second.hash, first.hash);
+
+        // Known content moves to the top instead of being stored twice
+        fs::write(&config_path, "a: 1\n").unwrap();
+        let again = store.backup(&config_path).unwrap();
+        assert_eq!(again.hash, first.hash);
+        let list = store.list(&config_path).unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].id, again.id);
+        assert_eq!(store.read(&list[0]).unwrap(), "a: 1\n");
+    }
+
+    #[test]
+    fn test_backup_keeps_config_extension() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.json");
+        fs::write(&config_path, "{\"a\": 1}").unwrap();
+        let store = BackupStore::new(BackupConfig { max_age_days: 0, ..BackupConfig::default() });
+
+        // Snapshots from before the extension was kept are still listed
+        let dir = store.dir_for(&config_path).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("20200101T000000.000Z-000000000000.yaml"), "{}").unwrap();
+
+        let entry = store.backup(&config_path).unwrap();
+        assert_eq!(entry.path.extension().unwrap(), "json");
+        let list = store.list(&config_path).unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].id, entry.id);
+        assert_eq!(store.read(&list[0]).unwrap(), "{\"a\": 1}");
+    }
+
+    #[test]
+    fn test_prune_by_count_and_age() {
+        let (_temp_dir, config_path) = setup("a: 0\n");
+        let store = BackupStore::new(BackupConfig { keep: 3, max_age_days: 30, ..BackupConfig::default() });
+
+        let dir = store.dir_for(&config_path).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("20200101T000000.000Z-000000000000.yaml");
+        fs::write(&old, "a: old\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not a backup").unwrap();
+
+        for i in 1..=4 {
+            fs::write(&config_path, format!("a: {}\n", i)).unwrap();
+            store.backup(&config_path).unwrap();
+        }
+
+        let list = store.list(&config_path).unwrap();
+        assert_eq!(list.len(), 3);
+        assert!(!old.exists());
+        assert!(dir.join("notes.txt").exists());
+        assert_eq!(store.read(&list[0]).unwrap(), "a: 4\n");
+    }
+
+    #[test]
+    fn test_find_and_restore() {
+        let (_temp_dir, config_path) = setup("a: 1\n");
+        let store = BackupStore::default();
+        let first = store.backup(&config_path).unwrap();
+        fs::write(&config_path, "a: 2\n").unwrap();
+        store.backup(&config_path).unwrap();
+        fs::write(&config_path, "a: broken\n").unwrap();
+
+        assert_eq!(store.find(&config_path, Some("2")).unwrap(), first);
+        assert_eq!(store.find(&config_path, Some(&first.id[..first.id.len() - 2])).unwrap(), first);
+        assert!(store.find(&config_path, Some("9")).is_err());
+        assert!(store.find(&config_path, Some("2099")).is_err());
+
+        let snapshot = store.restore(&config_path, &first).unwrap().unwrap();
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), "a: 1\n");
+        assert_eq!(store.read(&snapshot).unwrap(), "a: broken\n");
+        assert_eq!(store.list(&config_path).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_shared_dir_separates_same_names() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = BackupStore::new(BackupConfig {
+            dir: Some(temp_dir.path().join("backups").display().to_string()),
+            ..BackupConfig::default()
+        });
+
+        let a = store.dir_for(&temp_dir.path().join("one").join("config.yaml")).unwrap();
+        let b = store.dir_for(&temp_dir.path().join("two").join("config.yaml")).unwrap();
+        assert_ne!(a, b);
+        assert!(a.starts_with(temp_dir.path().join("backups")));
+    }
+}
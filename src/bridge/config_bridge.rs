@@ -32,6 +32,14 @@ impl ConfigBridge {
         })
     }
 
+    /// Create a configuration bridge for a passphrase-protected config
+    pub fn with_passphrase(passphrase: &str) -> BridgeResult<Self> {
+        let manager = ConfigManager::open_with_passphrase(passphrase)
+            .map_err(|e| BridgeError::Config(format!("Failed to create config manager: {}", e)))?;
+
+        Self::from_manager(manager)
+    }
+
     /// Create a bridge from an existing ConfigManager
     pub fn from_manager(manager: ConfigManager) -> BridgeResult<Self> {
         let runtime = Runtime::new()
@@ -219,6 +227,7 @@ mod tests {
             },
             enabled: true,
             health: crate::config::upstream::ProxyHealth::default(),
+            tags: Vec::new(),
         };
 
         // Add proxy
@@ -247,6 +256,7 @@ mod tests {
             },
             enabled: true,
             health: crate::config::upstream::ProxyHealth::default(),
+            tags: Vec::new(),
         };
 
         bridge.add_upstream(proxy.clone()).unwrap();
@@ -277,6 +287,7 @@ mod tests {
             },
             enabled: true,
             health: crate::config::upstream::ProxyHealth::default(),
+            tags: Vec::new(),
         };
 
         bridge.add_upstream(proxy).unwrap();
@@ -308,6 +319,7 @@ mod tests {
             },
             enabled: true,
             health: crate::config::upstream::ProxyHealth::default(),
+            tags: Vec::new(),
         };
 
         bridge.add_upstream(proxy).unwrap();
@@ -198,6 +198,7 @@ mod tests {
             },
             enabled: true,
             health: crate::config::upstream::ProxyHealth::default(),
+            tags: Vec::new(),
         }
     }
 
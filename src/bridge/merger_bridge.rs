@@ -2,8 +2,9 @@
 //!
 //! Provides synchronous access interface to ClashConfigMerger for GUI components
 
-use crate::merger::{ClashConfigMerger, MergeResult, MergerConfig};
+use crate::merger::{ApplyPlan, ClashConfigMerger, MergeResult, MergerConfig, PlannedApply, UnapplyResult};
 use super::{BridgeError, BridgeResult};
+use crate::config::BackupConfig;
 use std::path::Path;
 
 /// Configuration merger bridge
@@ -28,6 +29,12 @@ impl MergerBridge {
         }
     }
 
+    /// Set backup location and retention (used by merge and unapply)
+    pub fn with_backups(mut self, backups: BackupConfig) -> Self {
+        self.merger = self.merger.with_backups(backups);
+        self
+    }
+
     /// Merge configuration
     ///
     /// Add local proxy nodes to the Clash configuration
@@ -37,6 +44,19 @@ impl MergerBridge {
             .map_err(|e| BridgeError::Merger(format!("{:#}", e)))
     }
 
+    /// Apply a full plan (merge, rule rewrites, custom rules) in one write
+    pub fn apply_plan(plan: &ApplyPlan, config_path: impl AsRef<Path>) -> BridgeResult<PlannedApply> {
+        plan.execute(config_path.as_ref())
+            .map_err(|e| BridgeError::Merger(format!("{:#}", e)))
+    }
+
+    /// Remove chain proxies, chain groups and rule changes from the configuration
+    pub fn unapply(&self, config_path: impl AsRef<Path>) -> BridgeResult<UnapplyResult> {
+        self.merger
+            .unapply(config_path.as_ref())
+            .map_err(|e| BridgeError::Merger(format!("{:#}", e)))
+    }
+
     /// Validate if the configuration file is valid
     pub fn validate_config(&self, config_path: impl AsRef<Path>) -> BridgeResult<()> {
         let path = config_path.as_ref();
@@ -52,7 +72,7 @@ impl MergerBridge {
         let content = std::fs::read_to_string(path)
             .map_err(|e| BridgeError::Merger(format!("Unable to read configuration file: {}", e)))?;
 
-        serde_yaml::from_str::<serde_yaml::Value>(&content)
+        crate::yaml::parse_value(&content)
             .map_err(|e| BridgeError::Merger(format!("YAML format error: {}", e)))?;
 
         Ok(())
@@ -134,11 +154,9 @@ impl MergerBridgeBuilder {
             proxy_name: self.proxy_name,
             proxy_host: self.proxy_host,
             proxy_port: self.proxy_port,
-            proxy_username: None,
-            proxy_password: None,
             create_backup: self.create_backup,
             insert_at_beginning: self.insert_at_beginning,
-            chain_suffix: "-Chain".to_string(),
+            ..MergerConfig::default()
         };
 
         MergerBridge::with_config(config)
@@ -196,13 +214,10 @@ proxy-groups:
     fn test_merger_bridge_with_config() {
         let config = MergerConfig {
             proxy_name: "Custom-Proxy".to_string(),
-            proxy_host: "127.0.0.1".to_string(),
             proxy_port: 9999,
-            proxy_username: None,
-            proxy_password: None,
             create_backup: false,
             insert_at_beginning: false,
-            chain_suffix: "-Chain".to_string(),
+            ..MergerConfig::default()
         };
 
         let bridge = MergerBridge::with_config(config);
@@ -276,13 +291,10 @@ proxy-groups:
 
         let new_config = MergerConfig {
             proxy_name: "Updated-Proxy".to_string(),
-            proxy_host: "127.0.0.1".to_string(),
             proxy_port: 7777,
             proxy_username: Some("user".to_string()),
             proxy_password: Some("pass".to_string()),
-            create_backup: true,
-            insert_at_beginning: true,
-            chain_suffix: "-Chain".to_string(),
+            ..MergerConfig::default()
         };
 
         bridge.update_config(new_config);
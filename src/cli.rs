@@ -5,15 +5,36 @@
 //! Usage:
 //!   ccp info <config.yaml>              - Show rules groups and proxy info
 //!   ccp apply <config.yaml> [options]   - Apply chain proxies + rewrite rules
+//!   ccp diff <config.yaml> [options]    - Show what apply would change
 //!   ccp rules <config.yaml> [options]   - Rewrite rules only (no chain creation)
+//!   ccp unapply <config.yaml>           - Remove chains and revert rule changes
+//!   ccp rotate <config.yaml> [options]  - Rotate the fixed exit per the rotation policy
+//!   ccp backup <action> <config.yaml>   - List, show, diff or restore config backups
+//!   ccp proxy <action> [options]        - Manage the saved upstream proxy pool
+//!   ccp ignore <action> [pattern]       - Manage node names apply never chains
+//!   ccp region <action> [code]          - Manage the region table for --region-groups
+//!   ccp config rekey [options]          - Re-encrypt stored secrets with a new key
 
-use clap::{Parser, Subcommand};
-use clash_chain_patcher::config::ConfigManager;
-use clash_chain_patcher::merger::{ClashConfigMerger, MergerConfig};
-use clash_chain_patcher::patcher::{self, CustomRule, CustomRuleSet};
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use clash_chain_patcher::backup::{BackupEntry, BackupStore};
+use clash_chain_patcher::config::secret_ref::{is_secret_ref, resolve_secret};
+use clash_chain_patcher::config::secrets::PASSPHRASE_ENV;
+use clash_chain_patcher::config::{ConfigManager, SecretsError, UpstreamProxy};
+use clash_chain_patcher::fsutil;
+use clash_chain_patcher::health::{ProxyValidationResult, ProxyValidator, UpstreamSelector};
+use clash_chain_patcher::merger::filter::{default_ignore_patterns, ignore_match};
+use clash_chain_patcher::merger::{
+    diff, ApplyPlan, AutoGroup, AutoGroupType, BalanceStrategy, ChainHop, ChainOrder, ClashConfigMerger, DialerPolicy, GroupMatcher, InjectConfig,
+    InjectPosition, MergerConfig, NodeFilter, OutputStyle, Region, RegionTable, provider,
+};
+use clash_chain_patcher::patcher::{self, CustomRule, CustomRuleSet, Socks5Proxy};
+use clash_chain_patcher::proxy::config::UpstreamConfig;
+use clash_chain_patcher::rotation::{RotationReason, Rotator};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::{Duration, SystemTime};
 
 #[derive(Parser)]
 #[command(
@@ -36,18 +57,39 @@ enum Commands {
 
     /// Apply full chain patch: add SOCKS5 proxy, create relay chains, rewrite rules
     Apply {
+        #[command(flatten)]
+        args: ApplyArgs,
+
+        /// Print a unified diff of the planned changes instead of writing
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Show what apply would change, as a unified diff (same options as apply)
+    Diff {
+        #[command(flatten)]
+        args: ApplyArgs,
+    },
+
+    /// Rewrite rules only (no chain proxy creation)
+    Rules {
         /// Path to Clash YAML config file
         config: PathBuf,
 
-        /// SOCKS5 proxy (formats: host:port:user:pass or user:pass@host:port or host:port)
-        #[arg(short, long)]
-        proxy: String,
+        /// Rule replacements: "GroupName=Chain-Selector" or "GroupName=Chain-Auto"
+        /// Use "auto" to auto-detect and replace main group with Chain-Selector
+        #[arg(short, long, required = true)]
+        rewrite: Vec<String>,
 
-        /// Rewrite rules: replace proxy group with Chain-Selector or Chain-Auto
-        /// Format: "GroupName=Chain-Selector" or "GroupName=Chain-Auto"
-        /// Use "auto" to auto-detect main group and replace with Chain-Selector
-        #[arg(short, long)]
-        rewrite: Option<Vec<String>>,
+        /// Print a unified diff of the planned changes instead of writing
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Remove every chain artifact and revert rule changes made by apply
+    Unapply {
+        /// Path to Clash YAML config file
+        config: PathBuf,
 
         /// Skip creating backup
         #[arg(long)]
@@ -56,27 +98,46 @@ enum Commands {
         /// Chain suffix (default: "-Chain")
         #[arg(long, default_value = "-Chain")]
         suffix: String,
+    },
 
-        /// Custom rules to inject (highest priority, prepended to rules section)
-        /// Format: "TYPE:domain1,domain2:GROUP" (e.g. "DOMAIN-KEYWORD:lark,feishu:DIRECT")
-        /// or "TYPE,domain,GROUP" (e.g. "DOMAIN-SUFFIX,lark.com,DIRECT")
-        #[arg(long = "custom-rule")]
-        custom_rules: Option<Vec<String>>,
+    /// Rotate the fixed exit to the next healthy upstream (uses the saved rotation policy)
+    Rotate {
+        /// Path to Clash YAML config file
+        config: PathBuf,
 
-        /// Apply a saved custom rule preset by name
+        /// Run a single check/rotation pass and exit (for cron or timers)
         #[arg(long)]
-        preset: Option<Vec<String>>,
+        once: bool,
+
+        /// Rotate now regardless of the interval and failure triggers
+        #[arg(long)]
+        force: bool,
+
+        /// Skip creating backup
+        #[arg(long)]
+        no_backup: bool,
+
+        /// Chain suffix (default: "-Chain")
+        #[arg(long, default_value = "-Chain")]
+        suffix: String,
     },
 
-    /// Rewrite rules only (no chain proxy creation)
-    Rules {
-        /// Path to Clash YAML config file
-        config: PathBuf,
+    /// List, inspect and restore versioned backups of a Clash config
+    Backup {
+        #[command(subcommand)]
+        action: BackupAction,
+    },
 
-        /// Rule replacements: "GroupName=Chain-Selector" or "GroupName=Chain-Auto"
-        /// Use "auto" to auto-detect and replace main group with Chain-Selector
-        #[arg(short, long, required = true)]
-        rewrite: Vec<String>,
+    /// Manage the node name patterns apply never chains (saved in config)
+    Ignore {
+        #[command(subcommand)]
+        action: IgnoreAction,
+    },
+
+    /// Manage the region table behind --region-groups (saved in config)
+    Region {
+        #[command(subcommand)]
+        action: RegionAction,
     },
 
     /// Manage custom rule presets (saved in config)
@@ -84,6 +145,361 @@ enum Commands {
         #[command(subcommand)]
         action: PresetAction,
     },
+
+    /// Manage the saved upstream proxy pool (shared with the GUI)
+    Proxy {
+        #[command(subcommand)]
+        action: ProxyAction,
+    },
+
+    /// Manage the app config itself
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum BackupAction {
+    /// List backups of a config, newest first
+    List {
+        /// Path to Clash YAML config file
+        config: PathBuf,
+    },
+
+    /// Print the content of a backup
+    Show {
+        /// Path to Clash YAML config file
+        config: PathBuf,
+
+        /// Backup number from `list`, ID or unique ID prefix (default: newest)
+        backup: Option<String>,
+    },
+
+    /// Diff a backup against the current config
+    Diff {
+        /// Path to Clash YAML config file
+        config: PathBuf,
+
+        /// Backup number from `list`, ID or unique ID prefix (default: newest)
+        backup: Option<String>,
+    },
+
+    /// Restore a backup (the current config is backed up first)
+    Restore {
+        /// Path to Clash YAML config file
+        config: PathBuf,
+
+        /// Backup number from `list`, ID or unique ID prefix (default: newest)
+        backup: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Re-encrypt stored secrets with a new key file or a passphrase
+    Rekey {
+        /// Derive the key from a passphrase (read from CCP_NEW_PASSPHRASE or prompted)
+        #[arg(long, conflicts_with = "key_file")]
+        passphrase: bool,
+
+        /// Write a new random key to this file (default: the standard key location)
+        #[arg(long)]
+        key_file: Option<PathBuf>,
+    },
+}
+
+/// Options shared by `apply` and `diff`
+#[derive(Args)]
+#[command(group(
+    ArgGroup::new("source")
+        .required(true)
+        .args(["proxy", "from_pool", "proxy_id", "proxy_name"]),
+))]
+struct ApplyArgs {
+    /// Path to Clash YAML config file
+    config: PathBuf,
+
+    /// SOCKS5 proxy (formats: host:port:user:pass or user:pass@host:port or host:port),
+    /// or an env:VAR / file:PATH / cmd:COMMAND reference resolving to one
+    #[arg(short, long)]
+    proxy: Option<String>,
+
+    /// Use a saved pool upstream by ID (or unique ID prefix)
+    #[arg(long)]
+    proxy_id: Option<String>,
+
+    /// Use a saved pool upstream by name
+    #[arg(long)]
+    proxy_name: Option<String>,
+
+    /// Use the best healthy upstream from the saved pool instead of --proxy
+    #[arg(long)]
+    from_pool: bool,
+
+    /// Only consider pool upstreams with this tag (with --from-pool)
+    #[arg(long, requires = "from_pool")]
+    tag: Option<String>,
+
+    /// Health-check pool candidates before picking one (with --from-pool)
+    #[arg(long, requires = "from_pool")]
+    check: bool,
+
+    /// Rewrite rules: replace proxy group with Chain-Selector or an auto group
+    /// Format: "GroupName=Chain-Selector" or "GroupName=Chain-Auto"
+    /// Use "auto" to auto-detect main group and replace with Chain-Selector
+    #[arg(short, long)]
+    rewrite: Option<Vec<String>>,
+
+    /// Skip creating backup
+    #[arg(long)]
+    no_backup: bool,
+
+    /// Chain suffix (default: "-Chain")
+    #[arg(long, default_value = "-Chain")]
+    suffix: String,
+
+    /// Layout of generated proxies and groups: auto (match the file), flow or block
+    #[arg(long, default_value = "auto")]
+    style: OutputStyle,
+
+    /// Only chain nodes whose name matches this regex
+    #[arg(long)]
+    include: Option<String>,
+
+    /// Skip nodes whose name matches this regex
+    #[arg(long)]
+    exclude: Option<String>,
+
+    /// Only chain nodes of this type (repeatable or comma separated, e.g. "ss,vless")
+    #[arg(long = "type")]
+    node_types: Vec<String>,
+
+    /// Only chain nodes whose server matches this regex
+    #[arg(long)]
+    server: Option<String>,
+
+    /// Chain at most this many nodes
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// Also chain nodes matching the saved ignore patterns (see `ccp ignore`)
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// Chain-Auto group type: url-test, fallback or load-balance
+    #[arg(long, default_value = "url-test")]
+    auto_type: AutoGroupType,
+
+    /// Chain-Auto health check URL
+    #[arg(long)]
+    auto_url: Option<String>,
+
+    /// Chain-Auto health check interval in seconds
+    #[arg(long)]
+    auto_interval: Option<u64>,
+
+    /// Chain-Auto switch tolerance in ms (url-test only; default 50)
+    #[arg(long)]
+    auto_tolerance: Option<u32>,
+
+    /// Chain-Auto health check timeout in ms
+    #[arg(long)]
+    auto_timeout: Option<u32>,
+
+    /// Only health-check Chain-Auto while it is in use
+    #[arg(long)]
+    auto_lazy: bool,
+
+    /// Chain-Auto load-balance strategy: consistent-hashing or round-robin
+    #[arg(long)]
+    auto_strategy: Option<BalanceStrategy>,
+
+    /// Extra auto group over the chains (repeatable), as NAME:TYPE[,key=value...]
+    /// (e.g. "Chain-Fallback:fallback,timeout=2000")
+    #[arg(long = "auto-group")]
+    auto_groups: Vec<String>,
+
+    /// Also create a Chain-<CODE> url-test group per region found in node
+    /// names (e.g. Chain-HK, Chain-JP; see `ccp region`)
+    #[arg(long)]
+    region_groups: bool,
+
+    /// Group to add the chain groups to (repeatable): a name, /regex/ or
+    /// @main for the detected main group [default: @main]
+    #[arg(long = "inject-into")]
+    inject_into: Vec<String>,
+
+    /// Where the chain groups go in those groups: top, bottom or after:<member>
+    #[arg(long, default_value = "top")]
+    inject_position: InjectPosition,
+
+    /// Do not add the chain groups to any existing group
+    #[arg(long, conflicts_with = "inject_into")]
+    no_inject: bool,
+
+    /// Only chain these proxy-providers (repeatable) [default: all]
+    #[arg(long = "provider")]
+    providers: Vec<String>,
+
+    /// Do not chain proxy-provider nodes
+    #[arg(long, conflicts_with = "providers")]
+    no_providers: bool,
+
+    /// Write the chain clones to this local provider file next to the config
+    /// (e.g. chain-clones.yaml) instead of the config's proxies
+    #[arg(long, value_name = "FILE")]
+    clone_provider: Option<String>,
+
+    /// SOCKS5 hop between the node and the exit proxy (repeatable, in traffic
+    /// order), as [NAME=]PROXY in any --proxy format
+    #[arg(long = "hop", value_name = "[NAME=]PROXY")]
+    hops: Vec<String>,
+
+    /// Dial the exit proxy first and the node last (for exits that whitelist
+    /// the home IP)
+    #[arg(long)]
+    exit_first: bool,
+
+    /// The SOCKS5 exit and hops relay UDP (writes udp: true on them, and
+    /// lets UDP-based nodes be dialed exit first)
+    #[arg(long)]
+    udp: bool,
+
+    /// Nodes that already have a dialer-proxy: skip, flatten (chain the first
+    /// node of their chain instead) or nest (chain them anyway)
+    #[arg(long, default_value = "skip")]
+    dialer_policy: DialerPolicy,
+
+    /// Do not create Chain-Auto (extra --auto-group groups are still created)
+    #[arg(long, conflicts_with_all = [
+        "auto_url", "auto_interval", "auto_tolerance", "auto_timeout", "auto_lazy", "auto_strategy",
+    ])]
+    no_auto: bool,
+
+    /// Custom rules to inject (highest priority, prepended to rules section)
+    /// Format: "TYPE:domain1,domain2:GROUP" (e.g. "DOMAIN-KEYWORD:lark,feishu:DIRECT")
+    /// or "TYPE,domain,GROUP" (e.g. "DOMAIN-SUFFIX,lark.com,DIRECT")
+    #[arg(long = "custom-rule")]
+    custom_rules: Option<Vec<String>>,
+
+    /// Apply a saved custom rule preset by name
+    #[arg(long)]
+    preset: Option<Vec<String>>,
+}
+
+#[derive(Subcommand)]
+enum ProxyAction {
+    /// Add an upstream proxy to the pool
+    Add {
+        /// SOCKS5 proxy (formats: host:port:user:pass or user:pass@host:port or host:port)
+        proxy: String,
+
+        /// Display name (default: host:port)
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// Username, or an env:VAR / file:PATH / cmd:COMMAND reference (stored as given)
+        #[arg(long)]
+        username: Option<String>,
+
+        /// Password, or an env:VAR / file:PATH / cmd:COMMAND reference (stored as given);
+        /// prefix a literal that starts like a reference with literal:
+        #[arg(long)]
+        password: Option<String>,
+
+        /// Tag for --from-pool --tag selection (repeatable)
+        #[arg(short, long)]
+        tag: Vec<String>,
+
+        /// Add the proxy disabled
+        #[arg(long)]
+        disabled: bool,
+    },
+
+    /// List upstream proxies in the pool
+    List {
+        /// Only show proxies with this tag
+        #[arg(long)]
+        tag: Option<String>,
+
+        /// Print JSON instead of a table (passwords masked)
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show details of one upstream proxy
+    Show {
+        /// Proxy ID, unique ID prefix, or name
+        proxy: String,
+
+        /// Print JSON (password masked)
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Remove an upstream proxy from the pool
+    Remove {
+        /// Proxy ID, unique ID prefix, or name
+        proxy: String,
+    },
+
+    /// Enable an upstream proxy
+    Enable {
+        /// Proxy ID, unique ID prefix, or name
+        proxy: String,
+    },
+
+    /// Disable an upstream proxy
+    Disable {
+        /// Proxy ID, unique ID prefix, or name
+        proxy: String,
+    },
+
+    /// Health-check one upstream proxy, or all enabled ones
+    Check {
+        /// Proxy ID, unique ID prefix, or name (default: all enabled)
+        proxy: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum IgnoreAction {
+    /// List the ignore patterns
+    List,
+
+    /// Add a pattern (case-insensitive substring of node names)
+    Add {
+        pattern: String,
+    },
+
+    /// Remove a pattern
+    Remove {
+        pattern: String,
+    },
+
+    /// Restore the built-in patterns
+    Reset,
+}
+
+#[derive(Subcommand)]
+enum RegionAction {
+    /// List regions (built-in and saved) with their keywords
+    List,
+
+    /// Add name keywords to a region; an unknown code adds a region
+    Add {
+        /// Region code, e.g. JP or VN (becomes the Chain-<CODE> group)
+        code: String,
+        #[arg(required = true)]
+        keywords: Vec<String>,
+    },
+
+    /// Remove saved keywords from a region, or all of them
+    Remove {
+        code: String,
+        keywords: Vec<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -119,16 +535,30 @@ fn main() {
 
     match cli.command {
         Commands::Info { config } => cmd_info(&config),
-        Commands::Apply { config, proxy, rewrite, no_backup, suffix, custom_rules, preset } => {
-            cmd_apply(&config, &proxy, rewrite, no_backup, &suffix, custom_rules, preset);
+        Commands::Apply { args, dry_run } => {
+            let proxy = apply_source_proxy(&args);
+            cmd_apply(&args, proxy, dry_run);
+        }
+        Commands::Diff { args } => {
+            let proxy = apply_source_proxy(&args);
+            cmd_diff(&args, proxy);
+        }
+        Commands::Rules { config, rewrite, dry_run } => cmd_rules(&config, rewrite, dry_run),
+        Commands::Unapply { config, no_backup, suffix } => cmd_unapply(&config, no_backup, &suffix),
+        Commands::Rotate { config, once, force, no_backup, suffix } => {
+            cmd_rotate(&config, once, force, no_backup, &suffix);
         }
-        Commands::Rules { config, rewrite } => cmd_rules(&config, rewrite),
+        Commands::Ignore { action } => cmd_ignore(action),
+        Commands::Region { action } => cmd_region(action),
         Commands::Preset { action } => cmd_preset(action),
+        Commands::Proxy { action } => cmd_proxy(action),
+        Commands::Backup { action } => cmd_backup(action),
+        Commands::Config { action } => cmd_config(action),
     }
 }
 
 /// Show config info
-fn cmd_info(config_path: &PathBuf) {
+fn cmd_info(config_path: &Path) {
     let content = read_config(config_path);
 
     // Rule groups
@@ -144,11 +574,44 @@ fn cmd_info(config_path: &PathBuf) {
         println!("{:<40} {:>8}", group.name, group.count);
     }
 
-    // Proxy nodes
-    let config: serde_yaml::Value = serde_yaml::from_str(&content).unwrap_or_default();
+    // Proxy nodes, and the ones apply will not chain
+    let config = clash_chain_patcher::yaml::parse_value(&content).unwrap_or_default();
     if let Some(proxies) = config.get("proxies").and_then(|v| v.as_sequence()) {
+        let patterns = ConfigManager::ignore_settings();
+        let ignored: Vec<(&str, &str)> = proxies.iter()
+            .filter_map(|p| p.get("name").and_then(|v| v.as_str()))
+            .filter_map(|name| ignore_match(&patterns, name).map(|pattern| (name, pattern)))
+            .collect();
         println!();
-        println!("Proxy nodes: {}", proxies.len());
+        println!("Proxy nodes: {} ({} ignored)", proxies.len(), ignored.len());
+        for (name, pattern) in &ignored {
+            println!("  ignored: {}  (matches \"{}\")", name, pattern);
+        }
+
+        // Regions --region-groups would create
+        let table = ConfigManager::region_settings();
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        let mut unmatched = 0;
+        for name in proxies.iter().filter_map(|p| p.get("name").and_then(|v| v.as_str())) {
+            if ignore_match(&patterns, name).is_some() {
+                continue;
+            }
+            match table.classify(name) {
+                Some(code) => match counts.iter_mut().find(|(c, _)| *c == code) {
+                    Some((_, n)) => *n += 1,
+                    None => counts.push((code, 1)),
+                },
+                None => unmatched += 1,
+            }
+        }
+        let counts: Vec<String> = counts.iter().map(|(code, n)| format!("{} {}", code, n)).collect();
+        println!("Regions: {} ({} unmatched)", if counts.is_empty() { "none".to_string() } else { counts.join(", ") }, unmatched);
+    }
+
+    let providers = config.as_mapping().map(provider::provider_names).unwrap_or_default();
+    if !providers.is_empty() {
+        println!();
+        println!("Proxy providers: {}", providers.join(", "));
     }
 
     // Proxy groups
@@ -163,67 +626,221 @@ fn cmd_info(config_path: &PathBuf) {
     }
 }
 
-/// Apply full chain patch
-fn cmd_apply(config_path: &PathBuf, proxy_str: &str, rewrite: Option<Vec<String>>, no_backup: bool, suffix: &str, custom_rules: Option<Vec<String>>, preset: Option<Vec<String>>) {
-    // Parse proxy
-    let proxy = patcher::parse_proxy_string(proxy_str).unwrap_or_else(|| {
+/// Parse a --proxy argument or exit with error
+fn parse_proxy_arg(proxy_str: &str) -> Socks5Proxy {
+    // env:/file:/cmd: references resolve to the whole proxy string
+    if is_secret_ref(proxy_str) {
+        let resolved = resolve_secret(proxy_str).unwrap_or_else(|e| {
+            eprintln!("Error: {:#}", e);
+            process::exit(1);
+        });
+        return patcher::parse_proxy_string(&resolved).unwrap_or_else(|| {
+            eprintln!("Error: {} did not resolve to a valid proxy", proxy_str);
+            eprintln!("Formats: host:port:user:pass | user:pass@host:port | host:port");
+            process::exit(1);
+        });
+    }
+
+    patcher::parse_proxy_string(proxy_str).unwrap_or_else(|| {
         eprintln!("Error: Invalid proxy format: {}", proxy_str);
         eprintln!("Formats: host:port:user:pass | user:pass@host:port | host:port");
         process::exit(1);
+    })
+}
+
+/// Look up a saved pool upstream by ID or name or exit with error
+fn saved_pool_proxy(key: &str) -> Socks5Proxy {
+    let manager = load_config_manager();
+    let upstream = manager.resolve_upstream(key).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
     });
 
-    println!("Config: {}", config_path.display());
-    println!("Proxy:  {}:{}", proxy.host, proxy.port);
-    if let Some(ref u) = proxy.username {
-        println!("Auth:   {}:***", u);
+    if !upstream.enabled {
+        eprintln!("Warning: Upstream '{}' is disabled in the pool", upstream.name);
+    }
+    println!("Pool:   {}", upstream.name);
+
+    upstream_to_socks5(upstream)
+}
+
+/// Convert a pool entry into the proxy used for patching
+fn upstream_to_socks5(upstream: &UpstreamProxy) -> Socks5Proxy {
+    Socks5Proxy::new(
+        upstream.config.host.clone(),
+        upstream.config.port,
+        upstream.config.username.clone(),
+        upstream.config.password.clone(),
+    )
+}
+
+/// Pick the best healthy upstream from the saved pool or exit with error
+fn select_pool_proxy(tag: Option<&str>, check: bool) -> Socks5Proxy {
+    let mut manager = load_config_manager();
+
+    let selector = match tag {
+        Some(t) => UpstreamSelector::new().tag(t),
+        None => UpstreamSelector::new(),
+    };
+
+    let candidates: Vec<UpstreamProxy> = selector
+        .candidates(manager.list_upstreams())
+        .into_iter()
+        .cloned()
+        .collect();
+    if candidates.is_empty() {
+        eprintln!("Error: No enabled upstream proxies in pool{}", tag.map(|t| format!(" with tag '{}'", t)).unwrap_or_default());
+        process::exit(1);
+    }
+
+    if check {
+        println!("Checking {} pool upstreams...", candidates.len());
+        check_upstreams(&mut manager, candidates);
+        println!();
     }
-    println!();
 
-    // Step 1: Apply chain merge
+    let Some(best) = selector.select(manager.list_upstreams()) else {
+        eprintln!("Error: No healthy upstream in pool, refusing to apply");
+        if !check {
+            eprintln!("Run with --check to refresh health status first.");
+        }
+        process::exit(1);
+    };
+    if selector.is_stale(best, SystemTime::now()) {
+        eprintln!("Error: Best upstream '{}' was not checked recently, refusing to apply", best.name);
+        eprintln!("Run with --check to refresh health status first.");
+        process::exit(1);
+    }
+
+    println!(
+        "Pool:   {} ({}ms{})",
+        best.name,
+        best.health.latency_ms.unwrap_or_default(),
+        best.health.exit_ip.as_deref().map(|ip| format!(", exit {}", ip)).unwrap_or_default(),
+    );
+
+    upstream_to_socks5(best)
+}
+
+/// Proxy selected by the apply source options (--proxy, pool entry or best pool upstream)
+fn apply_source_proxy(args: &ApplyArgs) -> Socks5Proxy {
+    match (&args.proxy, args.proxy_id.as_ref().or(args.proxy_name.as_ref())) {
+        (Some(proxy_str), _) => parse_proxy_arg(proxy_str),
+        (None, Some(key)) => saved_pool_proxy(key),
+        (None, None) => select_pool_proxy(args.tag.as_deref(), args.check),
+    }
+}
+
+/// Build the apply plan (merge + rule rewrites + custom rules) from CLI options
+fn build_apply_plan(args: &ApplyArgs, proxy: &Socks5Proxy) -> ApplyPlan {
+    let auto_groups = build_auto_groups(args).unwrap_or_else(|e| {
+        eprintln!("Error: {:#}", e);
+        process::exit(1);
+    });
+    let region_groups = args.region_groups.then(ConfigManager::region_settings);
+    let targets: Vec<String> = std::iter::once("Chain-Selector".to_string())
+        .chain(auto_groups.iter().map(|g| g.name.clone()))
+        .chain(region_groups.iter().flat_map(|t| t.regions().iter().map(|r| format!("Chain-{}", r.code))))
+        .collect();
     let merger_config = MergerConfig {
         proxy_name: "Local-Chain-Proxy".to_string(),
         proxy_host: proxy.host.clone(),
         proxy_port: proxy.port,
         proxy_username: proxy.username.clone(),
         proxy_password: proxy.password.clone(),
-        create_backup: !no_backup,
+        proxy_udp: args.udp,
+        create_backup: !args.no_backup,
         insert_at_beginning: true,
-        chain_suffix: suffix.to_string(),
+        chain_suffix: args.suffix.clone(),
+        output_style: args.style,
+        node_filter: NodeFilter {
+            include: args.include.clone(),
+            exclude: args.exclude.clone(),
+            types: args.node_types.iter().flat_map(|t| t.split(',')).map(|t| t.trim().to_string()).collect(),
+            server: args.server.clone(),
+            limit: args.limit,
+            ignore: if args.no_ignore { Vec::new() } else { ConfigManager::ignore_settings() },
+        },
+        auto_groups,
+        region_groups,
+        inject: build_inject_config(args).unwrap_or_else(|e| {
+            eprintln!("Error: {:#}", e);
+            process::exit(1);
+        }),
+        providers: if args.no_providers {
+            Some(Vec::new())
+        } else {
+            (!args.providers.is_empty()).then(|| args.providers.clone())
+        },
+        clone_provider: args.clone_provider.clone(),
+        hops: ChainHop::parse_all(&args.hops)
+            .map(|hops| hops.into_iter().map(|hop| ChainHop { udp: args.udp, ..hop }).collect())
+            .unwrap_or_else(|e| {
+                eprintln!("Error: {:#}", e);
+                process::exit(1);
+            }),
+        chain_order: if args.exit_first { ChainOrder::ExitFirst } else { ChainOrder::NodeFirst },
+        dialer_policy: args.dialer_policy,
     };
 
-    let merger = ClashConfigMerger::with_config(merger_config);
-    match merger.merge(config_path) {
-        Ok(result) => {
-            println!("Chain merge:");
-            println!("  Proxy added: {}", result.proxy_added);
-            println!("  Chains created: {}", result.chains_created);
-            println!("  Groups updated: {}", result.groups_updated);
-            if let Some(backup) = result.backup_path {
-                println!("  Backup: {}", backup.display());
-            }
-            for w in &result.warnings {
-                println!("  Warning: {}", w);
-            }
+    let replacements = match &args.rewrite {
+        Some(rewrite_args) => parse_rewrite_args(rewrite_args, &args.config, &targets),
+        None => HashMap::new(),
+    };
+
+    ApplyPlan::new(merger_config)
+        .with_rule_rewrites(replacements)
+        .with_custom_rules(collect_custom_rules(&args.custom_rules, &args.preset))
+        .with_backups(ConfigManager::backup_settings())
+}
+
+/// Chain-Auto from the --auto-* options, followed by the --auto-group groups
+fn build_auto_groups(args: &ApplyArgs) -> anyhow::Result<Vec<AutoGroup>> {
+    let mut groups = Vec::new();
+    if !args.no_auto {
+        let mut auto = AutoGroup::new("Chain-Auto", args.auto_type);
+        if let Some(url) = &args.auto_url {
+            auto.url = url.clone();
         }
-        Err(e) => {
-            eprintln!("Error: Chain merge failed: {}", e);
-            process::exit(1);
+        if let Some(interval) = args.auto_interval {
+            auto.interval = interval;
         }
+        if args.auto_tolerance.is_some() {
+            auto.tolerance = args.auto_tolerance;
+        }
+        auto.timeout = args.auto_timeout;
+        auto.lazy = args.auto_lazy.then_some(true);
+        if args.auto_strategy.is_some() {
+            auto.strategy = args.auto_strategy;
+        }
+        auto.validate()?;
+        groups.push(auto);
     }
-
-    // Step 2: Rewrite rules if requested
-    if let Some(rewrite_args) = rewrite {
-        println!();
-        let replacements = parse_rewrite_args(&rewrite_args, config_path);
-        apply_rule_rewrites(config_path, &replacements);
+    for spec in &args.auto_groups {
+        groups.push(AutoGroup::parse(spec)?);
     }
+    Ok(groups)
+}
+
+/// Injection targets and position from --inject-into / --inject-position
+fn build_inject_config(args: &ApplyArgs) -> anyhow::Result<InjectConfig> {
+    let targets = if args.no_inject {
+        Vec::new()
+    } else if args.inject_into.is_empty() {
+        InjectConfig::default().targets
+    } else {
+        args.inject_into.iter().map(|t| GroupMatcher::parse(t)).collect::<anyhow::Result<_>>()?
+    };
+    Ok(InjectConfig { targets, position: args.inject_position.clone() })
+}
 
-    // Step 3: Inject custom rules if any
+/// Custom rules from --custom-rule flags and saved presets
+fn collect_custom_rules(custom_rules: &Option<Vec<String>>, preset: &Option<Vec<String>>) -> Vec<CustomRule> {
     let mut all_custom_rules: Vec<CustomRule> = Vec::new();
 
     // From --custom-rule flags
     if let Some(cr_args) = custom_rules {
-        for arg in &cr_args {
+        for arg in cr_args {
             all_custom_rules.extend(patcher::parse_custom_rule_string(arg));
         }
     }
@@ -231,7 +848,7 @@ fn cmd_apply(config_path: &PathBuf, proxy_str: &str, rewrite: Option<Vec<String>
     // From --preset flags
     if let Some(preset_names) = preset {
         if let Ok(manager) = ConfigManager::new() {
-            for name in &preset_names {
+            for name in preset_names {
                 if let Some(p) = manager.get_custom_rule_presets().iter().find(|p| p.name == *name) {
                     all_custom_rules.extend(p.rules.clone());
                     println!("Loaded preset: {} ({} rules)", name, p.rules.len());
@@ -242,37 +859,305 @@ fn cmd_apply(config_path: &PathBuf, proxy_str: &str, rewrite: Option<Vec<String>
         }
     }
 
-    if !all_custom_rules.is_empty() {
+    all_custom_rules
+}
+
+/// Apply full chain patch
+fn cmd_apply(args: &ApplyArgs, proxy: Socks5Proxy, dry_run: bool) {
+    let config_path = &args.config;
+    println!("Config: {}", config_path.display());
+    println!("Proxy:  {}:{}", proxy.host, proxy.port);
+    if let Some(ref u) = proxy.username {
+        println!("Auth:   {}:***", u);
+    }
+    println!();
+
+    let plan = build_apply_plan(args, &proxy);
+    if dry_run {
+        print_plan_diff(config_path, &plan);
+        return;
+    }
+
+    // Merge, rule rewrites and custom rules are rendered together and
+    // written once, so a failure never leaves a half-patched config
+    let planned = plan.execute(config_path).unwrap_or_else(|e| {
+        eprintln!("Error: Apply failed: {:#}", e);
+        process::exit(1);
+    });
+
+    println!("Chain merge:");
+    println!("  Proxy added: {}", planned.merge.proxy_added);
+    println!("  Chains created: {}", planned.merge.chains_created);
+    println!("  Groups updated: {}", planned.merge.groups_updated);
+    if let Some(ref backup) = planned.merge.backup_path {
+        println!("  Backup: {}", backup.display());
+    }
+    for w in &planned.merge.warnings {
+        println!("  Warning: {}", w);
+    }
+
+    if !plan.rule_rewrites().is_empty() {
+        println!();
+        println!("Rules rewrite:");
+        for (from, to) in plan.rule_rewrites() {
+            println!("  {} -> {}", from, to);
+        }
+        if planned.rules_rewritten > 0 {
+            println!("  Rewritten: {} rules", planned.rules_rewritten);
+        } else {
+            println!("  No rules matched for rewrite.");
+        }
+    }
+
+    if !plan.custom_rules().is_empty() {
         println!();
-        apply_custom_rule_injection(config_path, &all_custom_rules);
+        println!("Custom rules injection:");
+        for rule in plan.custom_rules().iter().filter(|r| r.enabled) {
+            println!("  {},{},{}", rule.match_type.clash_prefix(), rule.domain, rule.target_group);
+        }
+        if planned.rules_injected > 0 {
+            println!("  Injected: {} rules", planned.rules_injected);
+        } else {
+            println!("  No rules injected (no rules: section found or all disabled).");
+        }
     }
 
     println!();
     println!("Done.");
 }
 
+/// Print what apply would change without writing
+fn cmd_diff(args: &ApplyArgs, proxy: Socks5Proxy) {
+    let plan = build_apply_plan(args, &proxy);
+    print_plan_diff(&args.config, &plan);
+}
+
+/// Render the plan in memory and print a unified diff against the file
+fn print_plan_diff(config_path: &Path, plan: &ApplyPlan) {
+    let (diff, planned) = plan.diff_file(config_path).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    print_diff(&diff);
+    // On stderr so the diff itself stays usable as a patch
+    for w in &planned.merge.warnings {
+        eprintln!("Warning: {}", w);
+    }
+}
+
+/// Print a unified diff, colored when writing to a terminal
+fn print_diff(diff: &str) {
+    if diff.is_empty() {
+        println!("No changes.");
+    } else if std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none() {
+        print!("{}", diff::colorize(diff));
+    } else {
+        print!("{}", diff);
+    }
+}
+
+/// File name used in diff headers
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
 /// Rewrite rules only
-fn cmd_rules(config_path: &PathBuf, rewrite_args: Vec<String>) {
+fn cmd_rules(config_path: &Path, rewrite_args: Vec<String>, dry_run: bool) {
     println!("Config: {}", config_path.display());
     println!();
 
-    let replacements = parse_rewrite_args(&rewrite_args, config_path);
+    let targets = ["Chain-Selector".to_string(), "Chain-Auto".to_string()];
+    let replacements = parse_rewrite_args(&rewrite_args, config_path, &targets);
+    if dry_run {
+        let content = read_config(config_path);
+        let (output, _) = rewrite_from_origins(&content, &replacements);
+        let name = display_name(config_path);
+        print_diff(&diff::unified_diff(&content, &output, &format!("a/{}", name), &format!("b/{}", name), 3));
+        return;
+    }
     apply_rule_rewrites(config_path, &replacements);
 
     println!();
     println!("Done.");
 }
 
-/// Parse rewrite arguments into a replacement map
-fn parse_rewrite_args(args: &[String], config_path: &PathBuf) -> HashMap<String, String> {
+/// Remove chain proxies, chain groups and rule changes
+fn cmd_unapply(config_path: &Path, no_backup: bool, suffix: &str) {
+    println!("Config: {}", config_path.display());
+    println!();
+
+    let merger = ClashConfigMerger::with_config(MergerConfig {
+        create_backup: !no_backup,
+        chain_suffix: suffix.to_string(),
+        ..MergerConfig::default()
+    })
+    .with_backups(ConfigManager::backup_settings());
+
+    let result = merger.unapply(config_path).unwrap_or_else(|e| {
+        eprintln!("Error: Unapply failed: {}", e);
+        process::exit(1);
+    });
+
+    if result.is_empty() {
+        println!("No chain artifacts found.");
+        return;
+    }
+
+    println!("Removed:");
+    println!("  Proxies: {}", result.proxies_removed);
+    println!("  Groups: {}", result.groups_removed);
+    println!("  Groups cleaned: {}", result.groups_updated);
+    println!("  Rules restored: {}", result.rules_restored);
+    println!("  Custom rules removed: {}", result.rules_removed);
+    if let Some(backup) = result.backup_path {
+        println!("  Backup: {}", backup.display());
+    }
+    for w in &result.warnings {
+        println!("  Warning: {}", w);
+    }
+
+    println!();
+    println!("Done.");
+}
+
+/// Rotate the fixed exit according to the saved rotation policy
+fn cmd_rotate(config_path: &Path, once: bool, force: bool, no_backup: bool, suffix: &str) {
+    let mut manager = load_config_manager();
+
+    let policy = manager.rotation().clone();
+    if !policy.enabled && !force {
+        eprintln!("Error: Rotation is disabled (set rotation.enabled in {})", manager.config_path().display());
+        eprintln!("Use --force to rotate once anyway.");
+        process::exit(1);
+    }
+
+    let base = MergerConfig {
+        create_backup: !no_backup,
+        chain_suffix: suffix.to_string(),
+        node_filter: NodeFilter { ignore: manager.ignore_patterns().to_vec(), ..NodeFilter::default() },
+        ..MergerConfig::default()
+    };
+    let mut rotator = Rotator::with_merger_config(policy, base)
+        .with_backups(manager.config().backup.clone());
+    let check_interval = Duration::from_secs(manager.config().health_check.interval_seconds.max(30));
+
+    println!("Config: {}", config_path.display());
+    println!(
+        "Policy: every {} min, after {} failures",
+        rotator.policy().interval_minutes, rotator.policy().failure_threshold
+    );
+    println!();
+
+    loop {
+        check_rotation_candidates(&mut manager, &rotator);
+
+        let pool: Vec<UpstreamProxy> = manager.list_upstreams().to_vec();
+        let reason = if force {
+            Some(RotationReason::Manual)
+        } else {
+            rotator.due(&pool, chrono::Utc::now())
+        };
+
+        if let Some(reason) = reason {
+            match rotator.rotate(&pool, config_path, reason, chrono::Utc::now()) {
+                Ok(event) => {
+                    println!(
+                        "Rotated: {} ({}) -> {} ({}) [{}]",
+                        event.from_name.as_deref().unwrap_or("none"),
+                        event.old_exit_ip.as_deref().unwrap_or("unknown"),
+                        event.to_name,
+                        event.new_exit_ip.as_deref().unwrap_or("unknown"),
+                        event.reason,
+                    );
+                    if let Err(e) = manager.set_rotation(rotator.policy().clone()) {
+                        eprintln!("Warning: Failed to save rotation state: {}", e);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error: Rotation failed: {:#}", e);
+                    if once || force {
+                        process::exit(1);
+                    }
+                }
+            }
+        } else {
+            println!("No rotation due.");
+        }
+
+        if once || force {
+            break;
+        }
+        std::thread::sleep(check_interval);
+    }
+}
+
+/// Health-check every rotation candidate and persist the results
+fn check_rotation_candidates(manager: &mut ConfigManager, rotator: &Rotator) {
+    let candidates: Vec<UpstreamProxy> = rotator
+        .candidates(manager.list_upstreams())
+        .into_iter()
+        .cloned()
+        .collect();
+
+    if candidates.is_empty() {
+        println!("No enabled upstream proxies to rotate through.");
+        return;
+    }
+
+    check_upstreams(manager, candidates);
+}
+
+/// Health-check the given upstreams and persist the results
+fn check_upstreams(manager: &mut ConfigManager, upstreams: Vec<UpstreamProxy>) {
+    let validator = ProxyValidator::new(manager.config().health_check.timeout_seconds);
+
+    for mut proxy in upstreams {
+        let result = validator.validate(
+            &proxy.config.host,
+            proxy.config.port,
+            proxy.config.username.as_deref(),
+            proxy.config.password.as_deref(),
+        );
+        record_validation(&mut proxy, result);
+        println!("  {:<30} {}", proxy.name, proxy.health.status);
+        if let Err(e) = manager.update_upstream(proxy) {
+            eprintln!("Warning: Failed to save health status: {}", e);
+        }
+    }
+}
+
+/// Apply a validation result to a proxy's health record
+fn record_validation(proxy: &mut UpstreamProxy, result: ProxyValidationResult) {
+    if result.is_valid {
+        proxy.health.mark_healthy_with_details(
+            result.latency_ms.unwrap_or_default() as u64,
+            result.exit_ip,
+            result.location.as_ref().map(|l| l.format_short()),
+            result.location.as_ref().map(|l| l.country_code.clone()),
+        );
+    } else {
+        proxy.health.mark_unhealthy(result.error.unwrap_or_else(|| "Unknown error".to_string()));
+    }
+}
+
+/// Parse rewrite arguments into a replacement map; `targets` are the chain
+/// groups a rule may be pointed at
+fn parse_rewrite_args(args: &[String], config_path: &Path, targets: &[String]) -> HashMap<String, String> {
     let mut replacements = HashMap::new();
 
     for arg in args {
         if arg == "auto" {
-            // Auto-detect: find main proxy group (not DIRECT/REJECT, highest count)
+            // Auto-detect: the group most rules point at, before any earlier
+            // rewrite, leaving out DIRECT/REJECT and the generated groups
             let content = read_config(config_path);
-            let groups = patcher::extract_rule_groups(&content);
-            if let Some(main) = groups.iter().find(|g| g.name != "DIRECT" && g.name != "REJECT") {
+            let generated = ClashConfigMerger::new().generated_names(&content).unwrap_or_default();
+            let (original, _) = patcher::revert_rewrites_text(&content, |_, _| true);
+            let groups = patcher::extract_rule_groups(&original);
+            let main = groups.iter()
+                .find(|g| g.name != "DIRECT" && g.name != "REJECT" && !generated.contains(&g.name));
+            if let Some(main) = main {
                 println!("Auto-detected main group: {} ({} rules)", main.name, main.count);
                 replacements.insert(main.name.clone(), "Chain-Selector".to_string());
             } else {
@@ -280,8 +1165,8 @@ fn parse_rewrite_args(args: &[String], config_path: &PathBuf) -> HashMap<String,
             }
         } else if let Some((from, to)) = arg.split_once('=') {
             let to = to.trim();
-            if to != "Chain-Selector" && to != "Chain-Auto" {
-                eprintln!("Warning: Invalid target '{}', use Chain-Selector or Chain-Auto", to);
+            if !targets.iter().any(|t| t == to) {
+                eprintln!("Warning: Invalid target '{}', use one of: {}", to, targets.join(", "));
                 continue;
             }
             replacements.insert(from.trim().to_string(), to.to_string());
@@ -293,8 +1178,15 @@ fn parse_rewrite_args(args: &[String], config_path: &PathBuf) -> HashMap<String,
     replacements
 }
 
+/// Rewrite rules, starting over from the origins earlier rewrites of the
+/// same groups recorded
+fn rewrite_from_origins(content: &str, replacements: &HashMap<String, String>) -> (String, usize) {
+    let (reverted, _) = patcher::revert_rewrites_text(content, |was, _| replacements.contains_key(was));
+    patcher::rewrite_rules_text(&reverted, replacements)
+}
+
 /// Apply rule rewrites to a config file
-fn apply_rule_rewrites(config_path: &PathBuf, replacements: &HashMap<String, String>) {
+fn apply_rule_rewrites(config_path: &Path, replacements: &HashMap<String, String>) {
     if replacements.is_empty() {
         println!("No rule rewrites to apply.");
         return;
@@ -307,10 +1199,10 @@ fn apply_rule_rewrites(config_path: &PathBuf, replacements: &HashMap<String, Str
         println!("  {} -> {}", from, to);
     }
 
-    let (output, count) = patcher::rewrite_rules_text(&content, replacements);
-    if count > 0 {
-        std::fs::write(config_path, output).unwrap_or_else(|e| {
-            eprintln!("Error: Failed to write config: {}", e);
+    let (output, count) = rewrite_from_origins(&content, replacements);
+    if count > 0 && output != content {
+        fsutil::write_atomic(config_path, output).unwrap_or_else(|e| {
+            eprintln!("Error: Failed to write config: {:#}", e);
             process::exit(1);
         });
         println!("  Rewritten: {} rules", count);
@@ -319,39 +1211,96 @@ fn apply_rule_rewrites(config_path: &PathBuf, replacements: &HashMap<String, Str
     }
 }
 
-/// Apply custom rule injection to a config file
-fn apply_custom_rule_injection(config_path: &PathBuf, rules: &[CustomRule]) {
-    if rules.is_empty() {
-        return;
+/// Manage the ignore patterns
+fn cmd_ignore(action: IgnoreAction) {
+    let mut manager = load_config_manager();
+    let mut patterns = manager.ignore_patterns().to_vec();
+
+    match action {
+        IgnoreAction::List => {
+            if patterns.is_empty() {
+                println!("No ignore patterns.");
+            }
+            for p in &patterns {
+                println!("{}", p);
+            }
+            return;
+        }
+        IgnoreAction::Add { pattern } => {
+            if patterns.iter().any(|p| p.eq_ignore_ascii_case(&pattern)) {
+                println!("Pattern '{}' already in the list.", pattern);
+                return;
+            }
+            patterns.push(pattern.clone());
+            println!("Pattern '{}' added.", pattern);
+        }
+        IgnoreAction::Remove { pattern } => {
+            let before = patterns.len();
+            patterns.retain(|p| *p != pattern);
+            if patterns.len() == before {
+                eprintln!("Pattern '{}' not found.", pattern);
+                process::exit(1);
+            }
+            println!("Pattern '{}' removed.", pattern);
+        }
+        IgnoreAction::Reset => {
+            patterns = default_ignore_patterns();
+            println!("Ignore patterns reset to the built-in list.");
+        }
     }
 
-    let content = read_config(config_path);
+    manager.set_ignore_patterns(patterns).unwrap_or_else(|e| {
+        eprintln!("Error: Failed to save ignore patterns: {}", e);
+        process::exit(1);
+    });
+}
 
-    println!("Custom rules injection:");
-    for rule in rules {
-        if rule.enabled {
-            println!("  {},{},{}", rule.match_type.clash_prefix(), rule.domain, rule.target_group);
+/// Manage the saved regions
+fn cmd_region(action: RegionAction) {
+    let mut manager = load_config_manager();
+    let mut regions = manager.custom_regions().to_vec();
+
+    match action {
+        RegionAction::List => {
+            for region in RegionTable::with_custom(&regions).regions() {
+                println!("{:<4} {}", region.code, region.keywords.join(", "));
+            }
+            return;
+        }
+        RegionAction::Add { code, keywords } => {
+            let code = code.trim().to_ascii_uppercase();
+            match regions.iter_mut().find(|r| r.code == code) {
+                Some(region) => region.keywords.extend(keywords),
+                None => regions.push(Region { code: code.clone(), keywords }),
+            }
+            println!("Region '{}' updated.", code);
+        }
+        RegionAction::Remove { code, keywords } => {
+            let code = code.trim().to_ascii_uppercase();
+            let Some(index) = regions.iter().position(|r| r.code == code) else {
+                eprintln!("No saved keywords for region '{}' (built-in keywords cannot be removed).", code);
+                process::exit(1);
+            };
+            if keywords.is_empty() {
+                regions.remove(index);
+            } else {
+                regions[index].keywords.retain(|k| !keywords.contains(k));
+                if regions[index].keywords.is_empty() {
+                    regions.remove(index);
+                }
+            }
+            println!("Region '{}' updated.", code);
         }
     }
 
-    let (output, count) = patcher::inject_custom_rules_text(&content, rules);
-    if count > 0 {
-        std::fs::write(config_path, output).unwrap_or_else(|e| {
-            eprintln!("Error: Failed to write config: {}", e);
-            process::exit(1);
-        });
-        println!("  Injected: {} rules", count);
-    } else {
-        println!("  No rules injected (no rules: section found or all disabled).");
-    }
+    manager.set_custom_regions(regions).unwrap_or_else(|e| {
+        eprintln!("Error: Failed to save regions: {}", e);
+        process::exit(1);
+    });
 }
 
-/// Manage custom rule presets
 fn cmd_preset(action: PresetAction) {
-    let mut manager = ConfigManager::new().unwrap_or_else(|e| {
-        eprintln!("Error: Failed to load config: {}", e);
-        process::exit(1);
-    });
+    let mut manager = load_config_manager();
 
     match action {
         PresetAction::List => {
@@ -411,8 +1360,290 @@ fn cmd_preset(action: PresetAction) {
     }
 }
 
+/// Manage the saved upstream proxy pool
+fn cmd_proxy(action: ProxyAction) {
+    let mut manager = load_config_manager();
+
+    match action {
+        ProxyAction::Add { proxy, name, username, password, tag, disabled } => {
+            let parsed = parse_proxy_arg(&proxy);
+            if manager.list_upstreams().iter().any(|p| p.config.host == parsed.host && p.config.port == parsed.port) {
+                eprintln!("Error: Proxy {}:{} already exists in the pool", parsed.host, parsed.port);
+                process::exit(1);
+            }
+
+            let name = name.unwrap_or_else(|| format!("{}:{}", parsed.host, parsed.port));
+            let mut upstream = UpstreamProxy::new(name, UpstreamConfig {
+                host: parsed.host,
+                port: parsed.port,
+                username: username.or(parsed.username),
+                password: password.or(parsed.password),
+            });
+            upstream.enabled = !disabled;
+            upstream.tags = tag;
+
+            let id = upstream.id.clone();
+            let name = upstream.name.clone();
+            manager.add_upstream(upstream).unwrap_or_else(|e| {
+                eprintln!("Error: Failed to save proxy: {}", e);
+                process::exit(1);
+            });
+            println!("Proxy '{}' added ({}).", name, id);
+        }
+        ProxyAction::List { tag, json } => {
+            let proxies: Vec<UpstreamProxy> = manager
+                .list_upstreams()
+                .iter()
+                .filter(|p| tag.as_deref().is_none_or(|t| p.has_tag(t)))
+                .map(|p| p.redacted())
+                .collect();
+
+            if json {
+                print_json(&proxies);
+            } else if proxies.is_empty() {
+                println!("No proxies saved.");
+            } else {
+                println!("{:<10} {:<24} {:<24} {:<4} {:<10} {:>8}", "ID", "Name", "Address", "On", "Status", "Latency");
+                println!("{}", "-".repeat(85));
+                for p in &proxies {
+                    println!(
+                        "{:<10} {:<24} {:<24} {:<4} {:<10} {:>8}",
+                        &p.id[..p.id.len().min(8)],
+                        p.name,
+                        format!("{}:{}", p.config.host, p.config.port),
+                        if p.enabled { "yes" } else { "no" },
+                        format!("{:?}", p.health.status).to_lowercase(),
+                        p.health.latency_ms.map(|l| format!("{}ms", l)).unwrap_or_else(|| "-".to_string()),
+                    );
+                }
+            }
+        }
+        ProxyAction::Show { proxy, json } => {
+            let p = resolve_or_exit(&manager, &proxy).redacted();
+            if json {
+                print_json(&p);
+                return;
+            }
+
+            println!("Proxy: {}", p.name);
+            println!("{}", "-".repeat(40));
+            println!("  ID:       {}", p.id);
+            println!("  Address:  {}:{}", p.config.host, p.config.port);
+            if let Some(ref u) = p.config.username {
+                println!("  Auth:     {}:{}", u, p.config.password.as_deref().unwrap_or(""));
+            }
+            println!("  Enabled:  {}", p.enabled);
+            if !p.tags.is_empty() {
+                println!("  Tags:     {}", p.tags.join(", "));
+            }
+            println!("  Status:   {}", format!("{:?}", p.health.status).to_lowercase());
+            if let Some(latency) = p.health.latency_ms {
+                println!("  Latency:  {}ms", latency);
+            }
+            if let Some(ref ip) = p.health.exit_ip {
+                println!("  Exit IP:  {}", ip);
+            }
+            if let Some(ref location) = p.health.location {
+                println!("  Location: {}", location);
+            }
+            if p.health.total_checks > 0 {
+                println!("  Checks:   {} ({} failed)", p.health.total_checks, p.health.total_failures);
+            }
+            if let Some(ref err) = p.health.error {
+                println!("  Error:    {}", err);
+            }
+        }
+        ProxyAction::Remove { proxy } => {
+            let p = resolve_or_exit(&manager, &proxy).clone();
+            manager.remove_upstream(&p.id).unwrap_or_else(|e| {
+                eprintln!("Error: Failed to remove proxy: {}", e);
+                process::exit(1);
+            });
+            println!("Proxy '{}' removed.", p.name);
+        }
+        ProxyAction::Enable { proxy } => set_proxy_enabled(&mut manager, &proxy, true),
+        ProxyAction::Disable { proxy } => set_proxy_enabled(&mut manager, &proxy, false),
+        ProxyAction::Check { proxy } => {
+            let targets: Vec<UpstreamProxy> = match proxy {
+                Some(key) => vec![resolve_or_exit(&manager, &key).clone()],
+                None => manager.list_enabled_upstreams().into_iter().cloned().collect(),
+            };
+            if targets.is_empty() {
+                println!("No enabled proxies to check.");
+                return;
+            }
+
+            println!("Checking {} proxies...", targets.len());
+            check_upstreams(&mut manager, targets);
+        }
+    }
+}
+
+/// Enable or disable a pool entry
+fn set_proxy_enabled(manager: &mut ConfigManager, key: &str, enabled: bool) {
+    let p = resolve_or_exit(manager, key).clone();
+    manager.set_upstream_enabled(&p.id, enabled).unwrap_or_else(|e| {
+        eprintln!("Error: Failed to update proxy: {}", e);
+        process::exit(1);
+    });
+    println!("Proxy '{}' {}.", p.name, if enabled { "enabled" } else { "disabled" });
+}
+
+/// Resolve a pool entry by ID or name or exit with error
+fn resolve_or_exit<'a>(manager: &'a ConfigManager, key: &str) -> &'a UpstreamProxy {
+    manager.resolve_upstream(key).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    })
+}
+
+/// Print a value as pretty JSON or exit with error
+fn print_json<T: serde::Serialize>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => {
+            eprintln!("Error: Failed to serialize: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// List, show, diff or restore config backups
+fn cmd_backup(action: BackupAction) {
+    let store = BackupStore::new(ConfigManager::backup_settings());
+
+    match action {
+        BackupAction::List { config } => {
+            let entries = store.list(&config).unwrap_or_else(|e| {
+                eprintln!("Error: {:#}", e);
+                process::exit(1);
+            });
+            if entries.is_empty() {
+                println!("No backups for {}", config.display());
+                return;
+            }
+
+            if let Ok(dir) = store.dir_for(&config) {
+                println!("Backups in {}:", dir.display());
+            }
+            println!("{:>3}  {:<19}  {:>9}  ID", "#", "Created", "Size");
+            println!("{}", "-".repeat(72));
+            for (i, entry) in entries.iter().enumerate() {
+                println!(
+                    "{:>3}  {:<19}  {:>9}  {}",
+                    i + 1,
+                    entry.created_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"),
+                    entry.size,
+                    entry.id
+                );
+            }
+        }
+        BackupAction::Show { config, backup } => {
+            let entry = find_backup(&store, &config, backup.as_deref());
+            let content = store.read(&entry).unwrap_or_else(|e| {
+                eprintln!("Error: {:#}", e);
+                process::exit(1);
+            });
+            print!("{}", content);
+        }
+        BackupAction::Diff { config, backup } => {
+            let entry = find_backup(&store, &config, backup.as_deref());
+            let old = store.read(&entry).unwrap_or_else(|e| {
+                eprintln!("Error: {:#}", e);
+                process::exit(1);
+            });
+            let current = read_config(&config);
+            print_diff(&diff::unified_diff(
+                &old,
+                &current,
+                &format!("backup/{}", entry.id),
+                &format!("b/{}", display_name(&config)),
+                3,
+            ));
+        }
+        BackupAction::Restore { config, backup } => {
+            let entry = find_backup(&store, &config, backup.as_deref());
+            let snapshot = store.restore(&config, &entry).unwrap_or_else(|e| {
+                eprintln!("Error: Restore failed: {:#}", e);
+                process::exit(1);
+            });
+            println!("Restored {} from backup {}", config.display(), entry.id);
+            if let Some(snapshot) = snapshot {
+                println!("Previous content saved as backup {}", snapshot.id);
+            }
+        }
+    }
+}
+
+/// Look up a backup or exit with error
+fn find_backup(store: &BackupStore, config: &Path, key: Option<&str>) -> BackupEntry {
+    store.find(config, key).unwrap_or_else(|e| {
+        eprintln!("Error: {:#}", e);
+        process::exit(1);
+    })
+}
+
+/// Manage the app config
+fn cmd_config(action: ConfigAction) {
+    let mut manager = load_config_manager();
+
+    match action {
+        ConfigAction::Rekey { passphrase: true, .. } => {
+            let passphrase = std::env::var("CCP_NEW_PASSPHRASE").unwrap_or_else(|_| {
+                let first = prompt_passphrase("New passphrase: ");
+                if prompt_passphrase("Repeat passphrase: ") != first {
+                    eprintln!("Error: Passphrases do not match");
+                    process::exit(1);
+                }
+                first
+            });
+            manager.rekey_with_passphrase(&passphrase).unwrap_or_else(|e| {
+                eprintln!("Error: Rekey failed: {}", e);
+                process::exit(1);
+            });
+            println!("Secrets re-encrypted with a passphrase.");
+            println!("Set {} (or enter it when prompted) to use the config.", PASSPHRASE_ENV);
+        }
+        ConfigAction::Rekey { key_file, .. } => {
+            let path = manager.rekey_with_key_file(key_file).unwrap_or_else(|e| {
+                eprintln!("Error: Rekey failed: {}", e);
+                process::exit(1);
+            });
+            println!("Secrets re-encrypted with key file {}", path.display());
+        }
+    }
+}
+
+/// Prompt for a passphrase without echo or exit with error
+fn prompt_passphrase(prompt: &str) -> String {
+    rpassword::prompt_password(prompt).unwrap_or_else(|e| {
+        eprintln!("Error: Failed to read passphrase: {}", e);
+        process::exit(1);
+    })
+}
+
+/// Load the app config manager or exit with error
+///
+/// Prompts for the passphrase when the config is passphrase-protected and
+/// `CCP_PASSPHRASE` is not set.
+fn load_config_manager() -> ConfigManager {
+    let result = ConfigManager::new().or_else(|e| {
+        let needs_passphrase = matches!(e.downcast_ref(), Some(SecretsError::PassphraseRequired));
+        if needs_passphrase && std::io::stdin().is_terminal() {
+            ConfigManager::open_with_passphrase(&prompt_passphrase("Config passphrase: "))
+        } else {
+            Err(e)
+        }
+    });
+
+    result.unwrap_or_else(|e| {
+        eprintln!("Error: Failed to load config: {}", e);
+        process::exit(1);
+    })
+}
+
 /// Read config file content or exit with error
-fn read_config(path: &PathBuf) -> String {
+fn read_config(path: &Path) -> String {
     std::fs::read_to_string(path).unwrap_or_else(|e| {
         eprintln!("Error: Cannot read {}: {}", path.display(), e);
         process::exit(1);
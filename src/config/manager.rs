@@ -1,9 +1,14 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use super::secrets::{self, EncryptionConfig, KeySource, SecretCipher};
 use super::upstream::UpstreamProxy;
+use crate::fsutil;
+use crate::merger::filter::default_ignore_patterns;
+use crate::merger::region::{Region, RegionTable};
 use crate::patcher::CustomRuleSet;
 
 /// Application configuration manager
@@ -32,6 +37,27 @@ pub struct AppConfig {
     /// Whether built-in presets have been seeded (prevents re-seeding after user deletes them)
     #[serde(default)]
     pub presets_seeded: bool,
+
+    /// Exit rotation policy for the fixed upstream hop
+    #[serde(default)]
+    pub rotation: RotationConfig,
+
+    /// How secret fields are encrypted at rest
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+
+    /// Backup history of patched Clash configs
+    #[serde(default)]
+    pub backup: BackupConfig,
+
+    /// Node names containing any of these are never chained (subscription
+    /// banners such as traffic left or expiry date)
+    #[serde(default = "default_ignore_patterns")]
+    pub ignore_patterns: Vec<String>,
+
+    /// Regions added to the built-in table used for `Chain-<CODE>` groups
+    #[serde(default)]
+    pub regions: Vec<Region>,
 }
 
 impl Default for AppConfig {
@@ -44,6 +70,11 @@ impl Default for AppConfig {
             recent_files: Vec::new(),
             custom_rule_presets: Vec::new(),
             presets_seeded: false,
+            rotation: RotationConfig::default(),
+            encryption: EncryptionConfig::default(),
+            backup: BackupConfig::default(),
+            ignore_patterns: default_ignore_patterns(),
+            regions: Vec::new(),
         }
     }
 }
@@ -152,10 +183,77 @@ impl Default for HealthCheckConfig {
     }
 }
 
+/// Exit rotation configuration
+///
+/// Controls when the upstream used as the fixed SOCKS5 exit is swapped for
+/// the next healthy candidate in the pool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RotationConfig {
+    /// Whether rotation is enabled
+    pub enabled: bool,
+
+    /// Rotate after this many minutes (0 = no scheduled rotation)
+    pub interval_minutes: u64,
+
+    /// Rotate once the active upstream reaches this many consecutive
+    /// health failures (0 = never rotate on failures)
+    pub failure_threshold: u32,
+
+    /// Upstream IDs eligible for rotation (empty = all enabled upstreams)
+    pub candidate_ids: Vec<String>,
+
+    /// Upstream currently patched into the Clash config
+    pub active_upstream_id: Option<String>,
+
+    /// Time of the last rotation
+    pub last_rotated_at: Option<DateTime<Utc>>,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_minutes: 60,
+            failure_threshold: 3,
+            candidate_ids: Vec::new(),
+            active_upstream_id: None,
+            last_rotated_at: None,
+        }
+    }
+}
+
+/// Backup history configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    /// Directory for backups (default: `.ccp-backups` next to the Clash config)
+    pub dir: Option<String>,
+
+    /// Backups kept per config file (0 = unlimited)
+    pub keep: usize,
+
+    /// Remove backups older than this many days (0 = never)
+    pub max_age_days: u64,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            keep: 20,
+            max_age_days: 30,
+        }
+    }
+}
+
 /// Configuration manager
 pub struct ConfigManager {
     config_path: PathBuf,
     config: AppConfig,
+    cipher: SecretCipher,
+    /// Passphrase given to [`Self::open_with_passphrase`], kept for reloads
+    passphrase: Option<String>,
 }
 
 impl ConfigManager {
@@ -164,28 +262,30 @@ impl ConfigManager {
     /// Creates a default configuration if the config file doesn't exist
     pub fn new() -> Result<Self> {
         let config_path = Self::get_config_path()?;
+        Self::open(config_path, AppConfig::default(), None)
+    }
 
-        // Ensure the config directory exists
-        if let Some(parent) = config_path.parent() {
-            fs::create_dir_all(parent)
-                .context("Failed to create config directory")?;
-        }
-
-        // Load or create configuration
-        let config = if config_path.exists() {
-            Self::load_from_file(&config_path)?
-        } else {
-            let default_config = AppConfig::default();
-            Self::save_to_file(&config_path, &default_config)?;
-            default_config
-        };
-
-        Ok(Self { config_path, config })
+    /// Open the default config with an explicit passphrase
+    ///
+    /// The passphrase is never put into the process environment, so it is
+    /// not inherited by `cmd:` secret references.
+    pub fn open_with_passphrase(passphrase: &str) -> Result<Self> {
+        let config_path = Self::get_config_path()?;
+        Self::open(config_path, AppConfig::default(), Some(passphrase.to_string()))
     }
 
     /// Create a configuration manager with a specific path (for testing)
     #[cfg(test)]
     pub(crate) fn new_with_path(config_path: PathBuf) -> Result<Self> {
+        // Keep the key file next to the test config instead of the user's data dir
+        let mut default_config = AppConfig::default();
+        default_config.encryption.key_file =
+            Some(config_path.with_extension("key").display().to_string());
+        Self::open(config_path, default_config, None)
+    }
+
+    /// Load the config at `config_path`, or create it from `default_config`
+    fn open(config_path: PathBuf, default_config: AppConfig, passphrase: Option<String>) -> Result<Self> {
         // Ensure the config directory exists
         if let Some(parent) = config_path.parent() {
             fs::create_dir_all(parent)
@@ -193,15 +293,21 @@ impl ConfigManager {
         }
 
         // Load or create configuration
-        let config = if config_path.exists() {
-            Self::load_from_file(&config_path)?
+        let (config, cipher) = if config_path.exists() {
+            let (config, cipher, plaintext) = Self::read_config_file(&config_path, passphrase.as_deref())?;
+            if plaintext > 0 {
+                // Migrate legacy plaintext secrets right away
+                Self::write_config_file(&config_path, &config, &cipher)?;
+                tracing::info!("Encrypted {} plaintext secrets in {}", plaintext, config_path.display());
+            }
+            (config, cipher)
         } else {
-            let default_config = AppConfig::default();
-            Self::save_to_file(&config_path, &default_config)?;
-            default_config
+            let cipher = SecretCipher::resolve_with(&default_config.encryption, true, passphrase.as_deref())?;
+            Self::write_config_file(&config_path, &default_config, &cipher)?;
+            (default_config, cipher)
         };
 
-        Ok(Self { config_path, config })
+        Ok(Self { config_path, config, cipher, passphrase })
     }
 
     /// Get the configuration file path
@@ -214,23 +320,98 @@ impl ConfigManager {
             .join("config.json"))
     }
 
+    /// Backup settings from the default config file
+    ///
+    /// Only the `backup` section is read, so this works without unlocking
+    /// encrypted secrets. Falls back to defaults when it is missing.
+    pub fn backup_settings() -> BackupConfig {
+        Self::read_section("backup").unwrap_or_default()
+    }
+
+    /// Ignore patterns from the default config file, read the same way as
+    /// [`Self::backup_settings`]; the built-in list when none are saved
+    pub fn ignore_settings() -> Vec<String> {
+        Self::read_section("ignore_patterns").unwrap_or_else(default_ignore_patterns)
+    }
+
+    /// Region table (built-in plus saved regions) from the default config
+    /// file, read the same way as [`Self::backup_settings`]
+    pub fn region_settings() -> RegionTable {
+        RegionTable::with_custom(&Self::read_section::<Vec<Region>>("regions").unwrap_or_default())
+    }
+
+    /// Whether the default config file is passphrase-protected and no
+    /// passphrase is set in `CCP_PASSPHRASE` yet, read the same way as
+    /// [`Self::backup_settings`]
+    pub fn passphrase_required() -> bool {
+        let encryption: EncryptionConfig = Self::read_section("encryption").unwrap_or_default();
+        encryption.key_source == KeySource::Passphrase && std::env::var_os(secrets::PASSPHRASE_ENV).is_none()
+    }
+
+    /// One top-level entry of the default config file
+    fn read_section<T: serde::de::DeserializeOwned>(key: &str) -> Option<T> {
+        Self::get_config_path()
+            .ok()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+            .and_then(|value| value.get(key).cloned())
+            .and_then(|section| serde_json::from_value(section).ok())
+    }
+
     /// Load configuration from file
+    #[cfg(test)]
     fn load_from_file(path: &Path) -> Result<AppConfig> {
+        Self::read_config_file(path, None).map(|(config, _, _)| config)
+    }
+
+    /// Load configuration from file, decrypting secret fields
+    ///
+    /// Also returns the cipher in use and the number of plaintext secrets found.
+    fn read_config_file(path: &Path, passphrase: Option<&str>) -> Result<(AppConfig, SecretCipher, usize)> {
         let content = fs::read_to_string(path)
             .with_context(|| format!("Failed to read config file: {}", path.display()))?;
 
-        let config: AppConfig = serde_json::from_str(&content)
+        let mut value: serde_json::Value = serde_json::from_str(&content)
+            .context("Failed to parse config file")?;
+
+        let encryption: EncryptionConfig = value.get("encryption")
+            .map(|v| serde_json::from_value(v.clone()))
+            .transpose()
+            .context("Failed to parse encryption settings")?
+            .unwrap_or_default();
+
+        // Only create a key file for configs that have never been encrypted
+        let cipher = SecretCipher::resolve_with(&encryption, encryption.check.is_none(), passphrase)?;
+        if let Some(check) = &encryption.check {
+            cipher.verify_check(check)?;
+        }
+        // Configs that were never encrypted predate secret references
+        let plaintext = secrets::decrypt_secrets(&mut value, &cipher, encryption.check.is_none())?;
+
+        let config: AppConfig = serde_json::from_value(value)
             .context("Failed to parse config file")?;
 
-        Ok(config)
+        Ok((config, cipher, plaintext))
     }
 
     /// Save configuration to file
+    #[cfg(test)]
     fn save_to_file(path: &Path, config: &AppConfig) -> Result<()> {
-        let json = serde_json::to_string_pretty(config)
+        let cipher = SecretCipher::resolve(&config.encryption, true)?;
+        Self::write_config_file(path, config, &cipher)
+    }
+
+    /// Save configuration to file, encrypting secret fields
+    fn write_config_file(path: &Path, config: &AppConfig, cipher: &SecretCipher) -> Result<()> {
+        let mut value = serde_json::to_value(config)
+            .context("Failed to serialize config")?;
+        secrets::encrypt_secrets(&mut value, cipher)?;
+        value["encryption"]["check"] = serde_json::Value::String(cipher.check_value()?);
+
+        let json = serde_json::to_string_pretty(&value)
             .context("Failed to serialize config")?;
 
-        fs::write(path, json)
+        fsutil::write_atomic(path, json)
             .with_context(|| format!("Failed to write config file: {}", path.display()))?;
 
         Ok(())
@@ -238,14 +419,16 @@ impl ConfigManager {
 
     /// Save the current configuration
     pub fn save(&self) -> Result<()> {
-        Self::save_to_file(&self.config_path, &self.config)?;
+        Self::write_config_file(&self.config_path, &self.config, &self.cipher)?;
         tracing::info!("Config saved to: {}", self.config_path.display());
         Ok(())
     }
 
     /// Reload the configuration
     pub fn reload(&mut self) -> Result<()> {
-        self.config = Self::load_from_file(&self.config_path)?;
+        let (config, cipher, _) = Self::read_config_file(&self.config_path, self.passphrase.as_deref())?;
+        self.config = config;
+        self.cipher = cipher;
         tracing::info!("Config reloaded from: {}", self.config_path.display());
         Ok(())
     }
@@ -265,6 +448,79 @@ impl ConfigManager {
         &self.config_path
     }
 
+    // ===== Secret encryption =====
+
+    /// Re-encrypt all secrets with a new key file
+    ///
+    /// A fresh random key is written to `key_file` (or the default location)
+    /// only after the config has been saved with it. If the key can't be
+    /// installed, the config is saved again under the old key.
+    pub fn rekey_with_key_file(&mut self, key_file: Option<PathBuf>) -> Result<PathBuf> {
+        let encryption = EncryptionConfig {
+            key_source: KeySource::KeyFile,
+            key_file: key_file.map(|p| p.display().to_string()),
+            salt: None,
+            check: None,
+        };
+        let path = encryption.key_file_path()?;
+        let staged = path.with_extension("key.new");
+
+        let key = secrets::generate_key();
+        secrets::write_key_file(&staged, &key)?;
+
+        let previous = std::mem::replace(&mut self.config.encryption, encryption);
+        let cipher = SecretCipher::from_key(&key);
+        if let Err(e) = Self::write_config_file(&self.config_path, &self.config, &cipher) {
+            self.config.encryption = previous;
+            let _ = fs::remove_file(&staged);
+            return Err(e);
+        }
+
+        if let Err(e) = fs::rename(&staged, &path) {
+            // Put the config back under the old key, which is still in place
+            self.config.encryption = previous;
+            if let Err(restore) = Self::write_config_file(&self.config_path, &self.config, &self.cipher) {
+                return Err(restore).with_context(|| format!(
+                    "Failed to install key file {} ({}); the config now needs the key in {}",
+                    path.display(), e, staged.display(),
+                ));
+            }
+            let _ = fs::remove_file(&staged);
+            return Err(e).with_context(|| format!("Failed to install key file: {}", path.display()));
+        }
+        self.cipher = cipher;
+        self.passphrase = None;
+        tracing::info!("Config secrets re-encrypted with key file {}", path.display());
+        Ok(path)
+    }
+
+    /// Re-encrypt all secrets with a key derived from `passphrase`
+    pub fn rekey_with_passphrase(&mut self, passphrase: &str) -> Result<()> {
+        if passphrase.is_empty() {
+            anyhow::bail!("Passphrase must not be empty");
+        }
+
+        let salt = secrets::generate_salt();
+        let cipher = SecretCipher::from_passphrase(passphrase, &salt)?;
+        let encryption = EncryptionConfig {
+            key_source: KeySource::Passphrase,
+            key_file: None,
+            salt: Some(salt),
+            check: None,
+        };
+
+        let previous = std::mem::replace(&mut self.config.encryption, encryption);
+        if let Err(e) = Self::write_config_file(&self.config_path, &self.config, &cipher) {
+            self.config.encryption = previous;
+            return Err(e);
+        }
+
+        self.cipher = cipher;
+        self.passphrase = Some(passphrase.to_string());
+        tracing::info!("Config secrets re-encrypted with a passphrase");
+        Ok(())
+    }
+
     // ===== Upstream proxy management =====
 
     /// Add an upstream proxy
@@ -309,6 +565,29 @@ impl ConfigManager {
         self.config.upstream_proxies.iter().find(|p| p.id == id)
     }
 
+    /// Find an upstream proxy by display name
+    pub fn find_upstream_by_name(&self, name: &str) -> Option<&UpstreamProxy> {
+        self.config.upstream_proxies.iter().find(|p| p.name == name)
+    }
+
+    /// Resolve an upstream proxy by ID, unique ID prefix, or name
+    pub fn resolve_upstream(&self, key: &str) -> Result<&UpstreamProxy> {
+        if let Some(proxy) = self.get_upstream(key).or_else(|| self.find_upstream_by_name(key)) {
+            return Ok(proxy);
+        }
+
+        let matches: Vec<&UpstreamProxy> = self.config.upstream_proxies
+            .iter()
+            .filter(|p| key.len() >= 4 && p.id.starts_with(key))
+            .collect();
+
+        match matches.as_slice() {
+            [proxy] => Ok(proxy),
+            [] => anyhow::bail!("No upstream proxy matches '{}'", key),
+            _ => anyhow::bail!("'{}' matches {} upstream proxies, use a longer ID", key, matches.len()),
+        }
+    }
+
     /// Get all upstream proxies
     pub fn list_upstreams(&self) -> &[UpstreamProxy] {
         &self.config.upstream_proxies
@@ -334,6 +613,20 @@ impl ConfigManager {
         Ok(())
     }
 
+    // ===== Rotation policy =====
+
+    /// Get the exit rotation policy
+    pub fn rotation(&self) -> &RotationConfig {
+        &self.config.rotation
+    }
+
+    /// Replace the exit rotation policy
+    pub fn set_rotation(&mut self, rotation: RotationConfig) -> Result<()> {
+        self.config.rotation = rotation;
+        self.save()?;
+        Ok(())
+    }
+
     // ===== Recent files management =====
 
     /// Add a recently used file path
@@ -368,6 +661,34 @@ impl ConfigManager {
         Ok(())
     }
 
+    // ===== Ignore patterns =====
+
+    /// Node name patterns that are never chained
+    pub fn ignore_patterns(&self) -> &[String] {
+        &self.config.ignore_patterns
+    }
+
+    /// Replace the ignore patterns
+    pub fn set_ignore_patterns(&mut self, patterns: Vec<String>) -> Result<()> {
+        self.config.ignore_patterns = patterns;
+        self.save()?;
+        Ok(())
+    }
+
+    // ===== Regions =====
+
+    /// Regions added to the built-in table
+    pub fn custom_regions(&self) -> &[Region] {
+        &self.config.regions
+    }
+
+    /// Replace the saved regions
+    pub fn set_custom_regions(&mut self, regions: Vec<Region>) -> Result<()> {
+        self.config.regions = regions;
+        self.save()?;
+        Ok(())
+    }
+
     // ===== Custom rule preset management =====
 
     /// Get all custom rule presets
@@ -424,17 +745,32 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let config_path = temp_dir.path().join("config.json");
 
-        let config = AppConfig::default();
+        let mut config = AppConfig::default();
+        config.encryption.key_file =
+            Some(temp_dir.path().join("secret.key").display().to_string());
         ConfigManager::save_to_file(&config_path, &config).unwrap();
+        let cipher = SecretCipher::resolve(&config.encryption, false).unwrap();
 
         let manager = ConfigManager {
             config_path,
             config,
+            cipher,
+            passphrase: None,
         };
 
         (manager, temp_dir)
     }
 
+    fn add_test_proxy_with_password(manager: &mut ConfigManager, password: &str) {
+        let config = UpstreamConfig {
+            host: "127.0.0.1".to_string(),
+            port: 1080,
+            username: Some("user".to_string()),
+            password: Some(password.to_string()),
+        };
+        manager.add_upstream(UpstreamProxy::new("Secret".to_string(), config)).unwrap();
+    }
+
     #[test]
     fn test_config_save_and_load() {
         let (manager, _temp_dir) = create_test_config_manager();
@@ -541,4 +877,206 @@ mod tests {
         assert_eq!(enabled.len(), 1);
         assert_eq!(enabled[0].name, "Enabled");
     }
+
+    #[test]
+    fn test_resolve_upstream() {
+        let (mut manager, _temp_dir) = create_test_config_manager();
+
+        let config = UpstreamConfig {
+            host: "127.0.0.1".to_string(),
+            port: 1080,
+            username: None,
+            password: None,
+        };
+        let mut proxy = UpstreamProxy::new("Home".to_string(), config.clone());
+        proxy.id = "abcd1234-0000".to_string();
+        manager.add_upstream(proxy).unwrap();
+
+        let mut other = UpstreamProxy::new("Office".to_string(), config);
+        other.id = "abce5678-0000".to_string();
+        manager.add_upstream(other).unwrap();
+
+        assert_eq!(manager.resolve_upstream("Home").unwrap().id, "abcd1234-0000");
+        assert_eq!(manager.resolve_upstream("abce5678-0000").unwrap().name, "Office");
+        assert_eq!(manager.resolve_upstream("abcd").unwrap().name, "Home");
+        assert!(manager.resolve_upstream("abc").is_err());
+        assert!(manager.resolve_upstream("Missing").is_err());
+        assert!(manager.find_upstream_by_name("Office").is_some());
+    }
+
+    #[test]
+    fn test_rotation_defaults_for_legacy_config() {
+        let json = r#"{
+            "upstream_proxies": [],
+            "clash": { "config_path": null, "auto_monitor": false, "auto_merge": true,
+                       "api": { "enabled": false, "host": "127.0.0.1", "port": 9090, "secret": null } },
+            "local_proxy": { "name": "Local-Chain-Proxy", "listen": "127.0.0.1:10808" },
+            "health_check": { "enabled": true, "interval_seconds": 300,
+                              "test_url": "http://www.gstatic.com/generate_204",
+                              "timeout_seconds": 10, "failure_threshold": 3 }
+        }"#;
+
+        let config: AppConfig = serde_json::from_str(json).unwrap();
+        assert!(!config.rotation.enabled);
+        assert_eq!(config.rotation.interval_minutes, 60);
+        assert!(config.rotation.active_upstream_id.is_none());
+        assert_eq!(config.ignore_patterns, default_ignore_patterns());
+    }
+
+    #[test]
+    fn test_ignore_patterns_persisted() {
+        let (mut manager, _temp_dir) = create_test_config_manager();
+        manager.set_ignore_patterns(vec!["Info".to_string()]).unwrap();
+        manager.reload().unwrap();
+        assert_eq!(manager.ignore_patterns(), ["Info".to_string()]);
+
+        // An emptied list stays empty instead of being re-seeded
+        manager.set_ignore_patterns(Vec::new()).unwrap();
+        manager.reload().unwrap();
+        assert!(manager.ignore_patterns().is_empty());
+    }
+
+    #[test]
+    fn test_custom_regions_persisted() {
+        let (mut manager, _temp_dir) = create_test_config_manager();
+        assert!(manager.custom_regions().is_empty());
+        let vietnam = Region { code: "VN".to_string(), keywords: vec!["越南".to_string()] };
+        manager.set_custom_regions(vec![vietnam.clone()]).unwrap();
+        manager.reload().unwrap();
+        assert_eq!(manager.custom_regions(), [vietnam]);
+    }
+
+    #[test]
+    fn test_set_rotation() {
+        let (mut manager, _temp_dir) = create_test_config_manager();
+
+        let rotation = RotationConfig {
+            enabled: true,
+            interval_minutes: 15,
+            active_upstream_id: Some("abc".to_string()),
+            ..RotationConfig::default()
+        };
+        manager.set_rotation(rotation).unwrap();
+
+        let loaded = ConfigManager::load_from_file(&manager.config_path).unwrap();
+        assert!(loaded.rotation.enabled);
+        assert_eq!(loaded.rotation.interval_minutes, 15);
+        assert_eq!(loaded.rotation.active_upstream_id.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn test_secrets_encrypted_at_rest() {
+        let (mut manager, _temp_dir) = create_test_config_manager();
+        add_test_proxy_with_password(&mut manager, "hunter2");
+
+        let raw = fs::read_to_string(&manager.config_path).unwrap();
+        assert!(!raw.contains("hunter2"));
+        assert!(raw.contains(secrets::ENCRYPTED_PREFIX));
+
+        let loaded = ConfigManager::load_from_file(&manager.config_path).unwrap();
+        assert_eq!(loaded.upstream_proxies[0].config.password.as_deref(), Some("hunter2"));
+    }
+
+    #[test]
+    fn test_plaintext_config_migrated_on_open() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.json");
+        let key_file = temp_dir.path().join("secret.key").display().to_string();
+
+        // Legacy config: no encryption section, plaintext password
+        let mut legacy = serde_json::to_value(AppConfig::default()).unwrap();
+        legacy.as_object_mut().unwrap().remove("encryption");
+        legacy["encryption"] = serde_json::json!({ "key_file": key_file });
+        legacy["upstream_proxies"] = serde_json::json!([{
+            "id": "p1", "name": "Legacy", "enabled": true,
+            "config": { "host": "127.0.0.1", "port": 1080, "username": "u", "password": "hunter2" },
+            "health": { "status": "unknown", "latency_ms": null, "last_check": null,
+                        "error": null, "consecutive_failures": 0 }
+        }]);
+        fs::write(&config_path, serde_json::to_string_pretty(&legacy).unwrap()).unwrap();
+
+        let manager = ConfigManager::new_with_path(config_path.clone()).unwrap();
+        assert_eq!(manager.list_upstreams()[0].config.password.as_deref(), Some("hunter2"));
+
+        let raw = fs::read_to_string(&config_path).unwrap();
+        assert!(!raw.contains("hunter2"));
+        assert!(raw.contains("\"check\""));
+    }
+
+    #[test]
+    fn test_missing_key_file_is_an_error() {
+        let (mut manager, temp_dir) = create_test_config_manager();
+        add_test_proxy_with_password(&mut manager, "hunter2");
+
+        fs::remove_file(temp_dir.path().join("secret.key")).unwrap();
+        assert!(ConfigManager::load_from_file(&manager.config_path).is_err());
+    }
+
+    #[test]
+    fn test_rekey_with_key_file() {
+        let (mut manager, temp_dir) = create_test_config_manager();
+        add_test_proxy_with_password(&mut manager, "hunter2");
+
+        let new_key = temp_dir.path().join("rotated.key");
+        let path = manager.rekey_with_key_file(Some(new_key.clone())).unwrap();
+        assert_eq!(path, new_key);
+        assert!(!new_key.with_extension("key.new").exists());
+
+        let loaded = ConfigManager::load_from_file(&manager.config_path).unwrap();
+        assert_eq!(loaded.encryption.key_file.as_deref(), Some(new_key.display().to_string().as_str()));
+        assert_eq!(loaded.upstream_proxies[0].config.password.as_deref(), Some("hunter2"));
+
+        // The old key no longer opens the config
+        let old = SecretCipher::resolve(&manager.config.encryption, false).unwrap();
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&manager.config_path).unwrap()).unwrap();
+        let stale = SecretCipher::from_key(&secrets::read_key_file(&temp_dir.path().join("secret.key")).unwrap());
+        assert!(old.verify_check(raw["encryption"]["check"].as_str().unwrap()).is_ok());
+        assert!(stale.verify_check(raw["encryption"]["check"].as_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_rekey_with_key_file_keeps_old_key_on_failure() {
+        let (mut manager, temp_dir) = create_test_config_manager();
+        add_test_proxy_with_password(&mut manager, "hunter2");
+        let previous = manager.config().encryption.key_file.clone();
+
+        // A non-empty directory in the way makes installing the key fail
+        let blocked = temp_dir.path().join("blocked.key");
+        fs::create_dir_all(blocked.join("inner")).unwrap();
+        assert!(manager.rekey_with_key_file(Some(blocked.clone())).is_err());
+        assert!(!blocked.with_extension("key.new").exists());
+
+        let loaded = ConfigManager::load_from_file(&manager.config_path).unwrap();
+        assert_eq!(loaded.encryption.key_file, previous);
+        assert_eq!(loaded.upstream_proxies[0].config.password.as_deref(), Some("hunter2"));
+        assert_eq!(manager.config().encryption.key_file, previous);
+    }
+
+    #[test]
+    fn test_rekey_with_passphrase() {
+        let (mut manager, _temp_dir) = create_test_config_manager();
+        add_test_proxy_with_password(&mut manager, "hunter2");
+
+        manager.rekey_with_passphrase("correct horse").unwrap();
+        assert_eq!(manager.config().encryption.key_source, KeySource::Passphrase);
+
+        // Reload with the derived key (without touching the process environment)
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&manager.config_path).unwrap()).unwrap();
+        let salt = raw["encryption"]["salt"].as_str().unwrap();
+        let cipher = SecretCipher::from_passphrase("correct horse", salt).unwrap();
+        cipher.verify_check(raw["encryption"]["check"].as_str().unwrap()).unwrap();
+        let password = raw["upstream_proxies"][0]["config"]["password"].as_str().unwrap();
+        assert_eq!(cipher.decrypt(password).unwrap(), "hunter2");
+
+        // The passphrase is kept for reloads and can be given explicitly
+        manager.reload().unwrap();
+        let path = manager.config_path.clone();
+        let reopened = ConfigManager::open(path.clone(), AppConfig::default(), Some("correct horse".to_string())).unwrap();
+        assert_eq!(reopened.list_upstreams().len(), 1);
+        assert!(ConfigManager::open(path, AppConfig::default(), Some("wrong".to_string())).is_err());
+
+        assert!(manager.rekey_with_passphrase("").is_err());
+    }
 }
@@ -5,12 +5,20 @@
 /// - Clash configuration path and settings
 /// - Local proxy server configuration
 /// - Health check configuration
+/// - Exit rotation policy
+/// - Backup history settings
+/// - Encryption of secrets at rest
+/// - Secret references (`env:`, `file:`, `cmd:`) for credentials
 
 pub mod manager;
+pub mod secret_ref;
+pub mod secrets;
 pub mod upstream;
 
 pub use manager::{
-    AppConfig, ClashApiConfig, ClashConfig, ConfigManager, HealthCheckConfig, LocalProxyConfig,
+    AppConfig, BackupConfig, ClashApiConfig, ClashConfig, ConfigManager, HealthCheckConfig, LocalProxyConfig,
+    RotationConfig,
 };
+pub use secrets::{EncryptionConfig, KeySource, SecretCipher, SecretsError};
 pub use upstream::{HealthStatus, ProxyHealth, UpstreamProxy};
 pub use crate::patcher::{CustomRule, CustomRuleSet, RuleMatchType};
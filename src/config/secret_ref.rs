@@ -0,0 +1,207 @@
+//! Secret references for credentials
+//!
+//! Instead of a literal value, a username or password may be stored as
+//! `env:NAME`, `file:/path/to/secret` or `cmd:<shell command>`. References
+//! are resolved only when a connection is made or a Clash config is
+//! written, so the secret itself never lands in `config.json`, shell
+//! history or `ps` output.
+//!
+//! A literal value that happens to start with one of these prefixes is
+//! written with a `literal:` prefix, which is stripped when resolving.
+
+use anyhow::{anyhow, Context, Result};
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Prefix marking a value as literal even if it starts like a reference
+pub const LITERAL_PREFIX: &str = "literal:";
+
+/// A reference to a secret stored outside the app config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretRef {
+    /// Environment variable
+    Env(String),
+
+    /// File contents (trailing newline stripped)
+    File(PathBuf),
+
+    /// Standard output of a shell command (trailing newline stripped)
+    Cmd(String),
+}
+
+impl SecretRef {
+    /// Parse a value as a secret reference; literals return `None`
+    pub fn parse(value: &str) -> Option<Self> {
+        if let Some(name) = value.strip_prefix("env:") {
+            Some(SecretRef::Env(name.trim().to_string()))
+        } else if let Some(path) = value.strip_prefix("file:") {
+            Some(SecretRef::File(PathBuf::from(path.trim())))
+        } else {
+            value.strip_prefix("cmd:").map(|cmd| SecretRef::Cmd(cmd.trim().to_string()))
+        }
+    }
+
+    /// Resolve the reference to the secret value
+    pub fn resolve(&self) -> Result<String> {
+        match self {
+            SecretRef::Env(name) => {
+                if name.is_empty() {
+                    anyhow::bail!("Empty environment variable name in secret reference");
+                }
+                std::env::var(name)
+                    .with_context(|| format!("Environment variable {} is not set", name))
+            }
+            SecretRef::File(path) => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read secret file: {}", path.display()))?;
+                Ok(trim_line_ending(content))
+            }
+            SecretRef::Cmd(cmd) => {
+                if cmd.is_empty() {
+                    anyhow::bail!("Empty command in secret reference");
+                }
+                let output = shell_command(cmd)
+                    .output()
+                    .with_context(|| format!("Failed to run secret command: {}", cmd))?;
+                if !output.status.success() {
+                    anyhow::bail!(
+                        "Secret command failed ({}): {}",
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                }
+                let stdout = String::from_utf8(output.stdout)
+                    .context("Secret command output is not valid UTF-8")?;
+                Ok(trim_line_ending(stdout))
+            }
+        }
+    }
+}
+
+impl fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretRef::Env(name) => write!(f, "env:{}", name),
+            SecretRef::File(path) => write!(f, "file:{}", path.display()),
+            SecretRef::Cmd(cmd) => write!(f, "cmd:{}", cmd),
+        }
+    }
+}
+
+/// Check whether a value is a secret reference rather than a literal
+pub fn is_secret_ref(value: &str) -> bool {
+    SecretRef::parse(value).is_some()
+}
+
+/// Check whether a literal value would be read as a reference or lose its
+/// own `literal:` prefix unless escaped
+pub fn needs_escape(value: &str) -> bool {
+    is_secret_ref(value) || value.starts_with(LITERAL_PREFIX)
+}
+
+/// Escape a literal value that would otherwise be read as a reference
+pub fn escape_literal(value: &str) -> String {
+    if needs_escape(value) {
+        format!("{}{}", LITERAL_PREFIX, value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Resolve a value that may be a secret reference (literals pass through,
+/// without their `literal:` prefix)
+pub fn resolve_secret(value: &str) -> Result<String> {
+    if let Some(literal) = value.strip_prefix(LITERAL_PREFIX) {
+        return Ok(literal.to_string());
+    }
+    match SecretRef::parse(value) {
+        Some(secret) => secret
+            .resolve()
+            .map_err(|e| anyhow!("Failed to resolve secret reference {}: {:#}", secret, e)),
+        None => Ok(value.to_string()),
+    }
+}
+
+/// Resolve an optional value that may be a secret reference
+pub fn resolve_optional(value: Option<&str>) -> Result<Option<String>> {
+    value.map(resolve_secret).transpose()
+}
+
+fn trim_line_ending(mut value: String) -> String {
+    while value.ends_with('\n') || value.ends_with('\r') {
+        value.pop();
+    }
+    value
+}
+
+#[cfg(unix)]
+fn shell_command(cmd: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(cmd);
+    command
+}
+
+#[cfg(windows)]
+fn shell_command(cmd: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(cmd);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(SecretRef::parse("env:VENDOR_PASS"), Some(SecretRef::Env("VENDOR_PASS".to_string())));
+        assert_eq!(SecretRef::parse("file:/run/secrets/socks"), Some(SecretRef::File(PathBuf::from("/run/secrets/socks"))));
+        assert_eq!(SecretRef::parse("cmd:pass show vendor/socks"), Some(SecretRef::Cmd("pass show vendor/socks".to_string())));
+        assert_eq!(SecretRef::parse("plain-password"), None);
+        assert!(!is_secret_ref("environment"));
+    }
+
+    #[test]
+    fn test_resolve_literal_and_env() {
+        assert_eq!(resolve_secret("literal").unwrap(), "literal");
+
+        std::env::set_var("CCP_TEST_SECRET_REF", "from-env");
+        assert_eq!(resolve_secret("env:CCP_TEST_SECRET_REF").unwrap(), "from-env");
+        assert!(resolve_secret("env:CCP_TEST_SECRET_REF_MISSING").is_err());
+        assert_eq!(resolve_optional(None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_literal_escape() {
+        assert_eq!(escape_literal("env:not-a-ref"), "literal:env:not-a-ref");
+        assert_eq!(escape_literal("plain"), "plain");
+        assert!(!is_secret_ref(&escape_literal("cmd:rm -rf")));
+        assert_eq!(resolve_secret("literal:cmd:rm -rf").unwrap(), "cmd:rm -rf");
+        assert_eq!(escape_literal("literal:abc"), "literal:literal:abc");
+
+        // Escaped literals always resolve back to themselves
+        for value in ["plain", "env:HOME", "file:/etc/passwd", "cmd:true", "literal:abc", "literal:env:HOME"] {
+            assert_eq!(resolve_secret(&escape_literal(value)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_resolve_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("socks");
+        std::fs::write(&path, "from-file\n").unwrap();
+
+        let value = format!("file:{}", path.display());
+        assert_eq!(resolve_secret(&value).unwrap(), "from-file");
+        assert!(resolve_secret("file:/nonexistent/ccp-secret").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_cmd() {
+        assert_eq!(resolve_secret("cmd:printf 'from-cmd\\n'").unwrap(), "from-cmd");
+        assert!(resolve_secret("cmd:exit 3").is_err());
+    }
+}
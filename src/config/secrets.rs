@@ -0,0 +1,490 @@
+//! Encryption of secret fields in the app config
+//!
+//! Upstream passwords and the Clash API secret are written to `config.json`
+//! as `enc:v1:<base64(nonce || ciphertext)>` using XChaCha20-Poly1305. The
+//! key comes either from a random key file (created with 0600 permissions)
+//! or from a passphrase stretched with Argon2id. Plaintext values found on
+//! load are accepted and re-encrypted on the next save.
+
+use anyhow::{Context, Result};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::secret_ref::{escape_literal, is_secret_ref, needs_escape};
+
+/// Prefix marking an encrypted value
+pub const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+/// Environment variable holding the config passphrase
+pub const PASSPHRASE_ENV: &str = "CCP_PASSPHRASE";
+
+/// Environment variable overriding the default key file location
+pub const KEY_FILE_ENV: &str = "CCP_KEY_FILE";
+
+/// Known plaintext stored encrypted in `EncryptionConfig::check`
+const CHECK_PLAINTEXT: &str = "clash-chain-patcher";
+
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+
+/// Errors that callers may want to handle specifically
+#[derive(Debug, thiserror::Error)]
+pub enum SecretsError {
+    #[error("Config secrets are passphrase-protected; set {} or enter the passphrase", PASSPHRASE_ENV)]
+    PassphraseRequired,
+
+    #[error("Wrong passphrase or key file for config secrets")]
+    WrongKey,
+
+    #[error("Key file not found: {0} (required to decrypt config secrets)")]
+    KeyFileMissing(PathBuf),
+}
+
+/// Where the encryption key comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    /// Random key stored in a separate file
+    #[default]
+    KeyFile,
+
+    /// Key derived from a user passphrase (Argon2id)
+    Passphrase,
+}
+
+/// Encryption settings, stored unencrypted in the config
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    /// Key source
+    pub key_source: KeySource,
+
+    /// Key file path (None = `CCP_KEY_FILE` or the default location)
+    pub key_file: Option<String>,
+
+    /// Argon2 salt, base64 (passphrase mode only)
+    pub salt: Option<String>,
+
+    /// Known plaintext encrypted with the current key, to detect a wrong key
+    pub check: Option<String>,
+}
+
+impl EncryptionConfig {
+    /// Resolved key file path for key-file mode
+    pub fn key_file_path(&self) -> Result<PathBuf> {
+        match &self.key_file {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => default_key_file(),
+        }
+    }
+}
+
+/// Cipher for config secrets
+pub struct SecretCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl SecretCipher {
+    /// Create from a raw 32-byte key
+    pub fn from_key(key: &[u8; KEY_LEN]) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(key.into()),
+        }
+    }
+
+    /// Derive the key from a passphrase and base64 salt with Argon2id
+    pub fn from_passphrase(passphrase: &str, salt: &str) -> Result<Self> {
+        let salt = BASE64.decode(salt).context("Invalid encryption salt")?;
+        let mut key = [0u8; KEY_LEN];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow::anyhow!("Failed to derive key from passphrase: {}", e))?;
+        Ok(Self::from_key(&key))
+    }
+
+    /// Build the cipher described by `config`
+    ///
+    /// A missing key file is only created when `create_key_file` is set,
+    /// so an existing encrypted config never silently gets a fresh key.
+    pub fn resolve(config: &EncryptionConfig, create_key_file: bool) -> Result<Self> {
+        Self::resolve_with(config, create_key_file, None)
+    }
+
+    /// Like [`Self::resolve`], using `passphrase` in passphrase mode and
+    /// falling back to `CCP_PASSPHRASE` when it is `None`
+    pub fn resolve_with(
+        config: &EncryptionConfig,
+        create_key_file: bool,
+        passphrase: Option<&str>,
+    ) -> Result<Self> {
+        match config.key_source {
+            KeySource::KeyFile => {
+                let path = config.key_file_path()?;
+                if !path.exists() {
+                    if !create_key_file {
+                        return Err(SecretsError::KeyFileMissing(path).into());
+                    }
+                    write_key_file(&path, &generate_key())?;
+                    tracing::info!("Created config key file: {}", path.display());
+                }
+                Ok(Self::from_key(&read_key_file(&path)?))
+            }
+            KeySource::Passphrase => {
+                let passphrase = match passphrase {
+                    Some(passphrase) => passphrase.to_string(),
+                    None => std::env::var(PASSPHRASE_ENV)
+                        .map_err(|_| SecretsError::PassphraseRequired)?,
+                };
+                let salt = config.salt.as_deref()
+                    .context("Passphrase mode requires a salt in the encryption settings")?;
+                Self::from_passphrase(&passphrase, salt)
+            }
+        }
+    }
+
+    /// Encrypt a value into `enc:v1:<base64>`
+    pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt secret"))?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(payload)))
+    }
+
+    /// Decrypt an `enc:v1:<base64>` value
+    pub fn decrypt(&self, value: &str) -> Result<String> {
+        let encoded = value.strip_prefix(ENCRYPTED_PREFIX)
+            .context("Value is not encrypted")?;
+        let payload = BASE64.decode(encoded).context("Invalid encrypted value")?;
+        if payload.len() < NONCE_LEN {
+            anyhow::bail!("Encrypted value is truncated");
+        }
+
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let plaintext = self.cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| SecretsError::WrongKey)?;
+        String::from_utf8(plaintext).context("Decrypted secret is not valid UTF-8")
+    }
+
+    /// Encrypted check value for `EncryptionConfig::check`
+    pub fn check_value(&self) -> Result<String> {
+        self.encrypt(CHECK_PLAINTEXT)
+    }
+
+    /// Verify a check value written by `check_value`
+    pub fn verify_check(&self, check: &str) -> Result<()> {
+        match self.decrypt(check) {
+            Ok(plain) if plain == CHECK_PLAINTEXT => Ok(()),
+            _ => Err(SecretsError::WrongKey.into()),
+        }
+    }
+}
+
+/// Check whether a stored value is encrypted
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+/// Default key file: `CCP_KEY_FILE`, or the local data dir. The key must
+/// stay out of the config dir, which may be synced or backed up; on macOS
+/// both are `~/Library/Application Support`, so there the key goes to
+/// `~/.local/share` instead.
+pub fn default_key_file() -> Result<PathBuf> {
+    if let Ok(path) = std::env::var(KEY_FILE_ENV) {
+        return Ok(PathBuf::from(path));
+    }
+    let data_dir = key_data_dir()
+        .context("Failed to get local data directory")?;
+    let config_dir = dirs::config_dir()
+        .context("Failed to get config directory")?;
+    key_file_outside(&data_dir, &config_dir)
+}
+
+#[cfg(target_os = "macos")]
+fn key_data_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".local").join("share"))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn key_data_dir() -> Option<PathBuf> {
+    dirs::data_local_dir()
+}
+
+/// Key file under `data_dir`, refused when that is inside `config_dir`
+/// (e.g. `XDG_DATA_HOME` set below `XDG_CONFIG_HOME`)
+fn key_file_outside(data_dir: &Path, config_dir: &Path) -> Result<PathBuf> {
+    let path = data_dir.join("clash-chain-patcher").join("secret.key");
+    if path.starts_with(config_dir) {
+        anyhow::bail!(
+            "Default key file {} would be inside the config directory {}; set {} to a path outside it",
+            path.display(),
+            config_dir.display(),
+            KEY_FILE_ENV
+        );
+    }
+    Ok(path)
+}
+
+/// Generate a random 32-byte key
+pub fn generate_key() -> [u8; KEY_LEN] {
+    let mut key = [0u8; KEY_LEN];
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+/// Generate a random Argon2 salt, base64-encoded
+pub fn generate_salt() -> String {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    BASE64.encode(salt)
+}
+
+/// Read a key file (base64-encoded key)
+pub fn read_key_file(path: &Path) -> Result<[u8; KEY_LEN]> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read key file: {}", path.display()))?;
+    let bytes = BASE64.decode(content.trim())
+        .with_context(|| format!("Invalid key file: {}", path.display()))?;
+    bytes.try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid key length in {}", path.display()))
+}
+
+/// Write a key file readable only by the current user
+pub fn write_key_file(path: &Path, key: &[u8; KEY_LEN]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create key directory: {}", parent.display()))?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // mode() only applies to newly created files
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+                .with_context(|| format!("Failed to restrict key file: {}", path.display()))?;
+        }
+    }
+
+    use std::io::Write;
+    let mut file = options.open(path)
+        .with_context(|| format!("Failed to create key file: {}", path.display()))?;
+    file.write_all(BASE64.encode(key).as_bytes())
+        .with_context(|| format!("Failed to write key file: {}", path.display()))?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Apply `f` to every secret string field of a serialized `AppConfig`
+fn for_each_secret(root: &mut Value, mut f: impl FnMut(&mut String) -> Result<()>) -> Result<()> {
+    if let Some(proxies) = root.get_mut("upstream_proxies").and_then(Value::as_array_mut) {
+        for proxy in proxies {
+            if let Some(Value::String(s)) = proxy.pointer_mut("/config/password") {
+                f(s)?;
+            }
+        }
+    }
+    if let Some(Value::String(s)) = root.pointer_mut("/clash/api/secret") {
+        f(s)?;
+    }
+    Ok(())
+}
+
+/// Encrypt all secret fields in place
+///
+/// Already-encrypted values and secret references (`env:`, `file:`, `cmd:`)
+/// are kept as they are.
+pub fn encrypt_secrets(root: &mut Value, cipher: &SecretCipher) -> Result<()> {
+    for_each_secret(root, |s| {
+        if !s.is_empty() && !is_encrypted(s) && !is_secret_ref(s) {
+            *s = cipher.encrypt(s)?;
+        }
+        Ok(())
+    })
+}
+
+/// Decrypt all secret fields in place
+///
+/// Returns the number of plaintext secrets found (which need migrating).
+/// References are never encrypted, so decrypted values that look like one
+/// are literals and get escaped (see [`escape_literal`]). Other decrypted
+/// values are already stored escaped. In a `legacy` config (written before
+/// references existed) every plaintext value is a literal.
+pub fn decrypt_secrets(root: &mut Value, cipher: &SecretCipher, legacy: bool) -> Result<usize> {
+    let mut plaintext = 0;
+    for_each_secret(root, |s| {
+        if is_encrypted(s) {
+            let plain = cipher.decrypt(s)?;
+            *s = if is_secret_ref(&plain) { escape_literal(&plain) } else { plain };
+        } else if legacy && needs_escape(s) {
+            *s = escape_literal(s);
+            plaintext += 1;
+        } else if !s.is_empty() && !is_secret_ref(s) {
+            plaintext += 1;
+        }
+        Ok(())
+    })?;
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn sample_config() -> Value {
+        json!({
+            "upstream_proxies": [
+                { "id": "a", "config": { "host": "1.2.3.4", "port": 1080, "username": "u", "password": "secret" } },
+                { "id": "b", "config": { "host": "5.6.7.8", "port": 1080, "username": null, "password": null } }
+            ],
+            "clash": { "api": { "secret": "api-token" } }
+        })
+    }
+
+    #[test]
+    fn test_encrypt_roundtrip() {
+        let cipher = SecretCipher::from_key(&generate_key());
+        let encrypted = cipher.encrypt("p@ss:word").unwrap();
+
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("p@ss"));
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), "p@ss:word");
+        // Fresh nonce per value
+        assert_ne!(cipher.encrypt("p@ss:word").unwrap(), encrypted);
+    }
+
+    #[test]
+    fn test_wrong_key_rejected() {
+        let cipher = SecretCipher::from_key(&generate_key());
+        let other = SecretCipher::from_key(&generate_key());
+
+        let check = cipher.check_value().unwrap();
+        assert!(cipher.verify_check(&check).is_ok());
+        assert!(other.verify_check(&check).is_err());
+        assert!(other.decrypt(&cipher.encrypt("x").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_passphrase_key_derivation() {
+        let salt = generate_salt();
+        let a = SecretCipher::from_passphrase("correct horse", &salt).unwrap();
+        let b = SecretCipher::from_passphrase("correct horse", &salt).unwrap();
+        let c = SecretCipher::from_passphrase("battery staple", &salt).unwrap();
+
+        let encrypted = a.encrypt("secret").unwrap();
+        assert_eq!(b.decrypt(&encrypted).unwrap(), "secret");
+        assert!(c.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_encrypt_and_decrypt_config_fields() {
+        let cipher = SecretCipher::from_key(&generate_key());
+        let mut value = sample_config();
+
+        assert_eq!(decrypt_secrets(&mut value.clone(), &cipher, false).unwrap(), 2);
+
+        encrypt_secrets(&mut value, &cipher).unwrap();
+        let password = value["upstream_proxies"][0]["config"]["password"].as_str().unwrap();
+        assert!(is_encrypted(password));
+        assert!(!value.to_string().contains("api-token"));
+        assert!(value["upstream_proxies"][1]["config"]["password"].is_null());
+
+        assert_eq!(decrypt_secrets(&mut value, &cipher, false).unwrap(), 0);
+        assert_eq!(value, sample_config());
+    }
+
+    #[test]
+    fn test_secret_references_left_readable() {
+        let cipher = SecretCipher::from_key(&generate_key());
+        let mut value = sample_config();
+        value["upstream_proxies"][0]["config"]["password"] = json!("env:VENDOR_PASS");
+
+        encrypt_secrets(&mut value, &cipher).unwrap();
+        assert_eq!(value["upstream_proxies"][0]["config"]["password"], "env:VENDOR_PASS");
+        assert_eq!(decrypt_secrets(&mut value, &cipher, false).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_literals_that_look_like_references_are_escaped() {
+        let cipher = SecretCipher::from_key(&generate_key());
+        let mut value = sample_config();
+        value["upstream_proxies"][0]["config"]["password"] = json!(cipher.encrypt("file:abc").unwrap());
+        decrypt_secrets(&mut value, &cipher, false).unwrap();
+        assert_eq!(value["upstream_proxies"][0]["config"]["password"], "literal:file:abc");
+
+        let mut legacy = sample_config();
+        legacy["upstream_proxies"][0]["config"]["password"] = json!("cmd:abc");
+        assert_eq!(decrypt_secrets(&mut legacy, &cipher, true).unwrap(), 2);
+        assert_eq!(legacy["upstream_proxies"][0]["config"]["password"], "literal:cmd:abc");
+
+        // Escaped values survive an encrypt/decrypt round trip unchanged
+        let mut value = sample_config();
+        value["upstream_proxies"][0]["config"]["password"] = json!("literal:literal:abc");
+        encrypt_secrets(&mut value, &cipher).unwrap();
+        decrypt_secrets(&mut value, &cipher, false).unwrap();
+        assert_eq!(value["upstream_proxies"][0]["config"]["password"], "literal:literal:abc");
+
+        let mut legacy = sample_config();
+        legacy["upstream_proxies"][0]["config"]["password"] = json!("literal:abc");
+        decrypt_secrets(&mut legacy, &cipher, true).unwrap();
+        assert_eq!(legacy["upstream_proxies"][0]["config"]["password"], "literal:literal:abc");
+    }
+
+    #[test]
+    fn test_key_file_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("keys").join("secret.key");
+
+        let key = generate_key();
+        write_key_file(&path, &key).unwrap();
+        assert_eq!(read_key_file(&path).unwrap(), key);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_default_key_file_outside_config_dir() {
+        let home = Path::new("/home/u");
+        let path = key_file_outside(&home.join(".local/share"), &home.join(".config")).unwrap();
+        assert_eq!(path, home.join(".local/share/clash-chain-patcher/secret.key"));
+
+        let support = home.join("Library/Application Support");
+        assert!(key_file_outside(&support, &support).is_err());
+        assert!(key_file_outside(&home.join(".config/data"), &home.join(".config")).is_err());
+    }
+
+    #[test]
+    fn test_resolve_missing_key_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = EncryptionConfig {
+            key_file: Some(temp_dir.path().join("secret.key").display().to_string()),
+            ..EncryptionConfig::default()
+        };
+
+        assert!(SecretCipher::resolve(&config, false).is_err());
+        assert!(SecretCipher::resolve(&config, true).is_ok());
+        assert!(SecretCipher::resolve(&config, false).is_ok());
+    }
+}
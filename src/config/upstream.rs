@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use std::time::SystemTime;
 use uuid::Uuid;
 
+use crate::config::secret_ref::is_secret_ref;
 use crate::proxy::config::UpstreamConfig;
 
 /// Upstream proxy configuration
@@ -21,6 +22,10 @@ pub struct UpstreamProxy {
 
     /// Health status
     pub health: ProxyHealth,
+
+    /// Free-form labels used to select subsets of the pool (e.g. "us", "residential")
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 impl UpstreamProxy {
@@ -32,7 +37,24 @@ impl UpstreamProxy {
             enabled: true,
             config,
             health: ProxyHealth::default(),
+            tags: Vec::new(),
+        }
+    }
+
+    /// Copy with the password replaced by `***` (for display and JSON output)
+    ///
+    /// Secret references (`env:`, `file:`, `cmd:`) are shown as-is.
+    pub fn redacted(&self) -> Self {
+        let mut proxy = self.clone();
+        if proxy.config.password.as_deref().is_some_and(|p| !p.is_empty() && !is_secret_ref(p)) {
+            proxy.config.password = Some("***".to_string());
         }
+        proxy
+    }
+
+    /// Check whether the proxy carries a tag (case-insensitive)
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
     }
 
     /// Create from a proxy string
@@ -77,6 +99,14 @@ pub struct ProxyHealth {
     /// Country code (e.g., "US", "HK")
     #[serde(skip_serializing_if = "Option::is_none")]
     pub country_code: Option<String>,
+
+    /// Total number of completed checks
+    #[serde(default)]
+    pub total_checks: u32,
+
+    /// Total number of failed checks
+    #[serde(default)]
+    pub total_failures: u32,
 }
 
 impl Default for ProxyHealth {
@@ -90,6 +120,8 @@ impl Default for ProxyHealth {
             exit_ip: None,
             location: None,
             country_code: None,
+            total_checks: 0,
+            total_failures: 0,
         }
     }
 }
@@ -102,6 +134,7 @@ impl ProxyHealth {
         self.last_check = Some(SystemTime::now());
         self.error = None;
         self.consecutive_failures = 0;
+        self.total_checks = self.total_checks.saturating_add(1);
     }
 
     /// Mark as healthy with detailed information
@@ -125,6 +158,8 @@ impl ProxyHealth {
         self.last_check = Some(SystemTime::now());
         self.error = Some(error);
         self.consecutive_failures += 1;
+        self.total_checks = self.total_checks.saturating_add(1);
+        self.total_failures = self.total_failures.saturating_add(1);
     }
 
     /// Mark as checking
@@ -137,6 +172,15 @@ impl ProxyHealth {
     pub fn is_healthy(&self) -> bool {
         matches!(self.status, HealthStatus::Healthy)
     }
+
+    /// Fraction of checks that failed (0.0 when never checked)
+    pub fn failure_rate(&self) -> f64 {
+        if self.total_checks == 0 {
+            0.0
+        } else {
+            self.total_failures as f64 / self.total_checks as f64
+        }
+    }
 }
 
 /// Health status enumeration
@@ -262,6 +306,48 @@ mod tests {
         // Second failure
         health.mark_unhealthy("Still failing".to_string());
         assert_eq!(health.consecutive_failures, 2);
+
+        // Totals survive recovery
+        health.mark_healthy(90);
+        assert_eq!(health.total_checks, 4);
+        assert_eq!(health.total_failures, 2);
+        assert_eq!(health.failure_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_redacted_masks_password() {
+        let proxy = UpstreamProxy::from_proxy_string(
+            "HK Proxy".to_string(),
+            "64.32.179.160:60088:user:secret"
+        ).unwrap();
+
+        let redacted = proxy.redacted();
+        assert_eq!(redacted.config.username.as_deref(), Some("user"));
+        assert_eq!(redacted.config.password.as_deref(), Some("***"));
+        assert!(!serde_json::to_string(&redacted).unwrap().contains("secret"));
+        assert_eq!(proxy.config.password.as_deref(), Some("secret"));
+
+        let mut proxy = proxy;
+        proxy.config.password = Some("env:VENDOR_PASS".to_string());
+        assert_eq!(proxy.redacted().config.password.as_deref(), Some("env:VENDOR_PASS"));
+    }
+
+    #[test]
+    fn test_tags_default_for_legacy_entries() {
+        let json = r#"{
+            "id": "p1", "name": "Legacy", "enabled": true,
+            "config": { "host": "127.0.0.1", "port": 1080, "username": null, "password": null },
+            "health": { "status": "unknown", "latency_ms": null, "last_check": null,
+                        "error": null, "consecutive_failures": 0 }
+        }"#;
+
+        let mut proxy: UpstreamProxy = serde_json::from_str(json).unwrap();
+        assert!(proxy.tags.is_empty());
+        assert_eq!(proxy.health.total_checks, 0);
+
+        proxy.tags.push("US".to_string());
+        assert!(proxy.has_tag("us"));
+        assert!(!proxy.has_tag("hk"));
     }
 
     #[test]
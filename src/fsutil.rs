@@ -0,0 +1,173 @@
+//! Crash-safe file writes
+//!
+//! Clash re-reads its profile whenever it changes, so a config must never be
+//! observable half-written. [`write_atomic`] writes to a temp file in the
+//! same directory, fsyncs it, copies the original mode and ownership, and
+//! renames it over the target.
+
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::{info, warn};
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Atomically replace (or create) `path` with `content`
+///
+/// A symlinked path is resolved first and the link target is replaced, so
+/// the link itself stays in place (e.g. a profile symlinked into a dotfiles
+/// repo). Dangling symlinks are an error rather than silently replaced.
+pub fn write_atomic(path: &Path, content: impl AsRef<[u8]>) -> Result<()> {
+    let target = resolve_symlink(path)?;
+    let dir = match target.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = target
+        .file_name()
+        .with_context(|| format!("Not a file path: {}", target.display()))?
+        .to_string_lossy()
+        .to_string();
+    let original = fs::metadata(&target).ok();
+
+    let (tmp_path, mut file) = create_temp(&dir, &file_name)?;
+    let result = (|| -> Result<()> {
+        file.write_all(content.as_ref())
+            .with_context(|| format!("Failed to write: {}", tmp_path.display()))?;
+        if let Some(ref meta) = original {
+            copy_permissions(&tmp_path, meta)?;
+        }
+        file.sync_all()
+            .with_context(|| format!("Failed to sync: {}", tmp_path.display()))?;
+        drop(file);
+        fs::rename(&tmp_path, &target)
+            .with_context(|| format!("Failed to replace: {}", target.display()))
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return result;
+    }
+
+    sync_dir(&dir);
+    Ok(())
+}
+
+/// Follow a symlinked path to the file it points at
+fn resolve_symlink(path: &Path) -> Result<PathBuf> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => {
+            let target = fs::canonicalize(path).with_context(|| {
+                format!("Config path is a dangling symlink: {}", path.display())
+            })?;
+            info!("Writing through symlink {} -> {}", path.display(), target.display());
+            Ok(target)
+        }
+        _ => Ok(path.to_path_buf()),
+    }
+}
+
+/// Create a uniquely named hidden temp file next to the target
+fn create_temp(dir: &Path, file_name: &str) -> Result<(PathBuf, File)> {
+    loop {
+        let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tmp_path = dir.join(format!(".{}.{}.{}.ccp-tmp", file_name, std::process::id(), n));
+        match OpenOptions::new().write(true).create_new(true).open(&tmp_path) {
+            Ok(file) => return Ok((tmp_path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to create temp file in {}", dir.display())
+                })
+            }
+        }
+    }
+}
+
+/// Give the temp file the original file's mode and (on Unix) owner
+fn copy_permissions(tmp_path: &Path, original: &fs::Metadata) -> Result<()> {
+    fs::set_permissions(tmp_path, original.permissions())
+        .with_context(|| format!("Failed to set permissions on {}", tmp_path.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // Only root can give a file away; other users keep their own uid
+        if let Err(e) = std::os::unix::fs::chown(tmp_path, Some(original.uid()), Some(original.gid())) {
+            warn!("Could not preserve ownership of {}: {}", tmp_path.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// Persist the rename itself (best effort; not supported everywhere)
+fn sync_dir(dir: &Path) {
+    #[cfg(unix)]
+    if let Ok(d) = File::open(dir) {
+        let _ = d.sync_all();
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn temp_files(dir: &Path) -> usize {
+        fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".ccp-tmp"))
+            .count()
+    }
+
+    #[test]
+    fn test_write_creates_and_replaces() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.yaml");
+
+        write_atomic(&path, "a: 1\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a: 1\n");
+        write_atomic(&path, "a: 2\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a: 2\n");
+        assert_eq!(temp_files(temp_dir.path()), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_preserves_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.yaml");
+        fs::write(&path, "a: 1\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        write_atomic(&path, "a: 2\n").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o640);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_through_symlink() {
+        let temp_dir = TempDir::new().unwrap();
+        let real = temp_dir.path().join("real.yaml");
+        let link = temp_dir.path().join("config.yaml");
+        fs::write(&real, "a: 1\n").unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        write_atomic(&link, "a: 2\n").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&real).unwrap(), "a: 2\n");
+
+        let dangling = temp_dir.path().join("dangling.yaml");
+        std::os::unix::fs::symlink(temp_dir.path().join("missing.yaml"), &dangling).unwrap();
+        assert!(write_atomic(&dangling, "a: 3\n").is_err());
+        assert_eq!(temp_files(temp_dir.path()), 0);
+    }
+}
@@ -105,8 +105,18 @@ impl HealthChecker {
 
         let start = Instant::now();
 
+        // Resolve env:/file:/cmd: credential references
+        let config = match proxy.config.resolved() {
+            Ok(config) => config,
+            Err(e) => {
+                let error_msg = format!("Credential lookup failed: {:#}", e);
+                warn!("{}", error_msg);
+                return HealthCheckResult::unhealthy(error_msg);
+            }
+        };
+
         // Step 1: Test SOCKS5 connection
-        match self.test_socks5_connection(&proxy.config).await {
+        match self.test_socks5_connection(&config).await {
             Ok(_) => {
                 debug!("SOCKS5 connection test passed for {}", proxy.name);
             }
@@ -118,7 +128,7 @@ impl HealthChecker {
         }
 
         // Step 2: Test HTTP request through proxy
-        match self.test_http_request(&proxy.config).await {
+        match self.test_http_request(&config).await {
             Ok(_) => {
                 let latency = start.elapsed().as_millis() as u64;
                 info!("Health check passed for {} (latency: {}ms)", proxy.name, latency);
@@ -1,7 +1,9 @@
 //! Health checking module for upstream proxies
 
 pub mod checker;
+pub mod selector;
 pub mod validator;
 
 pub use checker::{HealthCheckConfig, HealthChecker, HealthCheckResult};
+pub use selector::UpstreamSelector;
 pub use validator::{LocationInfo, ProxyValidationResult, ProxyValidator};
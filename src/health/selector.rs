@@ -0,0 +1,172 @@
+//! Upstream selection by recent health
+//!
+//! Ranks healthy pool entries by latency, long-run failure rate and how
+//! recently they were checked. Lower scores are better.
+
+use std::time::{Duration, SystemTime};
+
+use crate::config::UpstreamProxy;
+
+/// Latency assumed for healthy proxies that have no measurement yet (ms)
+const UNKNOWN_LATENCY_MS: f64 = 2000.0;
+
+/// Penalty added to a proxy whose last check is older than `stale_after` (ms-equivalent)
+const STALE_PENALTY_MS: f64 = 1000.0;
+
+/// Picks the best upstream from the pool
+#[derive(Debug, Clone)]
+pub struct UpstreamSelector {
+    tag: Option<String>,
+    stale_after: Duration,
+}
+
+impl UpstreamSelector {
+    pub fn new() -> Self {
+        Self {
+            tag: None,
+            stale_after: Duration::from_secs(600),
+        }
+    }
+
+    /// Only consider proxies carrying this tag
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Age after which a check result counts as fully stale
+    pub fn stale_after(mut self, duration: Duration) -> Self {
+        self.stale_after = duration;
+        self
+    }
+
+    /// Enabled proxies matching the tag filter, in pool order
+    pub fn candidates<'a>(&self, pool: &'a [UpstreamProxy]) -> Vec<&'a UpstreamProxy> {
+        pool.iter()
+            .filter(|p| p.enabled)
+            .filter(|p| self.tag.as_deref().is_none_or(|t| p.has_tag(t)))
+            .collect()
+    }
+
+    /// Whether the proxy's last check is `stale_after` or older at `now`
+    pub fn is_stale(&self, proxy: &UpstreamProxy, now: SystemTime) -> bool {
+        proxy.health.last_check
+            .is_none_or(|checked| now.duration_since(checked).unwrap_or_default() >= self.stale_after)
+    }
+
+    /// Score a proxy at `now` (lower is better)
+    pub fn score(&self, proxy: &UpstreamProxy, now: SystemTime) -> f64 {
+        let health = &proxy.health;
+        let latency = health.latency_ms.map(|l| l as f64).unwrap_or(UNKNOWN_LATENCY_MS);
+
+        // Staleness ramps linearly from 0 (just checked) to 1 (stale_after or older)
+        let staleness = match health.last_check {
+            Some(checked) => {
+                let age = now.duration_since(checked).unwrap_or_default();
+                (age.as_secs_f64() / self.stale_after.as_secs_f64().max(1.0)).min(1.0)
+            }
+            None => 1.0,
+        };
+
+        latency * (1.0 + 4.0 * health.failure_rate()) + STALE_PENALTY_MS * staleness
+    }
+
+    /// Healthy candidates sorted best first, with their scores
+    pub fn rank<'a>(&self, pool: &'a [UpstreamProxy]) -> Vec<(&'a UpstreamProxy, f64)> {
+        let now = SystemTime::now();
+        let mut ranked: Vec<_> = self
+            .candidates(pool)
+            .into_iter()
+            .filter(|p| p.health.is_healthy())
+            .map(|p| (p, self.score(p, now)))
+            .collect();
+        ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+        ranked
+    }
+
+    /// Best healthy candidate, if any
+    pub fn select<'a>(&self, pool: &'a [UpstreamProxy]) -> Option<&'a UpstreamProxy> {
+        self.rank(pool).first().map(|(p, _)| *p)
+    }
+}
+
+impl Default for UpstreamSelector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::config::UpstreamConfig;
+
+    fn proxy(name: &str, latency: Option<u64>, tags: &[&str]) -> UpstreamProxy {
+        let config = UpstreamConfig {
+            host: "127.0.0.1".to_string(),
+            port: 1080,
+            username: None,
+            password: None,
+        };
+        let mut p = UpstreamProxy::new(name.to_string(), config);
+        if let Some(l) = latency {
+            p.health.mark_healthy(l);
+        }
+        p.tags = tags.iter().map(|t| t.to_string()).collect();
+        p
+    }
+
+    #[test]
+    fn test_select_lowest_latency() {
+        let pool = vec![proxy("slow", Some(300), &[]), proxy("fast", Some(80), &[])];
+        let best = UpstreamSelector::new().select(&pool).unwrap();
+        assert_eq!(best.name, "fast");
+    }
+
+    #[test]
+    fn test_select_skips_unhealthy_and_disabled() {
+        let mut disabled = proxy("disabled", Some(10), &[]);
+        disabled.enabled = false;
+        let mut failing = proxy("failing", Some(20), &[]);
+        failing.health.mark_unhealthy("timeout".to_string());
+        let pool = vec![disabled, failing, proxy("ok", Some(500), &[]), proxy("unchecked", None, &[])];
+
+        let best = UpstreamSelector::new().select(&pool).unwrap();
+        assert_eq!(best.name, "ok");
+    }
+
+    #[test]
+    fn test_failure_rate_penalty() {
+        let mut flaky = proxy("flaky", Some(100), &[]);
+        for _ in 0..3 {
+            flaky.health.mark_unhealthy("timeout".to_string());
+        }
+        flaky.health.mark_healthy(100);
+        let pool = vec![flaky, proxy("steady", Some(180), &[])];
+
+        let best = UpstreamSelector::new().select(&pool).unwrap();
+        assert_eq!(best.name, "steady");
+    }
+
+    #[test]
+    fn test_stale_check_penalty() {
+        let mut stale = proxy("stale", Some(100), &[]);
+        stale.health.last_check = Some(SystemTime::now() - Duration::from_secs(3600));
+        let pool = vec![stale, proxy("fresh", Some(400), &[])];
+
+        let best = UpstreamSelector::new().select(&pool).unwrap();
+        assert_eq!(best.name, "fresh");
+        let selector = UpstreamSelector::new();
+        assert!(selector.is_stale(&pool[0], SystemTime::now()));
+        assert!(!selector.is_stale(&pool[1], SystemTime::now()));
+    }
+
+    #[test]
+    fn test_tag_filter() {
+        let pool = vec![proxy("hk", Some(50), &["HK"]), proxy("us", Some(200), &["us"])];
+
+        let best = UpstreamSelector::new().tag("US").select(&pool).unwrap();
+        assert_eq!(best.name, "us");
+        assert!(UpstreamSelector::new().tag("jp").select(&pool).is_none());
+    }
+}
@@ -9,6 +9,8 @@ use std::io::{Read, Write};
 use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::time::{Duration, Instant};
 
+use crate::config::secret_ref;
+
 /// Proxy validation result with detailed information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProxyValidationResult {
@@ -74,6 +76,8 @@ impl ProxyValidator {
     /// * `username` - Optional username for authentication
     /// * `password` - Optional password for authentication
     ///
+    /// Credentials may be `env:` / `file:` / `cmd:` secret references.
+    ///
     /// # Returns
     /// Detailed validation result including exit IP and geolocation
     pub fn validate(
@@ -85,6 +89,23 @@ impl ProxyValidator {
     ) -> ProxyValidationResult {
         let proxy_str = format!("{}:{}", host, port);
 
+        let credentials = secret_ref::resolve_optional(username)
+            .and_then(|u| Ok((u, secret_ref::resolve_optional(password)?)));
+        let (username, password) = match credentials {
+            Ok(credentials) => credentials,
+            Err(e) => {
+                return ProxyValidationResult {
+                    proxy: proxy_str,
+                    is_valid: false,
+                    error: Some(format!("Credential lookup failed: {:#}", e)),
+                    exit_ip: None,
+                    location: None,
+                    latency_ms: None,
+                }
+            }
+        };
+        let (username, password) = (username.as_deref(), password.as_deref());
+
         // Step 1: Test SOCKS5 connection
         let start = Instant::now();
         match self.test_connection(host, port, username, password) {
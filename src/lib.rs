@@ -9,13 +9,21 @@
 //! 6. Configuration merging for Clash configs (merger module)
 //! 7. Bridge layer for GUI integration (bridge module)
 //! 8. Application state management (state module)
+//! 9. Scheduled exit rotation (rotation module)
+//! 10. Atomic config file writes (fsutil module)
+//! 11. Versioned config backups (backup module)
+//! 12. Format-preserving YAML editing (yaml module)
 
 // Re-export commonly used modules
+pub mod backup;
 pub mod bridge;
 pub mod config;
+pub mod fsutil;
 pub mod health;
 pub mod merger;
 pub mod patcher;
 pub mod proxy;
+pub mod rotation;
 pub mod state;
 pub mod watcher;
+pub mod yaml;
@@ -0,0 +1,221 @@
+//! Automatic chain groups
+//!
+//! Next to `Chain-Selector` the merger writes groups that pick a chain clone
+//! on their own: by default `Chain-Auto`, a `url-test` group. Each
+//! [`AutoGroup`] describes one such group; the list may also be empty.
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// Clash group type of an automatic group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoGroupType {
+    UrlTest,
+    Fallback,
+    LoadBalance,
+}
+
+impl AutoGroupType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UrlTest => "url-test",
+            Self::Fallback => "fallback",
+            Self::LoadBalance => "load-balance",
+        }
+    }
+}
+
+impl fmt::Display for AutoGroupType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AutoGroupType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "url-test" => Ok(Self::UrlTest),
+            "fallback" => Ok(Self::Fallback),
+            "load-balance" => Ok(Self::LoadBalance),
+            other => Err(format!("unknown group type '{}' (expected url-test, fallback or load-balance)", other)),
+        }
+    }
+}
+
+/// How a `load-balance` group spreads connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalanceStrategy {
+    ConsistentHashing,
+    RoundRobin,
+}
+
+impl BalanceStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ConsistentHashing => "consistent-hashing",
+            Self::RoundRobin => "round-robin",
+        }
+    }
+}
+
+impl fmt::Display for BalanceStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for BalanceStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "consistent-hashing" => Ok(Self::ConsistentHashing),
+            "round-robin" => Ok(Self::RoundRobin),
+            other => Err(format!("unknown strategy '{}' (expected consistent-hashing or round-robin)", other)),
+        }
+    }
+}
+
+/// One automatic group over all chain clones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoGroup {
+    pub name: String,
+    pub kind: AutoGroupType,
+    /// Health check URL
+    pub url: String,
+    /// Health check interval (seconds)
+    pub interval: u64,
+    /// Only switch when the new node is this much faster (ms, url-test only)
+    pub tolerance: Option<u32>,
+    /// Skip health checks while the group is unused
+    pub lazy: Option<bool>,
+    /// Health check timeout (ms)
+    pub timeout: Option<u32>,
+    /// Load-balance strategy (load-balance only)
+    pub strategy: Option<BalanceStrategy>,
+}
+
+impl Default for AutoGroup {
+    fn default() -> Self {
+        Self {
+            name: "Chain-Auto".to_string(),
+            kind: AutoGroupType::UrlTest,
+            url: "http://www.gstatic.com/generate_204".to_string(),
+            interval: 300,
+            tolerance: Some(50),
+            lazy: None,
+            timeout: None,
+            strategy: None,
+        }
+    }
+}
+
+impl AutoGroup {
+    /// Group of `kind` named `name` with the default health check
+    pub fn new(name: &str, kind: AutoGroupType) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            tolerance: (kind == AutoGroupType::UrlTest).then_some(50),
+            strategy: (kind == AutoGroupType::LoadBalance).then_some(BalanceStrategy::ConsistentHashing),
+            ..Self::default()
+        }
+    }
+
+    /// Set one parameter from its Clash key (`url`, `interval`, `tolerance`,
+    /// `lazy`, `timeout` or `strategy`)
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let value = value.trim();
+        let invalid = || anyhow::anyhow!("Invalid {} '{}' for group '{}'", key, value, self.name);
+        match key.trim() {
+            "url" => self.url = value.to_string(),
+            "interval" => self.interval = value.parse().map_err(|_| invalid())?,
+            "tolerance" => self.tolerance = Some(value.parse().map_err(|_| invalid())?),
+            "lazy" => self.lazy = Some(value.parse().map_err(|_| invalid())?),
+            "timeout" => self.timeout = Some(value.parse().map_err(|_| invalid())?),
+            "strategy" => self.strategy = Some(value.parse().map_err(|e: String| anyhow::anyhow!(e))?),
+            other => anyhow::bail!("Unknown group option '{}' (expected url, interval, tolerance, lazy, timeout or strategy)", other),
+        }
+        Ok(())
+    }
+
+    /// Parse `NAME:TYPE[,key=value...]`, e.g.
+    /// `Chain-LB:load-balance,strategy=round-robin,interval=600`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (name, rest) = spec.split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Expected NAME:TYPE[,key=value...], got '{}'", spec))?;
+        let mut parts = rest.split(',');
+        let kind = parts.next().unwrap_or_default().parse().map_err(|e: String| anyhow::anyhow!(e))?;
+        let mut group = Self::new(name.trim(), kind);
+        for option in parts.filter(|p| !p.trim().is_empty()) {
+            let (key, value) = option.split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Expected key=value, got '{}'", option.trim()))?;
+            group.set(key, value)?;
+        }
+        group.validate()?;
+        Ok(group)
+    }
+
+    /// Reject parameters that do not apply to the group type
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            anyhow::bail!("Auto group name must not be empty");
+        }
+        if self.tolerance.is_some() && self.kind != AutoGroupType::UrlTest {
+            anyhow::bail!("'{}': tolerance only applies to url-test groups", self.name);
+        }
+        if self.strategy.is_some() && self.kind != AutoGroupType::LoadBalance {
+            anyhow::bail!("'{}': strategy only applies to load-balance groups", self.name);
+        }
+        Ok(())
+    }
+
+    /// Keys after `name`, `type` and `proxies`, in Clash's order
+    pub fn options(&self) -> Vec<(&'static str, Value)> {
+        let mut options = vec![("url", json!(self.url)), ("interval", json!(self.interval))];
+        if let Some(tolerance) = self.tolerance {
+            options.push(("tolerance", json!(tolerance)));
+        }
+        if let Some(strategy) = self.strategy {
+            options.push(("strategy", json!(strategy.as_str())));
+        }
+        if let Some(lazy) = self.lazy {
+            options.push(("lazy", json!(lazy)));
+        }
+        if let Some(timeout) = self.timeout {
+            options.push(("timeout", json!(timeout)));
+        }
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_group_spec() {
+        let group = AutoGroup::parse("Chain-LB:load-balance,strategy=round-robin,interval=600,lazy=true").unwrap();
+        assert_eq!(group.kind, AutoGroupType::LoadBalance);
+        assert_eq!(group.strategy, Some(BalanceStrategy::RoundRobin));
+        assert_eq!(group.interval, 600);
+        assert_eq!(group.tolerance, None);
+        assert_eq!(
+            group.options(),
+            vec![("url", json!("http://www.gstatic.com/generate_204")), ("interval", json!(600)),
+                 ("strategy", json!("round-robin")), ("lazy", json!(true))]
+        );
+
+        let fallback = AutoGroup::parse("Chain-Fallback:fallback,timeout=2000").unwrap();
+        assert_eq!(fallback.timeout, Some(2000));
+
+        assert!(AutoGroup::parse("Chain-X").is_err());
+        assert!(AutoGroup::parse("Chain-X:relay").is_err());
+        assert!(AutoGroup::parse("Chain-X:fallback,tolerance=50").is_err());
+        assert!(AutoGroup::parse("Chain-X:url-test,interval=soon").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! Managed blocks: comment markers around generated content
+//!
+//! Everything the patcher adds to a Clash config (chain proxies, chain
+//! groups, references to them, custom rules) is wrapped in
+//!
+//! ```yaml
+//!   # >>> clash-chain-patcher
+//!   - { name: HK-Chain, ... }
+//!   # <<< clash-chain-patcher
+//! ```
+//!
+//! so a re-apply can replace exactly that content and unapply can remove it
+//! without guessing from names. Sections the patcher had to add get a
+//! [`CREATED_MARKER`] comment on their header, so unapply can drop them too.
+
+use anyhow::Result;
+
+/// Opening marker of a managed block
+pub const BLOCK_BEGIN: &str = "# >>> clash-chain-patcher";
+
+/// Closing marker of a managed block
+pub const BLOCK_END: &str = "# <<< clash-chain-patcher";
+
+/// Comment on the header of a section the patcher added (`proxies: # ccp: created`)
+pub const CREATED_MARKER: &str = "# ccp: created";
+
+/// Whether a section header carries [`CREATED_MARKER`]
+pub fn is_created(header: &str) -> bool {
+    header.trim_end().ends_with(CREATED_MARKER)
+}
+
+/// A section header without its [`CREATED_MARKER`]
+pub fn strip_created(header: &str) -> String {
+    match header.trim_end().strip_suffix(CREATED_MARKER) {
+        Some(rest) => rest.trim_end().to_string(),
+        None => header.to_string(),
+    }
+}
+
+/// Whether a line opens a managed block
+pub fn is_begin(line: &str) -> bool {
+    line.trim_start().starts_with(BLOCK_BEGIN)
+}
+
+/// Whether a line closes a managed block
+pub fn is_end(line: &str) -> bool {
+    line.trim_start().starts_with(BLOCK_END)
+}
+
+/// Whether the text contains any managed block
+pub fn has_blocks(content: &str) -> bool {
+    content.lines().any(is_begin)
+}
+
+/// Wrap generated lines in block markers indented by `indent`
+pub fn wrap(indent: &str, lines: Vec<String>) -> Vec<String> {
+    let mut out = Vec::with_capacity(lines.len() + 2);
+    out.push(format!("{}{}", indent, BLOCK_BEGIN));
+    out.extend(lines);
+    out.push(format!("{}{}", indent, BLOCK_END));
+    out
+}
+
+/// Split `lines` into content outside managed blocks and content inside them
+///
+/// With `indent` set, only blocks whose markers sit at exactly that indent
+/// are taken (e.g. top-level entries of a section, not references nested in
+/// one of its entries). Returns `(kept, removed)`; markers are dropped.
+pub fn take_blocks(lines: &[String], indent: Option<&str>) -> Result<(Vec<String>, Vec<String>)> {
+    let matches_indent = |line: &str| match indent {
+        Some(indent) => line.len() - line.trim_start().len() == indent.len(),
+        None => true,
+    };
+
+    let mut kept = Vec::with_capacity(lines.len());
+    let mut removed = Vec::new();
+    let mut open: Option<usize> = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        match open {
+            None if is_begin(line) && matches_indent(line) => open = Some(i),
+            None if is_end(line) && matches_indent(line) => {
+                anyhow::bail!("Unmatched '{}' marker: {}", BLOCK_END, line.trim());
+            }
+            None => kept.push(line.clone()),
+            Some(_) if is_end(line) && matches_indent(line) => open = None,
+            Some(_) if is_begin(line) && matches_indent(line) => {
+                anyhow::bail!("Nested '{}' marker: {}", BLOCK_BEGIN, line.trim());
+            }
+            Some(_) => removed.push(line.clone()),
+        }
+    }
+
+    if open.is_some() {
+        // Never guess where generated content ends
+        anyhow::bail!(
+            "'{}' without a matching '{}'; fix the config by hand",
+            BLOCK_BEGIN, BLOCK_END
+        );
+    }
+    Ok((kept, removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_wrap_and_take_round_trip() {
+        let original = lines("  - a\n  - b");
+        let mut patched = original.clone();
+        patched.extend(wrap("  ", vec!["  - a-Chain".to_string()]));
+
+        let (kept, removed) = take_blocks(&patched, None).unwrap();
+        assert_eq!(kept, original);
+        assert_eq!(removed, vec!["  - a-Chain".to_string()]);
+    }
+
+    #[test]
+    fn test_take_blocks_at_indent() {
+        let text = lines(
+            "  # >>> clash-chain-patcher\n  - Chain-Selector\n  # <<< clash-chain-patcher\n\
+             \x20 - name: Proxy\n    proxies:\n      # >>> clash-chain-patcher\n      - Chain-Selector\n      # <<< clash-chain-patcher\n      - HK",
+        );
+        let (kept, removed) = take_blocks(&text, Some("  ")).unwrap();
+        assert_eq!(removed, vec!["  - Chain-Selector".to_string()]);
+        assert_eq!(kept.len(), 6);
+        assert!(kept.iter().any(|l| is_begin(l)));
+    }
+
+    #[test]
+    fn test_unterminated_block_is_an_error() {
+        let text = lines("  # >>> clash-chain-patcher\n  - a\n  - b");
+        assert!(take_blocks(&text, None).is_err());
+        assert!(take_blocks(&lines("  # <<< clash-chain-patcher"), None).is_err());
+    }
+}
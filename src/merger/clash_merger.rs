@@ -5,20 +5,51 @@
 //!
 //! Implementation: clones each proxy node and adds the `dialer-proxy` field
 //! pointing at the local SOCKS5 hop. The cloned nodes are named `<name>-Chain`
-//! and referenced from `Chain-Selector` / `Chain-Auto` proxy-groups.
+//! and referenced from `Chain-Selector` / `Chain-Auto` proxy-groups. Nodes
+//! from `proxy-providers` are chained per provider (see [`super::provider`]).
+//! With `clone_provider` set, the clones go to a local provider file instead.
+//! Multi-hop chains and the exit-first order are built by [`super::hop`].
 //! This replaces the legacy `type: relay` proxy-groups, which Mihomo removed.
 //!
-//! Key design: uses serde_yaml for READ-ONLY analysis, but writes back using
-//! text manipulation to preserve the original YAML formatting (flow-style,
-//! indentation, quoting, comments).
+//! Key design: uses serde_yaml for READ-ONLY analysis, but writes back through
+//! the line-level [`Document`] model to preserve the original YAML formatting
+//! (flow-style, indentation, quoting, comments).
+//!
+//! Generated entries are wrapped in managed blocks (see [`super::block`]), so
+//! a re-apply replaces exactly what the last run wrote and user nodes that
+//! happen to be named `*-Chain` are left alone.
 
 use anyhow::{Context, Result};
 use serde_yaml::Value;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use tracing::info;
 
+use super::auto_group::{AutoGroup, AutoGroupType};
+use super::region::RegionTable;
+use super::block;
+use super::compat::{self, Decision};
+use super::dialer::{DialerGraph, DialerPolicy};
+use super::filter::NodeFilter;
+use super::hop::{self, ChainHop, ChainOrder, Outbound};
+use super::inject::InjectConfig;
+use super::json;
+use super::plan::ApplyPlan;
+use super::provider::{self, ProviderChain, ProviderFile};
+use crate::backup::BackupStore;
+use crate::config::BackupConfig;
+use crate::config::secret_ref;
+use crate::fsutil;
+use crate::yaml::cst::Style;
+use crate::yaml::{self, flow, Document, Entry, Section, Seg, Sequence};
+
+/// Proxy-group names created by the default configuration (and by versions
+/// before managed blocks)
+pub(super) const CHAIN_GROUPS: [&str; 2] = ["Chain-Selector", "Chain-Auto"];
+
 /// Configuration for the merger
 #[derive(Debug, Clone)]
 pub struct MergerConfig {
@@ -27,9 +58,32 @@ pub struct MergerConfig {
     pub proxy_port: u16,
     pub proxy_username: Option<String>,
     pub proxy_password: Option<String>,
+    /// The local proxy relays UDP
+    pub proxy_udp: bool,
     pub create_backup: bool,
     pub insert_at_beginning: bool,
     pub chain_suffix: String,
+    pub output_style: OutputStyle,
+    /// Which proxy nodes get a chain clone (all by default)
+    pub node_filter: NodeFilter,
+    /// Groups written after `Chain-Selector` (`Chain-Auto` by default; may
+    /// be empty)
+    pub auto_groups: Vec<AutoGroup>,
+    /// Also write a `Chain-<CODE>` group per region found in node names
+    pub region_groups: Option<RegionTable>,
+    /// Groups that get references to the chain groups (the main group by
+    /// default), and where
+    pub inject: InjectConfig,
+    /// Proxy providers to chain (all by default; an empty list chains none)
+    pub providers: Option<Vec<String>>,
+    /// Write the clones and the local proxy to this provider file (relative
+    /// to the config's directory) instead of the `proxies` section
+    pub clone_provider: Option<String>,
+    /// SOCKS5 hops between the node and the local proxy, in traffic order
+    pub hops: Vec<ChainHop>,
+    pub chain_order: ChainOrder,
+    /// What happens to nodes that already have a `dialer-proxy`
+    pub dialer_policy: DialerPolicy,
 }
 
 impl Default for MergerConfig {
@@ -40,13 +94,107 @@ impl Default for MergerConfig {
             proxy_port: 10808,
             proxy_username: None,
             proxy_password: None,
+            proxy_udp: false,
             create_backup: true,
             insert_at_beginning: true,
             chain_suffix: "-Chain".to_string(),
+            output_style: OutputStyle::Auto,
+            node_filter: NodeFilter::default(),
+            auto_groups: vec![AutoGroup::default()],
+            region_groups: None,
+            inject: InjectConfig::default(),
+            providers: None,
+            clone_provider: None,
+            hops: Vec::new(),
+            chain_order: ChainOrder::default(),
+            dialer_policy: DialerPolicy::default(),
+        }
+    }
+}
+
+impl MergerConfig {
+    /// Copy with a literal password replaced by `***` (for previews)
+    pub fn redacted(&self) -> Self {
+        let redact = |password: &Option<String>| password.as_ref().map(|p| {
+            if p.is_empty() || secret_ref::is_secret_ref(p) { p.clone() } else { "***".to_string() }
+        });
+        let hops = self.hops.iter()
+            .map(|hop| ChainHop { password: redact(&hop.password), ..hop.clone() })
+            .collect();
+        Self { proxy_password: redact(&self.proxy_password), hops, ..self.clone() }
+    }
+
+    /// Copy with `env:` / `file:` / `cmd:` credential references resolved
+    ///
+    /// Clash needs literal credentials, so this runs right before writing.
+    pub fn with_resolved_secrets(&self) -> Result<Self> {
+        Ok(Self {
+            proxy_username: secret_ref::resolve_optional(self.proxy_username.as_deref())?,
+            proxy_password: secret_ref::resolve_optional(self.proxy_password.as_deref())?,
+            hops: self.hops.iter()
+                .map(|hop| Ok(ChainHop {
+                    username: secret_ref::resolve_optional(hop.username.as_deref())?,
+                    password: secret_ref::resolve_optional(hop.password.as_deref())?,
+                    ..hop.clone()
+                }))
+                .collect::<Result<_>>()?,
+            ..self.clone()
+        })
+    }
+
+    /// The local proxy as the last hop of the chain
+    pub fn exit_hop(&self) -> ChainHop {
+        ChainHop {
+            name: self.proxy_name.clone(),
+            host: self.proxy_host.clone(),
+            port: self.proxy_port,
+            username: self.proxy_username.clone(),
+            password: self.proxy_password.clone(),
+            udp: self.proxy_udp,
         }
     }
+
+    /// Whether every SOCKS5 hop of the chain relays UDP
+    pub fn hops_relay_udp(&self) -> bool {
+        self.proxy_udp && self.hops.iter().all(|h| h.udp)
+    }
 }
 
+/// Layout of the generated proxies and proxy-groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputStyle {
+    /// Follow the majority of the section's existing entries (flow on a tie)
+    #[default]
+    Auto,
+    /// One line per entry: `- { name: HK-Chain, type: socks5, ... }`
+    Flow,
+    /// One key per line: `- name: HK-Chain` / `  type: socks5`
+    Block,
+}
+
+impl FromStr for OutputStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "flow" => Ok(Self::Flow),
+            "block" => Ok(Self::Block),
+            other => Err(format!("unknown output style '{}' (expected auto, flow or block)", other)),
+        }
+    }
+}
+
+/// Value of a key in a generated mapping (scalars are already quoted)
+enum FieldValue {
+    Scalar(String),
+    List(Vec<String>),
+}
+
+/// Members of a chain group: `proxies` or `use` with its list, plus the
+/// filter keys that go with it
+pub(super) type GroupMembers = ((&'static str, Vec<String>), Vec<(&'static str, serde_json::Value)>);
+
 /// Result of a merge operation
 #[derive(Debug, Clone)]
 pub struct MergeResult {
@@ -55,20 +203,55 @@ pub struct MergeResult {
     pub chains_created: usize,
     pub backup_path: Option<PathBuf>,
     pub warnings: Vec<String>,
+    /// Provider file to write next to the config (see `clone_provider`)
+    pub clone_provider: Option<ProviderFile>,
+}
+
+/// Result of an unapply operation
+#[derive(Debug, Clone, Default)]
+pub struct UnapplyResult {
+    pub proxies_removed: usize,
+    pub groups_removed: usize,
+    pub groups_updated: usize,
+    pub rules_restored: usize,
+    pub rules_removed: usize,
+    pub backup_path: Option<PathBuf>,
+    pub warnings: Vec<String>,
+    /// Path of the removed clone provider; `unapply` deletes the file
+    pub clone_provider: Option<String>,
+}
+
+impl UnapplyResult {
+    /// Whether the config contained no chain artifacts
+    pub fn is_empty(&self) -> bool {
+        self.proxies_removed == 0
+            && self.groups_removed == 0
+            && self.groups_updated == 0
+            && self.rules_restored == 0
+            && self.rules_removed == 0
+            && self.clone_provider.is_none()
+    }
 }
 
 /// Clash configuration merger (format-preserving)
 pub struct ClashConfigMerger {
     config: MergerConfig,
+    backups: BackupConfig,
 }
 
 impl ClashConfigMerger {
     pub fn new() -> Self {
-        Self { config: MergerConfig::default() }
+        Self::with_config(MergerConfig::default())
     }
 
     pub fn with_config(config: MergerConfig) -> Self {
-        Self { config }
+        Self { config, backups: BackupConfig::default() }
+    }
+
+    /// Where backups go and how many are kept (when `create_backup` is set)
+    pub fn with_backups(mut self, backups: BackupConfig) -> Self {
+        self.backups = backups;
+        self
     }
 
     pub fn config(&self) -> &MergerConfig {
@@ -76,9 +259,196 @@ impl ClashConfigMerger {
     }
 
     /// Merge local proxy configuration into Clash config file (format-preserving)
+    ///
+    /// Equivalent to an [`ApplyPlan`] without rule rewrites or custom rules.
     pub fn merge<P: AsRef<Path>>(&self, config_path: P) -> Result<MergeResult> {
+        ApplyPlan::new(self.config.clone())
+            .with_backups(self.backups.clone())
+            .execute(config_path.as_ref())
+            .map(|planned| planned.merge)
+    }
+
+    /// Merge into config text in memory, without touching any file
+    ///
+    /// Credentials are used as configured; secret references are not resolved.
+    pub fn merge_str(&self, content: &str) -> Result<(String, MergeResult)> {
+        let mut result = MergeResult {
+            proxy_added: false, groups_updated: 0, chains_created: 0,
+            backup_path: None, warnings: Vec::new(), clone_provider: None,
+        };
+        let output = self.merge_text(content, &mut result)?;
+        Ok((output, result))
+    }
+
+    /// Remove every chain artifact from a Clash config file (format-preserving)
+    ///
+    /// Drops the managed blocks (local proxy, `-Chain` clones, `Chain-Selector`
+    /// / `Chain-Auto` and references to them, the clone provider and its
+    /// file), and reverts rule rewrites and injected custom rules. The file is
+    /// left untouched when there is nothing to remove.
+    pub fn unapply<P: AsRef<Path>>(&self, config_path: P) -> Result<UnapplyResult> {
         let config_path = config_path.as_ref();
+        Self::check_writable(config_path)?;
+
+        info!("Removing chains from: {}", config_path.display());
 
+        let content = fs::read_to_string(config_path)
+            .with_context(|| format!("Failed to read: {}", config_path.display()))?;
+        let (output, mut result) = self.unapply_text(&content)?;
+        if result.is_empty() {
+            return Ok(result);
+        }
+
+        if self.config.create_backup {
+            let backup = BackupStore::new(self.backups.clone()).backup(config_path)?;
+            result.backup_path = Some(backup.path);
+        }
+
+        fsutil::write_atomic(config_path, &output)?;
+        if let Some(file) = &result.clone_provider {
+            let path = provider::file_path(config_path, file);
+            if path.exists() {
+                fs::remove_file(&path).with_context(|| format!("Failed to remove: {}", path.display()))?;
+            }
+        }
+
+        info!("Unapply completed: proxies={}, groups={}, rules restored={}, removed={}",
+            result.proxies_removed, result.groups_removed, result.rules_restored, result.rules_removed);
+        Ok(result)
+    }
+
+    /// Core text-based unapply logic
+    pub fn unapply_text(&self, content: &str) -> Result<(String, UnapplyResult)> {
+        if json::is_json(content) {
+            return self.unapply_json(content);
+        }
+        let parsed = yaml::parse_value(content)
+            .context("YAML parse error")?;
+        let config_map = parsed.as_mapping()
+            .context("Config root must be a YAML mapping")?;
+
+        let mut result = UnapplyResult::default();
+        let mut doc = Document::parse(content);
+        let mut updated: Vec<String> = Vec::new();
+        let mut generated: HashSet<String> = HashSet::new();
+        let mut removed_groups = Self::chain_refs([]);
+        let mut edits = Vec::new();
+
+        // Refuse to guess around broken markers
+        block::take_blocks(doc.lines(), None)?;
+        let legacy = self.is_legacy_patched(content, config_map);
+
+        // Rebuild the groups section: drop our groups, strip references to them
+        if let Some(groups) = doc.section("proxy-groups") {
+            let (mut user, ours) = Self::partition_section(&doc, &groups, |e| {
+                legacy && self.is_legacy_chain_group(e)
+            })?;
+            result.groups_removed = ours.len();
+            generated.extend(ours.iter().filter_map(Entry::name));
+            removed_groups.extend(ours.iter().filter_map(Entry::name));
+
+            for entry in &mut user.entries {
+                let stripped = Self::strip_chain_refs(entry, &removed_groups)?;
+                if stripped != *entry {
+                    result.groups_updated += 1;
+                    updated.extend(entry.name());
+                    *entry = stripped;
+                }
+            }
+            edits.push((groups.body, user.into_lines()));
+        }
+
+        // Rebuild the proxies section: drop the local proxy and chain clones
+        if let Some(proxies) = doc.section("proxies") {
+            let (user, ours) = Self::partition_section(&doc, &proxies, |e| {
+                legacy && self.is_legacy_chain_proxy(e)
+            })?;
+            result.proxies_removed = ours.len();
+            generated.extend(ours.iter().filter_map(Entry::name));
+            edits.push((proxies.body, user.into_lines()));
+        }
+
+        // Drop the clone provider entry; its file goes in `unapply`
+        if let Some(providers) = doc.section("proxy-providers") {
+            let (body, removed) = Self::providers_body(&doc, &providers, None)?;
+            if removed {
+                result.clone_provider = provider::clone_provider_path(config_map);
+                edits.push((providers.body, body));
+            }
+        }
+        doc.splice(edits);
+
+        // Drop the sections merge added once nothing else is left in them
+        let created = doc.sections()
+            .into_iter()
+            .filter(|s| block::is_created(&doc.lines()[s.header]))
+            .map(|s| {
+                let empty = doc.section_lines(&s).iter().all(|l| yaml::document::is_trivia(l));
+                let header = if empty { Vec::new() } else { vec![block::strip_created(&doc.lines()[s.header])] };
+                (s.header..s.header + 1, header)
+            })
+            .collect();
+        doc.splice(created);
+
+        // Revert rule rewrites; unmarked rules fall back to the main group
+        let main_group = self.fallback_group(&updated, config_map, &generated);
+        let fallback: HashMap<String, String> = main_group
+            .iter()
+            .flat_map(|g| removed_groups.iter().map(move |c| (c.clone(), g.clone())))
+            .collect();
+
+        doc.ensure_trailing_newline();
+        let (output, revert) = crate::patcher::revert_rules_text(&doc.to_string(), &fallback);
+        result.rules_restored = revert.restored;
+        result.rules_removed = revert.removed;
+
+        // The result must still be a valid config
+        let check = yaml::parse_value(&output)
+            .context("Unapply produced invalid YAML")?;
+        let leftover = check.get("rules")
+            .and_then(|r| r.as_sequence())
+            .map(|rules| rules.iter()
+                .filter_map(|r| r.as_str())
+                .filter(|r| r.split(',').any(|part| {
+                    let part = part.trim();
+                    CHAIN_GROUPS.contains(&part) || generated.contains(part)
+                }))
+                .count())
+            .unwrap_or(0);
+        if leftover > 0 {
+            result.warnings.push(format!(
+                "{} rules still reference removed chain groups; fix them by hand", leftover
+            ));
+        }
+
+        Ok((output, result))
+    }
+
+    /// Names of the proxies and groups an earlier run wrote into `content`,
+    /// plus the reserved chain group names and the local proxy; e.g. to
+    /// keep them out of main group detection
+    pub fn generated_names(&self, content: &str) -> Result<HashSet<String>> {
+        let mut names = Self::chain_refs([self.config.proxy_name.clone()]);
+        if json::is_json(content) {
+            let manifest = json::JsonConfig::parse(content)?.manifest;
+            names.extend(manifest.proxies.into_iter().chain(manifest.proxy_groups));
+            return Ok(names);
+        }
+        let parsed = yaml::parse_value(content).context("YAML parse error")?;
+        let config_map = parsed.as_mapping().context("Config root must be a YAML mapping")?;
+        let doc = Document::parse(content);
+        let legacy = self.is_legacy_patched(content, config_map);
+        for section in ["proxies", "proxy-groups"].into_iter().filter_map(|key| doc.section(key)) {
+            let (_, ours) = Self::partition_section(&doc, &section, |e| {
+                legacy && (self.is_legacy_chain_proxy(e) || self.is_legacy_chain_group(e))
+            })?;
+            names.extend(ours.iter().filter_map(Entry::name));
+        }
+        Ok(names)
+    }
+
+    /// Fail early for missing or read-only config files
+    pub(super) fn check_writable(config_path: &Path) -> Result<()> {
         if !config_path.exists() {
             anyhow::bail!("Config file does not exist: {}", config_path.display());
         }
@@ -90,376 +460,587 @@ impl ClashConfigMerger {
                 );
             }
         }
+        Ok(())
+    }
 
-        info!("Starting Clash config merge for: {}", config_path.display());
-
-        // Create single backup (delete old backups first)
-        let backup_path = if self.config.create_backup {
-            let bp = self.create_single_backup(config_path)?;
-            info!("Backup: {}", bp.display());
-            Some(bp)
-        } else {
-            None
+    /// Whether `content` was patched before managed blocks existed
+    ///
+    /// Such configs carry our reserved names but no markers; their clones can
+    /// only be told apart from user nodes by name and shape.
+    fn is_legacy_patched(&self, content: &str, config: &serde_yaml::Mapping) -> bool {
+        if block::has_blocks(content) {
+            return false;
+        }
+        let names = |key: &str| -> Vec<String> {
+            config.get(key)
+                .and_then(|v| v.as_sequence())
+                .map(|seq| seq.iter()
+                    .filter_map(|e| e.get("name").and_then(|n| n.as_str()).map(String::from))
+                    .collect())
+                .unwrap_or_default()
         };
+        names("proxies").contains(&self.config.proxy_name)
+            || names("proxy-groups").iter().any(|n| CHAIN_GROUPS.contains(&n.as_str()))
+    }
 
-        let content = fs::read_to_string(config_path)
-            .with_context(|| format!("Failed to read: {}", config_path.display()))?;
+    /// Proxy-group written by a pre-marker version: a chain group, or a
+    /// `-Chain` relay group from before `dialer-proxy`
+    fn is_legacy_chain_group(&self, entry: &Entry) -> bool {
+        match entry.name() {
+            Some(name) => CHAIN_GROUPS.contains(&name.as_str())
+                || (name.ends_with(&self.config.chain_suffix)
+                    && entry.get("type").as_deref() == Some("relay")),
+            None => false,
+        }
+    }
 
-        let mut result = MergeResult {
-            proxy_added: false, groups_updated: 0, chains_created: 0,
-            backup_path, warnings: Vec::new(),
-        };
+    /// Proxy written by a pre-marker version: the local proxy, or a `-Chain`
+    /// clone dialing through another node
+    fn is_legacy_chain_proxy(&self, entry: &Entry) -> bool {
+        match entry.name() {
+            Some(name) => name == self.config.proxy_name
+                || (name.ends_with(&self.config.chain_suffix)
+                    && entry.get("dialer-proxy").is_some()),
+            None => false,
+        }
+    }
 
-        let output = match self.merge_text(&content, &mut result) {
-            Ok(out) => out,
-            Err(e) => {
-                if let Some(ref bp) = result.backup_path {
-                    let _ = fs::copy(bp, config_path);
-                }
-                return Err(e);
+    /// Split a section into user entries and entries generated by the merger
+    ///
+    /// Generated entries are those inside managed blocks at the section's
+    /// entry indent, plus any matched by `is_legacy` (configs without markers).
+    fn partition_section(
+        doc: &Document,
+        section: &Section,
+        is_legacy: impl Fn(&Entry) -> bool,
+    ) -> Result<(Sequence, Vec<Entry>)> {
+        let lines = doc.section_lines(section);
+        let indent = Self::entry_indent(lines);
+        let (kept, removed) = block::take_blocks(lines, Some(&indent))?;
+        let mut generated = Sequence::parse(&removed).entries;
+        let mut user = Sequence::parse(&kept);
+        user.entries.retain(|entry| {
+            if is_legacy(entry) {
+                generated.push(entry.clone());
+                false
+            } else {
+                true
             }
-        };
-
-        fs::write(config_path, &output)
-            .with_context(|| format!("Failed to write: {}", config_path.display()))?;
+        });
+        Ok((user, generated))
+    }
 
-        info!("Merge completed: proxy_added={}, chains={}, groups={}",
-            result.proxy_added, result.chains_created, result.groups_updated);
-        Ok(result)
+    /// Entry indent of a section's sequence (two spaces for an empty one)
+    fn entry_indent(lines: &[String]) -> String {
+        Sequence::detect_indent(lines).unwrap_or_else(|| "  ".to_string())
     }
 
     /// Core text-based merge logic
     fn merge_text(&self, content: &str, result: &mut MergeResult) -> Result<String> {
+        if json::is_json(content) {
+            return self.merge_json(content, result);
+        }
+
         // Step 1: Parse with serde_yaml for READ-ONLY structure analysis
-        let parsed: Value = serde_yaml::from_str(content)
+        let parsed = yaml::parse_value(content)
             .context("YAML parse error")?;
         let config_map = parsed.as_mapping()
             .context("Config root must be a YAML mapping")?;
 
-        let proxy_entries = self.get_proxy_entries(config_map)?;
-        let proxy_names: Vec<String> = proxy_entries.iter().map(|(n, _)| n.clone()).collect();
-        let main_group = self.detect_main_group(config_map);
-        if let Some(ref name) = main_group {
-            info!("Detected main entry group: {}", name);
-        }
-
-        // Step 2: Text-based manipulation
-        let mut lines: Vec<String> = content.lines().map(String::from).collect();
+        // Step 2: Edit the document model, which keeps untouched lines as-is
+        let mut doc = Document::parse(content);
 
-        // Find section boundaries
-        let proxies_range = Self::find_section_range(&lines, "proxies");
-        let groups_range = Self::find_section_range(&lines, "proxy-groups");
+        // Refuse to guess around broken markers
+        block::take_blocks(doc.lines(), None)?;
+        let legacy = self.is_legacy_patched(content, config_map);
 
-        if proxies_range.is_none() || groups_range.is_none() {
-            anyhow::bail!("Config must have both 'proxies' and 'proxy-groups' sections");
+        // Find sections, creating missing ones (provider-only configs have
+        // no `proxies`)
+        let mut wanted = vec![("proxies", &["proxy-groups", "rules"][..]), ("proxy-groups", &["rules"][..])];
+        if let Some(path) = &self.config.clone_provider {
+            provider::check_file_path(path)?;
+            wanted.insert(0, ("proxy-providers", &["proxies", "proxy-groups", "rules"][..]));
+        }
+        for (key, before) in wanted {
+            if doc.section(key).is_none() {
+                doc.insert_section(key, before, Some(block::CREATED_MARKER));
+            }
+        }
+        let (Some(proxies), Some(groups)) = (doc.section("proxies"), doc.section("proxy-groups")) else {
+            unreachable!("sections were inserted above");
+        };
+        for section in [&proxies, &groups] {
+            if !doc.open_block(section) {
+                anyhow::bail!("'{}' must be a block list (one '- ' entry per line) to be patched", section.key);
+            }
         }
-        let (ps, pe) = proxies_range.unwrap();
-        let (gs, ge) = groups_range.unwrap();
+        let providers_section = doc.section("proxy-providers");
+        let clone_file = self.config.clone_provider.as_deref();
+        let providers_edit = match &providers_section {
+            Some(section) if clone_file.is_some() && !doc.open_block(section) => {
+                anyhow::bail!("'proxy-providers' must be a block mapping (one key per line) to be patched");
+            }
+            Some(section) => {
+                let (body, _) = Self::providers_body(&doc, section, clone_file)?;
+                Some((section.body.clone(), body))
+            }
+            None => None,
+        };
 
         // Detect indent style from original entries
-        let indent = Self::detect_indent(&lines, ps);
+        let indent = Self::entry_indent(doc.section_lines(&proxies));
+        let group_indent = Self::entry_indent(doc.section_lines(&groups));
 
-        // Step 3: Process proxy-groups section (do AFTER proxies since line numbers shift)
-        // We process groups first in reverse order to keep proxies indices valid
-        let groups_lines = lines[gs + 1..ge].to_vec();
-        let groups_entries = Self::split_entries(&groups_lines, &indent);
-
-        let mut new_groups_content: Vec<String> = Vec::new();
-
-        // Add Chain-Selector and Chain-Auto
-        let chain_names: Vec<String> = proxy_names.iter()
-            .map(|n| format!("'{}{}'", n, self.config.chain_suffix))
+        // Drop the output of the previous run; only user entries are kept
+        let (mut proxies_seq, old_proxies) = Self::partition_section(&doc, &proxies, |e| {
+            legacy && self.is_legacy_chain_proxy(e)
+        })?;
+        let (mut groups_seq, old_groups) = Self::partition_section(&doc, &groups, |e| {
+            legacy && self.is_legacy_chain_group(e)
+        })?;
+        let generated: HashSet<String> = old_proxies.iter().chain(&old_groups)
+            .filter_map(Entry::name)
             .collect();
-        let chain_list = chain_names.join(", ");
 
-        new_groups_content.push(format!(
-            "{}- {{ name: Chain-Selector, type: select, proxies: [{}] }}",
-            indent, chain_list
-        ));
-        new_groups_content.push(format!(
-            "{}- {{ name: Chain-Auto, type: url-test, proxies: [{}], url: 'http://www.gstatic.com/generate_204', interval: 300, tolerance: 50 }}",
-            indent, chain_list
-        ));
+        let proxy_entries = self.get_proxy_entries(config_map, &generated, &mut result.warnings)?;
+        let proxy_names: Vec<String> = proxy_entries.iter().map(|(n, _)| n.clone()).collect();
+        let providers = self.provider_chains(config_map, &proxy_names, result);
+
+        let group_names = self.chain_group_names();
+        let region_groups = self.region_groups(&proxy_names);
+        let user_entries = proxies_seq.entries.iter().chain(&groups_seq.entries);
+        let taken: Vec<String> = user_entries.filter_map(Entry::name).collect();
+        self.check_group_names(Self::extra_group_names(&region_groups, &providers), taken.iter().cloned())?;
+        let exit = self.config.exit_hop();
+        let sources = self.chain_sources(&proxy_names, &providers);
+        let members: Vec<String> = sources.iter().map(|(_, clone)| clone.clone()).collect();
+        result.warnings.extend(hop::check(&exit, &self.config.hops, self.config.chain_order, &members, taken)?);
+        let refs = Self::chain_refs(old_groups.iter().filter_map(Entry::name).chain(group_names.iter().cloned()));
+        let main_group = self.detect_main_group(config_map, &generated);
+        if let Some(ref name) = main_group {
+            info!("Detected main entry group: {}", name);
+        }
 
-        // Keep original groups (skip old chain artifacts), modify main group
-        for entry in &groups_entries {
-            let name = Self::extract_entry_name(&entry.lines);
-            if let Some(ref n) = name {
-                if n == "Chain-Selector" || n == "Chain-Auto" || n.ends_with(&self.config.chain_suffix) {
-                    continue; // skip old chain artifacts
-                }
-            }
-            // If this is the main group, inject Chain-Selector/Chain-Auto into its proxies
-            if main_group.as_deref() == name.as_deref() && name.is_some() {
-                let modified = self.inject_into_group_proxies(&entry.lines);
-                new_groups_content.extend(modified);
-            } else {
-                new_groups_content.extend(entry.lines.clone());
+        // Step 3: Process proxy-groups section
+        // Add Chain-Selector, the auto groups, the region groups and the
+        // provider groups, in the style of the user's groups
+        let group_style = self.resolve_style(&groups_seq.entries);
+        let list_indent = Self::group_list_indent(&groups_seq.entries, &group_indent);
+        let chain_group = |name: &str, kind: &str, ((key, list), extra): GroupMembers| {
+            let mut fields = vec![
+                ("name", FieldValue::Scalar(flow::quote_scalar(name))),
+                ("type", FieldValue::Scalar(kind.to_string())),
+                (key, FieldValue::List(list.iter().map(|n| flow::quote_scalar(n)).collect())),
+            ];
+            fields.extend(extra.into_iter().map(|(key, value)| match value {
+                serde_json::Value::String(s) => (key, FieldValue::Scalar(flow::quote_scalar(&s))),
+                other => (key, FieldValue::Scalar(other.to_string())),
+            }));
+            Self::format_mapping(group_style, &group_indent, &list_indent, fields)
+        };
+        let mut chain_groups = chain_group(CHAIN_GROUPS[0], "select", self.group_members(&members, true));
+        for auto in &self.config.auto_groups {
+            let (list, mut extra) = self.group_members(&members, true);
+            extra.extend(auto.options());
+            chain_groups.extend(chain_group(&auto.name, auto.kind.as_str(), (list, extra)));
+        }
+        for (region, members) in &region_groups {
+            let (list, mut extra) = self.group_members(members, false);
+            extra.extend(region.options());
+            chain_groups.extend(chain_group(&region.name, region.kind.as_str(), (list, extra)));
+        }
+        let (filters, _) = provider::group_filters(&self.config.node_filter);
+        for chain in &providers {
+            let via = self.health_check_group(&chain.group);
+            let mut extra: Vec<(&'static str, serde_json::Value)> = filters.iter()
+                .map(|(key, value)| (*key, serde_json::Value::from(value.as_str())))
+                .collect();
+            extra.extend(via.options());
+            let list = ("use", vec![chain.provider.clone()]);
+            chain_groups.extend(chain_group(&via.name, via.kind.as_str(), (list, extra)));
+        }
+        let chain_groups = block::wrap(&group_indent, chain_groups);
+
+        // Keep original groups: strip stale references to chain groups from
+        // all of them, then add fresh ones to the target groups
+        let user_groups: Vec<String> = groups_seq.entries.iter().filter_map(Entry::name).collect();
+        let (targets, warnings) = self.config.inject.select(&user_groups, main_group.as_deref())?;
+        result.warnings.extend(warnings);
+        result.groups_updated = 0;
+        for entry in &mut groups_seq.entries {
+            *entry = Self::strip_chain_refs(entry, &refs)?;
+            if entry.name().is_some_and(|name| targets.contains(&name))
+                && self.inject_into_group_proxies(entry, &group_names, &mut result.warnings)
+            {
+                result.groups_updated += 1;
             }
         }
 
         // Note: chain entries are no longer relay-type proxy-groups (removed in Mihomo).
         // They are emitted in the proxies section as cloned nodes with `dialer-proxy`.
-        result.chains_created = proxy_names.len();
+        result.chains_created = proxy_names.len() + providers.len();
 
         // Step 4: Process proxies section
-        let proxies_lines = lines[ps + 1..pe].to_vec();
-        let proxies_entries = Self::split_entries(&proxies_lines, &indent);
-
-        let mut new_proxies_content: Vec<String> = Vec::new();
-        for entry in &proxies_entries {
-            let name = Self::extract_entry_name(&entry.lines);
-            if let Some(ref n) = name {
-                // Drop old Local-Chain-Proxy and any prior cloned chain nodes
-                if n == &self.config.proxy_name || n.ends_with(&self.config.chain_suffix) {
-                    continue;
-                }
-            }
-            new_proxies_content.extend(entry.lines.clone());
-        }
-
         // Append chain entries: each is a SOCKS5 outbound (same server as
         // Local-Chain-Proxy) with `dialer-proxy: <vpn_node>`. This produces the
         // intended chain order — client → vpn_node → socks5 → target — so the
         // SOCKS5 server sees the VPN's exit IP (the IP it whitelists), not the
         // user's home IP. Equivalent to the old `relay: [vpn_node, socks5]`.
-        for (name, _) in &proxy_entries {
-            let chain_name = format!("{}{}", name, self.config.chain_suffix);
-            let cloned = self.format_chain_socks5_clone(&chain_name, name);
-            new_proxies_content.push(format!("{}- {}", indent, cloned));
+        // Intermediate hops nest one more `dialer-proxy` per hop (see
+        // [`super::hop`]); the local proxy itself comes after the clones.
+        // A provider file gets the same entries at its own indent
+        let proxy_style = self.resolve_style(&proxies_seq.entries);
+        let indent = if self.config.clone_provider.is_some() { "  ".to_string() } else { indent };
+        let nodes: HashMap<String, Value> = proxy_entries.into_iter().collect();
+        let outbounds = hop::outbounds(&exit, &self.config.hops, self.config.chain_order, &sources);
+        let over_clones: Vec<String> = group_names.iter().cloned()
+            .chain(region_groups.iter().map(|(g, _)| g.name.clone()))
+            .collect();
+        self.check_dialer_cycles(config_map, &generated, &outbounds, &over_clones, &members, &targets)?;
+        let mut generated_proxies: Vec<String> = Vec::new();
+        for outbound in &outbounds {
+            let fields = match outbound {
+                Outbound::Socks5 { name, hop, dialer } => Self::socks5_fields(hop, name, dialer.as_deref()),
+                Outbound::Node { name, node, dialer } => Self::node_fields(&hop::node_copy(&nodes[node], name, dialer)),
+            };
+            generated_proxies.extend(Self::format_mapping(proxy_style, &indent, "", fields));
         }
-
-        // Append new Local-Chain-Proxy
-        let proxy_line = self.format_proxy_entry(&indent);
-        new_proxies_content.push(proxy_line);
+        match &self.config.clone_provider {
+            Some(path) => result.clone_provider = Some(ProviderFile::new(path, generated_proxies, doc.is_crlf())),
+            None => proxies_seq.entries.push(Entry { lines: block::wrap(&indent, generated_proxies) }),
+        }
+        groups_seq.entries.insert(0, Entry { lines: chain_groups });
         result.proxy_added = true;
 
         // Step 5: Rebuild the file
-        // Splice the LATER section first so earlier indices stay valid.
-        if ps < gs {
-            // proxies before groups (common case)
-            lines.splice(gs + 1..ge, new_groups_content);
-            lines.splice(ps + 1..pe, new_proxies_content);
-        } else {
-            // groups before proxies (uncommon but valid YAML)
-            lines.splice(ps + 1..pe, new_proxies_content);
-            lines.splice(gs + 1..ge, new_groups_content);
+        let mut edits = vec![
+            (proxies.body, proxies_seq.into_lines()),
+            (groups.body, groups_seq.into_lines()),
+        ];
+        edits.extend(providers_edit);
+        doc.splice(edits);
+
+        // Ensure trailing newline
+        doc.ensure_trailing_newline();
+        Ok(doc.to_string())
+    }
+
+    /// Body of the `proxy-providers` section without the previous run's
+    /// managed block, plus a fresh clone provider entry pointing at `file`;
+    /// also whether a block was removed
+    fn providers_body(doc: &Document, section: &Section, file: Option<&str>) -> Result<(Vec<String>, bool)> {
+        let lines = doc.section_lines(section);
+        let indent = lines.iter()
+            .find(|l| !yaml::document::is_trivia(l))
+            .map_or("  ", |l| yaml::document::indent_of(l))
+            .to_string();
+        let (mut kept, _) = block::take_blocks(lines, Some(&indent))?;
+        let had_block = kept.len() < lines.len();
+        if let Some(file) = file {
+            let entry = vec![
+                format!("{}{}:", indent, provider::CLONE_PROVIDER),
+                format!("{}{}type: file", indent, indent),
+                format!("{}{}path: {}", indent, indent, flow::quote_scalar(file)),
+            ];
+            kept.splice(0..0, block::wrap(&indent, entry));
         }
+        Ok((kept, had_block))
+    }
 
-        result.groups_updated = if main_group.is_some() { 1 } else { 0 };
+    /// Member list of a chain group over the clones: `proxies: [clones]`,
+    /// or `use:` of the clone provider with a filter for all clones (`all`,
+    /// everything but the local proxy) or exactly `clones`
+    pub(super) fn group_members(
+        &self,
+        clones: &[String],
+        all: bool,
+    ) -> GroupMembers {
+        if self.config.clone_provider.is_none() {
+            return (("proxies", clones.to_vec()), Vec::new());
+        }
+        let config = &self.config;
+        let (key, filter) = if all {
+            ("exclude-filter", hop::helper_filter(&config.proxy_name, &config.hops, config.chain_order, &config.chain_suffix))
+        } else {
+            ("filter", provider::exact_filter(clones))
+        };
+        (("use", vec![provider::CLONE_PROVIDER.to_string()]), vec![(key, serde_json::Value::from(filter))])
+    }
 
-        // Ensure trailing newline
-        let mut output = lines.join("\n");
-        if !output.ends_with('\n') {
-            output.push('\n');
+    /// Style for entries generated into a section holding `entries`
+    fn resolve_style(&self, entries: &[Entry]) -> OutputStyle {
+        match self.config.output_style {
+            OutputStyle::Auto => {
+                let flow = entries.iter().filter(|e| e.is_flow()).count();
+                if entries.len() - flow > flow { OutputStyle::Block } else { OutputStyle::Flow }
+            }
+            style => style,
         }
-        Ok(output)
     }
 
-    /// Find the line range [start, end) for a top-level YAML section
-    fn find_section_range(lines: &[String], key: &str) -> Option<(usize, usize)> {
-        let header = format!("{}:", key);
-        let start = lines.iter().position(|l| {
-            let t = l.trim_start();
-            t == header || t.starts_with(&format!("{}: ", key))
-        })?;
+    /// Item indent of block-style `proxies:` lists in groups (two spaces
+    /// past the mapping keys when no group has one)
+    fn group_list_indent(entries: &[Entry], indent: &str) -> String {
+        entries.iter()
+            .find_map(|e| {
+                let proxies = e.node(&[Seg::Key("proxies")]).filter(|n| n.style() == Some(Style::Block))?;
+                let first = proxies.items().first()?.start;
+                Some(e.lines[first.line][..first.col].to_string())
+            })
+            .unwrap_or_else(|| format!("{}    ", indent))
+    }
+
+    /// Fields of a SOCKS5 outbound to the local proxy: the proxy itself, or
+    /// a chain clone dialing through `dialer_proxy`. Node names may contain
+    /// spaces / colons / emoji, so every string is YAML-quoted as needed.
+    fn socks5_fields(hop: &ChainHop, name: &str, dialer_proxy: Option<&str>) -> Vec<(String, FieldValue)> {
+        let scalar = |s: &str| FieldValue::Scalar(flow::quote_scalar(s));
+        let mut fields = vec![
+            ("name", scalar(name)),
+            ("type", scalar("socks5")),
+            ("server", scalar(&hop.host)),
+            ("port", FieldValue::Scalar(hop.port.to_string())),
+        ];
+        if let Some(u) = hop.username.as_deref().filter(|u| !u.is_empty()) {
+            fields.push(("username", scalar(u)));
+        }
+        if let Some(p) = hop.password.as_deref().filter(|p| !p.is_empty()) {
+            fields.push(("password", scalar(p)));
+        }
+        if hop.udp {
+            fields.push(("udp", FieldValue::Scalar("true".to_string())));
+        }
+        if let Some(dialer) = dialer_proxy {
+            fields.push(("dialer-proxy", scalar(dialer)));
+        }
+        fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect()
+    }
 
-        // End is the next top-level key (line starting with a non-space char and containing ':')
-        // Must exclude YAML list entries (starting with "- ") which are section content, not headers
-        let end = lines[start + 1..].iter().position(|l| {
-            !l.is_empty()
-                && !l.starts_with(' ')
-                && !l.starts_with('\t')
-                && !l.starts_with("- ")
-                && l.contains(':')
-        }).map(|p| p + start + 1).unwrap_or(lines.len());
+    /// Fields of a copied user node; nested values are written flow-style
+    fn node_fields(node: &Value) -> Vec<(String, FieldValue)> {
+        node.as_mapping()
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| (flow::render(key), FieldValue::Scalar(flow::render(value))))
+            .collect()
+    }
 
-        Some((start, end))
+    /// What each clone's chain starts from, and the clone's name: the nodes,
+    /// then the provider groups
+    pub(super) fn chain_sources(&self, proxy_names: &[String], providers: &[ProviderChain]) -> Vec<(String, String)> {
+        proxy_names.iter()
+            .map(|n| (n.clone(), format!("{}{}", n, self.config.chain_suffix)))
+            .chain(providers.iter().map(|p| (p.group.clone(), p.clone.clone())))
+            .collect()
     }
 
-    /// Detect indentation from the first entry in a section
-    fn detect_indent(lines: &[String], section_start: usize) -> String {
-        for line in &lines[section_start + 1..] {
-            if let Some(pos) = line.find("- ") {
-                return " ".repeat(pos);
+    /// Lines of a sequence entry holding a mapping, in `style` (Flow unless
+    /// Block); block lists put their items at `list_indent`
+    fn format_mapping<K: fmt::Display>(
+        style: OutputStyle,
+        indent: &str,
+        list_indent: &str,
+        fields: Vec<(K, FieldValue)>,
+    ) -> Vec<String> {
+        if style != OutputStyle::Block {
+            let parts: Vec<String> = fields.into_iter()
+                .map(|(key, value)| match value {
+                    FieldValue::Scalar(v) => format!("{}: {}", key, v),
+                    FieldValue::List(items) => format!("{}: [{}]", key, items.join(", ")),
+                })
+                .collect();
+            return vec![format!("{}- {{ {} }}", indent, parts.join(", "))];
+        }
+
+        let mut lines = Vec::new();
+        for (i, (key, value)) in fields.into_iter().enumerate() {
+            let lead = if i == 0 { format!("{}- ", indent) } else { format!("{}  ", indent) };
+            match value {
+                FieldValue::Scalar(v) => lines.push(format!("{}{}: {}", lead, key, v)),
+                FieldValue::List(items) if items.is_empty() => lines.push(format!("{}{}: []", lead, key)),
+                FieldValue::List(items) => {
+                    lines.push(format!("{}{}:", lead, key));
+                    lines.extend(items.into_iter().map(|item| format!("{}- {}", list_indent, item)));
+                }
             }
         }
-        String::new()
+        lines
     }
 
-    /// Split section content into individual entries
-    fn split_entries(section_lines: &[String], indent: &str) -> Vec<TextEntry> {
-        let prefix = format!("{}- ", indent);
-        let mut entries: Vec<TextEntry> = Vec::new();
-        let mut current: Vec<String> = Vec::new();
-
-        for line in section_lines {
-            if line.starts_with(&prefix) && !current.is_empty() {
-                entries.push(TextEntry { lines: current });
-                current = Vec::new();
+    /// Add the chain groups (`names`) to a group's proxies list at the
+    /// configured position. The entry must already be stripped of old
+    /// references.
+    /// Block-style references go in a managed block; flow-style lists cannot
+    /// hold comments, so there the group names identify them on the next run.
+    /// A block group without a proxies list (`use:` only) gets one inside
+    /// the managed block; a flow one is left alone with a warning.
+    fn inject_into_group_proxies(&self, entry: &mut Entry, names: &[String], warnings: &mut Vec<String>) -> bool {
+        let names: Vec<String> = names.iter().map(|n| flow::quote_scalar(n)).collect();
+        let group = entry.name().unwrap_or_default();
+        let Some(proxies) = entry.node(&[Seg::Key("proxies")]) else {
+            if Self::add_group_proxies(entry, &names) {
+                return true;
             }
-            if !line.is_empty() || !current.is_empty() {
-                current.push(line.clone());
+            warnings.push(format!("'{}' has no proxies list; add the chain groups to it by hand", group));
+            return false;
+        };
+        let members: Vec<String> = proxies.items().iter()
+            .map(|item| item.node.as_str().unwrap_or_default().to_string())
+            .collect();
+        let at = self.config.inject.insert_index(&group, &members, warnings);
+        entry.insert_items(&[Seg::Key("proxies")], at, &names, block::wrap)
+    }
+
+    /// Append a `proxies:` list of `names` to a block-style group, all of it
+    /// in a managed block; false for flow-style or unparsable entries
+    fn add_group_proxies(entry: &mut Entry, names: &[String]) -> bool {
+        let Some(root) = entry.root().filter(|r| r.style() == Some(Style::Block)) else {
+            return false;
+        };
+        let Some(last) = root.pairs().last() else {
+            return false;
+        };
+        let indent = " ".repeat(last.key.start.col);
+        let mut lines = vec![format!("{}proxies:", indent)];
+        lines.extend(names.iter().map(|name| format!("{}  - {}", indent, name)));
+        let at = last.value.span.end.line.max(last.key.span.end.line) + 1;
+        entry.lines.splice(at..at, block::wrap(&indent, lines));
+        true
+    }
+
+    /// Strip chain group references (`refs`) from a group entry: managed
+    /// blocks, unmarked block-style "- Chain-Selector" entries and names in
+    /// flow-style `proxies: [..]` lists. Lines without such references are
+    /// kept verbatim.
+    fn strip_chain_refs(entry: &Entry, refs: &HashSet<String>) -> Result<Entry> {
+        let (kept, _) = block::take_blocks(&entry.lines, None)?;
+        let mut stripped = Entry { lines: kept };
+        stripped.retain_items(&[Seg::Key("proxies")], |_, item| {
+            !item.as_str().is_some_and(|name| refs.contains(name))
+        });
+        Ok(stripped)
+    }
+
+    /// Names of the generated groups: `Chain-Selector`, then the auto groups
+    pub(super) fn chain_group_names(&self) -> Vec<String> {
+        std::iter::once(CHAIN_GROUPS[0].to_string())
+            .chain(self.config.auto_groups.iter().map(|g| g.name.clone()))
+            .collect()
+    }
+
+    /// Group names whose references are stripped from user groups: the
+    /// reserved names plus `groups` written by the merger
+    pub(super) fn chain_refs(groups: impl IntoIterator<Item = String>) -> HashSet<String> {
+        CHAIN_GROUPS.iter().map(|g| g.to_string()).chain(groups).collect()
+    }
+
+    /// `Chain-<CODE>` url-test groups over the chain clones of each region,
+    /// in table order, when region groups are enabled. They share the
+    /// health check of the first auto group.
+    pub(super) fn region_groups(&self, proxy_names: &[String]) -> Vec<(AutoGroup, Vec<String>)> {
+        let Some(table) = &self.config.region_groups else {
+            return Vec::new();
+        };
+        let mut members: HashMap<&str, Vec<String>> = HashMap::new();
+        for name in proxy_names {
+            if let Some(code) = table.classify(name) {
+                members.entry(code).or_default().push(format!("{}{}", name, self.config.chain_suffix));
             }
         }
-        if !current.is_empty() {
-            entries.push(TextEntry { lines: current });
-        }
-        entries
+        table.regions()
+            .iter()
+            .filter_map(|region| {
+                let clones = members.remove(region.code.as_str())?;
+                Some((self.health_check_group(&format!("Chain-{}", region.code)), clones))
+            })
+            .collect()
     }
 
-    /// Extract the `name:` value from an entry's lines
-    fn extract_entry_name(entry_lines: &[String]) -> Option<String> {
-        for line in entry_lines {
-            // Match "name:" as a standalone key, not as part of "hostname:" etc.
-            // Valid positions: start of line, after "- ", after "{ ", or after whitespace
-            let pos = line.find("name:").and_then(|p| {
-                if p == 0 { return Some(p); }
-                let before = line.as_bytes()[p - 1];
-                if before == b' ' || before == b'-' || before == b'{' || before == b'\t' {
-                    Some(p)
-                } else {
-                    None
-                }
-            });
-            if let Some(pos) = pos {
-                let after = line[pos + 5..].trim_start();
-                if after.is_empty() { continue; }
-
-                // Handle quoted names
-                let first_char = after.chars().next()?;
-                if first_char == '\'' || first_char == '"' {
-                    let rest = &after[1..];
-                    let end = rest.find(first_char)?;
-                    return Some(rest[..end].to_string());
-                }
-                // Unquoted: ends at comma, space+}, or end of line
-                let end = after.find(|c: char| c == ',' || c == '}')
-                    .unwrap_or(after.len());
-                return Some(after[..end].trim().to_string());
-            }
+    /// url-test group `name` with the health check of the first auto group
+    pub(super) fn health_check_group(&self, name: &str) -> AutoGroup {
+        let template = self.config.auto_groups.first().cloned().unwrap_or_default();
+        AutoGroup {
+            url: template.url,
+            interval: template.interval,
+            lazy: template.lazy,
+            timeout: template.timeout,
+            ..AutoGroup::new(name, AutoGroupType::UrlTest)
         }
-        None
     }
 
-    /// Format the Local-Chain-Proxy entry in flow style
-    fn format_proxy_entry(&self, indent: &str) -> String {
-        let mut parts = vec![
-            format!("name: {}", self.config.proxy_name),
-            "type: socks5".to_string(),
-            format!("server: {}", self.config.proxy_host),
-            format!("port: {}", self.config.proxy_port),
-        ];
-        if let Some(ref u) = self.config.proxy_username {
-            if !u.is_empty() { parts.push(format!("username: {}", u)); }
+    /// The chained providers, with warnings for missing ones, for node
+    /// filters they cannot apply and for a node filter that left nothing to
+    /// chain among the inline proxies (`proxy_names`) either
+    pub(super) fn provider_chains(
+        &self,
+        config: &serde_yaml::Mapping,
+        proxy_names: &[String],
+        result: &mut MergeResult,
+    ) -> Vec<ProviderChain> {
+        let available = provider::provider_names(config);
+        let (selected, warnings) = provider::select(&available, self.config.providers.as_deref());
+        result.warnings.extend(warnings);
+        if proxy_names.is_empty() && selected.is_empty() && !self.config.node_filter.is_empty() {
+            result.warnings.push("Node filter matched no proxies; no chains were created".to_string());
         }
-        if let Some(ref p) = self.config.proxy_password {
-            if !p.is_empty() { parts.push(format!("password: {}", p)); }
+        if !selected.is_empty() && self.config.chain_order == ChainOrder::ExitFirst {
+            result.warnings.push(
+                "Proxy-provider nodes cannot be dialed after the exit; providers are not chained in exit-first order".to_string(),
+            );
+            return Vec::new();
         }
-        format!("{}- {{ {} }}", indent, parts.join(", "))
+        if !selected.is_empty() {
+            result.warnings.extend(provider::group_filters(&self.config.node_filter).1);
+        }
+        selected.iter().map(|p| ProviderChain::new(p, &self.config.chain_suffix)).collect()
     }
 
-    /// Format a chain entry as a SOCKS5 outbound (same endpoint as
-    /// Local-Chain-Proxy) with `dialer-proxy: <vpn_node>`. The VPN node name
-    /// may contain spaces / colons / emoji, so it is YAML-quoted.
-    fn format_chain_socks5_clone(&self, chain_name: &str, dialer_proxy: &str) -> String {
-        let mut parts = vec![
-            format!("name: {}", quote_yaml_scalar(chain_name)),
-            "type: socks5".to_string(),
-            format!("server: {}", self.config.proxy_host),
-            format!("port: {}", self.config.proxy_port),
-        ];
-        if let Some(ref u) = self.config.proxy_username {
-            if !u.is_empty() { parts.push(format!("username: {}", u)); }
-        }
-        if let Some(ref p) = self.config.proxy_password {
-            if !p.is_empty() { parts.push(format!("password: {}", p)); }
-        }
-        parts.push(format!("dialer-proxy: {}", quote_yaml_scalar(dialer_proxy)));
-        format!("{{ {} }}", parts.join(", "))
-    }
-
-    /// Inject Chain-Selector and Chain-Auto into a group's proxies list.
-    /// Idempotent: existing references to Chain-Selector / Chain-Auto are
-    /// stripped first so re-applying the merge does not stack duplicates.
-    fn inject_into_group_proxies(&self, entry_lines: &[String]) -> Vec<String> {
-        // Pass 1: strip any existing block-style "- Chain-Selector" / "- Chain-Auto"
-        // and clean inline flow-style proxies: [..] lists of those names.
-        let cleaned: Vec<String> = entry_lines.iter().filter_map(|line| {
-            let t = line.trim();
-            if t == "- Chain-Selector" || t == "- Chain-Auto"
-                || t == "- 'Chain-Selector'" || t == "- 'Chain-Auto'"
-                || t == "- \"Chain-Selector\"" || t == "- \"Chain-Auto\""
-            {
-                return None;
-            }
-            if line.contains("proxies:") && line.contains('[') {
-                if let (Some(open), Some(close)) = (line.find('['), line.rfind(']')) {
-                    let inner = &line[open + 1..close];
-                    let parts: Vec<String> = inner
-                        .split(',')
-                        .map(|s| s.trim().to_string())
-                        .filter(|s| !s.is_empty()
-                            && s != "Chain-Selector" && s != "Chain-Auto"
-                            && s != "'Chain-Selector'" && s != "'Chain-Auto'"
-                            && s != "\"Chain-Selector\"" && s != "\"Chain-Auto\"")
-                        .collect();
-                    let new_line = format!("{}[{}]{}", &line[..open], parts.join(", "), &line[close + 1..]);
-                    return Some(new_line);
-                }
-            }
-            Some(line.clone())
-        }).collect();
-
-        // Pass 2: inject Chain-Selector / Chain-Auto at the top of the proxies list.
-        let mut result = Vec::with_capacity(cleaned.len() + 2);
-        for (i, line) in cleaned.iter().enumerate() {
-            // Flow style: proxies: [xxx, yyy]
-            if line.contains("proxies:") && line.contains('[') {
-                if let (Some(open), Some(close)) = (line.find('['), line.rfind(']')) {
-                    let inner = line[open + 1..close].trim();
-                    let new_inner = if inner.is_empty() {
-                        "Chain-Selector, Chain-Auto".to_string()
-                    } else {
-                        format!("Chain-Selector, Chain-Auto, {}", inner)
-                    };
-                    let new_line = format!("{}[{}]{}", &line[..open], new_inner, &line[close + 1..]);
-                    result.push(new_line);
-                    continue;
-                }
-            }
-            // Block style: detect "proxies:" on its own line
-            if line.trim() == "proxies:" {
-                result.push(line.clone());
-
-                // Detect sub-indent from the next existing list entry
-                let sub_indent = cleaned[i + 1..]
-                    .iter()
-                    .find(|l| l.trim().starts_with("- "))
-                    .map(|l| {
-                        let trimmed = l.trim_start();
-                        l[..l.len() - trimmed.len()].to_string()
-                    })
-                    .unwrap_or_else(|| {
-                        let prefix_len = line.find("proxies:").unwrap_or(0);
-                        line[..prefix_len].to_string()
-                    });
-
-                result.push(format!("{}- Chain-Selector", sub_indent));
-                result.push(format!("{}- Chain-Auto", sub_indent));
-                continue;
+    /// Generated group names besides the chain groups: region groups, then
+    /// the provider groups
+    pub(super) fn extra_group_names(regions: &[(AutoGroup, Vec<String>)], providers: &[ProviderChain]) -> Vec<String> {
+        regions.iter().map(|(g, _)| g.name.clone())
+            .chain(providers.iter().map(|p| p.group.clone()))
+            .collect()
+    }
+
+    /// Reject invalid or duplicate auto groups, and generated group names
+    /// (including the `extra` ones) already used by a user proxy or group
+    /// (`taken`)
+    pub(super) fn check_group_names(
+        &self,
+        extra: Vec<String>,
+        taken: impl IntoIterator<Item = String>,
+    ) -> Result<()> {
+        let mut seen = HashSet::new();
+        for name in self.chain_group_names().into_iter().chain(extra) {
+            if !seen.insert(name.clone()) {
+                anyhow::bail!("Group '{}' is generated twice; give each auto group its own name", name);
             }
-            result.push(line.clone());
         }
-        result
+        for auto in &self.config.auto_groups {
+            auto.validate()?;
+        }
+        if let Some(name) = taken.into_iter().find(|n| seen.contains(n)) {
+            anyhow::bail!("'{}' already names a proxy or group in the config; rename the auto group", name);
+        }
+        Ok(())
     }
 
     /// Get proxy (name, full Value) pairs from parsed config (read-only).
-    /// Skips Local-Chain-Proxy and any previously-cloned chain nodes.
-    fn get_proxy_entries(&self, config: &serde_yaml::Mapping) -> Result<Vec<(String, Value)>> {
+    /// Skips Local-Chain-Proxy, the `generated` entries of a previous run
+    /// and nodes that cannot carry a chain (see [`compat`]), then applies
+    /// the configured node filter. Skipped and doubtful nodes that the
+    /// filter would select end up in `warnings`.
+    pub(super) fn get_proxy_entries(
+        &self,
+        config: &serde_yaml::Mapping,
+        generated: &HashSet<String>,
+        warnings: &mut Vec<String>,
+    ) -> Result<Vec<(String, Value)>> {
+        // A bare `proxies:` (as left by unapply) holds no nodes
         let proxies = match config.get(&Value::String("proxies".to_string())) {
-            Some(p) => p,
-            None => return Ok(vec![]),
+            Some(p) if !p.is_null() => p,
+            _ => return Ok(vec![]),
         };
         let proxies_seq = proxies.as_sequence()
             .context("Proxies section must be a sequence")?;
@@ -467,18 +1048,118 @@ impl ClashConfigMerger {
         let mut out = Vec::new();
         for proxy in proxies_seq {
             if let Some(name) = proxy.get("name").and_then(|v| v.as_str()) {
-                if name != self.config.proxy_name && !name.ends_with(&self.config.chain_suffix) {
+                if name != self.config.proxy_name && !generated.contains(name) {
                     out.push((name.to_string(), proxy.clone()));
                 }
             }
         }
-        Ok(out)
+
+        // Incompatible nodes, then nodes that already dial through another
+        // proxy (per the dialer policy); flattened ones chain their root
+        let (order, udp) = (self.config.chain_order, self.config.hops_relay_udp());
+        let graph = DialerGraph::from_config(config, |n| n == self.config.proxy_name || generated.contains(n));
+        let nodes: HashMap<&str, &Value> = out.iter().map(|(n, v)| (n.as_str(), v)).collect();
+        let root = |name: &str| graph.root(name)
+            .filter(|r| nodes.get(r).is_some_and(|node| !matches!(compat::check(node, order, udp), Some((Decision::Skip, _)))));
+        let verdict = |name: &str, node: &Value| compat::check(node, order, udp).or_else(|| {
+            graph.dialer(name)?;
+            Some(match (self.config.dialer_policy, order) {
+                (DialerPolicy::Skip, _) => (Decision::Skip, "nodes that already dial through another proxy"),
+                (DialerPolicy::Flatten, _) if root(name).is_none() => {
+                    (Decision::Skip, "nodes whose dialer-proxy chain does not start at a usable proxy")
+                }
+                (DialerPolicy::Flatten, _) => {
+                    (Decision::Warn, "nodes that already dial through another proxy as the first node of their chain")
+                }
+                (DialerPolicy::Nest, ChainOrder::NodeFirst) => {
+                    (Decision::Warn, "nodes that already dial through another proxy; their chains get longer")
+                }
+                (DialerPolicy::Nest, ChainOrder::ExitFirst) => {
+                    (Decision::Warn, "nodes that already dial through another proxy; their copies dial through the hops instead")
+                }
+            })
+        });
+        let (skipped, chainable): (Vec<_>, Vec<_>) = out.iter().cloned()
+            .partition(|(name, node)| matches!(verdict(name, node), Some((Decision::Skip, _))));
+        let selected = self.config.node_filter.select(chainable)?;
+        // Only report skipped nodes the filter would have picked
+        let unlimited = NodeFilter { limit: None, ..self.config.node_filter.clone() };
+        let notes = unlimited.select(skipped)?.into_iter()
+            .chain(selected.iter().cloned())
+            .filter_map(|(name, node)| verdict(&name, &node).map(|(d, reason)| (d, reason, name)))
+            .collect();
+        warnings.extend(compat::warnings(notes));
+
+        let mut chained: Vec<(String, Value)> = Vec::new();
+        for (name, node) in selected {
+            let entry = match root(&name) {
+                Some(root) if self.config.dialer_policy == DialerPolicy::Flatten && root != name => {
+                    (root.to_string(), nodes[root].clone())
+                }
+                _ => (name, node),
+            };
+            if !chained.iter().any(|(n, _)| *n == entry.0) {
+                chained.push(entry);
+            }
+        }
+        Ok(chained)
+    }
+
+    /// Refuse output whose `dialer-proxy` references loop. The graph holds
+    /// the user's proxies and groups (without the previous run's `generated`
+    /// entries), the new `outbounds`, the chain groups (`groups`) over the
+    /// clones and the references to them from the `targets`.
+    pub(super) fn check_dialer_cycles(
+        &self,
+        config: &serde_yaml::Mapping,
+        generated: &HashSet<String>,
+        outbounds: &[Outbound],
+        groups: &[String],
+        clones: &[String],
+        targets: &[String],
+    ) -> Result<()> {
+        let ours = |n: &str| generated.contains(n) || CHAIN_GROUPS.contains(&n) || groups.iter().any(|g| g == n);
+        let mut graph = DialerGraph::from_config(config, ours);
+        for outbound in outbounds {
+            if let Some(dialer) = outbound.dialer() {
+                graph.add(outbound.name(), dialer);
+            }
+        }
+        for group in groups {
+            for clone in clones {
+                graph.add(group, clone);
+            }
+        }
+        for target in targets {
+            for group in self.chain_group_names() {
+                graph.add(target, &group);
+            }
+        }
+        if let Some(cycle) = graph.find_cycle() {
+            anyhow::bail!("dialer-proxy references would loop: {}", cycle.join(" → "));
+        }
+        Ok(())
+    }
+
+    /// Group that unmarked rule rewrites revert to: the detected main group
+    /// if it held chain references (`updated`), else the first group that did
+    pub(super) fn fallback_group(
+        &self,
+        updated: &[String],
+        config: &serde_yaml::Mapping,
+        generated: &HashSet<String>,
+    ) -> Option<String> {
+        let detected = self.detect_main_group(config, generated);
+        match updated.first() {
+            Some(first) if !detected.as_ref().is_some_and(|d| updated.contains(d)) => Some(first.clone()),
+            _ => detected,
+        }
     }
 
     /// Detect the main entry group from rules section (read-only)
-    fn detect_main_group(&self, config: &serde_yaml::Mapping) -> Option<String> {
+    pub(super) fn detect_main_group(&self, config: &serde_yaml::Mapping, generated: &HashSet<String>) -> Option<String> {
         // Skip our own chain groups
-        let skip: HashSet<&str> = ["Chain-Selector", "Chain-Auto"].into();
+        let skip = |name: &str| CHAIN_GROUPS.contains(&name) || generated.contains(name);
 
         // Priority 1: MATCH rule
         if let Some(rules) = config.get(&Value::String("rules".to_string())) {
@@ -489,7 +1170,7 @@ impl ClashConfigMerger {
                             let parts: Vec<&str> = rule_str.split(',').collect();
                             if parts.len() >= 2 {
                                 let group = parts[1].trim();
-                                if group != "DIRECT" && group != "REJECT" && !skip.contains(group) {
+                                if group != "DIRECT" && group != "REJECT" && !skip(group) {
                                     return Some(group.to_string());
                                 }
                             }
@@ -505,7 +1186,7 @@ impl ClashConfigMerger {
                 for group in seq {
                     if group.get("type").and_then(|v| v.as_str()) == Some("select") {
                         if let Some(name) = group.get("name").and_then(|v| v.as_str()) {
-                            if !name.starts_with("Chain-") && !name.ends_with("-Chain") {
+                            if !skip(name) {
                                 return Some(name.to_string());
                             }
                         }
@@ -515,62 +1196,16 @@ impl ClashConfigMerger {
         }
         None
     }
-
-    /// Create a single backup, removing any old backups for this file
-    fn create_single_backup<P: AsRef<Path>>(&self, config_path: P) -> Result<PathBuf> {
-        let config_path = config_path.as_ref();
-        let file_name = config_path.file_name().unwrap().to_string_lossy().to_string();
-        let backup_name = format!("{}.backup", file_name);
-        let backup_path = config_path.with_file_name(&backup_name);
-
-        // Delete old timestamped backups
-        if let Some(parent) = config_path.parent() {
-            if let Ok(entries) = fs::read_dir(parent) {
-                let prefix = format!("{}.backup", file_name);
-                for entry in entries.flatten() {
-                    let name = entry.file_name().to_string_lossy().to_string();
-                    if name.starts_with(&prefix) && name != backup_name {
-                        let _ = fs::remove_file(entry.path());
-                        info!("Removed old backup: {}", name);
-                    }
-                }
-            }
-        }
-
-        fs::copy(config_path, &backup_path).context("Failed to create backup")?;
-        Ok(backup_path)
-    }
-}
-
-struct TextEntry {
-    lines: Vec<String>,
 }
 
 impl Default for ClashConfigMerger {
     fn default() -> Self { Self::new() }
 }
 
-/// Quote a scalar string for flow-style YAML output if it contains characters
-/// that would break parsing or could be misinterpreted as another type.
-fn quote_yaml_scalar(s: &str) -> String {
-    let needs_quote = s.is_empty()
-        || s.starts_with(' ')
-        || s.ends_with(' ')
-        || s.starts_with(|c: char| matches!(c, '!' | '&' | '*' | '?' | '|' | '>' | '%' | '@' | '`' | '[' | ']' | '{' | '}' | '#' | ',' | '\'' | '"'))
-        || s.contains(|c: char| matches!(c, ':' | ',' | '{' | '}' | '[' | ']' | '#' | '\n' | '\t' | '\'' | '"' | '`'))
-        || matches!(s.to_ascii_lowercase().as_str(),
-            "true" | "false" | "null" | "yes" | "no" | "on" | "off" | "~")
-        || s.parse::<f64>().is_ok();
-    if needs_quote {
-        format!("'{}'", s.replace('\'', "''"))
-    } else {
-        s.to_string()
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::merger::GroupMatcher;
     use tempfile::TempDir;
 
     fn create_test_config() -> String {
@@ -898,6 +1533,440 @@ rules:
         assert_eq!(p_list.iter().filter(|n| **n == "Chain-Auto").count(), 1);
     }
 
+    #[test]
+    fn test_reapply_replaces_managed_blocks() {
+        let merger = ClashConfigMerger::new();
+        let (once, _) = merger.merge_str(&create_test_config()).unwrap();
+        let (twice, _) = merger.merge_str(&once).unwrap();
+        assert_eq!(once, twice);
+        // Proxies, groups and the main group's references: one block each
+        assert_eq!(once.matches(block::BLOCK_BEGIN).count(), 3);
+        assert_eq!(once.matches(block::BLOCK_END).count(), 3);
+    }
+
+    #[test]
+    fn test_user_chain_named_node_is_kept() {
+        let original = "proxies:\n  - { name: HK, type: ss, server: a.com, port: 443, cipher: aes-256-gcm, password: x }\n  \
+            - { name: Office-Chain, type: ss, server: b.com, port: 443, cipher: aes-256-gcm, password: x }\n\
+            proxy-groups:\n  - { name: Main, type: select, proxies: [HK, Office-Chain] }\n\
+            rules:\n  - MATCH,Main\n";
+        let merger = ClashConfigMerger::new();
+        let (output, result) = merger.merge_str(original).unwrap();
+        assert_eq!(result.chains_created, 2);
+
+        // The user's node stays and gets its own clone
+        let config: Value = serde_yaml::from_str(&output).unwrap();
+        let names: Vec<&str> = config["proxies"].as_sequence().unwrap()
+            .iter().filter_map(|p| p["name"].as_str()).collect();
+        assert!(names.contains(&"Office-Chain"));
+        assert!(names.contains(&"Office-Chain-Chain"));
+
+        let (reverted, _) = merger.unapply_text(&output).unwrap();
+        assert_eq!(reverted, original);
+    }
+
+    #[test]
+    fn test_legacy_unmarked_config_is_migrated() {
+        // Written by a version without managed blocks
+        let legacy = "proxies:\n  - { name: HK, type: ss, server: a.com, port: 443, cipher: aes-256-gcm, password: x }\n  \
+            - { name: HK-Chain, type: socks5, server: 1.2.3.4, port: 1080, dialer-proxy: HK }\n  \
+            - { name: Local-Chain-Proxy, type: socks5, server: 1.2.3.4, port: 1080 }\n\
+            proxy-groups:\n  - { name: Chain-Selector, type: select, proxies: ['HK-Chain'] }\n  \
+            - { name: Main, type: select, proxies: [Chain-Selector, HK] }\n\
+            rules:\n  - MATCH,Main\n";
+        let merger = ClashConfigMerger::new();
+        let (output, _) = merger.merge_str(legacy).unwrap();
+        let (fresh, _) = merger.merge_str(&merger.unapply_text(legacy).unwrap().0).unwrap();
+        assert_eq!(output, fresh);
+        assert!(!output.contains("HK-Chain-Chain"));
+    }
+
+    #[test]
+    fn test_irregular_layout_round_trip() {
+        // Comments between entries, a directive and second document, anchors,
+        // indentation-less sequences and groups before proxies (whose nested
+        // `proxies:` keys must not be taken for the top-level section)
+        let original = "%YAML 1.2\n---\nmixed-port: 7890\nproxy-groups:\n- name: Proxy\n  type: select\n  \
+            proxies:\n  - HK   # primary\n  - JP\nproxies: &nodes\n# Hong Kong\n\
+            - &hk {name: HK, type: ss, server: a.com, port: 443, cipher: aes-256-gcm, password: x}\n\n\
+            # Japan\n- name: JP\n  type: ss\n  server: b.com\n  port: 443\n  cipher: aes-256-gcm\n  password: x\n\
+            rules:\n- MATCH,Proxy\n---\nsecond: document\n";
+        let merger = ClashConfigMerger::new();
+        let (output, result) = merger.merge_str(original).unwrap();
+        assert_eq!(result.chains_created, 2);
+        assert_eq!(result.groups_updated, 1);
+        assert!(output.contains("  proxies:\n  # >>> clash-chain-patcher\n  - Chain-Selector\n"));
+        assert!(output.ends_with("---\nsecond: document\n"));
+
+        let config = yaml::parse_value(&output).unwrap();
+        let names: Vec<&str> = config["proxies"].as_sequence().unwrap()
+            .iter().filter_map(|p| p["name"].as_str()).collect();
+        assert_eq!(names, vec!["HK", "JP", "HK-Chain", "JP-Chain", "Local-Chain-Proxy"]);
+
+        let (reverted, _) = merger.unapply_text(&output).unwrap();
+        assert_eq!(reverted, original);
+    }
+
+    #[test]
+    fn test_broken_markers_are_an_error() {
+        let merger = ClashConfigMerger::new();
+        let (output, _) = merger.merge_str(&create_test_config()).unwrap();
+        let broken = output.replacen(block::BLOCK_END, "# edited", 1);
+        assert!(merger.merge_str(&broken).is_err());
+        assert!(merger.unapply_text(&broken).is_err());
+    }
+
+    #[test]
+    fn test_output_style_follows_file_unless_overridden() {
+        let merger = |style| ClashConfigMerger::with_config(MergerConfig {
+            proxy_host: "1.2.3.4".to_string(),
+            proxy_port: 1080,
+            output_style: style,
+            ..MergerConfig::default()
+        });
+
+        // Block-style file: generated entries are block mappings
+        let (output, _) = merger(OutputStyle::Auto).merge_str(&create_test_config()).unwrap();
+        assert!(output.contains(
+            "  - name: HK-01-Chain\n    type: socks5\n    server: 1.2.3.4\n    port: 1080\n    dialer-proxy: HK-01\n"
+        ));
+        assert!(output.contains(
+            "  - name: Chain-Selector\n    type: select\n    proxies:\n      - HK-01-Chain\n      - JP-01-Chain\n"
+        ));
+        assert!(!output.contains("{ name:"));
+        let (again, _) = merger(OutputStyle::Auto).merge_str(&output).unwrap();
+        assert_eq!(again, output);
+        let (reverted, _) = merger(OutputStyle::Auto).unapply_text(&output).unwrap();
+        assert_eq!(reverted, create_test_config());
+
+        // Explicit override in either direction
+        let (output, _) = merger(OutputStyle::Flow).merge_str(&create_test_config()).unwrap();
+        assert!(output.contains("  - { name: HK-01-Chain, type: socks5, server: 1.2.3.4, port: 1080, dialer-proxy: HK-01 }"));
+        let (output, _) = merger(OutputStyle::Block).merge_str(&create_flow_style_config()).unwrap();
+        assert!(output.contains("- name: Local-Chain-Proxy\n"));
+        let config: Value = serde_yaml::from_str(&output).unwrap();
+        assert_eq!(config["proxies"].as_sequence().unwrap().len(), 7);
+        assert_eq!(config["proxy-groups"][0]["proxies"].as_sequence().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_node_filter_limits_chains() {
+        let merger = |node_filter| ClashConfigMerger::with_config(MergerConfig {
+            node_filter,
+            ..MergerConfig::default()
+        });
+
+        let only_jp = NodeFilter { include: Some("^JP".to_string()), ..NodeFilter::default() };
+        let (output, result) = merger(only_jp).merge_str(&create_test_config()).unwrap();
+        assert_eq!(result.chains_created, 1);
+        assert!(output.contains("JP-01-Chain"));
+        assert!(!output.contains("HK-01-Chain"));
+
+        // A narrower filter on re-apply drops the clones it no longer selects
+        let nothing = NodeFilter { types: vec!["vmess".to_string()], ..NodeFilter::default() };
+        let (output, result) = merger(nothing).merge_str(&output).unwrap();
+        assert_eq!(result.chains_created, 0);
+        assert!(!output.contains("JP-01-Chain"));
+        assert_eq!(result.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_configurable_auto_groups() {
+        let merger = |auto_groups| ClashConfigMerger::with_config(MergerConfig {
+            auto_groups,
+            ..MergerConfig::default()
+        });
+        let mut fallback = AutoGroup::new("Chain-Auto", AutoGroupType::Fallback);
+        fallback.interval = 120;
+        fallback.lazy = Some(true);
+        let balance = AutoGroup::parse("Chain-LB:load-balance,strategy=round-robin").unwrap();
+
+        let (output, _) = merger(vec![fallback.clone(), balance]).merge_str(&create_flow_style_config()).unwrap();
+        assert!(output.contains(
+            "type: fallback, proxies: [🇭🇰 香港01-Chain, 🇯🇵 日本01-Chain, 🇺🇸 美国01-Chain], url: 'http://www.gstatic.com/generate_204', interval: 120, lazy: true }"
+        ));
+        assert!(output.contains("{ name: Chain-LB, type: load-balance,"));
+        assert!(output.contains("interval: 300, strategy: round-robin }"));
+        assert!(output.contains("proxies: [Chain-Selector, Chain-Auto, Chain-LB, 自动选择,"));
+
+        // Dropping a group on re-apply also drops its reference
+        let (output, _) = merger(vec![]).merge_str(&output).unwrap();
+        assert!(output.contains("proxies: [Chain-Selector, 自动选择,"));
+        assert!(!output.contains("Chain-Auto") && !output.contains("Chain-LB"));
+        let (reverted, _) = merger(vec![]).unapply_text(&output).unwrap();
+        assert_eq!(reverted, create_flow_style_config());
+
+        // Names must be unique and not taken by user groups
+        assert!(merger(vec![fallback.clone(), fallback]).merge_str(&create_test_config()).is_err());
+        let taken = AutoGroup::new("Auto", AutoGroupType::UrlTest);
+        assert!(merger(vec![taken]).merge_str(&create_test_config()).is_err());
+    }
+
+    #[test]
+    fn test_use_only_main_group_gets_proxies_list() {
+        let config = "proxy-providers:\n  prov:\n    type: file\n    path: ./prov.yaml\n\
+                      proxies:\n  - { name: HK, type: ss, server: a.com, port: 443, cipher: aes-256-gcm, password: x }\n\
+                      proxy-groups:\n  - name: Use\n    type: select\n    use: [prov]\n  - { name: Flow, type: select, use: [prov] }\n\
+                      rules:\n  - DOMAIN,a.com,Flow\n  - MATCH,Use\n";
+        let merger = ClashConfigMerger::with_config(MergerConfig {
+            inject: InjectConfig {
+                targets: vec![GroupMatcher::parse("Use").unwrap(), GroupMatcher::parse("Flow").unwrap()],
+                ..InjectConfig::default()
+            },
+            ..MergerConfig::default()
+        });
+        let (output, result) = merger.merge_str(config).unwrap();
+        assert_eq!(result.groups_updated, 1);
+        assert_eq!(result.warnings, vec!["'Flow' has no proxies list; add the chain groups to it by hand"]);
+        assert!(output.contains(
+            "    use: [prov]\n    # >>> clash-chain-patcher\n    proxies:\n      - Chain-Selector\n      - Chain-Auto\n    # <<< clash-chain-patcher\n  - { name: Flow"
+        ));
+        let parsed: Value = serde_yaml::from_str(&output).unwrap();
+        let groups = parsed["proxy-groups"].as_sequence().unwrap();
+        let used = groups.iter().find(|g| g["name"] == "Use").unwrap();
+        assert_eq!(used["proxies"][0], "Chain-Selector");
+
+        let (again, _) = merger.merge_str(&output).unwrap();
+        assert_eq!(again, output);
+        let (removed, unapplied) = merger.unapply_text(&output).unwrap();
+        assert_eq!(unapplied.groups_updated, 1);
+        assert_eq!(removed, config);
+    }
+
+    #[test]
+    fn test_inject_targets_and_position() {
+        let merger = |targets: &[&str], position: &str| ClashConfigMerger::with_config(MergerConfig {
+            inject: InjectConfig {
+                targets: targets.iter().map(|t| GroupMatcher::parse(t).unwrap()).collect(),
+                position: position.parse().unwrap(),
+            },
+            ..MergerConfig::default()
+        });
+
+        // Flow lists: after a member, at the top where the member is missing
+        let (output, result) = merger(&["@main", "/^自动/"], "after:自动选择")
+            .merge_str(&create_flow_style_config()).unwrap();
+        assert_eq!(result.groups_updated, 2);
+        assert_eq!(result.warnings, vec!["'自动选择' has no member '自动选择'; chain groups added at the top"]);
+        assert!(output.contains("proxies: [自动选择, Chain-Selector, Chain-Auto, '🇭🇰 香港01',"));
+        assert!(output.contains("proxies: [Chain-Selector, Chain-Auto, '🇭🇰 香港01', '🇯🇵 日本01', '🇺🇸 美国01'],"));
+
+        // Block lists: appended after the last member
+        let (output, _) = merger(&["Proxy", "Auto"], "bottom").merge_str(&create_test_config()).unwrap();
+        assert!(output.contains(
+            "      - \"JP-01\"\n      # >>> clash-chain-patcher\n      - Chain-Selector\n      - Chain-Auto\n      # <<< clash-chain-patcher\n  - name: \"Auto\""
+        ));
+        let (again, _) = merger(&["Proxy", "Auto"], "bottom").merge_str(&output).unwrap();
+        assert_eq!(again, output);
+
+        // Narrower targets on re-apply drop the stale references
+        let (output, result) = merger(&[], "top").merge_str(&output).unwrap();
+        assert_eq!(result.groups_updated, 0);
+        let config: Value = serde_yaml::from_str(&output).unwrap();
+        for group in config["proxy-groups"].as_sequence().unwrap().iter().skip(2) {
+            assert_eq!(group["proxies"].as_sequence().unwrap().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_provider_nodes_chained_through_group() {
+        let config = "mixed-port: 7890\nproxy-providers:\n  sub:\n    type: http\n    url: https://example.com/sub\n    path: ./sub.yaml\n  \
+                      backup:\n    type: file\n    path: ./backup.yaml\n# routing\nrules:\n  - MATCH,DIRECT\n";
+        let merger = ClashConfigMerger::with_config(MergerConfig {
+            providers: Some(vec!["sub".to_string(), "gone".to_string()]),
+            node_filter: NodeFilter { include: Some("HK".to_string()), ..NodeFilter::default() },
+            ..MergerConfig::default()
+        });
+        let (output, result) = merger.merge_str(config).unwrap();
+        assert_eq!(result.chains_created, 1);
+        assert_eq!(result.warnings, vec!["Proxy provider 'gone' not found"]);
+        assert!(output.contains("proxies: # ccp: created\n  # >>> clash-chain-patcher\n  - { name: sub-Chain, type: socks5, server: 127.0.0.1, port: 10808, dialer-proxy: Chain-Via-sub }\n"));
+        assert!(output.contains("  - { name: Chain-Selector, type: select, proxies: [sub-Chain] }\n"));
+        assert!(output.contains("  - { name: Chain-Via-sub, type: url-test, use: [sub], filter: HK, url: 'http://www.gstatic.com/generate_204', interval: 300, tolerance: 50 }\n"));
+        assert!(output.contains("    path: ./backup.yaml\n# routing\nproxies: # ccp: created\n  # >>> clash-chain-patcher\n"));
+        assert!(output.contains("  # <<< clash-chain-patcher\nrules:\n"));
+
+        let parsed: Value = serde_yaml::from_str(&output).unwrap();
+        assert_eq!(parsed["proxy-groups"].as_sequence().unwrap().len(), 3);
+        let (again, _) = merger.merge_str(&output).unwrap();
+        assert_eq!(again, output);
+
+        let (removed, unapplied) = merger.unapply_text(&output).unwrap();
+        assert_eq!(unapplied.proxies_removed, 2);
+        assert_eq!(removed, config);
+        assert_eq!(merger.merge_str(&removed).unwrap().0, output);
+    }
+
+    #[test]
+    fn test_clones_written_to_provider_file() {
+        let merger = |include: Option<&str>| ClashConfigMerger::with_config(MergerConfig {
+            clone_provider: Some("chain-clones.yaml".to_string()),
+            node_filter: NodeFilter { include: include.map(String::from), ..NodeFilter::default() },
+            region_groups: Some(RegionTable::builtin()),
+            ..MergerConfig::default()
+        });
+        let config = create_flow_style_config();
+        let (output, result) = merger(None).merge_str(&config).unwrap();
+        assert!(output.contains("proxy-providers: # ccp: created\n  # >>> clash-chain-patcher\n  Chain-Clones:\n    type: file\n    path: chain-clones.yaml\n"));
+        assert!(!output.contains("Local-Chain-Proxy, type: socks5"));
+        assert!(output.contains("  - { name: Chain-Selector, type: select, use: [Chain-Clones], exclude-filter: '^(?:Local\\-Chain\\-Proxy)$' }"));
+        assert!(output.contains("  - { name: Chain-HK, type: url-test, use: [Chain-Clones], filter: '^(?:🇭🇰 香港01\\-Chain)$',"));
+
+        let file = result.clone_provider.unwrap();
+        assert_eq!(file.path, "chain-clones.yaml");
+        let clones: Value = serde_yaml::from_str(&file.content).unwrap();
+        let names: Vec<&str> = clones["proxies"].as_sequence().unwrap().iter()
+            .map(|p| p["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["🇭🇰 香港01-Chain", "🇯🇵 日本01-Chain", "🇺🇸 美国01-Chain", "Local-Chain-Proxy"]);
+
+        // Fewer nodes: the file changes, the groups over all clones do not
+        let (again, result) = merger(Some("香港|日本")).merge_str(&output).unwrap();
+        assert_eq!(again.replace("Chain-US", "").lines().count() + 1, output.replace("Chain-US", "").lines().count());
+        assert!(result.clone_provider.unwrap().content.lines().count() < file.content.lines().count());
+
+        let (removed, unapplied) = merger(None).unapply_text(&output).unwrap();
+        assert_eq!(unapplied.clone_provider.as_deref(), Some("chain-clones.yaml"));
+        assert_eq!(removed, config);
+    }
+
+    #[test]
+    fn test_multi_hop_chains_in_both_orders() {
+        let hop = |name: &str, host: &str| ChainHop {
+            name: name.to_string(), host: host.to_string(), port: 1080, username: None, password: None, udp: false,
+        };
+        let merger = |order, hops| ClashConfigMerger::with_config(MergerConfig {
+            proxy_host: "1.2.3.4".to_string(),
+            proxy_port: 1080,
+            node_filter: NodeFilter { include: Some("香港".to_string()), ..NodeFilter::default() },
+            hops,
+            chain_order: order,
+            ..MergerConfig::default()
+        });
+        let config = create_flow_style_config();
+
+        let (output, _) = merger(ChainOrder::NodeFirst, vec![hop("Mid", "10.0.0.1")]).merge_str(&config).unwrap();
+        assert!(output.contains("    - { name: 🇭🇰 香港01-Chain-Mid, type: socks5, server: 10.0.0.1, port: 1080, dialer-proxy: 🇭🇰 香港01 }\n\
+            \x20   - { name: 🇭🇰 香港01-Chain, type: socks5, server: 1.2.3.4, port: 1080, dialer-proxy: 🇭🇰 香港01-Chain-Mid }\n\
+            \x20   - { name: Local-Chain-Proxy, type: socks5, server: 1.2.3.4, port: 1080 }\n"));
+        assert!(output.contains("proxies: [🇭🇰 香港01-Chain] }"));
+
+        let (output, result) = merger(ChainOrder::ExitFirst, vec![hop("Mid", "10.0.0.1")]).merge_str(&config).unwrap();
+        assert!(output.contains("    - { name: 🇭🇰 香港01-Chain, type: ss, server: example.com, port: 443, cipher: aes-256-gcm, password: secret, udp: true, dialer-proxy: Mid }\n\
+            \x20   - { name: Local-Chain-Proxy, type: socks5, server: 1.2.3.4, port: 1080 }\n\
+            \x20   - { name: Mid, type: socks5, server: 10.0.0.1, port: 1080, dialer-proxy: Local-Chain-Proxy }\n"));
+        assert_eq!(result.chains_created, 1);
+        let (again, _) = merger(ChainOrder::ExitFirst, vec![hop("Mid", "10.0.0.1")]).merge_str(&output).unwrap();
+        assert_eq!(again, output);
+        assert_eq!(merger(ChainOrder::ExitFirst, Vec::new()).unapply_text(&output).unwrap().0, config);
+
+        let revisit = merger(ChainOrder::NodeFirst, vec![hop("Mid", "10.0.0.1"), hop("Back", "1.2.3.4")]).merge_str(&config);
+        assert_eq!(revisit.unwrap().1.warnings, vec!["Chain visits 1.2.3.4:1080 more than once"]);
+        assert!(merger(ChainOrder::NodeFirst, vec![hop("自动选择", "10.0.0.1")]).merge_str(&config).is_err());
+        let copy_taken = config.replace("name: 自动选择,", "name: 🇭🇰 香港01-Chain-Mid,");
+        assert!(merger(ChainOrder::NodeFirst, vec![hop("Mid", "10.0.0.1")]).merge_str(&copy_taken).is_err());
+    }
+
+    #[test]
+    fn test_incompatible_nodes_skipped_with_warnings() {
+        let config = "proxies:\n\
+            \x20 - { name: HK, type: ss, server: hk, port: 1, cipher: aes-256-gcm, password: x }\n\
+            \x20 - { name: Block, type: reject }\n\
+            \x20 - { name: HY, type: hysteria2, server: hy, port: 443, password: x }\n\
+            \x20 - { name: Via, type: vmess, server: v, port: 1, uuid: u, dialer-proxy: HK }\n\
+            proxy-groups:\n\
+            \x20 - { name: Proxy, type: select, proxies: [HK, HY, Via] }\n";
+        let merger = |order, udp| ClashConfigMerger::with_config(MergerConfig {
+            chain_order: order,
+            proxy_udp: udp,
+            ..MergerConfig::default()
+        });
+
+        let (output, result) = merger(ChainOrder::NodeFirst, false).merge_str(config).unwrap();
+        assert_eq!(result.chains_created, 2);
+        assert!(!output.contains("Block-Chain"));
+        assert!(output.contains("HY-Chain"));
+        assert_eq!(result.warnings, vec![
+            "Not chained, pseudo nodes without a server: Block",
+            "Not chained, nodes that already dial through another proxy: Via",
+            "Chained UDP-based nodes ahead of SOCKS5 hops that do not relay UDP: HY",
+        ]);
+        let (_, result) = merger(ChainOrder::NodeFirst, true).merge_str(config).unwrap();
+        assert_eq!(result.warnings.len(), 2);
+
+        let (output, result) = merger(ChainOrder::ExitFirst, false).merge_str(config).unwrap();
+        assert!(!output.contains("HY-Chain"));
+        assert_eq!(result.warnings[1], "Not chained, UDP-based nodes behind SOCKS5 hops that do not relay UDP: HY");
+        let (output, _) = merger(ChainOrder::ExitFirst, true).merge_str(config).unwrap();
+        assert!(output.contains("  - { name: HY-Chain, type: hysteria2, server: hy, port: 443, password: x, dialer-proxy: Local-Chain-Proxy }\n"));
+        assert!(output.contains("  - { name: Local-Chain-Proxy, type: socks5, server: 127.0.0.1, port: 10808, udp: true }\n"));
+
+        // Nodes the filter drops are not reported
+        let filtered = ClashConfigMerger::with_config(MergerConfig {
+            node_filter: NodeFilter { include: Some("HK".to_string()), ..NodeFilter::default() },
+            ..MergerConfig::default()
+        });
+        assert!(filtered.merge_str(config).unwrap().1.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_dialer_policies_and_cycles() {
+        let config = "proxies:\n\
+            \x20 - { name: HK, type: ss, server: hk, port: 1, cipher: aes-256-gcm, password: x }\n\
+            \x20 - { name: Relay, type: socks5, server: r, port: 1, dialer-proxy: HK }\n\
+            \x20 - { name: Grouped, type: socks5, server: g, port: 1, dialer-proxy: Proxy }\n\
+            proxy-groups:\n\
+            \x20 - { name: Proxy, type: select, proxies: [HK, Relay] }\n";
+        let merger = |policy, include: Option<&str>, inject| ClashConfigMerger::with_config(MergerConfig {
+            dialer_policy: policy,
+            node_filter: NodeFilter { include: include.map(String::from), ..NodeFilter::default() },
+            inject: InjectConfig { targets: if inject { vec![GroupMatcher::Main] } else { Vec::new() }, ..InjectConfig::default() },
+            ..MergerConfig::default()
+        });
+
+        let (_, result) = merger(DialerPolicy::Skip, None, true).merge_str(config).unwrap();
+        assert_eq!(result.chains_created, 1);
+        assert_eq!(result.warnings, vec!["Not chained, nodes that already dial through another proxy: Relay, Grouped"]);
+
+        // Relay flattens to HK, which is chained once
+        let (output, result) = merger(DialerPolicy::Flatten, None, true).merge_str(config).unwrap();
+        assert_eq!(result.chains_created, 1);
+        assert_eq!(result.warnings, vec![
+            "Not chained, nodes whose dialer-proxy chain does not start at a usable proxy: Grouped",
+            "Chained nodes that already dial through another proxy as the first node of their chain: Relay",
+        ]);
+        assert_eq!(merger(DialerPolicy::Flatten, Some("Relay"), true).merge_str(config).unwrap().0, output);
+
+        // Grouped-Chain would dial Grouped, which dials through the group
+        // that now lists Chain-Selector
+        let err = merger(DialerPolicy::Nest, None, true).merge_str(config).unwrap_err();
+        assert_eq!(err.to_string(), "dialer-proxy references would loop: Grouped → Proxy → Chain-Selector → Grouped-Chain → Grouped");
+        let (output, result) = merger(DialerPolicy::Nest, None, false).merge_str(config).unwrap();
+        assert_eq!(result.chains_created, 3);
+        assert!(output.contains("  - { name: Relay-Chain, type: socks5, server: 127.0.0.1, port: 10808, dialer-proxy: Relay }\n"));
+    }
+
+    #[test]
+    fn test_region_groups_from_node_names() {
+        let merger = ClashConfigMerger::with_config(MergerConfig {
+            region_groups: Some(RegionTable::default()),
+            ..MergerConfig::default()
+        });
+        let (output, _) = merger.merge_str(&create_flow_style_config()).unwrap();
+        assert!(output.contains(
+            "    - { name: Chain-HK, type: url-test, proxies: [🇭🇰 香港01-Chain], url: 'http://www.gstatic.com/generate_204', interval: 300, tolerance: 50 }\n"
+        ));
+        assert!(output.contains("{ name: Chain-JP, type: url-test, proxies: [🇯🇵 日本01-Chain],"));
+        assert!(output.contains("{ name: Chain-US, type: url-test, proxies: [🇺🇸 美国01-Chain],"));
+        // Region groups are targets for rules, not entries of the main group
+        assert!(output.contains("proxies: [Chain-Selector, Chain-Auto, 自动选择,"));
+
+        let (again, _) = merger.merge_str(&output).unwrap();
+        assert_eq!(again, output);
+        let (reverted, _) = merger.unapply_text(&output).unwrap();
+        assert_eq!(reverted, create_flow_style_config());
+    }
+
     #[test]
     fn test_chain_clone_is_socks5_with_correct_dialer() {
         // Chain order must be client -> VPN -> SOCKS5 -> target.
@@ -966,21 +2035,136 @@ rules:
     }
 
     #[test]
-    fn test_single_backup() {
+    fn test_backups_are_versioned() {
         let temp_dir = TempDir::new().unwrap();
         let config_path = temp_dir.path().join("config.yaml");
-        fs::write(&config_path, create_test_config()).unwrap();
+        let original = create_test_config();
+        fs::write(&config_path, &original).unwrap();
 
+        // Re-applying the same patch does not add backups
         let merger = ClashConfigMerger::new();
+        let first = merger.merge(&config_path).unwrap().backup_path.unwrap();
+        assert!(merger.merge(&config_path).unwrap().backup_path.is_none());
+
+        // A second, different apply keeps the backup of the original
+        let merger = ClashConfigMerger::with_config(MergerConfig {
+            proxy_port: 1081,
+            ..MergerConfig::default()
+        });
+        let second = merger.merge(&config_path).unwrap().backup_path.unwrap();
+        assert_ne!(first, second);
+        assert_eq!(fs::read_to_string(&first).unwrap(), original);
+
+        let store = crate::backup::BackupStore::default();
+        assert_eq!(store.list(&config_path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_secret_reference_resolved_on_merge() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(&config_path, create_test_config()).unwrap();
+
+        std::env::set_var("CCP_TEST_MERGE_PASS", "resolved-pass");
+        let merger = ClashConfigMerger::with_config(MergerConfig {
+            proxy_username: Some("u".to_string()),
+            proxy_password: Some("env:CCP_TEST_MERGE_PASS".to_string()),
+            create_backup: false,
+            ..MergerConfig::default()
+        });
         merger.merge(&config_path).unwrap();
-        merger.merge(&config_path).unwrap();
+
+        let output = fs::read_to_string(&config_path).unwrap();
+        assert!(output.contains("resolved-pass"));
+        assert!(!output.contains("env:CCP_TEST_MERGE_PASS"));
+
+        // An unresolvable reference fails before the file is touched
+        let merger = ClashConfigMerger::with_config(MergerConfig {
+            proxy_password: Some("env:CCP_TEST_MERGE_PASS_MISSING".to_string()),
+            create_backup: false,
+            ..MergerConfig::default()
+        });
+        assert!(merger.merge(&config_path).is_err());
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), output);
+    }
+
+    #[test]
+    fn test_unapply_restores_flow_style_config() {
+        use std::collections::HashMap;
+
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.yaml");
+        let original = create_flow_style_config();
+        fs::write(&config_path, &original).unwrap();
+
+        let merger = ClashConfigMerger::with_config(MergerConfig {
+            create_backup: false,
+            ..MergerConfig::default()
+        });
         merger.merge(&config_path).unwrap();
 
-        // Should only have 1 backup file
-        let backups: Vec<_> = fs::read_dir(temp_dir.path()).unwrap()
-            .flatten()
-            .filter(|e| e.file_name().to_string_lossy().contains("backup"))
-            .collect();
-        assert_eq!(backups.len(), 1);
+        let content = fs::read_to_string(&config_path).unwrap();
+        let mut replacements = HashMap::new();
+        replacements.insert("蓝海加速".to_string(), "Chain-Selector".to_string());
+        let (content, _) = crate::patcher::rewrite_rules_text(&content, &replacements);
+        let rules = crate::patcher::parse_custom_rule_string("DOMAIN-KEYWORD,lark,DIRECT");
+        let (content, _) = crate::patcher::inject_custom_rules_text(&content, &rules);
+        fs::write(&config_path, content).unwrap();
+
+        let result = merger.unapply(&config_path).unwrap();
+        assert_eq!(result.proxies_removed, 4); // 3 clones + Local-Chain-Proxy
+        assert_eq!(result.groups_removed, 2);
+        assert_eq!(result.groups_updated, 1);
+        assert_eq!(result.rules_restored, 3);
+        assert_eq!(result.rules_removed, 1);
+        assert!(result.warnings.is_empty());
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), original);
+
+        // Nothing left to remove: file is not rewritten
+        let result = merger.unapply(&config_path).unwrap();
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_generated_names() {
+        let merger = ClashConfigMerger::with_config(MergerConfig {
+            region_groups: Some(RegionTable::builtin()),
+            ..MergerConfig::default()
+        });
+        let (output, _) = merger.merge_str(&create_test_config()).unwrap();
+        let names = merger.generated_names(&output).unwrap();
+        for name in ["Chain-Selector", "Chain-Auto", "Chain-HK", "Chain-JP", "HK-01-Chain", "Local-Chain-Proxy"] {
+            assert!(names.contains(name), "{}", name);
+        }
+        assert!(!names.contains("Proxy") && !names.contains("HK-01"));
+    }
+
+    #[test]
+    fn test_unapply_block_style_and_unmarked_rules() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.yaml");
+        let original = create_test_config();
+        fs::write(&config_path, &original).unwrap();
+
+        let merger = ClashConfigMerger::with_config(MergerConfig {
+            create_backup: false,
+            ..MergerConfig::default()
+        });
+        merger.merge(&config_path).unwrap();
+        assert_eq!(merger.unapply(&config_path).unwrap().proxies_removed, 3);
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), original);
+
+        // Rules rewritten by older versions carry no origin comment and
+        // fall back to the group that held Chain-Selector
+        let patched = "proxies:\n  - { name: HK, type: ss, server: a.com, port: 443, cipher: aes-256-gcm, password: x }\n  \
+            - { name: HK-Chain, type: socks5, server: 1.2.3.4, port: 1080, dialer-proxy: HK }\n\
+            proxy-groups:\n  - { name: Chain-Selector, type: select, proxies: [HK-Chain] }\n  \
+            - { name: Main, type: select, proxies: [Chain-Selector, HK] }\n\
+            rules:\n  - MATCH,Chain-Selector\n";
+        let (output, result) = merger.unapply_text(patched).unwrap();
+        assert_eq!(result.rules_restored, 1);
+        assert!(output.contains("- MATCH,Main"));
+        assert!(output.contains("proxies: [HK] }"));
+        assert!(!output.contains("Chain"));
     }
 }
@@ -0,0 +1,159 @@
+//! Which nodes can carry a chain
+//!
+//! A chain dials the SOCKS5 exit through a user node (or, exit first, the
+//! node through the SOCKS5 hops). Pseudo nodes such as `direct` have no
+//! server to do that, and protocols running over UDP (QUIC, WireGuard, KCP)
+//! can only be dialed through SOCKS5 hops that relay UDP. [`TABLE`] decides
+//! per node, from its `type` and transport fields, whether to chain it,
+//! chain it with a warning or skip it. Nodes that already dial through
+//! another proxy are handled by [`super::dialer`].
+
+use serde_yaml::Value;
+
+use super::hop::ChainOrder;
+
+/// What happens to a node that is not simply chained
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Chained, with a warning
+    Warn,
+    /// Not chained
+    Skip,
+}
+
+/// How a node reaches its server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// No server at all (`direct`, `reject`, ...)
+    Pseudo,
+    /// Over UDP
+    Udp,
+    /// Over TCP
+    Tcp,
+}
+
+/// Node types without a server
+const PSEUDO_TYPES: &[&str] = &["direct", "reject", "reject-drop", "pass", "dns", "compatible"];
+
+/// Node types whose protocol runs over UDP
+const UDP_TYPES: &[&str] = &["hysteria", "hysteria2", "tuic", "wireguard", "masque"];
+
+/// One row of [`TABLE`]; `None` matches any chain order or UDP support
+pub struct Rule {
+    pub transport: Transport,
+    pub order: Option<ChainOrder>,
+    /// Whether every SOCKS5 hop relays UDP
+    pub hops_udp: Option<bool>,
+    pub decision: Option<Decision>,
+    pub reason: &'static str,
+}
+
+/// Compatibility table; the first matching row wins, `decision: None`
+/// chains the node
+pub const TABLE: &[Rule] = &[
+    Rule {
+        transport: Transport::Pseudo, order: None, hops_udp: None,
+        decision: Some(Decision::Skip), reason: "pseudo nodes without a server",
+    },
+    Rule {
+        transport: Transport::Udp, order: Some(ChainOrder::ExitFirst), hops_udp: Some(false),
+        decision: Some(Decision::Skip), reason: "UDP-based nodes behind SOCKS5 hops that do not relay UDP",
+    },
+    Rule {
+        transport: Transport::Udp, order: Some(ChainOrder::NodeFirst), hops_udp: Some(false),
+        decision: Some(Decision::Warn), reason: "UDP-based nodes ahead of SOCKS5 hops that do not relay UDP",
+    },
+    Rule {
+        transport: Transport::Udp, order: None, hops_udp: None,
+        decision: None, reason: "",
+    },
+    Rule {
+        transport: Transport::Tcp, order: None, hops_udp: None,
+        decision: None, reason: "",
+    },
+];
+
+/// Transport of a node from its `type` and transport fields (`network`,
+/// the shadowsocks plugin and its mode), after applying `<<` merge keys
+pub fn transport(node: &Value) -> Transport {
+    let mut merged = node.clone();
+    let node = match merged.apply_merge() {
+        Ok(()) => &merged,
+        Err(_) => node,
+    };
+    let field = |value: Option<&Value>| value.and_then(Value::as_str).unwrap_or_default().to_ascii_lowercase();
+    let kind = field(node.get("type"));
+    let plugin = field(node.get("plugin"));
+    let plugin_mode = field(node.get("plugin-opts").and_then(|o| o.get("mode")));
+    if PSEUDO_TYPES.contains(&kind.as_str()) {
+        Transport::Pseudo
+    } else if UDP_TYPES.contains(&kind.as_str())
+        || matches!(field(node.get("network")).as_str(), "quic" | "kcp")
+        || plugin == "kcptun"
+        || plugin_mode == "quic"
+    {
+        Transport::Udp
+    } else {
+        Transport::Tcp
+    }
+}
+
+/// Decision for one node and its reason; `None` chains it silently
+pub fn check(node: &Value, order: ChainOrder, hops_udp: bool) -> Option<(Decision, &'static str)> {
+    let transport = transport(node);
+    let rule = TABLE.iter().find(|rule| {
+        rule.transport == transport
+            && rule.order.is_none_or(|o| o == order)
+            && rule.hops_udp.is_none_or(|u| u == hops_udp)
+    })?;
+    rule.decision.map(|decision| (decision, rule.reason))
+}
+
+/// One warning per decision and reason, listing the node names, in the
+/// order the reasons first appear
+pub fn warnings(notes: Vec<(Decision, &'static str, String)>) -> Vec<String> {
+    let mut grouped: Vec<(Decision, &'static str, Vec<String>)> = Vec::new();
+    for (decision, reason, name) in notes {
+        match grouped.iter_mut().find(|(d, r, _)| *d == decision && *r == reason) {
+            Some((_, _, names)) => names.push(name),
+            None => grouped.push((decision, reason, vec![name])),
+        }
+    }
+    grouped.into_iter()
+        .map(|(decision, reason, names)| match decision {
+            Decision::Skip => format!("Not chained, {}: {}", reason, names.join(", ")),
+            Decision::Warn => format!("Chained {}: {}", reason, names.join(", ")),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(yaml: &str) -> Value {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_table_decisions() {
+        let hy2 = node("{ name: HY, type: hysteria2, server: a, port: 443 }");
+        let quic = node("{ name: SS, type: ss, server: a, port: 1, plugin: v2ray-plugin, plugin-opts: { mode: quic } }");
+        assert_eq!(transport(&quic), Transport::Udp);
+        let merged = node("{ <<: { type: tuic, server: a }, name: T, port: 443 }");
+        assert_eq!(transport(&merged), Transport::Udp);
+        assert_eq!(check(&node("{ name: D, type: direct }"), ChainOrder::NodeFirst, true).unwrap().0, Decision::Skip);
+        assert_eq!(check(&hy2, ChainOrder::NodeFirst, false).unwrap().0, Decision::Warn);
+        assert_eq!(check(&hy2, ChainOrder::NodeFirst, true), None);
+        assert_eq!(check(&hy2, ChainOrder::ExitFirst, false).unwrap().0, Decision::Skip);
+        assert_eq!(check(&hy2, ChainOrder::ExitFirst, true), None);
+        assert_eq!(check(&node("{ name: V, type: vmess, server: a, port: 1, network: ws }"), ChainOrder::ExitFirst, false), None);
+
+        let notes = vec![
+            (Decision::Skip, "pseudo nodes without a server", "A".to_string()),
+            (Decision::Warn, "x", "N".to_string()),
+            (Decision::Skip, "pseudo nodes without a server", "B".to_string()),
+        ];
+        assert_eq!(warnings(notes), vec!["Not chained, pseudo nodes without a server: A, B", "Chained x: N"]);
+    }
+}
@@ -0,0 +1,178 @@
+//! Existing `dialer-proxy` references
+//!
+//! Subscriptions and other chaining tools can ship nodes that already dial
+//! through another proxy. Chaining those again gives an unexpected extra hop,
+//! or a loop when the node dials through a group that the chain groups get
+//! injected into. A [`DialerGraph`] holds who dials through (or may select)
+//! whom; [`DialerPolicy`] decides what happens to already-chained nodes.
+
+use serde_yaml::Value;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// What happens to nodes that already have a `dialer-proxy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DialerPolicy {
+    /// Leave them unchained
+    #[default]
+    Skip,
+    /// Chain the first node of their `dialer-proxy` chain instead
+    Flatten,
+    /// Chain them anyway, one more hop
+    Nest,
+}
+
+impl FromStr for DialerPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "skip" => Ok(Self::Skip),
+            "flatten" => Ok(Self::Flatten),
+            "nest" => Ok(Self::Nest),
+            other => Err(format!("unknown dialer policy '{}' (expected skip, flatten or nest)", other)),
+        }
+    }
+}
+
+/// Proxies and groups with what each dials through or may select
+#[derive(Debug, Default)]
+pub struct DialerGraph {
+    /// Names in config order, for stable cycle reports
+    order: Vec<String>,
+    edges: HashMap<String, Vec<String>>,
+    dialers: HashMap<String, String>,
+    proxies: HashSet<String>,
+}
+
+impl DialerGraph {
+    /// Graph of the config's proxies (`dialer-proxy`) and groups (`proxies`
+    /// members), leaving out the names `skip` matches
+    pub fn from_config(config: &serde_yaml::Mapping, skip: impl Fn(&str) -> bool) -> Self {
+        let entries = |key: &str| config.get(key).and_then(Value::as_sequence).cloned().unwrap_or_default();
+        let name_of = |entry: &Value| entry.get("name").and_then(Value::as_str).map(String::from);
+        let mut graph = Self::default();
+        for proxy in entries("proxies") {
+            let Some(name) = name_of(&proxy).filter(|n| !skip(n)) else { continue };
+            graph.proxies.insert(name.clone());
+            graph.node(&name);
+            if let Some(dialer) = proxy.get("dialer-proxy").and_then(Value::as_str) {
+                graph.dialers.insert(name.clone(), dialer.to_string());
+                graph.add(&name, dialer);
+            }
+        }
+        for group in entries("proxy-groups") {
+            let Some(name) = name_of(&group).filter(|n| !skip(n)) else { continue };
+            graph.node(&name);
+            let members = group.get("proxies").and_then(Value::as_sequence).cloned().unwrap_or_default();
+            for member in members.iter().filter_map(Value::as_str).filter(|m| !skip(m)) {
+                graph.add(&name, member);
+            }
+        }
+        graph
+    }
+
+    fn node(&mut self, name: &str) {
+        if !self.edges.contains_key(name) {
+            self.order.push(name.to_string());
+            self.edges.insert(name.to_string(), Vec::new());
+        }
+    }
+
+    /// `from` dials through or may select `to`
+    pub fn add(&mut self, from: &str, to: &str) {
+        self.node(from);
+        self.node(to);
+        self.edges.get_mut(from).expect("added above").push(to.to_string());
+    }
+
+    /// `dialer-proxy` of a user proxy
+    pub fn dialer(&self, name: &str) -> Option<&str> {
+        self.dialers.get(name).map(String::as_str)
+    }
+
+    /// First node of `name`'s `dialer-proxy` chain, if that is a user proxy
+    /// (not a group, a provider node or a loop)
+    pub fn root(&self, name: &str) -> Option<&str> {
+        let mut seen = HashSet::new();
+        let mut current = name;
+        while let Some(next) = self.dialer(current) {
+            if !seen.insert(current) {
+                return None;
+            }
+            current = next;
+        }
+        self.proxies.get(current).map(String::as_str)
+    }
+
+    /// Some loop through the references, as the names along it with the
+    /// first one repeated at the end
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        // 0: unvisited, 1: on the current path, 2: done
+        let mut state: HashMap<&str, u8> = HashMap::new();
+        for start in &self.order {
+            if state.contains_key(start.as_str()) {
+                continue;
+            }
+            let mut path: Vec<(&str, usize)> = vec![(start.as_str(), 0)];
+            state.insert(start.as_str(), 1);
+            while let Some((name, next)) = path.last_mut() {
+                let name = *name;
+                let Some(to) = self.edges[name].get(*next) else {
+                    state.insert(name, 2);
+                    path.pop();
+                    continue;
+                };
+                *next += 1;
+                match state.get(to.as_str()) {
+                    Some(1) => {
+                        let at = path.iter().position(|(n, _)| *n == to).expect("on the path");
+                        let mut cycle: Vec<String> = path[at..].iter().map(|(n, _)| n.to_string()).collect();
+                        cycle.push(to.clone());
+                        return Some(cycle);
+                    }
+                    Some(_) => {}
+                    None => {
+                        state.insert(to.as_str(), 1);
+                        path.push((to.as_str(), 0));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(yaml: &str) -> DialerGraph {
+        let config: Value = serde_yaml::from_str(yaml).unwrap();
+        DialerGraph::from_config(config.as_mapping().unwrap(), |n| n == "Old-Chain")
+    }
+
+    #[test]
+    fn test_roots_and_cycles() {
+        let mut g = graph("proxies:\n\
+            - { name: HK, type: ss }\n\
+            - { name: Relay, type: socks5, dialer-proxy: HK }\n\
+            - { name: Far, type: socks5, dialer-proxy: Relay }\n\
+            - { name: ViaGroup, type: socks5, dialer-proxy: Proxy }\n\
+            - { name: Old-Chain, type: socks5, dialer-proxy: Far }\n\
+            proxy-groups:\n\
+            - { name: Proxy, type: select, proxies: [ViaGroup, HK, Old-Chain] }\n");
+        assert_eq!(g.root("Far"), Some("HK"));
+        assert_eq!(g.root("HK"), Some("HK"));
+        assert_eq!(g.root("ViaGroup"), None);
+        assert_eq!(g.find_cycle(), Some(vec!["ViaGroup".to_string(), "Proxy".to_string(), "ViaGroup".to_string()]));
+
+        let mut g2 = graph("proxies:\n- { name: A, type: ss }\n- { name: B, type: socks5, dialer-proxy: A }\n");
+        assert_eq!(g2.find_cycle(), None);
+        g2.add("A", "B");
+        assert_eq!(g2.find_cycle().unwrap().join(" → "), "A → B → A");
+        g.add("HK", "HK");
+        assert!(g.find_cycle().is_some());
+        assert_eq!("flatten".parse::<DialerPolicy>(), Ok(DialerPolicy::Flatten));
+    }
+}
@@ -0,0 +1,266 @@
+//! Line-based unified diff for previewing config changes
+//!
+//! Uses the Myers O(ND) algorithm after trimming the common prefix and
+//! suffix, which keeps it cheap for large subscription configs where the
+//! merger only touches a few sections.
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// A single edit step between two line sequences
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    /// Line present in both (old index, new index)
+    Equal(usize, usize),
+    /// Line only in the old text
+    Delete(usize),
+    /// Line only in the new text
+    Insert(usize),
+}
+
+/// Unified diff of `old` against `new` with `context` lines around changes
+///
+/// Returns an empty string when the texts are identical.
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str, context: usize) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&a, &b);
+
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Equal(..)))
+        .map(|(i, _)| i)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // Old/new line counts consumed before each op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut o, mut n) = (0, 0);
+    for op in &ops {
+        positions.push((o, n));
+        match op {
+            Op::Equal(..) => { o += 1; n += 1; }
+            Op::Delete(_) => o += 1,
+            Op::Insert(_) => n += 1,
+        }
+    }
+    positions.push((o, n));
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+
+    let mut i = 0;
+    while i < changes.len() {
+        // Extend the hunk while the next change is within 2 * context lines
+        let mut j = i;
+        while j + 1 < changes.len() && changes[j + 1] - changes[j] <= 2 * context + 1 {
+            j += 1;
+        }
+        let start = changes[i].saturating_sub(context);
+        let end = (changes[j] + context + 1).min(ops.len());
+
+        let (o_start, n_start) = positions[start];
+        let (o_end, n_end) = positions[end];
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(o_start, o_end - o_start),
+            hunk_range(n_start, n_end - n_start)
+        ));
+        for op in &ops[start..end] {
+            match *op {
+                Op::Equal(x, _) => out.push_str(&format!(" {}\n", a[x])),
+                Op::Delete(x) => out.push_str(&format!("-{}\n", a[x])),
+                Op::Insert(y) => out.push_str(&format!("+{}\n", b[y])),
+            }
+        }
+
+        i = j + 1;
+    }
+
+    out
+}
+
+/// Add ANSI colors to a unified diff for terminal output
+pub fn colorize(diff: &str) -> String {
+    let mut out = String::with_capacity(diff.len() + diff.len() / 4);
+    for line in diff.lines() {
+        let color = if line.starts_with("+++") || line.starts_with("---") {
+            BOLD
+        } else if line.starts_with("@@") {
+            CYAN
+        } else if line.starts_with('+') {
+            GREEN
+        } else if line.starts_with('-') {
+            RED
+        } else {
+            ""
+        };
+        if color.is_empty() {
+            out.push_str(line);
+        } else {
+            out.push_str(&format!("{}{}{}", color, line, RESET));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// `start,count` as printed in a hunk header (1-based; `start` is the line
+/// before the hunk when `count` is 0)
+fn hunk_range(start: usize, count: usize) -> String {
+    if count == 0 {
+        format!("{},0", start)
+    } else if count == 1 {
+        format!("{}", start + 1)
+    } else {
+        format!("{},{}", start + 1, count)
+    }
+}
+
+/// Edit script turning `a` into `b`
+fn diff_lines(a: &[&str], b: &[&str]) -> Vec<Op> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let mut ops: Vec<Op> = (0..prefix).map(|i| Op::Equal(i, i)).collect();
+    let middle = myers(&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    ops.extend(middle.into_iter().map(|op| match op {
+        Op::Equal(x, y) => Op::Equal(x + prefix, y + prefix),
+        Op::Delete(x) => Op::Delete(x + prefix),
+        Op::Insert(y) => Op::Insert(y + prefix),
+    }));
+    let (a_tail, b_tail) = (a.len() - suffix, b.len() - suffix);
+    ops.extend((0..suffix).map(|i| Op::Equal(a_tail + i, b_tail + i)));
+    ops
+}
+
+/// Myers shortest edit script
+fn myers(a: &[&str], b: &[&str]) -> Vec<Op> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max as isize {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+            k += 2;
+        }
+    }
+
+    // Walk the trace backwards from (n, m)
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let idx = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) { k + 1 } else { k - 1 };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            ops.push(Op::Equal((x - 1) as usize, (y - 1) as usize));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                ops.push(Op::Insert((y - 1) as usize));
+            } else {
+                ops.push(Op::Delete((x - 1) as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+
+    ops.reverse();
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply_ops(a: &[&str], b: &[&str]) -> Vec<String> {
+        diff_lines(a, b)
+            .into_iter()
+            .filter_map(|op| match op {
+                Op::Equal(x, _) => Some(a[x].to_string()),
+                Op::Insert(y) => Some(b[y].to_string()),
+                Op::Delete(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_identical_texts() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "old", "new", 3), "");
+    }
+
+    #[test]
+    fn test_edit_script_rebuilds_new_text() {
+        let a = ["a", "b", "c", "a", "b", "b", "a"];
+        let b = ["c", "b", "a", "b", "a", "c"];
+        assert_eq!(apply_ops(&a, &b), b);
+
+        let a = ["x", "y"];
+        let b: [&str; 0] = [];
+        assert!(apply_ops(&a, &b).is_empty());
+    }
+
+    #[test]
+    fn test_unified_diff_hunks() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\n";
+        let diff = unified_diff(old, new, "config.yaml", "config.yaml (patched)", 1);
+        assert_eq!(
+            diff,
+            "--- config.yaml\n+++ config.yaml (patched)\n\
+             @@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n\
+             @@ -10 +10,2 @@\n j\n+k\n"
+        );
+    }
+
+    #[test]
+    fn test_insert_into_empty_section() {
+        let diff = unified_diff("", "a\n", "old", "new", 3);
+        assert!(diff.contains("@@ -0,0 +1 @@\n+a\n"));
+    }
+
+    #[test]
+    fn test_colorize() {
+        let colored = colorize("--- a\n+++ b\n@@ -1 +1 @@\n-x\n+y\n z\n");
+        assert!(colored.contains("\x1b[31m-x\x1b[0m"));
+        assert!(colored.contains("\x1b[32m+y\x1b[0m"));
+        assert!(colored.contains("\n z\n"));
+    }
+}
@@ -0,0 +1,179 @@
+//! Which proxy nodes get a chain clone
+//!
+//! By default every node in `proxies` is chained. A [`NodeFilter`] narrows
+//! that down by name (include / exclude regexes), node `type`, `server` and
+//! a cap on the number of nodes, and skips subscription pseudo-nodes matched
+//! by the ignore list.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde_yaml::Value;
+
+/// Subscription pseudo-nodes that only carry a banner (traffic left, expiry
+/// date, website); the seed of the persisted ignore list
+pub const DEFAULT_IGNORE_PATTERNS: &[&str] = &[
+    "剩余流量", "流量重置", "距离下次重置", "套餐到期", "到期时间", "过期时间",
+    "官网", "网址", "若节点超时", "Traffic", "Expire", "Emby", "SOCKS5",
+];
+
+pub fn default_ignore_patterns() -> Vec<String> {
+    DEFAULT_IGNORE_PATTERNS.iter().map(|p| p.to_string()).collect()
+}
+
+/// First of `patterns` that `name` contains (case-insensitive)
+pub fn ignore_match<'a>(patterns: &'a [String], name: &str) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    patterns.iter()
+        .map(String::as_str)
+        .find(|p| !p.is_empty() && name.contains(&p.to_lowercase()))
+}
+
+/// Node selection for the merger; the default selects every node
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodeFilter {
+    /// Only nodes whose name matches this regex
+    pub include: Option<String>,
+    /// Skip nodes whose name matches this regex
+    pub exclude: Option<String>,
+    /// Only nodes of these types (`ss`, `vless`, ...; case-insensitive)
+    pub types: Vec<String>,
+    /// Only nodes whose `server` matches this regex
+    pub server: Option<String>,
+    /// At most this many nodes, in config order, after the other filters
+    pub limit: Option<usize>,
+    /// Skip nodes whose name contains any of these (see [`ignore_match`])
+    pub ignore: Vec<String>,
+}
+
+impl NodeFilter {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Parse the GUI filter box: comma separated terms, where a plain word
+    /// keeps nodes whose name contains it, `!word` drops them, and
+    /// `type:ss`, `server:<regex>` and `limit:20` set the other filters
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut filter = Self::default();
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+
+        for term in spec.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            if let Some(t) = term.strip_prefix("type:") {
+                filter.types.push(t.trim().to_string());
+            } else if let Some(s) = term.strip_prefix("server:") {
+                filter.server = Some(s.trim().to_string());
+            } else if let Some(n) = term.strip_prefix("limit:") {
+                let n = n.trim().parse().with_context(|| format!("Invalid limit '{}'", n.trim()))?;
+                filter.limit = Some(n);
+            } else if let Some(word) = term.strip_prefix('!') {
+                exclude.push(regex::escape(word.trim()));
+            } else {
+                include.push(regex::escape(term));
+            }
+        }
+
+        let keywords = |words: Vec<String>| (!words.is_empty()).then(|| format!("(?i){}", words.join("|")));
+        filter.include = keywords(include);
+        filter.exclude = keywords(exclude);
+        filter.compile()?;
+        Ok(filter)
+    }
+
+    /// Keep the selected `(name, node)` pairs
+    pub fn select(&self, nodes: Vec<(String, Value)>) -> Result<Vec<(String, Value)>> {
+        let compiled = self.compile()?;
+        let field = |node: &Value, key: &str| node.get(key).and_then(Value::as_str).map(String::from);
+
+        let selected = nodes.into_iter().filter(|(name, node)| {
+            ignore_match(&self.ignore, name).is_none()
+                && compiled.include.as_ref().is_none_or(|re| re.is_match(name))
+                && !compiled.exclude.as_ref().is_some_and(|re| re.is_match(name))
+                && (self.types.is_empty()
+                    || field(node, "type").is_some_and(|t| self.types.iter().any(|w| w.eq_ignore_ascii_case(&t))))
+                && compiled.server.as_ref()
+                    .is_none_or(|re| field(node, "server").is_some_and(|s| re.is_match(&s)))
+        });
+        Ok(selected.take(self.limit.unwrap_or(usize::MAX)).collect())
+    }
+
+    fn compile(&self) -> Result<Compiled> {
+        let compile = |what: &str, pattern: &Option<String>| {
+            pattern.as_deref()
+                .map(|p| Regex::new(p).with_context(|| format!("Invalid {} pattern '{}'", what, p)))
+                .transpose()
+        };
+        Ok(Compiled {
+            include: compile("include", &self.include)?,
+            exclude: compile("exclude", &self.exclude)?,
+            server: compile("server", &self.server)?,
+        })
+    }
+}
+
+struct Compiled {
+    include: Option<Regex>,
+    exclude: Option<Regex>,
+    server: Option<Regex>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes() -> Vec<(String, Value)> {
+        [("HK 01", "ss", "hk1.example.com"), ("HK 02", "vless", "hk2.example.com"),
+         ("JP 01", "ss", "jp.example.net"), ("US 01", "trojan", "us.example.com")]
+            .iter()
+            .map(|(name, kind, server)| {
+                let node = serde_yaml::from_str(&format!("{{name: {}, type: {}, server: {}}}", name, kind, server)).unwrap();
+                (name.to_string(), node)
+            })
+            .collect()
+    }
+
+    fn names(filter: &NodeFilter) -> Vec<String> {
+        filter.select(nodes()).unwrap().into_iter().map(|(n, _)| n).collect()
+    }
+
+    #[test]
+    fn test_select_by_name_type_server_and_limit() {
+        assert_eq!(names(&NodeFilter::default()).len(), 4);
+
+        let filter = NodeFilter { include: Some("^(HK|JP)".into()), exclude: Some("02".into()), ..Default::default() };
+        assert_eq!(names(&filter), vec!["HK 01", "JP 01"]);
+
+        let filter = NodeFilter { types: vec!["SS".into()], limit: Some(1), ..Default::default() };
+        assert_eq!(names(&filter), vec!["HK 01"]);
+
+        let filter = NodeFilter { server: Some(r"\.com$".into()), types: vec!["vless".into(), "trojan".into()], ..Default::default() };
+        assert_eq!(names(&filter), vec!["HK 02", "US 01"]);
+
+        let filter = NodeFilter { include: Some("(".into()), ..Default::default() };
+        assert!(filter.select(nodes()).is_err());
+
+        let filter = NodeFilter { ignore: vec!["hk 0".into()], ..Default::default() };
+        assert_eq!(names(&filter), vec!["JP 01", "US 01"]);
+    }
+
+    #[test]
+    fn test_default_ignore_patterns_catch_banners() {
+        let patterns = default_ignore_patterns();
+        assert_eq!(ignore_match(&patterns, "剩余流量：98.5 GB"), Some("剩余流量"));
+        assert_eq!(ignore_match(&patterns, "套餐到期：2026-12-31"), Some("套餐到期"));
+        assert_eq!(ignore_match(&patterns, "Expire: 2026-12-31"), Some("Expire"));
+        assert_eq!(ignore_match(&patterns, "🇭🇰 香港 01"), None);
+    }
+
+    #[test]
+    fn test_parse_filter_box() {
+        let filter = NodeFilter::parse("hk, jp, !02, type:ss, limit:5").unwrap();
+        assert_eq!(filter.types, vec!["ss"]);
+        assert_eq!(filter.limit, Some(5));
+        assert_eq!(names(&filter), vec!["HK 01", "JP 01"]);
+
+        assert!(NodeFilter::parse("").unwrap().is_empty());
+        assert!(NodeFilter::parse("limit:x").is_err());
+        assert_eq!(names(&NodeFilter::parse("a.b").unwrap()), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,291 @@
+//! Multi-hop chains
+//!
+//! By default each chain clone is the SOCKS5 exit dialing through its node:
+//! node → exit. Intermediate hops ([`ChainHop`]) extend that to node → hop …
+//! → exit. Hops depend on the node before them, so every clone gets its own
+//! copy of each hop, nested through `dialer-proxy`.
+//!
+//! [`ChainOrder::ExitFirst`] walks the same chain the other way round, exit
+//! → hop … → node, for exits that whitelist the home IP. There the SOCKS5
+//! entries are shared and the clones are copies of the nodes dialing through
+//! the last hop.
+
+use anyhow::Result;
+use serde_yaml::Value;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use crate::patcher;
+
+/// One SOCKS5 proxy of a chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainHop {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Relays UDP (written as `udp: true`)
+    pub udp: bool,
+}
+
+impl ChainHop {
+    /// Parse `[NAME=]PROXY`, with PROXY in any `--proxy` format; unnamed
+    /// hops are called `default_name`
+    pub fn parse(spec: &str, default_name: &str) -> Result<Self> {
+        let spec = spec.trim();
+        let (name, proxy) = match spec.split_once('=') {
+            Some((name, proxy)) if !name.contains([':', '@']) => (name.trim(), proxy),
+            _ => (default_name, spec),
+        };
+        let Some(proxy) = patcher::parse_proxy_string(proxy) else {
+            anyhow::bail!("Invalid hop '{}' (expected [NAME=]host:port[:user:pass] or [NAME=]user:pass@host:port)", spec);
+        };
+        Ok(Self {
+            name: name.to_string(),
+            host: proxy.host,
+            port: proxy.port,
+            username: proxy.username,
+            password: proxy.password,
+            udp: false,
+        })
+    }
+
+    /// Parse hops in traffic order, naming unnamed ones `Hop-1`, `Hop-2`, …
+    pub fn parse_all(specs: &[String]) -> Result<Vec<Self>> {
+        specs.iter()
+            .enumerate()
+            .map(|(i, spec)| Self::parse(spec, &format!("Hop-{}", i + 1)))
+            .collect()
+    }
+
+    fn address(&self) -> String {
+        format!("{}:{}", self.host.to_ascii_lowercase(), self.port)
+    }
+}
+
+/// Direction of the chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChainOrder {
+    /// node → hops → exit: the exit sees the node's IP
+    #[default]
+    NodeFirst,
+    /// exit → hops (last first) → node: the exit sees the home IP
+    ExitFirst,
+}
+
+impl FromStr for ChainOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "node-first" => Ok(Self::NodeFirst),
+            "exit-first" => Ok(Self::ExitFirst),
+            other => Err(format!("unknown chain order '{}' (expected node-first or exit-first)", other)),
+        }
+    }
+}
+
+/// Outbound written to the proxies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outbound<'a> {
+    /// SOCKS5 entry for `hop`, dialing through `dialer`
+    Socks5 { name: String, hop: &'a ChainHop, dialer: Option<String> },
+    /// Copy of the user node `node`, dialing through `dialer`
+    Node { name: String, node: String, dialer: String },
+}
+
+impl Outbound<'_> {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Socks5 { name, .. } | Self::Node { name, .. } => name,
+        }
+    }
+
+    pub fn dialer(&self) -> Option<&str> {
+        match self {
+            Self::Socks5 { dialer, .. } => dialer.as_deref(),
+            Self::Node { dialer, .. } => Some(dialer),
+        }
+    }
+}
+
+/// Name of the copy of `hop` that dials through the clone's node
+pub fn hop_name(clone: &str, hop: &ChainHop) -> String {
+    format!("{}-{}", clone, hop.name)
+}
+
+/// Outbounds for the chained `sources` (what the chain starts from: a node
+/// or a provider group, and the clone's name), followed by the shared ones.
+/// In exit-first order every source must be a node.
+pub fn outbounds<'a>(
+    exit: &'a ChainHop,
+    hops: &'a [ChainHop],
+    order: ChainOrder,
+    sources: &[(String, String)],
+) -> Vec<Outbound<'a>> {
+    let mut out = Vec::new();
+    match order {
+        ChainOrder::NodeFirst => {
+            for (source, clone) in sources {
+                let mut dialer = source.clone();
+                for hop in hops {
+                    let name = hop_name(clone, hop);
+                    out.push(Outbound::Socks5 { name: name.clone(), hop, dialer: Some(dialer) });
+                    dialer = name;
+                }
+                out.push(Outbound::Socks5 { name: clone.clone(), hop: exit, dialer: Some(dialer) });
+            }
+            out.push(Outbound::Socks5 { name: exit.name.clone(), hop: exit, dialer: None });
+        }
+        ChainOrder::ExitFirst => {
+            let last = hops.first().map_or(&exit.name, |h| &h.name);
+            for (node, clone) in sources {
+                out.push(Outbound::Node { name: clone.clone(), node: node.clone(), dialer: last.clone() });
+            }
+            out.push(Outbound::Socks5 { name: exit.name.clone(), hop: exit, dialer: None });
+            let mut dialer = &exit.name;
+            for hop in hops.iter().rev() {
+                out.push(Outbound::Socks5 { name: hop.name.clone(), hop, dialer: Some(dialer.clone()) });
+                dialer = &hop.name;
+            }
+        }
+    }
+    out
+}
+
+/// `exclude-filter` matching the outbounds that are not clones: the exit and
+/// the shared hops (exit first) or each clone's hop copies (node first)
+pub fn helper_filter(exit: &str, hops: &[ChainHop], order: ChainOrder, chain_suffix: &str) -> String {
+    let escape = |names: Vec<&str>| names.into_iter().map(regex::escape).collect::<Vec<_>>().join("|");
+    let hop_names: Vec<&str> = hops.iter().map(|h| h.name.as_str()).collect();
+    match order {
+        ChainOrder::ExitFirst => {
+            format!("^(?:{})$", escape(std::iter::once(exit).chain(hop_names).collect()))
+        }
+        ChainOrder::NodeFirst if hops.is_empty() => format!("^(?:{})$", regex::escape(exit)),
+        ChainOrder::NodeFirst => {
+            format!("^(?:{})$|{}-(?:{})$", regex::escape(exit), regex::escape(chain_suffix), escape(hop_names))
+        }
+    }
+}
+
+/// Reject chains whose outbounds cannot be named apart: hops without a
+/// name, names used twice, and hop names (or, node first, the per-clone hop
+/// copies of `clones`) already `taken` by a user proxy or group. Servers
+/// visited twice only warn: each visit is its own outbound, so nothing
+/// loops. Returns the warnings.
+pub fn check(
+    exit: &ChainHop,
+    hops: &[ChainHop],
+    order: ChainOrder,
+    clones: &[String],
+    taken: impl IntoIterator<Item = String>,
+) -> Result<Vec<String>> {
+    let mut names = HashSet::from([exit.name.as_str()]);
+    let mut addresses = HashSet::from([exit.address()]);
+    let mut warnings = Vec::new();
+    for hop in hops {
+        if hop.name.trim().is_empty() {
+            anyhow::bail!("Hop {} has no name", hop.address());
+        }
+        if !names.insert(hop.name.as_str()) {
+            anyhow::bail!("Hop name '{}' is used twice in the chain", hop.name);
+        }
+        if !addresses.insert(hop.address()) {
+            warnings.push(format!("Chain visits {} more than once", hop.address()));
+        }
+    }
+    let copies: HashSet<String> = match order {
+        ChainOrder::NodeFirst => clones.iter().flat_map(|c| hops.iter().map(move |h| hop_name(c, h))).collect(),
+        ChainOrder::ExitFirst => HashSet::new(),
+    };
+    for name in taken {
+        if hops.iter().any(|h| h.name == name) {
+            anyhow::bail!("Hop name '{}' already names a proxy or group in the config; rename the hop", name);
+        }
+        if copies.contains(&name) {
+            anyhow::bail!("Hop copy '{}' already names a proxy or group in the config; rename the hop", name);
+        }
+    }
+    Ok(warnings)
+}
+
+/// Copy of a node's mapping under `name`, dialing through `dialer`
+pub fn node_copy(node: &Value, name: &str, dialer: &str) -> Value {
+    let mut copy = node.clone();
+    if let Some(map) = copy.as_mapping_mut() {
+        map.insert(Value::from("name"), Value::from(name));
+        map.insert(Value::from("dialer-proxy"), Value::from(dialer));
+    }
+    copy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hop(name: &str, host: &str) -> ChainHop {
+        ChainHop { name: name.to_string(), host: host.to_string(), port: 1080, username: None, password: None, udp: false }
+    }
+
+    #[test]
+    fn test_parse_hops() {
+        let hops = ChainHop::parse_all(&["Mid=10.0.0.1:1080".to_string(), "u:p@10.0.0.2:1081".to_string()]).unwrap();
+        assert_eq!(hops[0], hop("Mid", "10.0.0.1"));
+        assert_eq!((hops[1].name.as_str(), hops[1].port, hops[1].password.as_deref()), ("Hop-2", 1081, Some("p")));
+        assert!(ChainHop::parse("Mid=nowhere", "Hop-1").is_err());
+        assert_eq!("exit-first".parse::<ChainOrder>(), Ok(ChainOrder::ExitFirst));
+    }
+
+    #[test]
+    fn test_outbounds_in_both_orders() {
+        let exit = hop("Local-Chain-Proxy", "1.2.3.4");
+        let hops = vec![hop("A", "10.0.0.1"), hop("B", "10.0.0.2")];
+        let sources = vec![("HK".to_string(), "HK-Chain".to_string())];
+        let dialers = |order| -> Vec<(String, Option<String>)> {
+            outbounds(&exit, &hops, order, &sources).into_iter()
+                .map(|o| match o {
+                    Outbound::Socks5 { name, dialer, .. } => (name, dialer),
+                    Outbound::Node { name, dialer, .. } => (name, Some(dialer)),
+                })
+                .collect()
+        };
+        let pair = |n: &str, d: Option<&str>| (n.to_string(), d.map(String::from));
+        assert_eq!(dialers(ChainOrder::NodeFirst), vec![
+            pair("HK-Chain-A", Some("HK")),
+            pair("HK-Chain-B", Some("HK-Chain-A")),
+            pair("HK-Chain", Some("HK-Chain-B")),
+            pair("Local-Chain-Proxy", None),
+        ]);
+        assert_eq!(dialers(ChainOrder::ExitFirst), vec![
+            pair("HK-Chain", Some("A")),
+            pair("Local-Chain-Proxy", None),
+            pair("B", Some("Local-Chain-Proxy")),
+            pair("A", Some("B")),
+        ]);
+        assert_eq!(helper_filter("Local-Chain-Proxy", &hops, ChainOrder::NodeFirst, "-Chain"), r"^(?:Local\-Chain\-Proxy)$|\-Chain-(?:A|B)$");
+        assert_eq!(helper_filter("L", &hops, ChainOrder::ExitFirst, "-Chain"), "^(?:L|A|B)$");
+    }
+
+    #[test]
+    fn test_check_names_and_revisits() {
+        let exit = hop("Local-Chain-Proxy", "1.2.3.4");
+        let check = |hops: &[ChainHop], order, taken: &[&str]| {
+            let clones = vec!["HK-Chain".to_string()];
+            check(&exit, hops, order, &clones, taken.iter().map(|t| t.to_string()))
+        };
+        let node_first = ChainOrder::NodeFirst;
+        assert_eq!(check(&[hop("A", "10.0.0.1"), hop("B", "10.0.0.2")], node_first, &[]).unwrap(), Vec::<String>::new());
+        let warnings = check(&[hop("A", "10.0.0.1"), hop("B", "1.2.3.4")], node_first, &[]).unwrap();
+        assert_eq!(warnings, vec!["Chain visits 1.2.3.4:1080 more than once"]);
+        assert!(check(&[hop("A", "10.0.0.1"), hop("A", "10.0.0.2")], node_first, &[]).is_err());
+        assert!(check(&[hop("Local-Chain-Proxy", "10.0.0.1")], node_first, &[]).is_err());
+        assert!(check(&[hop("HK", "10.0.0.1")], node_first, &["HK"]).is_err());
+
+        // Node first, every clone gets its own copy of each hop
+        let err = check(&[hop("Mid", "10.0.0.1")], node_first, &["HK-Chain-Mid"]).unwrap_err();
+        assert_eq!(err.to_string(), "Hop copy 'HK-Chain-Mid' already names a proxy or group in the config; rename the hop");
+        assert!(check(&[hop("Mid", "10.0.0.1")], ChainOrder::ExitFirst, &["HK-Chain-Mid"]).is_ok());
+    }
+}
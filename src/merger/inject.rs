@@ -0,0 +1,163 @@
+//! Where references to the chain groups are added
+//!
+//! By default `Chain-Selector` and the auto groups are prepended to the
+//! detected main group. An [`InjectConfig`] picks other target groups (by
+//! name or regex, e.g. service groups such as "AI" or "Streaming"), where in
+//! their member list the references go, or turns injection off.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::str::FromStr;
+
+/// One target of the injection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupMatcher {
+    /// The detected main group (`@main`)
+    Main,
+    /// A group with exactly this name
+    Name(String),
+    /// Groups whose name matches this regex (`/regex/`)
+    Pattern(String),
+}
+
+impl GroupMatcher {
+    /// Parse `@main`, `/regex/` or a plain group name
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        if spec == "@main" {
+            return Ok(Self::Main);
+        }
+        match spec.strip_prefix('/').and_then(|s| s.strip_suffix('/')) {
+            Some(pattern) if !pattern.is_empty() => {
+                Regex::new(pattern).with_context(|| format!("Invalid group pattern '{}'", pattern))?;
+                Ok(Self::Pattern(pattern.to_string()))
+            }
+            _ if spec.is_empty() => anyhow::bail!("Empty inject target"),
+            _ => Ok(Self::Name(spec.to_string())),
+        }
+    }
+}
+
+/// Position of the references in a target group's member list
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum InjectPosition {
+    #[default]
+    Top,
+    Bottom,
+    /// Right after this member (at the top when the group lacks it)
+    After(String),
+}
+
+impl FromStr for InjectPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim() {
+            "top" => Ok(Self::Top),
+            "bottom" => Ok(Self::Bottom),
+            other => match other.strip_prefix("after:") {
+                Some(member) if !member.trim().is_empty() => Ok(Self::After(member.trim().to_string())),
+                _ => Err(format!("unknown position '{}' (expected top, bottom or after:<member>)", other)),
+            },
+        }
+    }
+}
+
+/// Target groups and position of the chain group references
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InjectConfig {
+    /// Groups that get the references; empty turns injection off
+    pub targets: Vec<GroupMatcher>,
+    pub position: InjectPosition,
+}
+
+impl Default for InjectConfig {
+    fn default() -> Self {
+        Self { targets: vec![GroupMatcher::Main], position: InjectPosition::Top }
+    }
+}
+
+impl InjectConfig {
+    /// No group gets references to the chain groups
+    pub fn none() -> Self {
+        Self { targets: Vec::new(), position: InjectPosition::Top }
+    }
+
+    /// Names among `groups` (user groups, in config order) that get the
+    /// references, plus a warning for each named target that is missing
+    pub fn select(&self, groups: &[String], main: Option<&str>) -> Result<(Vec<String>, Vec<String>)> {
+        let mut selected: Vec<String> = Vec::new();
+        let mut warnings = Vec::new();
+        for target in &self.targets {
+            let matches: Vec<&String> = match target {
+                GroupMatcher::Main => groups.iter().filter(|g| Some(g.as_str()) == main).collect(),
+                GroupMatcher::Name(name) => {
+                    let found: Vec<&String> = groups.iter().filter(|g| *g == name).collect();
+                    if found.is_empty() {
+                        warnings.push(format!("Inject target group '{}' not found", name));
+                    }
+                    found
+                }
+                GroupMatcher::Pattern(pattern) => {
+                    let re = Regex::new(pattern).with_context(|| format!("Invalid group pattern '{}'", pattern))?;
+                    groups.iter().filter(|g| re.is_match(g)).collect()
+                }
+            };
+            for name in matches {
+                if !selected.contains(name) {
+                    selected.push(name.clone());
+                }
+            }
+        }
+        Ok((selected, warnings))
+    }
+
+    /// Index in the `members` of `group` where the references go; warns
+    /// when an `After` member is missing
+    pub fn insert_index(&self, group: &str, members: &[String], warnings: &mut Vec<String>) -> usize {
+        match &self.position {
+            InjectPosition::Top => 0,
+            InjectPosition::Bottom => members.len(),
+            InjectPosition::After(member) => match members.iter().position(|m| m == member) {
+                Some(i) => i + 1,
+                None => {
+                    warnings.push(format!("'{}' has no member '{}'; chain groups added at the top", group, member));
+                    0
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_targets_and_position() {
+        let groups: Vec<String> = ["Proxy", "AI", "Streaming", "Final"].iter().map(|s| s.to_string()).collect();
+        let config = InjectConfig {
+            targets: vec![
+                GroupMatcher::parse("@main").unwrap(),
+                GroupMatcher::parse("/^(AI|Stream)/").unwrap(),
+                GroupMatcher::parse("Missing").unwrap(),
+            ],
+            position: "after:DIRECT".parse().unwrap(),
+        };
+        let (selected, warnings) = config.select(&groups, Some("Proxy")).unwrap();
+        assert_eq!(selected, vec!["Proxy", "AI", "Streaming"]);
+        assert_eq!(warnings, vec!["Inject target group 'Missing' not found"]);
+        assert!(InjectConfig::none().select(&groups, Some("Proxy")).unwrap().0.is_empty());
+
+        let members: Vec<String> = ["HK", "DIRECT", "JP"].iter().map(|s| s.to_string()).collect();
+        let mut warnings = Vec::new();
+        assert_eq!(config.insert_index("AI", &members, &mut warnings), 2);
+        assert_eq!(config.insert_index("AI", &members[..1], &mut warnings), 0);
+        assert_eq!(warnings, vec!["'AI' has no member 'DIRECT'; chain groups added at the top"]);
+        let bottom = InjectConfig { position: InjectPosition::Bottom, ..InjectConfig::default() };
+        assert_eq!(bottom.insert_index("AI", &members, &mut warnings), 3);
+
+        assert!(GroupMatcher::parse("/(/").is_err());
+        assert!("middle".parse::<InjectPosition>().is_err());
+    }
+}
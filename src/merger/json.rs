@@ -0,0 +1,957 @@
+//! JSON Clash configs
+//!
+//! Clash and Mihomo accept JSON configs, since JSON is valid YAML. JSON has
+//! no comments, so the managed blocks used for YAML cannot be written there.
+//! Instead the config is edited structurally (key order kept) and written back
+//! over its source: unchanged values keep their text, new ones follow the
+//! indentation and layout around them. What the patcher generated is recorded
+//! under a top-level [`MANIFEST_KEY`] entry, which Clash ignores.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use super::clash_merger::{ClashConfigMerger, MergeResult, UnapplyResult, CHAIN_GROUPS};
+use super::hop::{self, ChainHop, Outbound};
+use super::provider::{self, ProviderFile};
+use crate::patcher::{self, RuleRevert};
+use crate::yaml;
+
+/// Top-level key listing what the patcher generated
+pub const MANIFEST_KEY: &str = "x-clash-chain-patcher";
+
+/// Whether `content` is a JSON config (an object, optionally after a BOM)
+pub fn is_json(content: &str) -> bool {
+    let body = content.trim_start_matches('\u{feff}').trim_start();
+    body.starts_with('{') && serde_json::from_str::<serde::de::IgnoredAny>(body).is_ok()
+}
+
+/// Names and rules written by the patcher, stored under [`MANIFEST_KEY`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Manifest {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub proxies: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub proxy_groups: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub proxy_providers: Vec<String>,
+    /// Injected custom rules
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<String>,
+    /// Rewritten rules and the group each originally pointed at
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rewritten: Vec<Rewrite>,
+    /// Top-level keys the patcher added
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub created: Vec<String>,
+    /// `use:`-only groups the patcher gave a `proxies` list
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub proxy_lists: Vec<String>,
+}
+
+/// A rewritten rule (JSON counterpart of the `# ccp: was` comment)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rewrite {
+    pub rule: String,
+    pub was: String,
+}
+
+impl Manifest {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A parsed JSON config plus the source text it is written back over
+pub struct JsonConfig {
+    pub root: Map<String, Value>,
+    pub manifest: Manifest,
+    /// Source with LF line endings and no BOM, and the spans of its values
+    source: String,
+    tree: Node,
+    /// Indent unit; `None` for single-line (minified) JSON
+    unit: Option<String>,
+    /// `": "` / `", "` rather than `":"` / `","`
+    spaced: bool,
+    crlf: bool,
+    bom: bool,
+}
+
+impl JsonConfig {
+    pub fn parse(content: &str) -> Result<Self> {
+        let bom = content.starts_with('\u{feff}');
+        let crlf = content.contains("\r\n");
+        // Raw newlines only occur between tokens, so this keeps the values
+        let source = content.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+        let root = match serde_json::from_str(&source).context("JSON parse error")? {
+            Value::Object(map) => map,
+            _ => anyhow::bail!("Config root must be a JSON object"),
+        };
+        let manifest = match root.get(MANIFEST_KEY) {
+            Some(value) => serde_json::from_value(value.clone())
+                .with_context(|| format!("Invalid '{}' entry", MANIFEST_KEY))?,
+            None => Manifest::default(),
+        };
+        let tree = Scanner { text: &source, pos: 0 }.value().context("JSON parse error")?;
+
+        // The first indented line gives the indent unit of one level
+        let body = &source[tree.range.clone()];
+        let unit = body.contains('\n').then(|| {
+            body.lines()
+                .skip(1)
+                .map(|line| &line[..line.len() - line.trim_start().len()])
+                .find(|ws| !ws.is_empty())
+                .unwrap_or("  ")
+                .to_string()
+        });
+        let spaced = body.contains("\": ") || body.contains("\" : ");
+
+        Ok(Self { root, manifest, source, tree, unit, spaced, crlf, bom })
+    }
+
+    /// Mutable list under a top-level key (`None` when missing or not a list)
+    pub fn list_mut(&mut self, key: &str) -> Option<&mut Vec<Value>> {
+        self.root.get_mut(key).and_then(Value::as_array_mut)
+    }
+
+    /// Add `value` under `key`, right before the first of the `before` keys
+    /// that is present (last otherwise)
+    pub fn insert_before(&mut self, key: &str, value: Value, before: &[&str]) {
+        match self.root.keys().position(|k| before.contains(&k.as_str())) {
+            Some(at) => {
+                self.root.shift_insert(at, key.to_string(), value);
+            }
+            None => {
+                self.root.insert(key.to_string(), value);
+            }
+        }
+    }
+
+    /// Remove the providers the manifest lists; returns the removed ones
+    fn take_providers(&mut self) -> Map<String, Value> {
+        let names = std::mem::take(&mut self.manifest.proxy_providers);
+        let Some(providers) = self.root.get_mut("proxy-providers").and_then(Value::as_object_mut) else {
+            return Map::new();
+        };
+        names.iter().filter_map(|n| providers.shift_remove_entry(n)).collect()
+    }
+
+    /// Write back over the source: unchanged values keep their text, new ones
+    /// follow the layout around them. An empty manifest is dropped.
+    pub fn render(mut self) -> Result<String> {
+        if self.manifest.is_empty() {
+            self.root.shift_remove(MANIFEST_KEY);
+        } else {
+            // Insert keeps the position of an existing entry
+            self.root.insert(MANIFEST_KEY.to_string(), serde_json::to_value(&self.manifest)?);
+        }
+
+        let root = Value::Object(std::mem::take(&mut self.root));
+        let mut text = format!(
+            "{}{}{}",
+            &self.source[..self.tree.range.start],
+            self.write_value(&root, Some(&self.tree), "", false),
+            &self.source[self.tree.range.end..],
+        );
+        if self.crlf {
+            text = text.replace('\n', "\r\n");
+        }
+        if self.bom {
+            text.insert(0, '\u{feff}');
+        }
+        Ok(text)
+    }
+
+    fn text_of(&self, node: &Node) -> &str {
+        &self.source[node.range.clone()]
+    }
+
+    /// Indent of the line `node` starts on, if nothing precedes it there
+    fn line_indent(&self, node: &Node) -> Option<String> {
+        let before = &self.source[..node.range.start];
+        let line = &before[before.rfind('\n').map_or(0, |i| i + 1)..];
+        line.chars().all(char::is_whitespace).then(|| line.to_string())
+    }
+
+    /// `value` as text starting on a line indented by `indent`; `node` is
+    /// the value it replaces, `inline` whether the parent is on one line
+    fn write_value(&self, value: &Value, node: Option<&Node>, indent: &str, inline: bool) -> String {
+        if let Some(node) = node {
+            if serde_json::from_str::<Value>(self.text_of(node)).ok().as_ref() == Some(value) {
+                return self.text_of(node).to_string();
+            }
+        }
+        let children: &[(Option<String>, Node)] = node.map_or(&[], |n| &n.children);
+        let parts: Vec<(Option<&String>, &Value, Option<&Node>)> = match value {
+            Value::Array(items) => {
+                // Pair items with the original ones: equal first, then same name
+                let originals: Vec<Option<Value>> = children.iter()
+                    .map(|(_, n)| serde_json::from_str(self.text_of(n)).ok())
+                    .collect();
+                let mut by_text: HashMap<String, Vec<usize>> = HashMap::new();
+                let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+                for (i, original) in originals.iter().enumerate().rev() {
+                    if let Some(original) = original {
+                        by_text.entry(original.to_string()).or_default().push(i);
+                        if let Some(name) = name_of(original) {
+                            by_name.entry(name).or_default().push(i);
+                        }
+                    }
+                }
+                let mut used = vec![false; children.len()];
+                let mut take = |indices: Option<&mut Vec<usize>>| {
+                    let indices = indices?;
+                    while let Some(i) = indices.pop() {
+                        if !used[i] {
+                            used[i] = true;
+                            return Some(i);
+                        }
+                    }
+                    None
+                };
+                items.iter()
+                    .map(|item| {
+                        let matched = take(by_text.get_mut(&item.to_string()))
+                            .or_else(|| take(name_of(item).and_then(|n| by_name.get_mut(n))));
+                        (None, item, matched.map(|i| &children[i].1))
+                    })
+                    .collect()
+            }
+            Value::Object(map) => map.iter()
+                .map(|(key, item)| {
+                    let original = children.iter().find(|(k, _)| k.as_ref() == Some(key));
+                    (Some(key), item, original.map(|(_, n)| n))
+                })
+                .collect(),
+            scalar => return scalar.to_string(),
+        };
+        let (open, close) = if value.is_array() { ('[', ']') } else { ('{', '}') };
+        if parts.is_empty() {
+            return format!("{}{}", open, close);
+        }
+
+        // Keep a collection on one line if it was; new ones follow the parent
+        let Some(unit) = &self.unit else {
+            return self.write_inline(open, close, parts, indent);
+        };
+        let one_line = match node {
+            Some(n) if !n.children.is_empty() => !self.text_of(n).contains('\n'),
+            _ => inline,
+        };
+        if one_line {
+            return self.write_inline(open, close, parts, indent);
+        }
+
+        let child_indent = children.first()
+            .and_then(|(_, n)| self.line_indent(n))
+            .unwrap_or_else(|| format!("{}{}", indent, unit));
+        // New items are written on one line when their siblings are
+        let siblings_inline = children.first()
+            .is_some_and(|(_, n)| !n.children.is_empty() && !self.text_of(n).contains('\n'));
+        let items: Vec<String> = parts.into_iter()
+            .map(|(key, item, original)| {
+                self.with_key(key, self.write_value(item, original, &child_indent, siblings_inline))
+            })
+            .collect();
+        format!(
+            "{}\n{}{}\n{}{}",
+            open, child_indent, items.join(&format!(",\n{}", child_indent)), indent, close
+        )
+    }
+
+    fn write_inline(&self, open: char, close: char, parts: Vec<(Option<&String>, &Value, Option<&Node>)>, indent: &str) -> String {
+        let items: Vec<String> = parts.into_iter()
+            .map(|(key, item, original)| self.with_key(key, self.write_value(item, original, indent, true)))
+            .collect();
+        let comma = if self.spaced { ", " } else { "," };
+        format!("{}{}{}", open, items.join(comma), close)
+    }
+
+    fn with_key(&self, key: Option<&String>, value: String) -> String {
+        match key {
+            Some(key) => {
+                let colon = if self.spaced { ": " } else { ":" };
+                format!("{}{}{}", Value::String(key.clone()), colon, value)
+            }
+            None => value,
+        }
+    }
+}
+
+/// Source span of a JSON value, with its items or members
+struct Node {
+    range: Range<usize>,
+    children: Vec<(Option<String>, Node)>,
+}
+
+/// Locates values in JSON that serde_json has already accepted
+struct Scanner<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Scanner<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Option<Node> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let mut children = Vec::new();
+        match self.peek()? {
+            open @ (b'{' | b'[') => {
+                self.pos += 1;
+                loop {
+                    self.skip_whitespace();
+                    match self.peek()? {
+                        b'}' | b']' => break,
+                        b',' => {
+                            self.pos += 1;
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    let key = if open == b'{' {
+                        let key_start = self.pos;
+                        self.string()?;
+                        let key = serde_json::from_str(&self.text[key_start..self.pos]).ok()?;
+                        self.skip_whitespace();
+                        (self.peek()? == b':').then_some(())?;
+                        self.pos += 1;
+                        Some(key)
+                    } else {
+                        None
+                    };
+                    children.push((key, self.value()?));
+                }
+                self.pos += 1;
+            }
+            b'"' => self.string()?,
+            _ => {
+                while !matches!(self.peek(), None | Some(b',' | b']' | b'}' | b' ' | b'\t' | b'\n' | b'\r')) {
+                    self.pos += 1;
+                }
+            }
+        }
+        Some(Node { range: start..self.pos, children })
+    }
+
+    /// Skip a string starting at its opening quote
+    fn string(&mut self) -> Option<()> {
+        self.pos += 1;
+        loop {
+            match self.peek()? {
+                b'\\' => self.pos += 2,
+                b'"' => {
+                    self.pos += 1;
+                    return Some(());
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+}
+
+fn name_of(entry: &Value) -> Option<&str> {
+    entry.get("name").and_then(Value::as_str)
+}
+
+/// Drop chain group references (`refs`) from a group's `proxies`; true if
+/// any were
+fn strip_chain_refs(group: &mut Value, refs: &HashSet<String>) -> bool {
+    let Some(proxies) = group.get_mut("proxies").and_then(Value::as_array_mut) else {
+        return false;
+    };
+    let before = proxies.len();
+    proxies.retain(|p| !p.as_str().is_some_and(|p| refs.contains(p)));
+    proxies.len() != before
+}
+
+/// Remove the `proxies` list of a group in `created` (see
+/// [`Manifest::proxy_lists`]) once it is empty again
+fn drop_created_list(group: &mut Value, created: &[String]) {
+    let ours = name_of(group).is_some_and(|n| created.iter().any(|c| c == n));
+    let empty = group.get("proxies").and_then(Value::as_array).is_some_and(Vec::is_empty);
+    if ours && empty {
+        if let Some(map) = group.as_object_mut() {
+            map.shift_remove("proxies");
+        }
+    }
+}
+
+impl ClashConfigMerger {
+    /// JSON counterpart of the text merge
+    pub(super) fn merge_json(&self, content: &str, result: &mut MergeResult) -> Result<String> {
+        let parsed = yaml::parse_value(content).context("JSON parse error")?;
+        let config_map = parsed.as_mapping().context("Config root must be a JSON object")?;
+        let mut config = JsonConfig::parse(content)?;
+        let settings = self.config();
+
+        for (key, before) in [("proxies", &["proxy-groups", "rules"][..]), ("proxy-groups", &["rules"][..])] {
+            match config.root.get(key) {
+                Some(Value::Array(_)) => {}
+                Some(Value::Null) => {
+                    config.root.insert(key.to_string(), json!([]));
+                }
+                Some(_) => anyhow::bail!("'{}' must be a list to be patched", key),
+                None => {
+                    config.insert_before(key, json!([]), before);
+                    config.manifest.created.push(key.to_string());
+                }
+            }
+        }
+
+        // Swap the clone provider of the previous run for a fresh one
+        config.take_providers();
+        if let Some(path) = &settings.clone_provider {
+            provider::check_file_path(path)?;
+            if config.root.get("proxy-providers").is_none_or(Value::is_null) {
+                if config.root.shift_remove("proxy-providers").is_none() {
+                    config.manifest.created.push("proxy-providers".to_string());
+                }
+                config.insert_before("proxy-providers", json!({}), &["proxies", "proxy-groups", "rules"]);
+            }
+            let providers = config.root.get_mut("proxy-providers")
+                .and_then(Value::as_object_mut)
+                .context("'proxy-providers' must be an object to be patched")?;
+            providers.shift_insert(0, provider::CLONE_PROVIDER.to_string(), json!({ "type": "file", "path": path }));
+            config.manifest.proxy_providers = vec![provider::CLONE_PROVIDER.to_string()];
+        }
+
+        // Reserved names are always ours, even without a manifest
+        let generated: HashSet<String> = config.manifest.proxies.iter()
+            .chain(&config.manifest.proxy_groups)
+            .cloned()
+            .chain(CHAIN_GROUPS.iter().map(|g| g.to_string()))
+            .chain([settings.proxy_name.clone()])
+            .collect();
+
+        let proxy_entries = self.get_proxy_entries(config_map, &generated, &mut result.warnings)?;
+        let proxy_names: Vec<String> = proxy_entries.iter().map(|(n, _)| n.clone()).collect();
+        let nodes: HashMap<String, serde_yaml::Value> = proxy_entries.into_iter().collect();
+        let providers = self.provider_chains(config_map, &proxy_names, result);
+
+        let group_names = self.chain_group_names();
+        let region_groups = self.region_groups(&proxy_names);
+        let extra_groups = ClashConfigMerger::extra_group_names(&region_groups, &providers);
+        let taken: Vec<String> = ["proxies", "proxy-groups"].iter()
+            .filter_map(|key| config.root.get(*key).and_then(Value::as_array))
+            .flatten()
+            .filter_map(|e| name_of(e).map(String::from))
+            .filter(|n| !generated.contains(n))
+            .collect();
+        self.check_group_names(extra_groups, taken.iter().cloned())?;
+        let sources = self.chain_sources(&proxy_names, &providers);
+        let clones: Vec<String> = sources.iter().map(|(_, clone)| clone.clone()).collect();
+        result.warnings.extend(hop::check(&settings.exit_hop(), &settings.hops, settings.chain_order, &clones, taken)?);
+        let refs = ClashConfigMerger::chain_refs(config.manifest.proxy_groups.iter().chain(&group_names).cloned());
+        let main_group = self.detect_main_group(config_map, &generated);
+
+        // Proxies: drop the previous run's output, append clones and the local proxy
+        let socks5 = |name: &str, hop: &ChainHop, dialer: Option<&String>| {
+            let mut proxy = json!({
+                "name": name,
+                "type": "socks5",
+                "server": hop.host,
+                "port": hop.port,
+            });
+            for (key, value) in [("username", &hop.username), ("password", &hop.password)] {
+                if let Some(value) = value.as_ref().filter(|v| !v.is_empty()) {
+                    proxy[key] = json!(value);
+                }
+            }
+            if hop.udp {
+                proxy["udp"] = json!(true);
+            }
+            if let Some(dialer) = dialer {
+                proxy["dialer-proxy"] = json!(dialer);
+            }
+            proxy
+        };
+        let exit = settings.exit_hop();
+        let outbounds = hop::outbounds(&exit, &settings.hops, settings.chain_order, &sources);
+        let generated_proxies = outbounds.iter()
+            .map(|outbound| match outbound {
+                Outbound::Socks5 { name, hop, dialer } => Ok(socks5(name, hop, dialer.as_ref())),
+                Outbound::Node { name, node, dialer } => {
+                    let node = hop::node_copy(&nodes[node], name, dialer);
+                    serde_json::to_value(node).context("Node cannot be copied to JSON")
+                }
+            })
+            .collect::<Result<Vec<Value>>>()?;
+        let generated_names: Vec<String> = generated_proxies.iter()
+            .filter_map(|p| name_of(p).map(String::from))
+            .collect();
+
+        let proxies = config.list_mut("proxies").context("'proxies' must be a list")?;
+        proxies.retain(|p| !name_of(p).is_some_and(|n| generated.contains(n)));
+        // JSON mappings are YAML flow mappings, so they go to a provider file as-is
+        match &settings.clone_provider {
+            Some(path) => {
+                let lines = generated_proxies.iter().map(|p| format!("  - {}", p)).collect();
+                result.clone_provider = Some(ProviderFile::new(path, lines, config.crlf));
+            }
+            None => proxies.extend(generated_proxies),
+        }
+
+        // Groups: replace the chain groups, reference them from the target
+        // groups; `use:`-only targets get a `proxies` list
+        let old_lists = std::mem::take(&mut config.manifest.proxy_lists);
+        let mut proxy_lists = Vec::new();
+        let groups = config.list_mut("proxy-groups").context("'proxy-groups' must be a list")?;
+        groups.retain(|g| !name_of(g).is_some_and(|n| generated.contains(n)));
+        let user_groups: Vec<String> = groups.iter().filter_map(|g| name_of(g).map(String::from)).collect();
+        let (targets, warnings) = settings.inject.select(&user_groups, main_group.as_deref())?;
+        result.warnings.extend(warnings);
+        result.groups_updated = 0;
+        for group in groups.iter_mut() {
+            strip_chain_refs(group, &refs);
+            drop_created_list(group, &old_lists);
+            let Some(name) = name_of(group).filter(|n| targets.iter().any(|t| t == n)).map(String::from) else {
+                continue;
+            };
+            if let Some(map) = group.as_object_mut().filter(|m| !m.contains_key("proxies")) {
+                map.insert("proxies".to_string(), json!([]));
+                proxy_lists.push(name.clone());
+            }
+            if let Some(list) = group.get_mut("proxies").and_then(Value::as_array_mut) {
+                let members: Vec<String> = list.iter().map(|m| m.as_str().unwrap_or_default().to_string()).collect();
+                let at = settings.inject.insert_index(&name, &members, &mut result.warnings);
+                list.splice(at..at, group_names.iter().map(|g| json!(g)));
+                result.groups_updated += 1;
+            }
+        }
+        let members: Vec<String> = sources.into_iter().map(|(_, clone)| clone).collect();
+        let over_clones: Vec<String> = group_names.iter().cloned()
+            .chain(region_groups.iter().map(|(g, _)| g.name.clone()))
+            .collect();
+        self.check_dialer_cycles(config_map, &generated, &outbounds, &over_clones, &members, &targets)?;
+        let over_clones = |name: &str, kind: &str, clones: &[String], all: bool| {
+            let ((key, list), filter) = self.group_members(clones, all);
+            let mut group = json!({ "name": name, "type": kind });
+            group[key] = json!(list);
+            for (key, value) in filter {
+                group[key] = value;
+            }
+            group
+        };
+        let mut chain_groups = vec![over_clones(CHAIN_GROUPS[0], "select", &members, true)];
+        let auto_groups = settings.auto_groups.iter().map(|auto| (auto, &members, true));
+        let region_groups = region_groups.iter().map(|(region, members)| (region, members, false));
+        for (auto, members, all) in auto_groups.chain(region_groups) {
+            let mut group = over_clones(&auto.name, auto.kind.as_str(), members, all);
+            for (key, value) in auto.options() {
+                group[key] = value;
+            }
+            chain_groups.push(group);
+        }
+        let (filters, _) = provider::group_filters(&settings.node_filter);
+        for chain in &providers {
+            let via = self.health_check_group(&chain.group);
+            let mut group = json!({ "name": via.name, "type": via.kind.as_str(), "use": [chain.provider] });
+            for (key, value) in &filters {
+                group[*key] = json!(value);
+            }
+            for (key, value) in via.options() {
+                group[key] = value;
+            }
+            chain_groups.push(group);
+        }
+        let generated_groups: Vec<String> = chain_groups.iter()
+            .filter_map(|g| name_of(g).map(String::from))
+            .collect();
+        groups.splice(0..0, chain_groups);
+
+        config.manifest.proxies = if settings.clone_provider.is_some() { Vec::new() } else { generated_names };
+        config.manifest.proxy_groups = generated_groups;
+        config.manifest.proxy_lists = proxy_lists;
+
+        result.chains_created = proxy_names.len() + providers.len();
+        result.proxy_added = true;
+        config.render()
+    }
+
+    /// JSON counterpart of the text unapply
+    pub(super) fn unapply_json(&self, content: &str) -> Result<(String, UnapplyResult)> {
+        let parsed = yaml::parse_value(content).context("JSON parse error")?;
+        let config_map = parsed.as_mapping().context("Config root must be a JSON object")?;
+        let mut config = JsonConfig::parse(content)?;
+        let mut result = UnapplyResult::default();
+        let mut updated: Vec<String> = Vec::new();
+
+        let ours_proxies: HashSet<String> = config.manifest.proxies.iter()
+            .cloned()
+            .chain([self.config().proxy_name.clone()])
+            .collect();
+        let ours_groups = ClashConfigMerger::chain_refs(config.manifest.proxy_groups.iter().cloned());
+        let proxy_lists = config.manifest.proxy_lists.clone();
+
+        if let Some(groups) = config.list_mut("proxy-groups") {
+            let before = groups.len();
+            groups.retain(|g| !name_of(g).is_some_and(|n| ours_groups.contains(n)));
+            result.groups_removed = before - groups.len();
+            for group in groups.iter_mut() {
+                if strip_chain_refs(group, &ours_groups) {
+                    result.groups_updated += 1;
+                    updated.extend(name_of(group).map(String::from));
+                }
+                drop_created_list(group, &proxy_lists);
+            }
+        }
+        if let Some(proxies) = config.list_mut("proxies") {
+            let before = proxies.len();
+            proxies.retain(|p| !name_of(p).is_some_and(|n| ours_proxies.contains(n)));
+            result.proxies_removed = before - proxies.len();
+        }
+        result.clone_provider = config.take_providers()
+            .get(provider::CLONE_PROVIDER)
+            .and_then(|p| p.get("path"))
+            .and_then(Value::as_str)
+            .map(String::from);
+
+        // Unrecorded rewrites fall back to the main group
+        let generated: HashSet<String> = ours_proxies.union(&ours_groups).cloned().collect();
+        let main_group = self.fallback_group(&updated, config_map, &generated);
+        let fallback: HashMap<String, String> = main_group
+            .iter()
+            .flat_map(|g| ours_groups.iter().map(move |c| (c.clone(), g.clone())))
+            .collect();
+        let revert = revert_rules(&mut config, &fallback);
+        result.rules_restored = revert.restored;
+        result.rules_removed = revert.removed;
+
+        let leftover = config.list_mut("rules")
+            .map(|rules| rules.iter()
+                .filter_map(Value::as_str)
+                .filter(|r| r.split(',').any(|part| generated.contains(part.trim())))
+                .count())
+            .unwrap_or(0);
+        if leftover > 0 {
+            result.warnings.push(format!(
+                "{} rules still reference removed chain groups; fix them by hand", leftover
+            ));
+        }
+
+        // Drop the keys merge added once nothing else is left in them
+        for key in std::mem::take(&mut config.manifest.created) {
+            let empty = match config.root.get(&key) {
+                Some(Value::Array(items)) => items.is_empty(),
+                Some(Value::Object(map)) => map.is_empty(),
+                _ => false,
+            };
+            if empty {
+                config.root.shift_remove(&key);
+            }
+        }
+
+        config.manifest = Manifest::default();
+        Ok((config.render()?, result))
+    }
+}
+
+/// JSON counterpart of [`patcher::rewrite_rules_text`]; origins go to the manifest
+pub fn rewrite_rules_text(content: &str, replacements: &HashMap<String, String>) -> Result<(String, usize)> {
+    let mut config = JsonConfig::parse(content)?;
+    let injected = config.manifest.rules.clone();
+    let mut rewritten = std::mem::take(&mut config.manifest.rewritten);
+    let mut count = 0;
+
+    if let Some(rules) = config.list_mut("rules") {
+        for rule in rules.iter_mut() {
+            let Some(text) = rule.as_str().filter(|r| !injected.iter().any(|i| i == r)) else {
+                continue;
+            };
+            let replaced = replacements.iter().find_map(|(old_group, new_group)| {
+                patcher::replace_rule_group(text, old_group, new_group).map(|new| (old_group, new))
+            });
+            let Some((old_group, new)) = replaced else {
+                continue;
+            };
+            count += 1;
+            // Keep the first recorded origin when a rule is rewritten twice
+            match rewritten.iter_mut().find(|r| r.rule == text) {
+                Some(origin) => origin.rule = new.clone(),
+                None => rewritten.push(Rewrite { rule: new.clone(), was: old_group.clone() }),
+            }
+            *rule = Value::String(new);
+        }
+    }
+
+    config.manifest.rewritten = rewritten;
+    Ok((config.render()?, count))
+}
+
+/// JSON counterpart of [`patcher::inject_custom_rules_text`] for formatted rules
+pub fn inject_custom_rules_text(content: &str, new_rules: Vec<String>) -> Result<(String, usize)> {
+    let mut config = JsonConfig::parse(content)?;
+    let previous = std::mem::take(&mut config.manifest.rules);
+    let Some(rules) = config.list_mut("rules") else {
+        return Ok((content.to_string(), 0));
+    };
+
+    drop_injected(rules, &previous);
+    let count = new_rules.len();
+    rules.splice(0..0, new_rules.iter().map(|r| json!(r)));
+    config.manifest.rules = new_rules;
+    Ok((config.render()?, count))
+}
+
+/// JSON counterpart of [`patcher::revert_rules_text`]
+pub fn revert_rules_text(content: &str, fallback: &HashMap<String, String>) -> Result<(String, RuleRevert)> {
+    let mut config = JsonConfig::parse(content)?;
+    let revert = revert_rules(&mut config, fallback);
+    Ok((config.render()?, revert))
+}
+
+fn revert_rules(config: &mut JsonConfig, fallback: &HashMap<String, String>) -> RuleRevert {
+    let injected = std::mem::take(&mut config.manifest.rules);
+    let mut rewritten = std::mem::take(&mut config.manifest.rewritten);
+    let mut revert = RuleRevert::default();
+    let Some(rules) = config.list_mut("rules") else {
+        return revert;
+    };
+
+    revert.removed = drop_injected(rules, &injected);
+    for rule in rules.iter_mut() {
+        let Some(text) = rule.as_str() else { continue };
+        let recorded = rewritten.iter().position(|r| r.rule == text).and_then(|i| {
+            let origin = rewritten.remove(i);
+            let current = patcher::extract_group_from_rule(text)?;
+            patcher::replace_rule_group(text, &current, &origin.was)
+        });
+        let restored = recorded.or_else(|| {
+            fallback.iter().find_map(|(from, to)| patcher::replace_rule_group(text, from, to))
+        });
+        if let Some(restored) = restored {
+            revert.restored += 1;
+            *rule = Value::String(restored);
+        }
+    }
+    revert
+}
+
+/// JSON counterpart of [`patcher::revert_rewrites_text`]
+pub fn revert_rewrites_text(content: &str, pick: impl Fn(&str, &str) -> bool) -> Result<(String, usize)> {
+    let mut config = JsonConfig::parse(content)?;
+    let mut rewritten = std::mem::take(&mut config.manifest.rewritten);
+    let mut count = 0;
+
+    if let Some(rules) = config.list_mut("rules") {
+        for rule in rules.iter_mut() {
+            let Some(text) = rule.as_str() else { continue };
+            let Some(i) = rewritten.iter().position(|r| r.rule == text) else { continue };
+            let Some(current) = patcher::extract_group_from_rule(text) else { continue };
+            if !pick(&rewritten[i].was, &current) {
+                continue;
+            }
+            if let Some(restored) = patcher::replace_rule_group(text, &current, &rewritten[i].was) {
+                rewritten.remove(i);
+                *rule = Value::String(restored);
+                count += 1;
+            }
+        }
+    }
+
+    config.manifest.rewritten = rewritten;
+    Ok((config.render()?, count))
+}
+
+/// Remove one occurrence of each injected rule; returns how many were found
+fn drop_injected(rules: &mut Vec<Value>, injected: &[String]) -> usize {
+    let mut removed = 0;
+    for text in injected {
+        if let Some(i) = rules.iter().position(|r| r.as_str() == Some(text)) {
+            rules.remove(i);
+            removed += 1;
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merger::{ChainOrder, MergerConfig};
+
+    const CONFIG: &str = r#"{
+    "port": 7890,
+    "proxies": [
+        {
+            "name": "HK 01",
+            "type": "ss",
+            "server": "hk.example.com",
+            "port": 443
+        },
+        {
+            "name": "JP 01",
+            "type": "ss",
+            "server": "jp.example.com",
+            "port": 443
+        }
+    ],
+    "proxy-groups": [
+        {
+            "name": "Proxy",
+            "type": "select",
+            "proxies": [
+                "HK 01",
+                "JP 01"
+            ]
+        }
+    ],
+    "rules": [
+        "DOMAIN-SUFFIX,google.com,Proxy",
+        "MATCH,Proxy"
+    ]
+}
+"#;
+
+    fn merger() -> ClashConfigMerger {
+        ClashConfigMerger::with_config(MergerConfig {
+            proxy_host: "1.2.3.4".to_string(),
+            proxy_port: 1080,
+            ..MergerConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_merge_json_keeps_style_and_order() {
+        let (output, result) = merger().merge_str(CONFIG).unwrap();
+        assert_eq!(result.chains_created, 2);
+        assert!(is_json(&output));
+        // Same indent, key order and trailing newline as the input
+        assert!(output.starts_with("{\n    \"port\": 7890,\n    \"proxies\": [\n        {\n            \"name\": \"HK 01\","));
+        assert!(output.ends_with("}\n"));
+
+        let value: Value = serde_json::from_str(&output).unwrap();
+        let keys: Vec<&str> = value.as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(keys, vec!["port", "proxies", "proxy-groups", "rules", MANIFEST_KEY]);
+        assert_eq!(value["proxies"][2]["name"], "HK 01-Chain");
+        assert_eq!(value["proxies"][2]["dialer-proxy"], "HK 01");
+        assert_eq!(value["proxies"][4]["name"], "Local-Chain-Proxy");
+        assert_eq!(value["proxy-groups"][0]["name"], "Chain-Selector");
+        assert_eq!(value["proxy-groups"][2]["proxies"][0], "Chain-Selector");
+
+        let (again, _) = merger().merge_str(&output).unwrap();
+        assert_eq!(again, output);
+    }
+
+    #[test]
+    fn test_json_rules_round_trip() {
+        let replacements = HashMap::from([("Proxy".to_string(), "Chain-Selector".to_string())]);
+        let (merged, _) = merger().merge_str(CONFIG).unwrap();
+        let (rewritten, count) = patcher::rewrite_rules_text(&merged, &replacements);
+        assert_eq!(count, 2);
+        let rules = patcher::parse_custom_rule_string("DOMAIN,x.com,DIRECT");
+        let (injected, count) = patcher::inject_custom_rules_text(&rewritten, &rules);
+        assert_eq!(count, 1);
+        let (twice, _) = patcher::inject_custom_rules_text(&injected, &rules);
+        assert_eq!(twice, injected);
+
+        let value: Value = serde_json::from_str(&injected).unwrap();
+        assert_eq!(value["rules"][0], "DOMAIN,x.com,DIRECT");
+        assert_eq!(value["rules"][2], "MATCH,Chain-Selector");
+
+        // Undoing the rewrites keeps the injected rule
+        let (reverted, count) = patcher::revert_rewrites_text(&injected, |was, _| was == "Proxy");
+        assert_eq!(count, 2);
+        let value: Value = serde_json::from_str(&reverted).unwrap();
+        assert_eq!(value["rules"][0], "DOMAIN,x.com,DIRECT");
+        assert_eq!(value["rules"][2], "MATCH,Proxy");
+        assert!(value[MANIFEST_KEY].get("rewritten").is_none());
+
+        let (output, result) = merger().unapply_text(&injected).unwrap();
+        assert_eq!(result.rules_restored, 2);
+        assert_eq!(result.rules_removed, 1);
+        assert_eq!(result.proxies_removed, 3);
+        assert_eq!(output, CONFIG);
+    }
+
+    #[test]
+    fn test_minified_crlf_and_bom_kept() {
+        let compact = r#"{"proxies":[{"name":"A","type":"ss","server":"a","port":1}],"proxy-groups":[{"name":"G","type":"select","proxies":["A"]}],"rules":["MATCH,G"]}"#;
+        let (output, _) = merger().merge_str(compact).unwrap();
+        assert!(!output.contains('\n'));
+        assert_eq!(merger().unapply_text(&output).unwrap().0, compact);
+
+        let tabbed = "\u{feff}{\r\n\t\"proxies\": [],\r\n\t\"proxy-groups\": []\r\n}";
+        let (output, _) = merger().merge_str(tabbed).unwrap();
+        assert!(output.starts_with("\u{feff}{\r\n\t\"proxies\": [\r\n\t\t{"));
+        assert!(!output.replace("\r\n", "").contains('\n'));
+        assert_eq!(merger().unapply_text(&output).unwrap().0, tabbed);
+    }
+
+    #[test]
+    fn test_provider_only_json_gets_lists() {
+        let config = r#"{"proxy-providers":{"sub":{"type":"file","path":"./sub.yaml"}},"rules":["MATCH,DIRECT"]}"#;
+        let (output, result) = merger().merge_str(config).unwrap();
+        assert_eq!(result.chains_created, 1);
+        let parsed: Value = serde_json::from_str(&output).unwrap();
+        let keys: Vec<&String> = parsed.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["proxy-providers", "proxies", "proxy-groups", "rules", MANIFEST_KEY]);
+        assert_eq!(parsed["proxies"][0]["dialer-proxy"], json!("Chain-Via-sub"));
+        assert_eq!(parsed["proxy-groups"][2]["use"], json!(["sub"]));
+        assert_eq!(parsed[MANIFEST_KEY]["proxy-groups"], json!(["Chain-Selector", "Chain-Auto", "Chain-Via-sub"]));
+        let (removed, _) = merger().unapply_text(&output).unwrap();
+        assert_eq!(removed, config);
+    }
+
+    #[test]
+    fn test_use_only_json_group_gets_proxies_list() {
+        let config = r#"{"proxy-providers":{"sub":{"type":"file","path":"./sub.yaml"}},"proxies":[],"proxy-groups":[{"name":"Use","type":"select","use":["sub"]}],"rules":["MATCH,Use"]}"#;
+        let (output, result) = merger().merge_str(config).unwrap();
+        assert_eq!(result.groups_updated, 1);
+        let parsed: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(parsed["proxy-groups"][3]["proxies"], json!(["Chain-Selector", "Chain-Auto"]));
+        assert_eq!(parsed[MANIFEST_KEY]["proxy-lists"], json!(["Use"]));
+        assert_eq!(merger().merge_str(&output).unwrap().0, output);
+        let (removed, _) = merger().unapply_text(&output).unwrap();
+        assert_eq!(removed, config);
+    }
+
+    #[test]
+    fn test_exit_first_json_copies_nodes() {
+        let config = r#"{"proxies":[{"name":"HK","type":"vmess","server":"hk","port":1,"ws-opts":{"path":"/ws"}}],"proxy-groups":[],"rules":[]}"#;
+        let merger = ClashConfigMerger::with_config(MergerConfig {
+            hops: vec![ChainHop::parse("Mid=10.0.0.1:1080", "Hop-1").unwrap()],
+            chain_order: ChainOrder::ExitFirst,
+            ..merger().config().clone()
+        });
+        let (output, _) = merger.merge_str(config).unwrap();
+        let parsed: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(parsed["proxies"][1], json!({"name": "HK-Chain", "type": "vmess", "server": "hk", "port": 1, "ws-opts": {"path": "/ws"}, "dialer-proxy": "Mid"}));
+        assert_eq!(parsed["proxies"][3]["dialer-proxy"], json!("Local-Chain-Proxy"));
+        assert_eq!(parsed[MANIFEST_KEY]["proxies"], json!(["HK-Chain", "Local-Chain-Proxy", "Mid"]));
+        assert_eq!(merger.unapply_text(&output).unwrap().0, config);
+    }
+
+    #[test]
+    fn test_hand_formatted_json_keeps_layout() {
+        let config = r#"{
+  "mixed-port": 7890,
+  "proxies": [
+    {"name": "HK", "type": "ss", "server": "hk", "port": 1}
+  ],
+  "proxy-groups": [
+    {"name": "Proxy", "type": "select", "proxies": ["HK", "DIRECT"]}
+  ],
+  "rules": ["DOMAIN,a.com,Proxy", "MATCH,Proxy"]
+}
+"#;
+        let (output, _) = merger().merge_str(config).unwrap();
+        assert!(output.contains("  \"proxies\": [\n    {\"name\": \"HK\", \"type\": \"ss\", \"server\": \"hk\", \"port\": 1},\n    {\"name\": \"HK-Chain\", \"type\": \"socks5\", "));
+        assert!(output.contains("{\"name\": \"Proxy\", \"type\": \"select\", \"proxies\": [\"Chain-Selector\", \"Chain-Auto\", \"HK\", \"DIRECT\"]}"));
+        assert!(output.contains("\n  \"rules\": [\"DOMAIN,a.com,Proxy\", \"MATCH,Proxy\"],\n"));
+        assert_eq!(merger().unapply_text(&output).unwrap().0, config);
+    }
+}
@@ -1,5 +1,24 @@
 //! Configuration merging module for Clash configs
 
+pub mod auto_group;
+pub mod block;
 pub mod clash_merger;
+pub mod compat;
+pub mod dialer;
+pub mod diff;
+pub mod filter;
+pub mod hop;
+pub mod inject;
+pub mod json;
+pub mod plan;
+pub mod provider;
+pub mod region;
 
-pub use clash_merger::{ClashConfigMerger, MergerConfig, MergeResult};
+pub use auto_group::{AutoGroup, AutoGroupType, BalanceStrategy};
+pub use clash_merger::{ClashConfigMerger, MergerConfig, MergeResult, OutputStyle, UnapplyResult};
+pub use dialer::DialerPolicy;
+pub use filter::NodeFilter;
+pub use hop::{ChainHop, ChainOrder};
+pub use inject::{GroupMatcher, InjectConfig, InjectPosition};
+pub use plan::{ApplyPlan, PlannedApply};
+pub use region::{Region, RegionTable};
@@ -0,0 +1,379 @@
+//! Apply plan: chain merge, rule rewrites and custom rules in one pass
+//!
+//! The plan renders the whole patch on an in-memory buffer, so it can be
+//! previewed as a diff, and is written back in a single step: a failure at
+//! any stage leaves the config untouched instead of half-patched.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+use super::clash_merger::{ClashConfigMerger, MergeResult, MergerConfig};
+use super::diff;
+use super::provider;
+use crate::backup::BackupStore;
+use crate::config::BackupConfig;
+use crate::fsutil;
+use crate::patcher::{self, CustomRule};
+use crate::yaml;
+
+/// Everything `ccp apply` (or the GUI Apply button) does to a config
+#[derive(Debug, Clone)]
+pub struct ApplyPlan {
+    merger: MergerConfig,
+    rule_rewrites: HashMap<String, String>,
+    custom_rules: Vec<CustomRule>,
+    backups: BackupConfig,
+}
+
+/// Patched config text and what changed
+#[derive(Debug, Clone)]
+pub struct PlannedApply {
+    pub content: String,
+    pub merge: MergeResult,
+    pub rules_rewritten: usize,
+    pub rules_injected: usize,
+}
+
+impl ApplyPlan {
+    pub fn new(merger: MergerConfig) -> Self {
+        Self {
+            merger,
+            rule_rewrites: HashMap::new(),
+            custom_rules: Vec::new(),
+            backups: BackupConfig::default(),
+        }
+    }
+
+    /// Rewrite rules from one group to another (e.g. `Proxy` -> `Chain-Selector`)
+    pub fn with_rule_rewrites(mut self, rewrites: HashMap<String, String>) -> Self {
+        self.rule_rewrites = rewrites;
+        self
+    }
+
+    /// Custom rules to inject at the top of the rules section
+    pub fn with_custom_rules(mut self, rules: Vec<CustomRule>) -> Self {
+        self.custom_rules = rules;
+        self
+    }
+
+    /// Where backups go and how many are kept (when `create_backup` is set)
+    pub fn with_backups(mut self, backups: BackupConfig) -> Self {
+        self.backups = backups;
+        self
+    }
+
+    pub fn merger_config(&self) -> &MergerConfig {
+        &self.merger
+    }
+
+    pub fn rule_rewrites(&self) -> &HashMap<String, String> {
+        &self.rule_rewrites
+    }
+
+    pub fn custom_rules(&self) -> &[CustomRule] {
+        &self.custom_rules
+    }
+
+    /// Run merge, rule rewrites and custom rule injection on `content`
+    ///
+    /// Rules an earlier run rewrote are first pointed back at their recorded
+    /// group when this plan rewrites that group again, or when the group
+    /// they point at is no longer in the merged config (e.g. `Chain-Auto`
+    /// after `--no-auto`).
+    pub fn render(&self, content: &str) -> Result<PlannedApply> {
+        let merger = ClashConfigMerger::with_config(self.merger.clone());
+        let (merged, merge) = merger.merge_str(content)?;
+        let defined = yaml::parse_value(&merged).context("Merged config is not valid YAML")?;
+        let missing = patcher::missing_rule_targets(&defined);
+        let (reverted, _) = patcher::revert_rewrites_text(&merged, |was, current| {
+            self.rule_rewrites.contains_key(was) || missing.iter().any(|m| m == current)
+        });
+        let (rewritten, rules_rewritten) = patcher::rewrite_rules_text(&reverted, &self.rule_rewrites);
+        let (content, rules_injected) = patcher::inject_custom_rules_text(&rewritten, &self.custom_rules);
+
+        Ok(PlannedApply { content, merge, rules_rewritten, rules_injected })
+    }
+
+    /// Render the plan against a config file and write the result once
+    ///
+    /// Secret references are resolved, the output must parse as YAML, and the
+    /// file is replaced atomically. Nothing is written (and no backup taken)
+    /// when the config is already up to date. A clone provider file is
+    /// written first, and only when its content changed.
+    pub fn execute(&self, config_path: &Path) -> Result<PlannedApply> {
+        ClashConfigMerger::check_writable(config_path)?;
+        info!("Applying chain patch to: {}", config_path.display());
+
+        let content = fs::read_to_string(config_path)
+            .with_context(|| format!("Failed to read: {}", config_path.display()))?;
+
+        let resolved = Self {
+            merger: self.merger.with_resolved_secrets()?,
+            ..self.clone()
+        };
+        let mut planned = resolved.render(&content)?;
+        let patched = yaml::parse_value(&planned.content)
+            .context("Patched config is not valid YAML; nothing was written")?;
+
+        // Rules may only lack a target when they already did
+        let before = yaml::parse_value(&content).map(|v| patcher::missing_rule_targets(&v)).unwrap_or_default();
+        let missing: Vec<String> = patcher::missing_rule_targets(&patched)
+            .into_iter()
+            .filter(|group| !before.contains(group))
+            .collect();
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "Rules would point at missing proxies or groups: {}; nothing was written",
+                missing.join(", ")
+            );
+        }
+
+        // The config must never point at a missing provider file
+        if let Some(file) = &planned.merge.clone_provider {
+            let path = provider::file_path(config_path, &file.path);
+            if fs::read_to_string(&path).ok().as_deref() != Some(file.content.as_str()) {
+                if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                    fs::create_dir_all(dir).with_context(|| format!("Failed to create: {}", dir.display()))?;
+                }
+                fsutil::write_atomic(&path, &file.content)?;
+                info!("Wrote clone provider: {}", path.display());
+            }
+        }
+
+        if planned.content == content {
+            info!("Config already up to date");
+            return Ok(planned);
+        }
+
+        if self.merger.create_backup {
+            let backup = BackupStore::new(self.backups.clone()).backup(config_path)?;
+            planned.merge.backup_path = Some(backup.path);
+        }
+
+        fsutil::write_atomic(config_path, &planned.content)?;
+
+        info!("Apply completed: chains={}, groups={}, rules rewritten={}, injected={}",
+            planned.merge.chains_created, planned.merge.groups_updated,
+            planned.rules_rewritten, planned.rules_injected);
+        Ok(planned)
+    }
+
+    /// Unified diff of the planned changes (literal passwords masked), with
+    /// the rendered plan for its warnings and counts
+    pub fn diff(&self, content: &str, name: &str) -> Result<(String, PlannedApply)> {
+        let planned = self.preview(content)?;
+        Ok((file_diff(content, &planned.content, name), planned))
+    }
+
+    /// [`Self::diff`] of a config file, followed by the diff of its clone
+    /// provider file when one is written
+    pub fn diff_file(&self, config_path: &Path) -> Result<(String, PlannedApply)> {
+        let content = fs::read_to_string(config_path)
+            .with_context(|| format!("Failed to read: {}", config_path.display()))?;
+        let name = config_path.file_name()
+            .map_or_else(|| config_path.display().to_string(), |n| n.to_string_lossy().to_string());
+        let planned = self.preview(&content)?;
+        let mut out = file_diff(&content, &planned.content, &name);
+        if let Some(file) = &planned.merge.clone_provider {
+            let old = fs::read_to_string(provider::file_path(config_path, &file.path)).unwrap_or_default();
+            out.push_str(&file_diff(&old, &file.content, &file.path));
+        }
+        Ok((out, planned))
+    }
+
+    /// Rendered plan with literal passwords masked
+    fn preview(&self, content: &str) -> Result<PlannedApply> {
+        let preview = Self {
+            merger: self.merger.redacted(),
+            ..self.clone()
+        };
+        preview.render(content)
+    }
+}
+
+fn file_diff(old: &str, new: &str, name: &str) -> String {
+    diff::unified_diff(old, new, &format!("a/{}", name), &format!("b/{}", name), 3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const CONFIG: &str = "proxies:\n  - { name: HK, type: ss, server: a.com, port: 443, cipher: aes-256-gcm, password: x }\n\
+                          proxy-groups:\n  - { name: Proxy, type: select, proxies: [HK] }\n\
+                          rules:\n  - DOMAIN,a.com,Proxy\n  - MATCH,Proxy\n";
+
+    fn plan() -> ApplyPlan {
+        let mut rewrites = HashMap::new();
+        rewrites.insert("Proxy".to_string(), "Chain-Selector".to_string());
+        ApplyPlan::new(MergerConfig {
+            proxy_host: "1.2.3.4".to_string(),
+            proxy_port: 1080,
+            proxy_username: Some("u".to_string()),
+            proxy_password: Some("hunter2".to_string()),
+            ..MergerConfig::default()
+        })
+        .with_rule_rewrites(rewrites)
+        .with_custom_rules(patcher::parse_custom_rule_string("DOMAIN-KEYWORD,lark,DIRECT"))
+    }
+
+    #[test]
+    fn test_render_runs_full_pipeline() {
+        let planned = plan().render(CONFIG).unwrap();
+        assert_eq!(planned.merge.chains_created, 1);
+        assert_eq!(planned.rules_rewritten, 2);
+        assert_eq!(planned.rules_injected, 1);
+        assert!(planned.content.contains("HK-Chain"));
+        assert!(planned.content.contains("- MATCH,Chain-Selector"));
+        assert!(planned.content.contains("DOMAIN-KEYWORD,lark,DIRECT"));
+    }
+
+    #[test]
+    fn test_reapply_rewrites_from_recorded_origin() {
+        let first = plan().render(CONFIG).unwrap().content;
+        let mut rewrites = HashMap::new();
+        rewrites.insert("Proxy".to_string(), "Chain-Auto".to_string());
+        let planned = plan().with_rule_rewrites(rewrites).render(&first).unwrap();
+        assert_eq!(planned.rules_rewritten, 2);
+        assert!(planned.content.contains("  - MATCH,Chain-Auto # ccp: was Proxy\n"));
+        assert!(!planned.content.contains("Chain-Selector # ccp"));
+
+        // Without Chain-Auto its rules go back to where they came from
+        let no_auto = ApplyPlan::new(MergerConfig { auto_groups: Vec::new(), ..plan().merger_config().clone() });
+        let planned = no_auto.render(&planned.content).unwrap();
+        assert!(planned.content.contains("  - MATCH,Proxy\n"));
+        assert!(!planned.content.contains("Chain-Auto"));
+    }
+
+    #[test]
+    fn test_execute_refuses_rules_without_target() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.yaml");
+        // A rule pointed at Chain-Auto by hand, without a `# ccp: was` origin
+        let edited = CONFIG.replace("MATCH,Proxy", "MATCH,Chain-Auto");
+        let content = ApplyPlan::new(MergerConfig::default()).render(&edited).unwrap().content;
+        fs::write(&config_path, &content).unwrap();
+        let no_auto = ApplyPlan::new(MergerConfig { auto_groups: Vec::new(), create_backup: false, ..MergerConfig::default() });
+
+        let err = no_auto.execute(&config_path).unwrap_err();
+        assert_eq!(err.to_string(), "Rules would point at missing proxies or groups: Chain-Auto; nothing was written");
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), content);
+    }
+
+    /// Line-ending, BOM and whitespace variants of one config, run through
+    /// the merger, rule rewriter and rule injector
+    const FIXTURES: [(&str, &str); 4] = [
+        ("crlf", include_str!("../../tests/fixtures/crlf.yaml")),
+        ("bom", include_str!("../../tests/fixtures/bom.yaml")),
+        ("tabs", include_str!("../../tests/fixtures/tabs.yaml")),
+        ("trailing_whitespace", include_str!("../../tests/fixtures/trailing_whitespace.yaml")),
+    ];
+
+    #[test]
+    fn test_fixtures_keep_encoding_and_round_trip() {
+        for (name, original) in FIXTURES {
+            let planned = plan().render(original).unwrap();
+            let content = &planned.content;
+            assert_eq!(planned.merge.chains_created, 2, "{}", name);
+            assert_eq!(planned.rules_rewritten, 2, "{}", name);
+            assert_eq!(planned.rules_injected, 1, "{}", name);
+
+            // Encoding details survive, generated lines included
+            assert_eq!(content.starts_with('\u{feff}'), original.starts_with('\u{feff}'), "{}", name);
+            let crlf = original.contains("\r\n");
+            assert_eq!(content.contains('\r'), crlf, "{}", name);
+            if crlf {
+                assert_eq!(content.matches('\n').count(), content.matches("\r\n").count(), "{}", name);
+            }
+
+            let config = yaml::parse_value(content).unwrap();
+            assert_eq!(config["proxies"].as_sequence().unwrap().len(), 5, "{}", name);
+            assert_eq!(plan().render(content).unwrap().content, *content, "{} re-apply", name);
+
+            let (reverted, _) = ClashConfigMerger::new().unapply_text(content).unwrap();
+            assert_eq!(reverted, original, "{} unapply", name);
+        }
+    }
+
+    #[test]
+    fn test_diff_masks_password() {
+        let (diff, _) = plan().diff(CONFIG, "config.yaml").unwrap();
+        assert!(diff.starts_with("--- a/config.yaml\n+++ b/config.yaml\n"));
+        assert!(diff.contains("+  - { name: Local-Chain-Proxy"));
+        assert!(diff.contains("-  - MATCH,Proxy"));
+        assert!(diff.contains("password: '***'"));
+        assert!(!diff.contains("hunter2"));
+
+        // Merge warnings come back with the diff
+        let config = CONFIG.replace("proxies:\n", "proxies:\n  - { name: Block, type: reject }\n");
+        let (_, planned) = plan().diff(&config, "config.yaml").unwrap();
+        assert_eq!(planned.merge.warnings, vec!["Not chained, pseudo nodes without a server: Block"]);
+    }
+
+    #[test]
+    fn test_execute_writes_full_pipeline_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(&config_path, CONFIG).unwrap();
+
+        let planned = plan().execute(&config_path).unwrap();
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), planned.content);
+        assert_eq!(planned.content, plan().render(CONFIG).unwrap().content);
+        assert!(planned.merge.backup_path.is_some());
+
+        // Re-running is a no-op: no rewrite, no new backup
+        let again = plan().execute(&config_path).unwrap();
+        assert_eq!(again.content, planned.content);
+        assert!(again.merge.backup_path.is_none());
+
+        let leftovers: Vec<_> = fs::read_dir(temp_dir.path()).unwrap()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().contains("ccp-tmp"))
+            .collect();
+        assert!(leftovers.is_empty());
+    }
+
+    #[test]
+    fn test_execute_writes_clone_provider_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.yaml");
+        fs::write(&config_path, CONFIG).unwrap();
+        let plan = ApplyPlan::new(MergerConfig {
+            create_backup: false,
+            clone_provider: Some("providers/chain.yaml".to_string()),
+            ..MergerConfig::default()
+        });
+
+        plan.execute(&config_path).unwrap();
+        let file_path = temp_dir.path().join("providers/chain.yaml");
+        let clones = fs::read_to_string(&file_path).unwrap();
+        assert!(clones.contains("  - { name: HK-Chain, type: socks5, server: 127.0.0.1, port: 10808, dialer-proxy: HK }"));
+        assert!(!fs::read_to_string(&config_path).unwrap().contains("HK-Chain"));
+
+        let (diff, _) = plan.diff_file(&config_path).unwrap();
+        assert!(diff.is_empty());
+        fs::remove_file(&file_path).unwrap();
+        assert!(plan.diff_file(&config_path).unwrap().0.starts_with("--- a/providers/chain.yaml\n"));
+
+        plan.execute(&config_path).unwrap();
+        assert!(file_path.exists());
+        ClashConfigMerger::with_config(plan.merger_config().clone()).unapply(&config_path).unwrap();
+        assert!(!file_path.exists());
+    }
+
+    #[test]
+    fn test_execute_failure_leaves_file_untouched() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.yaml");
+        let broken = "proxies:\n  - { name: HK, type: ss\nrules:\n  - MATCH,Proxy\n";
+        fs::write(&config_path, broken).unwrap();
+
+        assert!(plan().execute(&config_path).is_err());
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), broken);
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,186 @@
+//! Chains over `proxy-providers` nodes
+//!
+//! Provider nodes are only known to Clash at runtime, so they cannot be
+//! cloned one by one like the inline `proxies`. Instead each chained provider
+//! gets a `Chain-Via-<provider>` url-test group over its nodes (`use:`, with
+//! the node filter as `filter:` / `exclude-filter:`) and one SOCKS5 clone
+//! whose `dialer-proxy` is that group. This keeps the chain order of the
+//! inline clones, VPN node first and the SOCKS5 hop last; a companion
+//! provider with `override: { dialer-proxy: ... }` would dial the provider
+//! nodes through the SOCKS5 proxy, the other way round.
+//!
+//! The merger can also write its own clones into a local provider file
+//! ([`CLONE_PROVIDER`]), so large subscriptions don't bloat the config.
+
+use anyhow::Result;
+use serde_yaml::Value;
+use std::path::{Component, Path, PathBuf};
+
+use super::filter::NodeFilter;
+
+/// Name of the `type: file` provider holding the chain clones when they
+/// are written to a provider file
+pub const CLONE_PROVIDER: &str = "Chain-Clones";
+
+/// First line of a generated provider file
+const PROVIDER_FILE_HEADER: &str = "# Generated by clash-chain-patcher; rewritten on every apply";
+
+/// Local provider file with the chain clones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderFile {
+    /// Relative to the config's directory, as written in the provider's `path`
+    pub path: String,
+    pub content: String,
+}
+
+impl ProviderFile {
+    /// File holding the `proxies` lines (entries at two spaces)
+    pub fn new(path: &str, proxies: Vec<String>, crlf: bool) -> Self {
+        let ending = if crlf { "\r\n" } else { "\n" };
+        let mut lines = vec![PROVIDER_FILE_HEADER.to_string(), "proxies:".to_string()];
+        lines.extend(proxies);
+        Self { path: path.to_string(), content: lines.join(ending) + ending }
+    }
+}
+
+/// Location of a provider file `path` written in the config at `config_path`
+pub fn file_path(config_path: &Path, path: &str) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new("")).join(path)
+}
+
+/// Reject provider file paths that leave the config's directory; Mihomo
+/// only loads providers from below its home directory
+pub fn check_file_path(path: &str) -> Result<()> {
+    let relative = Path::new(path).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if path.trim().is_empty() || !relative {
+        anyhow::bail!("Provider file '{}' must be a relative path inside the config's directory", path);
+    }
+    Ok(())
+}
+
+/// `filter` matching exactly `names`
+pub fn exact_filter(names: &[String]) -> String {
+    let names: Vec<String> = names.iter().map(|n| regex::escape(n)).collect();
+    format!("^(?:{})$", names.join("|"))
+}
+
+/// Generated names for one chained provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderChain {
+    pub provider: String,
+    /// Group over the provider's nodes that the clone dials through
+    pub group: String,
+    /// SOCKS5 clone listed in the chain groups
+    pub clone: String,
+}
+
+impl ProviderChain {
+    pub fn new(provider: &str, chain_suffix: &str) -> Self {
+        Self {
+            provider: provider.to_string(),
+            group: format!("Chain-Via-{}", provider),
+            clone: format!("{}{}", provider, chain_suffix),
+        }
+    }
+}
+
+/// Names under `proxy-providers` in file order, without [`CLONE_PROVIDER`]
+pub fn provider_names(config: &serde_yaml::Mapping) -> Vec<String> {
+    config.get("proxy-providers")
+        .and_then(Value::as_mapping)
+        .map(|providers| providers.keys()
+            .filter_map(|k| k.as_str().filter(|k| *k != CLONE_PROVIDER).map(String::from))
+            .collect())
+        .unwrap_or_default()
+}
+
+/// `path` of the [`CLONE_PROVIDER`] entry, if the config has one
+pub fn clone_provider_path(config: &serde_yaml::Mapping) -> Option<String> {
+    config.get("proxy-providers")?
+        .get(CLONE_PROVIDER)?
+        .get("path")?
+        .as_str()
+        .map(String::from)
+}
+
+/// Providers to chain: every one of `available` unless `wanted` names some,
+/// plus a warning for each wanted provider that is missing
+pub fn select(available: &[String], wanted: Option<&[String]>) -> (Vec<String>, Vec<String>) {
+    let Some(wanted) = wanted else {
+        return (available.to_vec(), Vec::new());
+    };
+    let warnings = wanted.iter()
+        .filter(|w| !available.contains(w))
+        .map(|w| format!("Proxy provider '{}' not found", w))
+        .collect();
+    let selected = available.iter().filter(|a| wanted.contains(a)).cloned().collect();
+    (selected, warnings)
+}
+
+/// `filter` / `exclude-filter` of the via groups from the node filter, plus
+/// a warning when it uses criteria that only apply to inline proxies
+pub fn group_filters(filter: &NodeFilter) -> (Vec<(&'static str, String)>, Option<String>) {
+    let mut fields = Vec::new();
+    if let Some(include) = &filter.include {
+        fields.push(("filter", include.clone()));
+    }
+    // Ignore patterns are case-insensitive substrings (see `ignore_match`)
+    let ignore: Vec<String> = filter.ignore.iter()
+        .filter(|p| !p.is_empty())
+        .map(|p| regex::escape(p))
+        .collect();
+    let ignore = (!ignore.is_empty()).then(|| format!("(?i:{})", ignore.join("|")));
+    let exclude = match (&filter.exclude, ignore) {
+        (Some(exclude), Some(ignore)) => Some(format!("(?:{})|{}", exclude, ignore)),
+        (exclude, ignore) => exclude.clone().or(ignore),
+    };
+    if let Some(exclude) = exclude {
+        fields.push(("exclude-filter", exclude));
+    }
+    let unsupported = !filter.types.is_empty() || filter.server.is_some() || filter.limit.is_some();
+    let warning = unsupported
+        .then(|| "The type, server and limit filters do not apply to proxy-provider nodes".to_string());
+    (fields, warning)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_providers_and_filters() {
+        let available = vec!["sub".to_string(), "backup".to_string()];
+        assert_eq!(select(&available, None).0, available);
+        let wanted = vec!["backup".to_string(), "gone".to_string()];
+        let (selected, warnings) = select(&available, Some(&wanted));
+        assert_eq!(selected, vec!["backup"]);
+        assert_eq!(warnings, vec!["Proxy provider 'gone' not found"]);
+
+        let filter = NodeFilter {
+            include: Some("HK|JP".to_string()),
+            exclude: Some("test".to_string()),
+            ignore: vec!["剩余流量".to_string(), "Expire".to_string()],
+            ..NodeFilter::default()
+        };
+        let (fields, warning) = group_filters(&filter);
+        assert_eq!(fields, vec![("filter", "HK|JP".to_string()), ("exclude-filter", "(?:test)|(?i:剩余流量|Expire)".to_string())]);
+        assert!(warning.is_none());
+        let limited = NodeFilter { limit: Some(3), ..NodeFilter::default() };
+        assert_eq!(group_filters(&limited), (vec![], Some("The type, server and limit filters do not apply to proxy-provider nodes".to_string())));
+        assert_eq!(ProviderChain::new("sub", "-Chain").group, "Chain-Via-sub");
+    }
+
+    #[test]
+    fn test_provider_file_paths_and_filters() {
+        assert!(check_file_path("chain-clones.yaml").is_ok());
+        assert!(check_file_path("./providers/chain.yaml").is_ok());
+        assert!(check_file_path("../chain.yaml").is_err());
+        assert!(check_file_path("/etc/chain.yaml").is_err());
+        assert!(check_file_path(" ").is_err());
+
+        let names = vec!["HK 01-Chain".to_string(), "a.b-Chain".to_string()];
+        assert_eq!(exact_filter(&names), r"^(?:HK 01\-Chain|a\.b\-Chain)$");
+        let file = ProviderFile::new("c.yaml", vec!["  - { name: a }".to_string()], false);
+        assert_eq!(file.content, format!("{}\nproxies:\n  - {{ name: a }}\n", PROVIDER_FILE_HEADER));
+    }
+}
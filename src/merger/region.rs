@@ -0,0 +1,142 @@
+//! Region classification of proxy nodes
+//!
+//! Subscription node names carry region hints: flag emoji (`🇭🇰`), ISO codes
+//! (`US-LAX`) and place names (`日本`, `Tokyo`). A [`RegionTable`] maps them
+//! to region codes, so the merger can write one `Chain-<CODE>` group per
+//! region. The built-in table can be extended with saved [`Region`] entries.
+
+use serde::{Deserialize, Serialize};
+
+/// Built-in regions: code, then keywords (flag, codes, place names)
+const BUILTIN_REGIONS: &[(&str, &[&str])] = &[
+    ("HK", &["🇭🇰", "HK", "HKG", "Hong Kong", "HongKong", "香港"]),
+    ("TW", &["🇹🇼", "TW", "TWN", "Taiwan", "台湾", "臺灣", "台北", "Taipei"]),
+    ("JP", &["🇯🇵", "JP", "JPN", "Japan", "日本", "东京", "東京", "Tokyo", "大阪", "Osaka"]),
+    ("SG", &["🇸🇬", "SG", "SGP", "Singapore", "新加坡", "狮城"]),
+    ("KR", &["🇰🇷", "KR", "KOR", "Korea", "韩国", "韓國", "首尔", "Seoul"]),
+    ("US", &["🇺🇸", "US", "USA", "United States", "America", "美国", "美國",
+             "洛杉矶", "Los Angeles", "LAX", "圣何塞", "San Jose", "硅谷", "纽约", "New York"]),
+    ("GB", &["🇬🇧", "UK", "GB", "United Kingdom", "Britain", "英国", "伦敦", "London"]),
+    ("DE", &["🇩🇪", "DE", "Germany", "德国", "法兰克福", "Frankfurt"]),
+    ("FR", &["🇫🇷", "FR", "France", "法国", "巴黎", "Paris"]),
+    ("NL", &["🇳🇱", "NL", "Netherlands", "荷兰", "阿姆斯特丹", "Amsterdam"]),
+    ("CA", &["🇨🇦", "CA", "Canada", "加拿大"]),
+    ("AU", &["🇦🇺", "AU", "Australia", "澳大利亚", "澳洲", "悉尼", "Sydney"]),
+    ("RU", &["🇷🇺", "RU", "Russia", "俄罗斯", "莫斯科", "Moscow"]),
+    ("IN", &["🇮🇳", "India", "印度"]),
+    ("TR", &["🇹🇷", "Turkey", "Türkiye", "土耳其"]),
+];
+
+/// One region: its code and the keywords that identify it in node names
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    pub code: String,
+    pub keywords: Vec<String>,
+}
+
+/// Ordered regions used to classify node names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionTable {
+    regions: Vec<Region>,
+}
+
+impl Default for RegionTable {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl RegionTable {
+    pub fn builtin() -> Self {
+        let regions = BUILTIN_REGIONS.iter()
+            .map(|(code, keywords)| Region {
+                code: code.to_string(),
+                keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            })
+            .collect();
+        Self { regions }
+    }
+
+    /// Built-in table plus saved regions: keywords of a known code are
+    /// added to it, unknown codes are appended
+    pub fn with_custom(custom: &[Region]) -> Self {
+        let mut table = Self::builtin();
+        for region in custom {
+            let code = region.code.trim().to_ascii_uppercase();
+            match table.regions.iter_mut().find(|r| r.code == code) {
+                Some(known) => known.keywords.extend(region.keywords.iter().cloned()),
+                None => table.regions.push(Region { code, keywords: region.keywords.clone() }),
+            }
+        }
+        table
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Region code of a node name: the region whose keyword appears
+    /// earliest in the name
+    pub fn classify(&self, name: &str) -> Option<&str> {
+        self.regions.iter()
+            .filter_map(|r| {
+                r.keywords.iter()
+                    .filter_map(|k| keyword_position(name, k))
+                    .min()
+                    .map(|at| (at, r.code.as_str()))
+            })
+            .min_by_key(|(at, _)| *at)
+            .map(|(_, code)| code)
+    }
+}
+
+/// Byte offset of `keyword` in `name`. ASCII keywords match
+/// case-insensitively and only as whole words (`US` in `US-LAX` or `US01`,
+/// not in `Virus`); others (emoji, CJK) match anywhere.
+fn keyword_position(name: &str, keyword: &str) -> Option<usize> {
+    if keyword.is_empty() {
+        return None;
+    }
+    if !keyword.is_ascii() {
+        return name.find(keyword);
+    }
+    let haystack = name.to_ascii_lowercase();
+    let needle = keyword.to_ascii_lowercase();
+    let is_letter = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphabetic());
+    haystack.match_indices(&needle)
+        .map(|(at, _)| at)
+        .find(|&at| {
+            !is_letter(haystack[..at].chars().next_back())
+                && !is_letter(haystack[at + needle.len()..].chars().next())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_node_names() {
+        let table = RegionTable::builtin();
+        assert_eq!(table.classify("🇭🇰 Hong Kong 01"), Some("HK"));
+        assert_eq!(table.classify("US-LAX"), Some("US"));
+        assert_eq!(table.classify("日本 02 | 1x"), Some("JP"));
+        assert_eq!(table.classify("hk01"), Some("HK"));
+        assert_eq!(table.classify("Virus Scanner"), None);
+        assert_eq!(table.classify("Plus Node"), None);
+        // The earliest hint wins
+        assert_eq!(table.classify("香港→日本 中转"), Some("HK"));
+    }
+
+    #[test]
+    fn test_custom_regions_extend_table() {
+        let custom = vec![
+            Region { code: "jp".to_string(), keywords: vec!["Nippon".to_string()] },
+            Region { code: "VN".to_string(), keywords: vec!["越南".to_string(), "Vietnam".to_string()] },
+        ];
+        let table = RegionTable::with_custom(&custom);
+        assert_eq!(table.classify("Nippon 03"), Some("JP"));
+        assert_eq!(table.classify("越南 01"), Some("VN"));
+        assert_eq!(table.regions().last().unwrap().code, "VN");
+    }
+}
@@ -11,6 +11,11 @@ use serde::{Deserialize, Serialize};
 use serde_yaml::{Mapping, Value};
 use std::collections::{HashMap, HashSet};
 
+use crate::merger::filter::{default_ignore_patterns, ignore_match};
+use crate::merger::{block, json};
+use crate::yaml::cst::{Kind, Pos, ScalarStyle, Span, Style};
+use crate::yaml::{Document, Node, Seg, Sequence};
+
 /// SOCKS5 proxy configuration
 #[derive(Debug, Clone)]
 pub struct Socks5Proxy {
@@ -57,9 +62,20 @@ impl Socks5Proxy {
 }
 
 /// Patcher options
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct PatchOptions {
     pub filter_keywords: Vec<String>,
+    /// Node names containing any of these are skipped
+    pub ignore_patterns: Vec<String>,
+}
+
+impl Default for PatchOptions {
+    fn default() -> Self {
+        Self {
+            filter_keywords: Vec::new(),
+            ignore_patterns: default_ignore_patterns(),
+        }
+    }
 }
 
 /// Result of patching operation
@@ -210,11 +226,10 @@ pub fn preview_patch(
 
     let existing_names = get_existing_proxy_names(&config);
 
-    // Skip patterns
-    let skip_patterns = vec!["若节点超时", "Emby", "SOCKS5"];
+    // Skip subscription pseudo-nodes
     let valid_proxies: Vec<String> = existing_names
         .iter()
-        .filter(|name| !skip_patterns.iter().any(|pat| name.contains(pat)))
+        .filter(|name| ignore_match(&options.ignore_patterns, name).is_none())
         .cloned()
         .collect();
 
@@ -271,11 +286,10 @@ pub fn apply_patch(
 
     let existing_names = get_existing_proxy_names(&config);
 
-    // Skip patterns
-    let skip_patterns = vec!["若节点超时", "Emby", "SOCKS5"];
+    // Skip subscription pseudo-nodes
     let valid_proxies: Vec<String> = existing_names
         .iter()
-        .filter(|name| !skip_patterns.iter().any(|pat| name.contains(pat)))
+        .filter(|name| ignore_match(&options.ignore_patterns, name).is_none())
         .cloned()
         .collect();
 
@@ -483,7 +497,7 @@ pub struct CustomRuleSet {
 /// - `TYPE,PATTERN,GROUP` (e.g. "DOMAIN,example.com,Proxy")
 /// - `TYPE,PATTERN,GROUP,extra` (e.g. "IP-CIDR,1.1.1.1/32,Proxy,no-resolve")
 /// - `MATCH,GROUP` (the default/fallback rule)
-fn extract_group_from_rule(rule: &str) -> Option<String> {
+pub(crate) fn extract_group_from_rule(rule: &str) -> Option<String> {
     let parts: Vec<&str> = rule.split(',').collect();
     match parts.len() {
         // MATCH,GROUP
@@ -497,7 +511,7 @@ fn extract_group_from_rule(rule: &str) -> Option<String> {
 /// Extract all unique proxy groups referenced in the rules section,
 /// along with their rule counts. Results are sorted by count (descending).
 pub fn extract_rule_groups(config_content: &str) -> Vec<RuleGroup> {
-    let config: Value = match serde_yaml::from_str(config_content) {
+    let config = match crate::yaml::parse_value(config_content) {
         Ok(v) => v,
         Err(_) => return vec![],
     };
@@ -505,6 +519,35 @@ pub fn extract_rule_groups(config_content: &str) -> Vec<RuleGroup> {
     extract_rule_groups_from_value(&config)
 }
 
+/// Built-in policies a rule may point at
+const BUILTIN_POLICIES: &[&str] = &["DIRECT", "REJECT", "REJECT-DROP", "PASS", "COMPATIBLE", "GLOBAL"];
+
+/// Groups that rules point at but the config does not define as a proxy,
+/// group or built-in policy, once each in rule order. Logic rules (`AND`,
+/// `OR`, `NOT`) and `SUB-RULE` are not checked.
+pub fn missing_rule_targets(config: &Value) -> Vec<String> {
+    let mut known: HashSet<&str> = BUILTIN_POLICIES.iter().copied().collect();
+    for key in ["proxies", "proxy-groups"] {
+        let entries = config.get(key).and_then(Value::as_sequence).map(Vec::as_slice).unwrap_or_default();
+        known.extend(entries.iter().filter_map(|e| e.get("name").and_then(Value::as_str)));
+    }
+
+    let mut missing: Vec<String> = Vec::new();
+    let rules = config.get("rules").and_then(Value::as_sequence).map(Vec::as_slice).unwrap_or_default();
+    for rule in rules.iter().filter_map(Value::as_str) {
+        let kind = rule.split(',').next().unwrap_or_default().trim();
+        if matches!(kind, "AND" | "OR" | "NOT" | "SUB-RULE") {
+            continue;
+        }
+        if let Some(group) = extract_group_from_rule(rule) {
+            if !known.contains(group.as_str()) && !missing.contains(&group) {
+                missing.push(group);
+            }
+        }
+    }
+    missing
+}
+
 /// Extract rule groups from a parsed YAML Value
 pub fn extract_rule_groups_from_value(config: &Value) -> Vec<RuleGroup> {
     let mut counts: HashMap<String, usize> = HashMap::new();
@@ -582,73 +625,86 @@ pub fn rewrite_rules_in_value(config: &mut Value, replacements: &HashMap<String,
     rewritten
 }
 
+/// Trailing comment recording the group a rewritten rule originally pointed at
+pub const REWRITE_MARKER: &str = "# ccp: was ";
+
+/// Trailing comment that marked injected rules before managed blocks
+/// (still recognized so older configs can be cleaned up)
+pub const CUSTOM_RULE_MARKER: &str = "# ccp: custom";
+
+/// Replace the group `old_group` in a rule line with `new_group`.
+///
+/// Matches ",old_group" at a word boundary: it must be followed by
+/// end-of-line, a quote char, a comma (no-resolve suffix), or whitespace.
+pub(crate) fn replace_rule_group(line: &str, old_group: &str, new_group: &str) -> Option<String> {
+    let pattern = format!(",{}", old_group);
+    let pos = line.find(&pattern)?;
+    let after = pos + pattern.len();
+    let is_boundary = match line[after..].chars().next() {
+        None => true,                          // end of line
+        Some('\'' | '"') => true,              // closing quote
+        Some(',') => true,                     // e.g. ,Proxy,no-resolve
+        Some(c) if c.is_whitespace() => true,  // trailing space
+        _ => false,                            // part of longer name like ProxyMedia
+    };
+    is_boundary.then(|| format!("{},{}{}", &line[..pos], new_group, &line[after..]))
+}
+
 /// Text-based rules rewrite that preserves original YAML formatting.
 /// Unlike `rewrite_rules`, this does NOT parse/serialize YAML, so flow style,
 /// quoting, indentation, and comments are all preserved.
+///
+/// Each rewritten rule gets a trailing `# ccp: was <group>` comment so
+/// `revert_rules_text` can restore it later. JSON configs are handled by
+/// [`json::rewrite_rules_text`].
 pub fn rewrite_rules_text(content: &str, replacements: &HashMap<String, String>) -> (String, usize) {
     if replacements.is_empty() {
         return (content.to_string(), 0);
     }
+    if json::is_json(content) {
+        return json::rewrite_rules_text(content, replacements).unwrap_or_else(|_| (content.to_string(), 0));
+    }
 
-    let mut count = 0;
-    let mut in_rules_section = false;
-
-    let lines: Vec<String> = content.lines().map(|line| {
-        let trimmed = line.trim_start();
-
-        // Detect top-level section changes (line starts at column 0 with "key:")
-        // Exclude YAML list entries ("- ") which are section content, not headers
-        if !line.starts_with(' ') && !line.starts_with('\t') && !line.starts_with("- ") && !line.is_empty() {
-            in_rules_section = trimmed.starts_with("rules:");
-        }
-
-        if !in_rules_section {
-            return line.to_string();
-        }
-
-        // Only process rule lines (contain comma-separated rule parts)
-        // Rule lines look like: "    - 'DOMAIN-SUFFIX,google.com,Proxy'"
-        // or: "- DOMAIN-SUFFIX,google.com,Proxy"
-        if !trimmed.starts_with("- ") {
-            return line.to_string();
+    let mut doc = Document::parse(content);
+    let Some(rules) = doc.node(&[Seg::Key("rules")]) else {
+        return (content.to_string(), 0);
+    };
+    let managed = managed_lines(doc.lines());
+
+    let mut edits = Vec::new();
+    for rule in block_rules(&rules) {
+        // Generated rules are replaced wholesale, never rewritten
+        let line = &doc.lines()[rule.span.start.line];
+        if managed[rule.span.start.line] {
+            continue;
         }
 
-        for (old_group, new_group) in replacements {
-            // Match ",oldgroup" at a word boundary: must be followed by
-            // end-of-line, quote char, comma (no-resolve suffix), or whitespace
-            let pattern = format!(",{}", old_group);
-            if let Some(pos) = line.find(&pattern) {
-                let after = pos + pattern.len();
-                let next_char = line[after..].chars().next();
-                let is_boundary = match next_char {
-                    None => true,                          // end of line
-                    Some('\'' | '"') => true,              // closing quote
-                    Some(',') => true,                     // e.g. ,Proxy,no-resolve
-                    Some(c) if c.is_whitespace() => true,  // trailing space
-                    _ => false,                            // part of longer name like ProxyMedia
-                };
-                if is_boundary {
-                    count += 1;
-                    let replacement = format!(",{}", new_group);
-                    return format!("{}{}{}", &line[..pos], replacement, &line[after..]);
-                }
+        let text = &line[rule.span.start.col..rule.span.end.col];
+        let rewritten = replacements
+            .iter()
+            .find_map(|(old_group, new_group)| Some((old_group, replace_rule_group(text, old_group, new_group)?)));
+        if let Some((old_group, mut new_text)) = rewritten {
+            new_text.push_str(&line[rule.span.end.col..]);
+            // Keep the first recorded origin when a rule is rewritten twice
+            if !line.contains(REWRITE_MARKER) && !line.contains(CUSTOM_RULE_MARKER) {
+                new_text.push_str(&format!(" {}{}", REWRITE_MARKER, old_group));
             }
+            edits.push((to_line_end(rule.span.start, line), new_text));
         }
-
-        line.to_string()
-    }).collect();
-
-    // Preserve trailing newline if original had one
-    let mut output = lines.join("\n");
-    if content.ends_with('\n') && !output.ends_with('\n') {
-        output.push('\n');
     }
-    (output, count)
+
+    let count = edits.len();
+    doc.replace(edits);
+    (doc.to_string(), count)
 }
 
 /// Text-based custom rules injection that preserves original YAML formatting.
 /// Injects custom rules at the top of the `rules:` section for highest priority.
 /// Returns `(new_content, injected_count)`.
+///
+/// Injected rules are wrapped in a managed block; the block from an earlier
+/// run is replaced rather than stacked (for JSON configs, the rules recorded
+/// by [`json::inject_custom_rules_text`]).
 pub fn inject_custom_rules_text(content: &str, rules: &[CustomRule]) -> (String, usize) {
     // Filter to enabled rules with non-empty domains
     let active: Vec<&CustomRule> = rules
@@ -659,80 +715,222 @@ pub fn inject_custom_rules_text(content: &str, rules: &[CustomRule]) -> (String,
     if active.is_empty() {
         return (content.to_string(), 0);
     }
-
-    let lines: Vec<&str> = content.lines().collect();
-    let mut rules_header_idx = None;
-
-    // Find the `rules:` section header
-    for (i, line) in lines.iter().enumerate() {
-        let trimmed = line.trim_start();
-        if !line.starts_with(' ') && !line.starts_with('\t') && !line.is_empty() {
-            if trimmed.starts_with("rules:") {
-                rules_header_idx = Some(i);
-                break;
-            }
-        }
+    if json::is_json(content) {
+        let rules = active.iter().map(|r| custom_rule_string(r)).collect();
+        return json::inject_custom_rules_text(content, rules).unwrap_or_else(|_| (content.to_string(), 0));
     }
 
-    let Some(header_idx) = rules_header_idx else {
+    let mut doc = Document::parse(content);
+    let Some(section) = doc.section("rules") else {
         return (content.to_string(), 0);
     };
+    if !doc.open_block(&section) {
+        return (content.to_string(), 0);
+    }
 
-    // Detect indent and quoting from first existing rule entry
-    let mut indent = "  ".to_string();
-    let mut use_quotes = false;
-    let mut quote_char = '\'';
-
-    for line in &lines[header_idx + 1..] {
-        let trimmed = line.trim_start();
-        if trimmed.starts_with("- ") {
-            // Detect indent
-            let leading = line.len() - trimmed.len();
-            indent = " ".repeat(leading);
-
-            // Detect quoting: check if the rule value after "- " is quoted
-            let after_dash = trimmed.strip_prefix("- ").unwrap_or("");
-            if after_dash.starts_with('\'') || after_dash.starts_with('"') {
-                use_quotes = true;
-                quote_char = after_dash.chars().next().unwrap();
+    // Drop rules injected by a previous run
+    drop_injected_rules(&mut doc);
+
+    // Follow the quoting of the first existing rule
+    let quote_char = doc.node(&[Seg::Key("rules"), Seg::Index(0)]).and_then(|rule| match rule.kind {
+        Kind::Scalar(ScalarStyle::SingleQuoted, _) => Some('\''),
+        Kind::Scalar(ScalarStyle::DoubleQuoted, _) => Some('"'),
+        _ => None,
+    });
+    let new_rules: Vec<String> = active
+        .iter()
+        .map(|r| {
+            let rule_str = custom_rule_string(r);
+            match quote_char {
+                Some(q) => format!("{}{}{}", q, rule_str, q),
+                None => rule_str,
             }
-            break;
-        }
-        // Stop if we hit another top-level section
-        if !line.starts_with(' ') && !line.starts_with('\t') && !line.is_empty() && !trimmed.starts_with('#') {
-            break;
+        })
+        .collect();
+
+    // Right after the rules: header, in a managed block
+    if !doc.insert_items(&[Seg::Key("rules")], 0, &new_rules, block::wrap) {
+        return (content.to_string(), 0);
+    }
+    (doc.to_string(), new_rules.len())
+}
+
+/// Clash rule text of a custom rule, e.g. `IP-CIDR,1.1.1.1/32,DIRECT,no-resolve`
+fn custom_rule_string(rule: &CustomRule) -> String {
+    let mut rule_str = format!("{},{},{}", rule.match_type.clash_prefix(), rule.domain.trim(), rule.target_group);
+    if rule.match_type.needs_no_resolve() {
+        rule_str.push_str(",no-resolve");
+    }
+    rule_str
+}
+
+/// Remove managed blocks (and legacy `# ccp: custom` rules) from the rules
+/// section; returns the number of rules removed
+fn drop_injected_rules(doc: &mut Document) -> usize {
+    let Some(section) = doc.section("rules") else {
+        return 0;
+    };
+    let mut kept = Vec::new();
+    let mut dropped = Vec::new();
+    let mut in_block = false;
+    for line in doc.section_lines(&section) {
+        if block::is_begin(line) {
+            in_block = true;
+        } else if block::is_end(line) {
+            in_block = false;
+        } else if in_block {
+            dropped.push(line.clone());
+        } else {
+            kept.push(line.clone());
         }
     }
+    let mut removed = Sequence::parse(&dropped).entries.len();
+    doc.splice(vec![(section.body, kept)]);
+
+    let legacy: Vec<usize> = doc.node(&[Seg::Key("rules")])
+        .map(|rules| rules.items()
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| doc.lines()[item.node.span.end.line].contains(CUSTOM_RULE_MARKER))
+            .map(|(i, _)| i)
+            .collect())
+        .unwrap_or_default();
+    removed += doc.retain_items(&[Seg::Key("rules")], |i, _| !legacy.contains(&i));
+    removed
+}
 
-    // Generate new rule lines
-    let new_lines: Vec<String> = active
+/// Rules of a block list written on one line each (the ones that can
+/// carry a trailing comment)
+fn block_rules(rules: &Node) -> impl Iterator<Item = &Node> {
+    let block = rules.style() == Some(Style::Block);
+    rules.items()
         .iter()
-        .map(|r| {
-            let mut rule_str = format!("{},{},{}", r.match_type.clash_prefix(), r.domain.trim(), r.target_group);
-            if r.match_type.needs_no_resolve() {
-                rule_str.push_str(",no-resolve");
-            }
-            if use_quotes {
-                format!("{}- {}{}{}", indent, quote_char, rule_str, quote_char)
-            } else {
-                format!("{}- {}", indent, rule_str)
+        .map(|item| &item.node)
+        .filter(move |node| block && node.as_str().is_some() && node.span.start.line == node.span.end.line)
+}
+
+/// Whether each line sits inside a managed block (markers included)
+fn managed_lines(lines: &[String]) -> Vec<bool> {
+    let mut in_block = false;
+    lines.iter()
+        .map(|line| {
+            if block::is_begin(line) {
+                in_block = true;
+            } else if block::is_end(line) {
+                in_block = false;
+                return true;
             }
+            in_block
         })
-        .collect();
+        .collect()
+}
+
+/// Span from `start` to the end of its line
+fn to_line_end(start: Pos, line: &str) -> Span {
+    Span { start, end: Pos { col: line.len(), ..start } }
+}
+
+/// Outcome of `revert_rules_text`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleRevert {
+    /// Rewritten rules pointed back at their original group
+    pub restored: usize,
+    /// Injected custom rules removed
+    pub removed: usize,
+}
+
+/// Undo `rewrite_rules_text` and `inject_custom_rules_text` (format-preserving).
+///
+/// Rules with a `# ccp: was <group>` comment get their group back, managed
+/// blocks of injected rules (and legacy `# ccp: custom` rules) are dropped.
+/// Rules rewritten before these comments existed are mapped through
+/// `fallback` (e.g. `Chain-Selector` -> main group).
+pub fn revert_rules_text(content: &str, fallback: &HashMap<String, String>) -> (String, RuleRevert) {
+    if json::is_json(content) {
+        return json::revert_rules_text(content, fallback)
+            .unwrap_or_else(|_| (content.to_string(), RuleRevert::default()));
+    }
+    let mut revert = RuleRevert::default();
+    let mut doc = Document::parse(content);
+    if doc.section("rules").is_none() {
+        return (content.to_string(), revert);
+    }
+
+    revert.removed = drop_injected_rules(&mut doc);
+    let Some(rules) = doc.node(&[Seg::Key("rules")]) else {
+        return (doc.to_string(), revert);
+    };
+
+    let mut edits = Vec::new();
+    for rule in block_rules(&rules) {
+        let line = &doc.lines()[rule.span.start.line];
+        let text = &line[rule.span.start.col..rule.span.end.col];
+
+        if let Some(origin) = recorded_origin(rule, line) {
+            edits.push((to_line_end(rule.span.start, line), origin.restored));
+            continue;
+        }
+
+        let restored = fallback
+            .iter()
+            .find_map(|(from, to)| replace_rule_group(text, from, to));
+        if let Some(restored) = restored {
+            edits.push((rule.span, restored));
+        }
+    }
 
-    let count = new_lines.len();
+    revert.restored = edits.len();
+    doc.replace(edits);
+    (doc.to_string(), revert)
+}
 
-    // Rebuild: insert new lines right after the rules: header
-    let mut result = Vec::with_capacity(lines.len() + count);
-    result.extend(lines[..=header_idx].iter().map(|s| s.to_string()));
-    result.extend(new_lines);
-    result.extend(lines[header_idx + 1..].iter().map(|s| s.to_string()));
+/// A rule rewritten by [`rewrite_rules_text`] and how to undo it
+struct Origin {
+    /// Group recorded in the `# ccp: was` comment
+    was: String,
+    /// Group the rule points at now
+    current: String,
+    /// Rule pointed back at `was`, up to the line end, without the comment
+    restored: String,
+}
 
-    let mut output = result.join("\n");
-    if content.ends_with('\n') && !output.ends_with('\n') {
-        output.push('\n');
+fn recorded_origin(rule: &Node, line: &str) -> Option<Origin> {
+    let text = &line[rule.span.start.col..rule.span.end.col];
+    let after = &line[rule.span.end.col..];
+    let pos = after.find(REWRITE_MARKER)?;
+    let was = after[pos + REWRITE_MARKER.len()..].trim().to_string();
+    // Drop only the space added with the marker; keep the rest as-is
+    let kept = after[..pos].strip_suffix(' ').unwrap_or(&after[..pos]);
+    let current = rule.as_str().and_then(extract_group_from_rule)?;
+    let restored = format!("{}{}", replace_rule_group(text, &current, &was)?, kept);
+    Some(Origin { was, current, restored })
+}
+
+/// Undo the rewrites for which `pick(origin, current group)` holds, leaving
+/// other rewrites and injected rules alone; returns how many were undone
+///
+/// Lets a re-apply rewrite from the recorded origins instead of the groups
+/// an earlier run put in. JSON configs are handled by
+/// [`json::revert_rewrites_text`].
+pub fn revert_rewrites_text(content: &str, pick: impl Fn(&str, &str) -> bool) -> (String, usize) {
+    if json::is_json(content) {
+        return json::revert_rewrites_text(content, pick).unwrap_or_else(|_| (content.to_string(), 0));
     }
-    (output, count)
+    let mut doc = Document::parse(content);
+    let Some(rules) = doc.node(&[Seg::Key("rules")]) else {
+        return (content.to_string(), 0);
+    };
+    let edits: Vec<(Span, String)> = block_rules(&rules)
+        .filter_map(|rule| {
+            let line = &doc.lines()[rule.span.start.line];
+            let origin = recorded_origin(rule, line)?;
+            pick(&origin.was, &origin.current).then(|| (to_line_end(rule.span.start, line), origin.restored))
+        })
+        .collect();
+
+    let count = edits.len();
+    doc.replace(edits);
+    (doc.to_string(), count)
 }
 
 /// Parse a custom rule from a CLI string.
@@ -914,6 +1112,64 @@ rules:
         assert!(!output.contains("Chain-SelectorMedia")); // No corruption
     }
 
+    #[test]
+    fn test_rewrite_rules_text_records_origin() {
+        let yaml = "rules:\n  - 'DOMAIN,a.com,Proxy'\n  - MATCH,Proxy\n";
+        let mut replacements = HashMap::new();
+        replacements.insert("Proxy".to_string(), "Chain-Selector".to_string());
+        let (output, _) = rewrite_rules_text(yaml, &replacements);
+        assert!(output.contains("- 'DOMAIN,a.com,Chain-Selector' # ccp: was Proxy"));
+        assert!(output.contains("- MATCH,Chain-Selector # ccp: was Proxy"));
+
+        // A second rewrite keeps the first recorded origin
+        let mut replacements = HashMap::new();
+        replacements.insert("Chain-Selector".to_string(), "Chain-Auto".to_string());
+        let (output, count) = rewrite_rules_text(&output, &replacements);
+        assert_eq!(count, 2);
+        assert!(output.contains("- MATCH,Chain-Auto # ccp: was Proxy"));
+        assert!(!output.contains("was Chain-Selector"));
+    }
+
+    #[test]
+    fn test_missing_rule_targets() {
+        let config: Value = serde_yaml::from_str(
+            "proxies: [{ name: HK }]\nproxy-groups: [{ name: Proxy }]\n\
+             rules:\n  - DOMAIN,a.com,HK\n  - AND,((DOMAIN,b.com),(NETWORK,UDP)),Lost\n  - IP-CIDR,1.1.1.1/32,Gone,no-resolve\n  - MATCH,Proxy\n  - MATCH,REJECT\n",
+        ).unwrap();
+        assert_eq!(missing_rule_targets(&config), vec!["Gone"]);
+    }
+
+    #[test]
+    fn test_revert_rules_text() {
+        let yaml = "rules:\n  - 'DOMAIN,a.com,Proxy'\n  - DOMAIN,b.com,DIRECT # keep\n  - MATCH,Proxy\n";
+        let mut replacements = HashMap::new();
+        replacements.insert("Proxy".to_string(), "Chain-Selector".to_string());
+        let (rewritten, _) = rewrite_rules_text(yaml, &replacements);
+        let rules = vec![CustomRule {
+            match_type: RuleMatchType::DomainKeyword,
+            domain: "lark".to_string(),
+            target_group: "DIRECT".to_string(),
+            enabled: true,
+        }];
+        let (patched, _) = inject_custom_rules_text(&rewritten, &rules);
+
+        let (output, revert) = revert_rules_text(&patched, &HashMap::new());
+        assert_eq!(revert, RuleRevert { restored: 2, removed: 1 });
+        assert_eq!(output, yaml);
+    }
+
+    #[test]
+    fn test_revert_rules_text_unmarked_fallback() {
+        let yaml = "rules:\n  - DOMAIN,a.com,Chain-Auto\n  - MATCH,Chain-Selector\n";
+        let mut fallback = HashMap::new();
+        fallback.insert("Chain-Selector".to_string(), "Proxy".to_string());
+        fallback.insert("Chain-Auto".to_string(), "Proxy".to_string());
+
+        let (output, revert) = revert_rules_text(yaml, &fallback);
+        assert_eq!(revert.restored, 2);
+        assert_eq!(output, "rules:\n  - DOMAIN,a.com,Proxy\n  - MATCH,Proxy\n");
+    }
+
     // === Custom Rules Tests ===
 
     #[test]
@@ -986,9 +1242,39 @@ rules:
         // Custom rules should appear before existing rules
         let lines: Vec<&str> = output.lines().collect();
         assert_eq!(lines[0], "rules:");
-        assert!(lines[1].contains("DOMAIN-SUFFIX,lark.com,DIRECT"));
-        assert!(lines[2].contains("DOMAIN-KEYWORD,feishu,DIRECT"));
-        assert!(lines[3].contains("DOMAIN,a.com,Proxy"));
+        assert_eq!(lines[1], "  # >>> clash-chain-patcher");
+        assert!(lines[2].contains("DOMAIN-SUFFIX,lark.com,DIRECT"));
+        assert!(lines[3].contains("DOMAIN-KEYWORD,feishu,DIRECT"));
+        assert_eq!(lines[4], "  # <<< clash-chain-patcher");
+        assert!(lines[5].contains("DOMAIN,a.com,Proxy"));
+    }
+
+    #[test]
+    fn test_inject_custom_rules_replaces_previous_run() {
+        let yaml = "rules:\n  - MATCH,Proxy\n";
+        let rules = vec![CustomRule {
+            match_type: RuleMatchType::DomainSuffix,
+            domain: "lark.com".to_string(),
+            target_group: "DIRECT".to_string(),
+            enabled: true,
+        }];
+        let (once, _) = inject_custom_rules_text(yaml, &rules);
+        let (twice, count) = inject_custom_rules_text(&once, &rules);
+        assert_eq!(count, 1);
+        assert_eq!(once, twice);
+        assert_eq!(twice.matches("lark.com").count(), 1);
+    }
+
+    #[test]
+    fn test_inject_custom_rules_replaces_legacy_markers() {
+        let yaml = "rules:\n  - DOMAIN-SUFFIX,old.com,DIRECT # ccp: custom\n  - MATCH,Proxy\n";
+        let rules = parse_custom_rule_string("DOMAIN-SUFFIX,lark.com,DIRECT");
+        let (output, _) = inject_custom_rules_text(yaml, &rules);
+        assert!(!output.contains("old.com"));
+
+        let (reverted, revert) = revert_rules_text(&output, &HashMap::new());
+        assert_eq!(revert.removed, 1);
+        assert_eq!(reverted, "rules:\n  - MATCH,Proxy\n");
     }
 
     #[test]
@@ -1,7 +1,10 @@
 //! Configuration structures for the proxy server
 
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
+use crate::config::secret_ref;
+
 /// Configuration for the proxy server
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProxyConfig {
@@ -49,6 +52,16 @@ impl Default for UpstreamConfig {
 }
 
 impl UpstreamConfig {
+    /// Copy with `env:` / `file:` / `cmd:` credential references resolved
+    pub fn resolved(&self) -> Result<Self> {
+        Ok(Self {
+            host: self.host.clone(),
+            port: self.port,
+            username: secret_ref::resolve_optional(self.username.as_deref())?,
+            password: secret_ref::resolve_optional(self.password.as_deref())?,
+        })
+    }
+
     /// Parse upstream config from proxy string
     ///
     /// Supports two formats:
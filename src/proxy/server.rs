@@ -25,6 +25,10 @@ impl ProxyServer {
     ///
     /// This function will block until the server is stopped.
     pub async fn start(&self) -> Result<()> {
+        // Resolve credential references up front, so a bad one fails here
+        // rather than on every connection
+        self.upstream.credentials()?;
+
         let listener = TcpListener::bind(&self.config.listen_addr)
             .await
             .context("Failed to bind to listen address")?;
@@ -3,19 +3,33 @@
 use crate::proxy::config::UpstreamConfig;
 use anyhow::{Context, Result};
 use fast_socks5::client::{Config as Socks5ClientConfig, Socks5Stream};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use tokio::net::TcpStream;
 use tracing::debug;
 
 /// Upstream SOCKS5 proxy
 pub struct UpstreamProxy {
     config: UpstreamConfig,
+    /// `config` with its secret references resolved, once
+    credentials: OnceLock<UpstreamConfig>,
 }
 
 impl UpstreamProxy {
     /// Create a new upstream proxy
     pub fn new(config: UpstreamConfig) -> Self {
-        Self { config }
+        Self { config, credentials: OnceLock::new() }
+    }
+
+    /// Credentials with `env:` / `file:` / `cmd:` references resolved
+    ///
+    /// Resolved on first use and cached, so a `cmd:` reference is not run
+    /// again for every connection. Failures are not cached.
+    pub fn credentials(&self) -> Result<&UpstreamConfig> {
+        if let Some(credentials) = self.credentials.get() {
+            return Ok(credentials);
+        }
+        let resolved = self.config.resolved()?;
+        Ok(self.credentials.get_or_init(|| resolved))
     }
 
     /// Connect to target through upstream SOCKS5 proxy
@@ -39,10 +53,11 @@ impl UpstreamProxy {
 
         let upstream_addr = format!("{}:{}", self.config.host, self.config.port);
         let socks_config = Socks5ClientConfig::default();
+        let credentials = self.credentials()?;
 
         // Use fast-socks5 to establish connection through upstream
         let socks_stream = if let (Some(username), Some(password)) =
-            (&self.config.username, &self.config.password)
+            (&credentials.username, &credentials.password)
         {
             // With authentication
             Socks5Stream::connect_with_password(
@@ -91,3 +106,24 @@ impl From<UpstreamConfig> for UpstreamProxy {
 pub fn create_upstream(config: UpstreamConfig) -> Arc<UpstreamProxy> {
     Arc::new(UpstreamProxy::new(config))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credentials_resolved_once() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("socks");
+        std::fs::write(&path, "from-file\n").unwrap();
+        let upstream = UpstreamProxy::new(UpstreamConfig {
+            username: Some("user".to_string()),
+            password: Some(format!("file:{}", path.display())),
+            ..UpstreamConfig::default()
+        });
+
+        assert_eq!(upstream.credentials().unwrap().password.as_deref(), Some("from-file"));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(upstream.credentials().unwrap().password.as_deref(), Some("from-file"));
+    }
+}
@@ -0,0 +1,8 @@
+//! Exit rotation module
+//!
+//! Swaps the fixed SOCKS5 exit on a schedule, or once the active upstream
+//! keeps failing health checks, and re-patches the Clash config.
+
+pub mod rotator;
+
+pub use rotator::{RotationEvent, RotationReason, Rotator};
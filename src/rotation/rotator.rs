@@ -0,0 +1,337 @@
+//! Exit rotation policy and executor
+//!
+//! The rotator only looks at the health data already recorded on each
+//! `UpstreamProxy`; callers are expected to run health checks first and
+//! persist the updated policy after a rotation.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use std::fmt;
+use std::path::Path;
+use tracing::info;
+
+use crate::config::{BackupConfig, RotationConfig, UpstreamProxy};
+use crate::merger::{ClashConfigMerger, MergeResult, MergerConfig};
+
+/// Why a rotation was triggered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RotationReason {
+    /// No active upstream (never rotated, or it left the candidate set)
+    Initial,
+
+    /// The rotation interval elapsed
+    Interval,
+
+    /// The active upstream reached the failure threshold
+    Unhealthy { failures: u32 },
+
+    /// Requested explicitly by the user
+    Manual,
+}
+
+impl fmt::Display for RotationReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RotationReason::Initial => write!(f, "no active upstream"),
+            RotationReason::Interval => write!(f, "interval elapsed"),
+            RotationReason::Unhealthy { failures } => {
+                write!(f, "{} consecutive health failures", failures)
+            }
+            RotationReason::Manual => write!(f, "manual"),
+        }
+    }
+}
+
+/// A completed rotation
+#[derive(Debug, Clone)]
+pub struct RotationEvent {
+    pub reason: RotationReason,
+    pub from_id: Option<String>,
+    pub from_name: Option<String>,
+    pub to_id: String,
+    pub to_name: String,
+    pub old_exit_ip: Option<String>,
+    pub new_exit_ip: Option<String>,
+    pub merge: MergeResult,
+}
+
+/// Applies a `RotationConfig` to an upstream pool
+pub struct Rotator {
+    policy: RotationConfig,
+    base: MergerConfig,
+    backups: BackupConfig,
+}
+
+impl Rotator {
+    pub fn new(policy: RotationConfig) -> Self {
+        Self::with_merger_config(policy, MergerConfig::default())
+    }
+
+    /// Create a rotator whose merges start from `base` (suffix, backup, ...)
+    pub fn with_merger_config(policy: RotationConfig, base: MergerConfig) -> Self {
+        Self { policy, base, backups: BackupConfig::default() }
+    }
+
+    /// Backup location and retention for the configs this rotator patches
+    pub fn with_backups(mut self, backups: BackupConfig) -> Self {
+        self.backups = backups;
+        self
+    }
+
+    /// Current policy, including the active upstream and last rotation time
+    pub fn policy(&self) -> &RotationConfig {
+        &self.policy
+    }
+
+    /// Enabled upstreams eligible for rotation, in pool order
+    pub fn candidates<'a>(&self, pool: &'a [UpstreamProxy]) -> Vec<&'a UpstreamProxy> {
+        pool.iter()
+            .filter(|p| p.enabled)
+            .filter(|p| {
+                self.policy.candidate_ids.is_empty()
+                    || self.policy.candidate_ids.iter().any(|id| id == &p.id)
+            })
+            .collect()
+    }
+
+    /// The upstream currently patched into Clash, if it is still a candidate
+    pub fn active<'a>(&self, pool: &'a [UpstreamProxy]) -> Option<&'a UpstreamProxy> {
+        let id = self.policy.active_upstream_id.as_deref()?;
+        self.candidates(pool).into_iter().find(|p| p.id == id)
+    }
+
+    /// Check whether the policy calls for a rotation at `now`
+    pub fn due(&self, pool: &[UpstreamProxy], now: DateTime<Utc>) -> Option<RotationReason> {
+        if !self.policy.enabled {
+            return None;
+        }
+
+        let Some(active) = self.active(pool) else {
+            return Some(RotationReason::Initial);
+        };
+
+        let failures = active.health.consecutive_failures;
+        if self.policy.failure_threshold > 0 && failures >= self.policy.failure_threshold {
+            return Some(RotationReason::Unhealthy { failures });
+        }
+
+        if self.policy.interval_minutes > 0 {
+            let interval = Duration::minutes(self.policy.interval_minutes as i64);
+            match self.policy.last_rotated_at {
+                Some(last) if now - last < interval => {}
+                _ => return Some(RotationReason::Interval),
+            }
+        }
+
+        None
+    }
+
+    /// Next healthy candidate after the active one (round-robin)
+    pub fn next_upstream<'a>(&self, pool: &'a [UpstreamProxy]) -> Option<&'a UpstreamProxy> {
+        let candidates = self.candidates(pool);
+        let active_id = self.policy.active_upstream_id.as_deref();
+        let start = active_id
+            .and_then(|id| candidates.iter().position(|p| p.id == id))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+
+        (0..candidates.len())
+            .map(|offset| candidates[(start + offset) % candidates.len()])
+            .find(|p| Some(p.id.as_str()) != active_id && p.health.is_healthy())
+    }
+
+    /// Merger configuration that uses `upstream` as the fixed exit
+    pub fn merger_config_for(&self, upstream: &UpstreamProxy) -> MergerConfig {
+        MergerConfig {
+            proxy_host: upstream.config.host.clone(),
+            proxy_port: upstream.config.port,
+            proxy_username: upstream.config.username.clone(),
+            proxy_password: upstream.config.password.clone(),
+            ..self.base.clone()
+        }
+    }
+
+    /// Rotate to the next healthy candidate and re-patch the Clash config
+    pub fn rotate(
+        &mut self,
+        pool: &[UpstreamProxy],
+        clash_config: &Path,
+        reason: RotationReason,
+        now: DateTime<Utc>,
+    ) -> Result<RotationEvent> {
+        let from = self.active(pool);
+        let to = self
+            .next_upstream(pool)
+            .context("No healthy upstream available to rotate to")?;
+
+        let merger = ClashConfigMerger::with_config(self.merger_config_for(to))
+            .with_backups(self.backups.clone());
+        let merge = merger
+            .merge(clash_config)
+            .with_context(|| format!("Failed to patch Clash config with {}", to.name))?;
+
+        let event = RotationEvent {
+            reason,
+            from_id: from.map(|p| p.id.clone()),
+            from_name: from.map(|p| p.name.clone()),
+            to_id: to.id.clone(),
+            to_name: to.name.clone(),
+            old_exit_ip: from.and_then(|p| p.health.exit_ip.clone()),
+            new_exit_ip: to.health.exit_ip.clone(),
+            merge,
+        };
+
+        info!(
+            "Rotated exit {} ({}) -> {} ({}): {}",
+            event.from_name.as_deref().unwrap_or("none"),
+            event.old_exit_ip.as_deref().unwrap_or("unknown"),
+            event.to_name,
+            event.new_exit_ip.as_deref().unwrap_or("unknown"),
+            event.reason,
+        );
+
+        self.policy.active_upstream_id = Some(event.to_id.clone());
+        self.policy.last_rotated_at = Some(now);
+        Ok(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::config::UpstreamConfig;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn upstream(name: &str, port: u16, healthy: bool, exit_ip: &str) -> UpstreamProxy {
+        let config = UpstreamConfig {
+            host: "10.0.0.1".to_string(),
+            port,
+            username: None,
+            password: None,
+        };
+        let mut proxy = UpstreamProxy::new(name.to_string(), config);
+        if healthy {
+            proxy.health.mark_healthy_with_details(50, Some(exit_ip.to_string()), None, None);
+        } else {
+            proxy.health.mark_unhealthy("timeout".to_string());
+        }
+        proxy
+    }
+
+    fn policy(active: Option<&UpstreamProxy>) -> RotationConfig {
+        RotationConfig {
+            enabled: true,
+            interval_minutes: 30,
+            failure_threshold: 2,
+            active_upstream_id: active.map(|p| p.id.clone()),
+            last_rotated_at: Some(Utc::now()),
+            ..RotationConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_due_when_disabled() {
+        let pool = vec![upstream("A", 1080, true, "1.1.1.1")];
+        let rotator = Rotator::new(RotationConfig::default());
+        assert_eq!(rotator.due(&pool, Utc::now()), None);
+    }
+
+    #[test]
+    fn test_due_initial_and_interval() {
+        let pool = vec![upstream("A", 1080, true, "1.1.1.1")];
+
+        let rotator = Rotator::new(policy(None));
+        assert_eq!(rotator.due(&pool, Utc::now()), Some(RotationReason::Initial));
+
+        let rotator = Rotator::new(policy(Some(&pool[0])));
+        assert_eq!(rotator.due(&pool, Utc::now()), None);
+        let later = Utc::now() + Duration::minutes(31);
+        assert_eq!(rotator.due(&pool, later), Some(RotationReason::Interval));
+    }
+
+    #[test]
+    fn test_due_on_failure_threshold() {
+        let mut pool = vec![upstream("A", 1080, false, "")];
+        let rotator = Rotator::new(policy(Some(&pool[0])));
+        assert_eq!(rotator.due(&pool, Utc::now()), None);
+
+        pool[0].health.mark_unhealthy("timeout".to_string());
+        assert_eq!(
+            rotator.due(&pool, Utc::now()),
+            Some(RotationReason::Unhealthy { failures: 2 })
+        );
+    }
+
+    #[test]
+    fn test_next_upstream_round_robin_skips_unhealthy() {
+        let pool = vec![
+            upstream("A", 1080, true, "1.1.1.1"),
+            upstream("B", 1081, false, ""),
+            upstream("C", 1082, true, "3.3.3.3"),
+        ];
+
+        let rotator = Rotator::new(policy(Some(&pool[0])));
+        assert_eq!(rotator.next_upstream(&pool).unwrap().name, "C");
+
+        let rotator = Rotator::new(policy(Some(&pool[2])));
+        assert_eq!(rotator.next_upstream(&pool).unwrap().name, "A");
+    }
+
+    #[test]
+    fn test_next_upstream_respects_candidates() {
+        let pool = vec![
+            upstream("A", 1080, true, "1.1.1.1"),
+            upstream("B", 1081, true, "2.2.2.2"),
+            upstream("C", 1082, true, "3.3.3.3"),
+        ];
+
+        let mut config = policy(Some(&pool[0]));
+        config.candidate_ids = vec![pool[0].id.clone(), pool[2].id.clone()];
+        let rotator = Rotator::new(config);
+        assert_eq!(rotator.next_upstream(&pool).unwrap().name, "C");
+    }
+
+    #[test]
+    fn test_rotate_repatches_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.yaml");
+        fs::write(
+            &path,
+            "proxies:\n  - { name: HK, type: ss, server: a.com, port: 443, cipher: aes-256-gcm, password: x }\n\
+             proxy-groups:\n  - { name: Proxy, type: select, proxies: [HK] }\n\
+             rules:\n  - MATCH,Proxy\n",
+        )
+        .unwrap();
+
+        let pool = vec![
+            upstream("A", 1080, true, "1.1.1.1"),
+            upstream("B", 1081, true, "2.2.2.2"),
+        ];
+        let base = MergerConfig { create_backup: false, ..MergerConfig::default() };
+        let mut rotator = Rotator::with_merger_config(policy(Some(&pool[0])), base);
+
+        let now = Utc::now();
+        let event = rotator.rotate(&pool, &path, RotationReason::Manual, now).unwrap();
+        assert_eq!(event.from_name.as_deref(), Some("A"));
+        assert_eq!(event.to_name, "B");
+        assert_eq!(event.old_exit_ip.as_deref(), Some("1.1.1.1"));
+        assert_eq!(event.new_exit_ip.as_deref(), Some("2.2.2.2"));
+        assert_eq!(rotator.policy().active_upstream_id.as_deref(), Some(pool[1].id.as_str()));
+        assert_eq!(rotator.policy().last_rotated_at, Some(now));
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("port: 1081"));
+    }
+
+    #[test]
+    fn test_rotate_without_healthy_candidate() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.yaml");
+        fs::write(&path, "proxies: []\nproxy-groups: []\n").unwrap();
+
+        let pool = vec![upstream("A", 1080, true, "1.1.1.1"), upstream("B", 1081, false, "")];
+        let mut rotator = Rotator::new(policy(Some(&pool[0])));
+        assert!(rotator.rotate(&pool, &path, RotationReason::Manual, Utc::now()).is_err());
+    }
+}
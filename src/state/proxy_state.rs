@@ -2,8 +2,9 @@
 //!
 //! Manages upstream proxies, health checks, monitoring, and other state
 
-use crate::bridge::{ConfigBridge, HealthBridge, MergerBridge, WatcherBridge};
-use crate::config::UpstreamProxy;
+use crate::bridge::{BridgeResult, ConfigBridge, HealthBridge, MergerBridge, WatcherBridge};
+use crate::config::{ConfigManager, UpstreamProxy};
+use crate::merger::ApplyPlan;
 use crate::patcher::CustomRuleSet;
 use std::path::PathBuf;
 
@@ -39,6 +40,9 @@ pub struct ProxyState {
     /// Clash configuration file path
     clash_config_path: Option<PathBuf>,
 
+    /// Plan from the last successful apply (re-used by the file watcher)
+    last_apply_plan: Option<ApplyPlan>,
+
     /// Error message
     error_message: Option<String>,
 
@@ -54,9 +58,18 @@ impl ProxyState {
 
     /// Initialize state (create all bridge objects)
     pub fn initialize(&mut self) -> Result<(), String> {
+        self.initialize_with(ConfigBridge::new())
+    }
+
+    /// Initialize state for a passphrase-protected config
+    pub fn initialize_with_passphrase(&mut self, passphrase: &str) -> Result<(), String> {
+        self.initialize_with(ConfigBridge::with_passphrase(passphrase))
+    }
+
+    fn initialize_with(&mut self, config_bridge: BridgeResult<ConfigBridge>) -> Result<(), String> {
         // Create configuration bridge
         self.config_bridge = Some(
-            ConfigBridge::new()
+            config_bridge
                 .map_err(|e| format!("Failed to create config bridge: {}", e))?,
         );
 
@@ -202,18 +215,34 @@ impl ProxyState {
 
     // ===== Merger Bridge related methods =====
 
+    /// Remember the plan of a successful apply so re-applies repeat it exactly
+    pub fn set_last_apply_plan(&mut self, plan: ApplyPlan) {
+        self.last_apply_plan = Some(plan);
+    }
+
     /// Merge configuration to Clash config file
+    ///
+    /// Re-runs the last applied plan (including rule rewrites and custom
+    /// rules); falls back to a plain merge with the bridge configuration.
     pub fn merge_to_clash(&mut self) -> Result<(), String> {
         let clash_path = self
             .clash_config_path
             .as_ref()
             .ok_or("Clash config path not set")?;
 
-        self.merger_bridge
-            .as_ref()
-            .ok_or("Merger bridge not initialized")?
-            .merge(clash_path)
-            .map_err(|e| e.to_string())?;
+        let plan = match &self.last_apply_plan {
+            Some(plan) => plan.clone(),
+            None => ApplyPlan::new(
+                self.merger_bridge
+                    .as_ref()
+                    .ok_or("Merger bridge not initialized")?
+                    .config()
+                    .clone(),
+            )
+            .with_backups(ConfigManager::backup_settings()),
+        };
+
+        MergerBridge::apply_plan(&plan, clash_path).map_err(|e| e.to_string())?;
 
         Ok(())
     }
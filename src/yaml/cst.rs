@@ -0,0 +1,947 @@
+//! Concrete syntax tree of YAML text
+//!
+//! [`parse`] maps out every document of a stream and every node in it with
+//! its position in the source lines: block and flow collections, scalars in
+//! all five styles, aliases and node properties (`&anchor`, `!tag`). Comments
+//! and blank lines belong to no node, so replacing a node's span leaves them
+//! where they are. Indentation-less sequences (`key:` followed by `- item` at
+//! the key's column) and tabs used as separation are handled.
+//!
+//! Nodes are addressed by a path of keys and indices ([`Seg`]): [`Node::at`]
+//! walks the tree as written (what edits need), [`Doc::lookup`] also follows
+//! aliases and `<<` merge keys (what reads need).
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::flow;
+
+/// Position in the source: line index and byte offset within that line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// Source range of a node (`end` is exclusive)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
+/// How a collection is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// One entry per line (`- a` / `key: a`)
+    Block,
+    /// Between brackets (`[a, b]` / `{ key: a }`)
+    Flow,
+}
+
+/// How a scalar is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarStyle {
+    Plain,
+    SingleQuoted,
+    DoubleQuoted,
+    /// `|` block scalar
+    Literal,
+    /// `>` block scalar
+    Folded,
+}
+
+/// What a node holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// Nothing written (`key:` with no value); reads as null
+    Empty,
+    /// Style and value (quotes removed, escapes and folding applied)
+    Scalar(ScalarStyle, String),
+    /// `*name`
+    Alias(String),
+    Mapping(Style, Vec<Pair>),
+    Sequence(Style, Vec<Item>),
+}
+
+/// A node and where it is written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    /// Start of the node's properties, or of its content when it has none
+    pub start: Pos,
+    /// The content, properties excluded; empty for [`Kind::Empty`]
+    pub span: Span,
+    pub anchor: Option<String>,
+    pub tag: Option<String>,
+    pub kind: Kind,
+}
+
+/// `key: value` of a mapping
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pair {
+    pub key: Node,
+    pub value: Node,
+}
+
+/// Entry of a sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    /// The `-` of a block entry, the start of a flow entry
+    pub start: Pos,
+    pub node: Node,
+}
+
+/// One document of a stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Doc {
+    /// Lines of the document: after a `---` line, up to the next `---` or
+    /// `...` line (directives and markers excluded)
+    pub lines: Range<usize>,
+    /// Root node; `None` for a document without content
+    pub root: Option<Node>,
+}
+
+/// One step of a path: a mapping key or a sequence index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Seg<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+impl<'a> From<&'a str> for Seg<'a> {
+    fn from(key: &'a str) -> Self {
+        Seg::Key(key)
+    }
+}
+
+impl From<usize> for Seg<'_> {
+    fn from(index: usize) -> Self {
+        Seg::Index(index)
+    }
+}
+
+/// Parse YAML source lines (without line endings) into its documents
+pub fn parse(lines: &[String]) -> Vec<Doc> {
+    let mut parser = Parser { lines, end: lines.len() };
+    let mut docs = Vec::new();
+    let mut next = 0;
+
+    while next < lines.len() {
+        let first = (next..lines.len()).find(|&i| !(lines[i].starts_with('%') || is_trivia(&lines[i])));
+        let Some(first) = first else {
+            break;
+        };
+        if is_document_end(&lines[first]) {
+            next = first + 1;
+            continue;
+        }
+        let explicit = is_document_start(&lines[first]);
+        let body = if explicit { first + 1 } else { next };
+        let end = (body..lines.len())
+            .find(|&i| is_document_start(&lines[i]) || is_document_end(&lines[i]))
+            .unwrap_or(lines.len());
+
+        parser.end = end;
+        let at = if explicit { Pos { line: first, col: 3 } } else { Pos { line: first, col: 0 } };
+        let root = parser.block_node(at, -1, Context::Root);
+        docs.push(Doc { lines: body..end, root: (root.kind != Kind::Empty || root.anchor.is_some()).then_some(root) });
+
+        next = if end < lines.len() && is_document_end(&lines[end]) { end + 1 } else { end };
+    }
+    docs
+}
+
+/// Blank or comment-only line
+pub fn is_trivia(line: &str) -> bool {
+    let content = line.trim_start_matches([' ', '\t']);
+    content.is_empty() || content.starts_with('#')
+}
+
+fn is_document_start(line: &str) -> bool {
+    line.strip_prefix("---").is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t']))
+}
+
+fn is_document_end(line: &str) -> bool {
+    line.strip_prefix("...").is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t']))
+}
+
+impl Node {
+    fn empty(at: Pos) -> Self {
+        Self { start: at, span: Span { start: at, end: at }, anchor: None, tag: None, kind: Kind::Empty }
+    }
+
+    /// Direct child at `seg`, without following aliases or merge keys
+    pub fn child(&self, seg: Seg) -> Option<&Node> {
+        match (seg, &self.kind) {
+            (Seg::Key(key), Kind::Mapping(..)) => self.pair(key).map(|p| &p.value),
+            (Seg::Index(i), Kind::Sequence(_, items)) => items.get(i).map(|item| &item.node),
+            _ => None,
+        }
+    }
+
+    /// Node at `path` below this one, as written
+    pub fn at(&self, path: &[Seg]) -> Option<&Node> {
+        path.iter().try_fold(self, |node, seg| node.child(*seg))
+    }
+
+    /// The pair whose key reads `key`
+    pub fn pair(&self, key: &str) -> Option<&Pair> {
+        self.pairs().iter().find(|p| p.key.as_str() == Some(key))
+    }
+
+    /// Pairs of a mapping (none for other nodes)
+    pub fn pairs(&self) -> &[Pair] {
+        match &self.kind {
+            Kind::Mapping(_, pairs) => pairs,
+            _ => &[],
+        }
+    }
+
+    /// Items of a sequence (none for other nodes)
+    pub fn items(&self) -> &[Item] {
+        match &self.kind {
+            Kind::Sequence(_, items) => items,
+            _ => &[],
+        }
+    }
+
+    /// Value of a scalar
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.kind {
+            Kind::Scalar(_, value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Style of a collection
+    pub fn style(&self) -> Option<Style> {
+        match &self.kind {
+            Kind::Mapping(style, _) | Kind::Sequence(style, _) => Some(*style),
+            _ => None,
+        }
+    }
+
+    pub fn is_sequence(&self) -> bool {
+        matches!(self.kind, Kind::Sequence(..))
+    }
+
+    /// Source text of the content, lines joined with `\n`
+    pub fn text(&self, lines: &[String]) -> String {
+        let Span { start, end } = self.span;
+        if start.line == end.line {
+            return lines[start.line][start.col..end.col].to_string();
+        }
+        let mut parts = vec![&lines[start.line][start.col..]];
+        parts.extend(lines[start.line + 1..end.line].iter().map(String::as_str));
+        parts.push(&lines[end.line][..end.col]);
+        parts.join("\n")
+    }
+
+    /// Anchored nodes at or below this one
+    fn collect_anchors<'a>(&'a self, anchors: &mut HashMap<&'a str, &'a Node>) {
+        if let Some(anchor) = &self.anchor {
+            anchors.insert(anchor, self);
+        }
+        match &self.kind {
+            Kind::Mapping(_, pairs) => pairs.iter().for_each(|p| {
+                p.key.collect_anchors(anchors);
+                p.value.collect_anchors(anchors);
+            }),
+            Kind::Sequence(_, items) => items.iter().for_each(|i| i.node.collect_anchors(anchors)),
+            _ => {}
+        }
+    }
+}
+
+impl Doc {
+    /// Node at `path`, following aliases and `<<` merge keys
+    pub fn lookup(&self, path: &[Seg]) -> Option<&Node> {
+        let root = self.root.as_ref()?;
+        let mut anchors = HashMap::new();
+        root.collect_anchors(&mut anchors);
+        let mut node = resolve(root, &anchors)?;
+        for seg in path {
+            node = resolve(merged_child(node, *seg, &anchors, 0)?, &anchors)?;
+        }
+        Some(node)
+    }
+}
+
+/// The node an alias refers to (other nodes as they are)
+fn resolve<'a>(node: &'a Node, anchors: &HashMap<&str, &'a Node>) -> Option<&'a Node> {
+    match &node.kind {
+        Kind::Alias(name) => anchors.get(name.as_str()).copied(),
+        _ => Some(node),
+    }
+}
+
+/// Child at `seg`, looking through `<<` merge keys for missing keys
+fn merged_child<'a>(node: &'a Node, seg: Seg, anchors: &HashMap<&str, &'a Node>, depth: usize) -> Option<&'a Node> {
+    if let Some(child) = node.child(seg) {
+        return Some(child);
+    }
+    let (Seg::Key(_), Some(merge)) = (seg, node.pair("<<")) else {
+        return None;
+    };
+    // Anchors cannot refer forward, but stay safe on malformed input
+    if depth > 16 {
+        return None;
+    }
+    let merged = resolve(&merge.value, anchors)?;
+    let sources: Vec<&Node> = match &merged.kind {
+        Kind::Sequence(_, items) => items.iter().filter_map(|i| resolve(&i.node, anchors)).collect(),
+        _ => vec![merged],
+    };
+    sources.into_iter().find_map(|source| merged_child(source, seg, anchors, depth + 1))
+}
+
+/// Where a block node sits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    /// Root of a document
+    Root,
+    /// Entry of a block sequence
+    Item,
+    /// Value of a block mapping key (may be an indentation-less sequence)
+    Value,
+}
+
+struct Parser<'a> {
+    lines: &'a [String],
+    /// End of the current document (exclusive line index)
+    end: usize,
+}
+
+impl Parser<'_> {
+    /// Text from `at` to the end of its line ("" past the document)
+    fn rest(&self, at: Pos) -> &str {
+        if at.line >= self.end {
+            return "";
+        }
+        self.lines[at.line].get(at.col..).unwrap_or("")
+    }
+
+    fn skip_space(&self, at: Pos) -> Pos {
+        let rest = self.rest(at);
+        Pos { col: at.col + rest.len() - rest.trim_start_matches([' ', '\t']).len(), ..at }
+    }
+
+    /// Only whitespace or a comment left on the line from `at`
+    fn at_line_end(&self, at: Pos) -> bool {
+        let content = self.skip_space(at);
+        let rest = self.rest(content);
+        rest.is_empty() || (rest.starts_with('#') && (content.col == 0 || content.col > at.col || self.after_space(content)))
+    }
+
+    fn after_space(&self, at: Pos) -> bool {
+        self.lines[at.line][..at.col].ends_with([' ', '\t'])
+    }
+
+    /// First line from `from` on with content
+    fn next_content(&self, from: usize) -> Option<usize> {
+        (from..self.end).find(|&i| !is_trivia(&self.lines[i]))
+    }
+
+    /// Column of the first non-blank character of a line
+    fn indent(&self, line: usize) -> usize {
+        let text = &self.lines[line];
+        text.len() - text.trim_start_matches([' ', '\t']).len()
+    }
+
+    /// A `-` entry indicator at `at`
+    fn is_dash(&self, at: Pos) -> bool {
+        self.rest(at)
+            .strip_prefix('-')
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t']))
+    }
+
+    fn end_of_line(&self, line: usize) -> Pos {
+        Pos { line, col: self.lines[line].len() }
+    }
+
+    /// `&anchor` and `!tag` at `at`: position after them, anchor, tag
+    fn properties(&self, mut at: Pos, flow: bool) -> (Pos, Option<String>, Option<String>) {
+        let (mut anchor, mut tag) = (None, None);
+        loop {
+            at = self.skip_space(at);
+            let rest = self.rest(at);
+            let Some(first) = rest.chars().next().filter(|c| matches!(c, '&' | '!')) else {
+                return (at, anchor, tag);
+            };
+            let len = rest
+                .find(|c: char| c.is_whitespace() || (flow && matches!(c, ',' | '[' | ']' | '{' | '}')))
+                .unwrap_or(rest.len());
+            let token = rest[..len].to_string();
+            if first == '&' {
+                anchor = Some(token[1..].to_string());
+            } else {
+                tag = Some(token);
+            }
+            at.col += len;
+        }
+    }
+
+    /// A node in block context starting at `at` (after `key:`, `- `, `---`
+    /// or at the start of a document), nested deeper than `parent`
+    fn block_node(&self, at: Pos, parent: isize, context: Context) -> Node {
+        let (content, anchor, tag) = self.properties(at, false);
+        let with_properties = |mut node: Node| {
+            if anchor.is_some() || tag.is_some() {
+                node.start = self.skip_space(at);
+                node.anchor = anchor.clone();
+                node.tag = tag.clone();
+            }
+            node
+        };
+
+        if self.at_line_end(content) {
+            let next = self.next_content(content.line + 1).map(|line| Pos { line, col: self.indent(line) });
+            let node = match next {
+                Some(next) if next.col as isize > parent => self.block_content(next, parent, true),
+                // `key:` followed by `- item` at the key's own column
+                Some(next) if context == Context::Value && next.col as isize == parent && self.is_dash(next) => {
+                    self.block_sequence(next)
+                }
+                _ => Node::empty(if anchor.is_some() || tag.is_some() { content } else { at }),
+            };
+            return with_properties(node);
+        }
+
+        let map = context != Context::Value;
+        let start = self.skip_space(at);
+        if map && start != content && flow::find_key_colon(self.rest(start), false).is_some() {
+            // Properties in front of an implicit key belong to the key
+            return self.block_mapping(start);
+        }
+        with_properties(self.block_content(content, parent, map))
+    }
+
+    /// Node whose content starts at `at`; `map` allows a block mapping
+    fn block_content(&self, at: Pos, parent: isize, map: bool) -> Node {
+        let rest = self.rest(at);
+        if self.is_dash(at) {
+            return self.block_sequence(at);
+        }
+        if map && rest.strip_prefix('?').is_some_and(|r| r.is_empty() || r.starts_with([' ', '\t'])) {
+            return self.block_mapping(at);
+        }
+        if map && flow::find_key_colon(rest, false).is_some() {
+            return self.block_mapping(at);
+        }
+        match rest.chars().next() {
+            Some('|' | '>') => self.block_scalar(at, parent),
+            Some('[' | '{') => self.flow_node(at),
+            Some('\'' | '"') => self.quoted(at),
+            Some('*') => self.alias(at, false),
+            _ => self.plain(at, parent),
+        }
+    }
+
+    fn block_mapping(&self, start: Pos) -> Node {
+        let column = start.col;
+        let mut pairs: Vec<Pair> = Vec::new();
+        let mut at = start;
+
+        loop {
+            let rest = self.rest(at);
+            let pair = if rest.strip_prefix('?').is_some_and(|r| r.is_empty() || r.starts_with([' ', '\t'])) {
+                let key = self.block_node(Pos { col: at.col + 1, ..at }, column as isize, Context::Item);
+                let colon = self
+                    .next_content(key.span.end.line + 1)
+                    .map(|line| Pos { line, col: column })
+                    .filter(|&p| self.indent(p.line) == column && self.rest(p).starts_with(':'));
+                let value = match colon {
+                    Some(colon) => self.block_node(Pos { col: colon.col + 1, ..colon }, column as isize, Context::Value),
+                    None => Node::empty(key.span.end),
+                };
+                Pair { key, value }
+            } else {
+                let Some(colon) = flow::find_key_colon(rest, false) else {
+                    break;
+                };
+                let key = self.key(at, at.col + colon);
+                let value = self.block_node(Pos { line: at.line, col: at.col + colon + 1 }, column as isize, Context::Value);
+                Pair { key, value }
+            };
+            let last = pair.value.span.end.line.max(pair.key.span.end.line);
+            pairs.push(pair);
+
+            match self.next_content(last + 1) {
+                Some(line) if self.indent(line) == column && !self.is_dash(Pos { line, col: column }) => {
+                    at = Pos { line, col: column };
+                }
+                _ => break,
+            }
+        }
+
+        let end = pairs.last().map_or(start, |p| p.value.span.end.max(p.key.span.end));
+        Node { start, span: Span { start, end }, anchor: None, tag: None, kind: Kind::Mapping(Style::Block, pairs) }
+    }
+
+    /// Implicit key from `at` up to its colon
+    fn key(&self, at: Pos, colon: usize) -> Node {
+        let (content, anchor, tag) = self.properties(at, false);
+        let text = self.lines[at.line][content.col..colon].trim_end();
+        let end = Pos { line: at.line, col: content.col + text.len() };
+        let kind = match text.chars().next() {
+            Some('\'' | '"') => return Node { start: self.skip_space(at), anchor, tag, ..self.quoted(content) },
+            Some('*') => Kind::Alias(text[1..].to_string()),
+            _ => Kind::Scalar(ScalarStyle::Plain, text.to_string()),
+        };
+        Node { start: self.skip_space(at), span: Span { start: content, end }, anchor, tag, kind }
+    }
+
+    fn block_sequence(&self, start: Pos) -> Node {
+        let column = start.col;
+        let mut items = Vec::new();
+        let mut at = start;
+
+        loop {
+            let node = self.block_node(Pos { col: at.col + 1, ..at }, column as isize, Context::Item);
+            let last = node.span.end.line.max(at.line);
+            items.push(Item { start: at, node });
+
+            match self.next_content(last + 1) {
+                Some(line) if self.indent(line) == column && self.is_dash(Pos { line, col: column }) => {
+                    at = Pos { line, col: column };
+                }
+                _ => break,
+            }
+        }
+
+        let end = items.last().map_or(start, |i: &Item| i.node.span.end.max(Pos { col: i.start.col + 1, ..i.start }));
+        Node { start, span: Span { start, end }, anchor: None, tag: None, kind: Kind::Sequence(Style::Block, items) }
+    }
+
+    /// `|` or `>` scalar: the header at `at` and the lines indented past
+    /// `parent` below it
+    fn block_scalar(&self, at: Pos, parent: isize) -> Node {
+        let header = flow::strip_comment(self.rest(at));
+        let literal = header.starts_with('|');
+        let chomp = header.chars().find(|c| matches!(c, '+' | '-'));
+        let explicit = header.chars().find_map(|c| c.to_digit(10)).filter(|d| *d > 0);
+
+        let first = (at.line + 1..self.end).find(|&i| !self.lines[i].trim().is_empty());
+        let content_indent = match (explicit, first) {
+            (Some(d), _) => Some((parent + d as isize).max(0) as usize),
+            (None, first) => first.map(|first| self.indent(first)),
+        }
+        .filter(|&indent| indent as isize > parent);
+
+        let mut end = Pos { line: at.line, col: at.col + header.len() };
+        let mut body: Vec<&str> = Vec::new();
+        if let Some(content_indent) = content_indent {
+            for i in at.line + 1..self.end {
+                let line = &self.lines[i];
+                if line.trim().is_empty() {
+                    body.push("");
+                } else if self.indent(i) >= content_indent {
+                    body.push(line.get(content_indent..).unwrap_or(""));
+                    end = self.end_of_line(i);
+                } else {
+                    break;
+                }
+            }
+        }
+        // Trailing blank lines are not part of the span
+        let kept = body.iter().rposition(|l| !l.is_empty()).map_or(0, |p| p + 1);
+        let trailing = body.len() - kept;
+        body.truncate(kept);
+
+        let mut value = if literal {
+            body.join("\n")
+        } else {
+            let mut folded = String::new();
+            for (i, line) in body.iter().enumerate() {
+                if i > 0 {
+                    let more_indented = line.starts_with([' ', '\t']) || body[i - 1].starts_with([' ', '\t']);
+                    folded.push(if line.is_empty() || body[i - 1].is_empty() || more_indented { '\n' } else { ' ' });
+                }
+                folded.push_str(line);
+            }
+            folded
+        };
+        match chomp {
+            Some('-') => {}
+            Some(_) => value.push_str(&"\n".repeat(trailing + usize::from(kept > 0))),
+            None if kept > 0 => value.push('\n'),
+            None => {}
+        }
+
+        let style = if literal { ScalarStyle::Literal } else { ScalarStyle::Folded };
+        Node { start: at, span: Span { start: at, end }, anchor: None, tag: None, kind: Kind::Scalar(style, value) }
+    }
+
+    /// Plain scalar in block context, continued on lines indented past
+    /// `parent`
+    fn plain(&self, at: Pos, parent: isize) -> Node {
+        let rest = self.rest(at);
+        let first = flow::strip_comment(rest);
+        let mut end = Pos { line: at.line, col: at.col + first.len() };
+        let mut value = first.to_string();
+
+        if flow::comment_start(rest).is_none() {
+            let mut blanks = 0;
+            for i in at.line + 1..self.end {
+                let line = &self.lines[i];
+                let content = line.trim();
+                if content.is_empty() {
+                    blanks += 1;
+                    continue;
+                }
+                if content.starts_with('#') || self.indent(i) as isize <= parent {
+                    break;
+                }
+                let text = flow::strip_comment(line.trim_start_matches([' ', '\t']));
+                value.push_str(&if blanks > 0 { "\n".repeat(blanks) } else { " ".to_string() });
+                value.push_str(text);
+                end = Pos { line: i, col: self.indent(i) + text.len() };
+                blanks = 0;
+                if flow::comment_start(line.trim_start_matches([' ', '\t'])).is_some() {
+                    break;
+                }
+            }
+        }
+
+        Node { start: at, span: Span { start: at, end }, anchor: None, tag: None, kind: Kind::Scalar(ScalarStyle::Plain, value) }
+    }
+
+    /// `'...'` or `"..."` starting at `at`, possibly over several lines
+    fn quoted(&self, at: Pos) -> Node {
+        let quote = self.rest(at).chars().next().unwrap_or('"');
+        let mut parts: Vec<String> = Vec::new();
+        let mut line = at.line;
+        let mut col = at.col + 1;
+
+        let end = loop {
+            let text = &self.lines[line];
+            let mut chars = text[col..].char_indices().peekable();
+            let mut close = None;
+            while let Some((i, c)) = chars.next() {
+                if quote == '\'' && c == '\'' {
+                    if chars.peek().is_some_and(|(_, n)| *n == '\'') {
+                        chars.next();
+                        continue;
+                    }
+                    close = Some(col + i);
+                    break;
+                }
+                if quote == '"' && c == '\\' {
+                    chars.next();
+                    continue;
+                }
+                if quote == '"' && c == '"' {
+                    close = Some(col + i);
+                    break;
+                }
+            }
+            match close {
+                Some(close) => {
+                    parts.push(text[col..close].to_string());
+                    break Pos { line, col: close + 1 };
+                }
+                None if line + 1 < self.end => {
+                    parts.push(text[col..].to_string());
+                    line += 1;
+                    col = 0;
+                }
+                // Unterminated: take the rest of the document
+                None => {
+                    parts.push(text[col..].to_string());
+                    break self.end_of_line(line);
+                }
+            }
+        };
+
+        let value = fold_quoted(&parts, quote);
+        let style = if quote == '\'' { ScalarStyle::SingleQuoted } else { ScalarStyle::DoubleQuoted };
+        Node { start: at, span: Span { start: at, end }, anchor: None, tag: None, kind: Kind::Scalar(style, value) }
+    }
+
+    fn alias(&self, at: Pos, flow: bool) -> Node {
+        let rest = self.rest(at);
+        let len = rest
+            .find(|c: char| c.is_whitespace() || (flow && matches!(c, ',' | '[' | ']' | '{' | '}')))
+            .unwrap_or(rest.len());
+        let name = rest[1..len].to_string();
+        let end = Pos { col: at.col + len, ..at };
+        Node { start: at, span: Span { start: at, end }, anchor: None, tag: None, kind: Kind::Alias(name) }
+    }
+
+    /// Skip whitespace, line breaks and comments inside a flow collection
+    fn flow_skip(&self, mut at: Pos) -> Pos {
+        loop {
+            at = self.skip_space(at);
+            if at.line >= self.end {
+                return at;
+            }
+            let rest = self.rest(at);
+            if rest.is_empty() || (rest.starts_with('#') && (at.col == 0 || self.after_space(at))) {
+                if at.line + 1 >= self.end {
+                    return Pos { col: self.lines[at.line].len(), ..at };
+                }
+                at = Pos { line: at.line + 1, col: 0 };
+            } else {
+                return at;
+            }
+        }
+    }
+
+    fn next_char(&self, at: Pos) -> Option<char> {
+        self.rest(at).chars().next()
+    }
+
+    /// A node inside a flow collection (or a flow collection itself)
+    fn flow_node(&self, at: Pos) -> Node {
+        let (content, anchor, tag) = self.properties(at, true);
+        let content = self.flow_skip(content);
+        let mut node = match self.next_char(content) {
+            Some('[') => self.flow_collection(content, ']'),
+            Some('{') => self.flow_collection(content, '}'),
+            Some('\'' | '"') => self.quoted(content),
+            Some('*') => self.alias(content, true),
+            Some(',' | ']' | '}') | None => Node::empty(content),
+            Some(_) => self.flow_plain(content),
+        };
+        if anchor.is_some() || tag.is_some() {
+            node.start = self.skip_space(at);
+            node.anchor = anchor;
+            node.tag = tag;
+        }
+        node
+    }
+
+    /// `[...]` or `{...}` opening at `at`
+    fn flow_collection(&self, start: Pos, close: char) -> Node {
+        let mut items: Vec<Item> = Vec::new();
+        let mut pairs: Vec<Pair> = Vec::new();
+        let mut at = self.flow_skip(Pos { col: start.col + 1, ..start });
+
+        let end = loop {
+            match self.next_char(at) {
+                None => break at,
+                Some(c) if c == close => break Pos { col: at.col + 1, ..at },
+                Some(',') => {
+                    at = self.flow_skip(Pos { col: at.col + 1, ..at });
+                    continue;
+                }
+                Some(']' | '}') => break Pos { col: at.col + 1, ..at },
+                Some(_) => {}
+            }
+
+            let explicit = self.rest(at).strip_prefix('?').is_some_and(|r| r.starts_with([' ', '\t']) || r.is_empty());
+            let entry = if explicit { self.flow_skip(Pos { col: at.col + 1, ..at }) } else { at };
+            let node = self.flow_node(entry);
+            let after = self.flow_skip(node.span.end);
+            let is_pair = self.next_char(after) == Some(':');
+
+            if close == '}' || is_pair || explicit {
+                let key = node;
+                let value = if is_pair {
+                    let value_at = self.flow_skip(Pos { col: after.col + 1, ..after });
+                    match self.next_char(value_at) {
+                        Some(',' | ']' | '}') | None => Node::empty(Pos { col: after.col + 1, ..after }),
+                        _ => self.flow_node(value_at),
+                    }
+                } else {
+                    Node::empty(key.span.end)
+                };
+                let next = self.flow_skip(value.span.end.max(key.span.end));
+                if close == '}' {
+                    pairs.push(Pair { key, value });
+                } else {
+                    // `[a: b]` is a sequence of one-pair mappings
+                    let span = Span { start: key.start, end: value.span.end.max(key.span.end) };
+                    let pair = Pair { key, value };
+                    items.push(Item { start: at, node: Node { start: at, span, anchor: None, tag: None, kind: Kind::Mapping(Style::Flow, vec![pair]) } });
+                }
+                at = next;
+            } else {
+                let next = after;
+                items.push(Item { start: at, node });
+                at = next;
+            }
+
+            // Malformed input: stop rather than loop
+            if !matches!(self.next_char(at), Some(',') | Some(']') | Some('}') | None) {
+                break at;
+            }
+        };
+
+        let kind = if close == '}' { Kind::Mapping(Style::Flow, pairs) } else { Kind::Sequence(Style::Flow, items) };
+        Node { start, span: Span { start, end }, anchor: None, tag: None, kind }
+    }
+
+    /// Plain scalar inside a flow collection (one line)
+    fn flow_plain(&self, at: Pos) -> Node {
+        let rest = self.rest(at);
+        let mut len = rest.len();
+        for (i, c) in rest.char_indices() {
+            let next = rest[i + c.len_utf8()..].chars().next();
+            let ends = match c {
+                ',' | '[' | ']' | '{' | '}' => true,
+                ':' => next.is_none_or(|n| n.is_whitespace() || matches!(n, ',' | '[' | ']' | '{' | '}')),
+                '#' => i > 0 && rest[..i].ends_with([' ', '\t']),
+                _ => false,
+            };
+            if ends {
+                len = i;
+                break;
+            }
+        }
+        let text = rest[..len].trim_end();
+        let end = Pos { col: at.col + text.len(), ..at };
+        Node { start: at, span: Span { start: at, end }, anchor: None, tag: None, kind: Kind::Scalar(ScalarStyle::Plain, text.to_string()) }
+    }
+}
+
+/// Value of a quoted scalar from the text between its quotes, one part per
+/// line: line breaks fold to spaces (blank lines to newlines), escapes in
+/// double quotes are decoded
+fn fold_quoted(parts: &[String], quote: char) -> String {
+    let mut folded = String::new();
+    let mut blanks = 0;
+    for (i, part) in parts.iter().enumerate() {
+        let mut text = part.as_str();
+        if i > 0 {
+            text = text.trim_start_matches([' ', '\t']);
+        }
+        if i + 1 < parts.len() {
+            text = text.trim_end_matches([' ', '\t']);
+        }
+        if i > 0 && i + 1 < parts.len() && text.is_empty() {
+            blanks += 1;
+            continue;
+        }
+        if i > 0 {
+            let escaped_break = quote == '"' && folded.ends_with('\\') && !folded.ends_with("\\\\");
+            if escaped_break {
+                folded.pop();
+            } else {
+                folded.push_str(&if blanks > 0 { "\n".repeat(blanks) } else { " ".to_string() });
+            }
+        }
+        folded.push_str(text);
+        blanks = 0;
+    }
+
+    if quote == '\'' {
+        return folded.replace("''", "'");
+    }
+    let mut out = String::with_capacity(folded.len());
+    let mut chars = folded.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let hex = |chars: &mut std::str::Chars, n: usize| {
+            let digits: String = chars.take(n).collect();
+            u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32)
+        };
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t' | '\t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some('e') => out.push('\u{1b}'),
+            Some('x') => out.extend(hex(&mut chars, 2)),
+            Some('u') => out.extend(hex(&mut chars, 4)),
+            Some('U') => out.extend(hex(&mut chars, 8)),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    fn root(text: &str) -> Node {
+        parse(&lines(text)).remove(0).root.unwrap()
+    }
+
+    #[test]
+    fn test_block_collections_and_positions() {
+        let text = "proxies:\n  - name: a\n    port: 1\n  # between\n  - { name: 'b, c', alpn: [h2, http/1.1] }\nrules:\n- MATCH,a\n";
+        let src = lines(text);
+        let root = root(text);
+        let proxies = root.at(&["proxies".into()]).unwrap();
+        assert_eq!(proxies.style(), Some(Style::Block));
+        assert_eq!(proxies.items().len(), 2);
+        assert_eq!(proxies.items()[1].start, Pos { line: 4, col: 2 });
+        assert_eq!(root.at(&["proxies".into(), 0.into(), "port".into()]).unwrap().as_str(), Some("1"));
+
+        let second = root.at(&["proxies".into(), 1.into()]).unwrap();
+        assert_eq!(second.style(), Some(Style::Flow));
+        assert_eq!(second.at(&["name".into()]).unwrap().as_str(), Some("b, c"));
+        assert_eq!(second.at(&["alpn".into()]).unwrap().text(&src), "[h2, http/1.1]");
+
+        // Indentation-less sequence at the key's column
+        let rules = root.at(&["rules".into()]).unwrap();
+        assert_eq!(rules.items().len(), 1);
+        assert_eq!(rules.items()[0].node.as_str(), Some("MATCH,a"));
+    }
+
+    #[test]
+    fn test_documents_and_markers() {
+        let text = "%YAML 1.2\n---\na: 1\n...\n# next\n--- !tag\nb: [x,\n  y]\n---\n";
+        let docs = parse(&lines(text));
+        assert_eq!(docs.len(), 3);
+        assert_eq!(docs[0].lines, 2..3);
+        assert_eq!(docs[1].root.as_ref().unwrap().tag.as_deref(), Some("!tag"));
+        let b = docs[1].lookup(&["b".into()]).unwrap();
+        assert_eq!(b.items().len(), 2);
+        assert_eq!(b.span.end, Pos { line: 7, col: 4 });
+        assert!(docs[2].root.is_none());
+    }
+
+    #[test]
+    fn test_anchors_aliases_and_merge_keys() {
+        let text = "base: &base\n  type: ss\n  udp: true\nnodes:\n  - <<: *base\n    name: a\n  - &b name: b\n    <<: [*base]\n    udp: false\n  - *base\n";
+        let docs = parse(&lines(text));
+        let doc = &docs[0];
+        assert_eq!(doc.lookup(&["nodes".into(), 0.into(), "type".into()]).unwrap().as_str(), Some("ss"));
+        assert_eq!(doc.lookup(&["nodes".into(), 1.into(), "udp".into()]).unwrap().as_str(), Some("false"));
+        assert_eq!(doc.lookup(&["nodes".into(), 2.into(), "udp".into()]).unwrap().as_str(), Some("true"));
+        // As written, the merged key is not there
+        assert!(doc.root.as_ref().unwrap().at(&["nodes".into(), 0.into(), "type".into()]).is_none());
+        let key = &doc.root.as_ref().unwrap().at(&["nodes".into(), 1.into()]).unwrap().pairs()[0].key;
+        assert_eq!(key.anchor.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_scalars_tabs_and_comments() {
+        let text = "a:\t'it''s' # c\n\"b c\": \"x\\ty\"\nc: |\n  line 1\n    # kept\n\nd: >-\n  one\n  two\ne: plain\n  continued\nf:   # empty\ng: x#y\n";
+        let root = root(text);
+        let get = |k: &str| root.at(&[k.into()]).unwrap().as_str().map(String::from);
+        assert_eq!(get("a").as_deref(), Some("it's"));
+        assert_eq!(get("b c").as_deref(), Some("x\ty"));
+        assert_eq!(get("c").as_deref(), Some("line 1\n  # kept\n"));
+        assert_eq!(get("d").as_deref(), Some("one two"));
+        assert_eq!(get("e").as_deref(), Some("plain continued"));
+        assert_eq!(root.at(&["f".into()]).unwrap().kind, Kind::Empty);
+        assert_eq!(get("g").as_deref(), Some("x#y"));
+        assert_eq!(root.at(&["c".into()]).unwrap().span.end, Pos { line: 4, col: 10 });
+    }
+
+    #[test]
+    fn test_multiline_flow_and_quoted_values() {
+        let text = "groups:\n  - name: \"long\n      name\"\n    proxies: [\n      a, # first\n      'b'\n    ]\n  - name: c\n";
+        let src = lines(text);
+        let root = root(text);
+        let groups = root.at(&["groups".into()]).unwrap();
+        assert_eq!(groups.items().len(), 2);
+        assert_eq!(groups.at(&[0.into(), "name".into()]).unwrap().as_str(), Some("long name"));
+        let proxies = groups.at(&[0.into(), "proxies".into()]).unwrap();
+        let names: Vec<&str> = proxies.items().iter().filter_map(|i| i.node.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(proxies.text(&src), "[\n      a, # first\n      'b'\n    ]");
+    }
+}
@@ -0,0 +1,664 @@
+//! Format-preserving YAML document model
+//!
+//! A [`Document`] keeps the original lines and reads its structure from the
+//! concrete syntax tree ([`super::cst`]): top-level sections of the first
+//! YAML document, the entries of a block sequence, and any node by path.
+//! Edits replace the source of single nodes (inserting or dropping sequence
+//! items, rewriting a scalar) or whole line ranges, so everything not edited
+//! — comments, quoting, flow vs block style, blank lines — is written back
+//! unchanged. The line-ending style (LF or CRLF) and a UTF-8 BOM are detected
+//! on parse and restored on render, including for inserted lines.
+
+use std::fmt;
+use std::ops::Range;
+
+use super::cst::{self, Item, Kind, Node, Pos, ScalarStyle, Seg, Span, Style};
+use super::flow;
+
+pub use super::cst::is_trivia;
+
+/// UTF-8 byte order mark
+const BOM: &str = "\u{feff}";
+
+/// A YAML file as lines plus the structure found in them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    lines: Vec<String>,
+    trailing_newline: bool,
+    line_ending: &'static str,
+    bom: bool,
+}
+
+/// A top-level `key:` of the first document and the lines of its value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// Unquoted key
+    pub key: String,
+    /// Line index of the `key:` header
+    pub header: usize,
+    /// Inline value after the colon, without node properties or comment
+    /// (`[]` for `proxies: []`, empty for a block value)
+    pub inline: String,
+    /// Lines after the header up to the next top-level key
+    pub body: Range<usize>,
+}
+
+/// One entry of a block sequence: its `- ` line, the lines nested under it
+/// and the comments directly above it
+///
+/// The lines parse on their own as a one-entry sequence; paths passed to
+/// its methods start at the entry's node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub lines: Vec<String>,
+}
+
+/// A block sequence split into entries
+///
+/// `leading` holds lines before the first entry that don't belong to it,
+/// `trailing` the blank and comment lines after the last entry's content.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sequence {
+    pub leading: Vec<String>,
+    pub entries: Vec<Entry>,
+    pub trailing: Vec<String>,
+}
+
+impl Document {
+    pub fn parse(text: &str) -> Self {
+        let (bom, text) = match text.strip_prefix(BOM) {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        // Mixed files take the majority style
+        let crlf = text.matches("\r\n").count();
+        let lf = text.matches('\n').count() - crlf;
+        Self {
+            lines: text.lines().map(String::from).collect(),
+            trailing_newline: text.ends_with('\n'),
+            line_ending: if crlf > lf { "\r\n" } else { "\n" },
+            bom,
+        }
+    }
+
+    /// Whether lines end in `\r\n`
+    pub fn is_crlf(&self) -> bool {
+        self.line_ending == "\r\n"
+    }
+
+    /// Whether the text started with a UTF-8 byte order mark
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// The first YAML document; later documents are kept verbatim but
+    /// never edited
+    fn first_document(&self) -> Option<cst::Doc> {
+        cst::parse(&self.lines).into_iter().next()
+    }
+
+    /// Root node of the first document
+    pub fn root(&self) -> Option<Node> {
+        self.first_document()?.root
+    }
+
+    /// Node at `path` in the first document, as written (aliases and merge
+    /// keys are not followed)
+    pub fn node(&self, path: &[Seg]) -> Option<Node> {
+        self.root()?.at(path).cloned()
+    }
+
+    /// Line range of the first YAML document
+    ///
+    /// Skips directives and a leading `---`; ends at `...` or the next `---`.
+    pub fn body(&self) -> Range<usize> {
+        self.first_document().map_or(0..self.lines.len(), |doc| doc.lines)
+    }
+
+    /// Top-level sections of the first document, in file order
+    pub fn sections(&self) -> Vec<Section> {
+        let Some(doc) = self.first_document() else {
+            return Vec::new();
+        };
+        let Some(root) = doc.root.filter(|root| root.style() == Some(Style::Block)) else {
+            return Vec::new();
+        };
+        let pairs = root.pairs();
+        pairs
+            .iter()
+            .enumerate()
+            .map(|(n, pair)| {
+                let header = pair.key.start.line;
+                let value = &pair.value;
+                let inline = if value.kind != Kind::Empty && value.span.start.line == header {
+                    flow::strip_comment(&self.lines[header][value.span.start.col..]).trim().to_string()
+                } else {
+                    String::new()
+                };
+                Section {
+                    key: pair.key.as_str().map_or_else(|| pair.key.text(&self.lines), String::from),
+                    header,
+                    inline,
+                    body: header + 1..pairs.get(n + 1).map_or(doc.lines.end, |next| next.key.start.line),
+                }
+            })
+            .collect()
+    }
+
+    /// The top-level section `key`, if present
+    pub fn section(&self, key: &str) -> Option<Section> {
+        self.sections().into_iter().find(|s| s.key == key)
+    }
+
+    /// Lines of a section's body
+    pub fn section_lines(&self, section: &Section) -> &[String] {
+        &self.lines[section.body.clone()]
+    }
+
+    /// Turn `key: []` / `key: {}` (or an explicit null, `~` / `null`) into
+    /// `key:` so block entries can follow the header
+    ///
+    /// Returns false for other inline values, which can't take entries.
+    pub fn open_block(&mut self, section: &Section) -> bool {
+        let Some(value) = self.node(&[Seg::Key(&section.key)]) else {
+            return false;
+        };
+        let empty = match &value.kind {
+            Kind::Empty | Kind::Sequence(Style::Block, _) | Kind::Mapping(Style::Block, _) => return true,
+            Kind::Sequence(Style::Flow, items) => items.is_empty(),
+            Kind::Mapping(Style::Flow, pairs) => pairs.is_empty(),
+            Kind::Scalar(ScalarStyle::Plain, v) => matches!(v.as_str(), "~" | "null"),
+            _ => false,
+        };
+        if !empty || value.span.start.line != section.header || value.span.end.line != section.header {
+            return false;
+        }
+        let line = &self.lines[section.header];
+        let rebuilt = format!("{}{}", line[..value.span.start.col].trim_end(), &line[value.span.end.col..]);
+        self.lines[section.header] = rebuilt;
+        true
+    }
+
+    /// Add an empty top-level `key:` section, with an optional comment after
+    /// the colon, right before the header of the first of the `before`
+    /// sections, or at the end of the first document when none of them is
+    /// present
+    pub fn insert_section(&mut self, key: &str, before: &[&str], comment: Option<&str>) {
+        let at = self.sections()
+            .into_iter()
+            .find(|s| before.contains(&s.key.as_str()))
+            .map_or_else(|| self.body().end, |s| s.header);
+        let header = match comment {
+            Some(comment) => format!("{}: {}", key, comment),
+            None => format!("{}:", key),
+        };
+        self.lines.insert(at, header);
+    }
+
+    /// Replace the source of nodes (see [`Node::span`]); spans refer to the
+    /// document before any of the edits and must not overlap
+    pub fn replace(&mut self, edits: Vec<(Span, String)>) {
+        replace_spans(&mut self.lines, edits);
+    }
+
+    /// Insert scalars (as written, e.g. quoted) into the sequence at `path`
+    /// of the first document before index `at`; see [`insert_items`]
+    pub fn insert_items(
+        &mut self,
+        path: &[Seg],
+        at: usize,
+        items: &[String],
+        wrap: impl FnOnce(&str, Vec<String>) -> Vec<String>,
+    ) -> bool {
+        match self.root() {
+            Some(root) => insert_items(&mut self.lines, &root, path, at, items, wrap),
+            None => false,
+        }
+    }
+
+    /// Drop the items of the sequence at `path` of the first document that
+    /// `keep` rejects (given their index and node); returns how many
+    pub fn retain_items(&mut self, path: &[Seg], keep: impl Fn(usize, &Node) -> bool) -> usize {
+        match self.root() {
+            Some(root) => retain_items(&mut self.lines, &root, path, keep),
+            None => 0,
+        }
+    }
+
+    /// Replace line ranges; ranges refer to the document before any of the
+    /// edits and must not overlap
+    pub fn splice(&mut self, mut edits: Vec<(Range<usize>, Vec<String>)>) {
+        edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+        for pair in edits.windows(2) {
+            assert!(pair[1].0.end <= pair[0].0.start, "overlapping document edits");
+        }
+        for (range, lines) in edits {
+            self.lines.splice(range, lines);
+        }
+    }
+
+    /// Make sure the rendered text ends with a newline
+    pub fn ensure_trailing_newline(&mut self) {
+        self.trailing_newline = true;
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.bom {
+            f.write_str(BOM)?;
+        }
+        f.write_str(&self.lines.join(self.line_ending))?;
+        if self.trailing_newline && !self.lines.is_empty() {
+            f.write_str(self.line_ending)?;
+        }
+        Ok(())
+    }
+}
+
+impl Sequence {
+    /// Split the lines of a block sequence into entries
+    ///
+    /// Comments at or left of the entry indent belong to the entry below
+    /// them; deeper comments stay with the entry they are nested in. Lines
+    /// that hold no block sequence all end up in `leading`.
+    pub fn parse(lines: &[String]) -> Self {
+        let Some(root) = block_sequence(lines) else {
+            return Sequence { leading: lines.to_vec(), ..Sequence::default() };
+        };
+        let column = root.items()[0].start.col;
+        let attached = |line: &str| {
+            line.trim().is_empty() || (is_trivia(line) && indent_of(line).len() <= column)
+        };
+
+        // Each entry starts at its `-`, or higher up at the comments and
+        // blank lines right above it
+        let mut starts = Vec::new();
+        let mut floor = 0;
+        for item in root.items() {
+            let mut start = item.start.line;
+            while start > floor && attached(&lines[start - 1]) {
+                start -= 1;
+            }
+            starts.push(start);
+            floor = item.start.line + 1;
+        }
+        let last = root.items().last().map_or(0, |item| item.start.line);
+        let end = (last..lines.len()).rev().find(|&i| !attached(&lines[i])).map_or(last + 1, |i| i + 1);
+
+        let mut seq = Sequence { leading: lines[..starts[0]].to_vec(), ..Sequence::default() };
+        for (n, &start) in starts.iter().enumerate() {
+            let next = starts.get(n + 1).copied().unwrap_or(end);
+            seq.entries.push(Entry { lines: lines[start..next].to_vec() });
+        }
+        seq.trailing = lines[end..].to_vec();
+        seq
+    }
+
+    /// Entry indent of a block sequence: whitespace before the first `-`
+    pub fn detect_indent(lines: &[String]) -> Option<String> {
+        let root = block_sequence(lines)?;
+        let first = root.items()[0].start;
+        Some(lines[first.line][..first.col].to_string())
+    }
+
+    /// Lines back in order
+    pub fn into_lines(self) -> Vec<String> {
+        let mut lines = self.leading;
+        lines.extend(self.entries.into_iter().flat_map(|e| e.lines));
+        lines.extend(self.trailing);
+        lines
+    }
+}
+
+/// Root of `lines` when it is a non-empty block sequence
+fn block_sequence(lines: &[String]) -> Option<Node> {
+    cst::parse(lines)
+        .into_iter()
+        .next()?
+        .root
+        .filter(|root| root.is_sequence() && root.style() == Some(Style::Block) && !root.items().is_empty())
+}
+
+impl Entry {
+    /// The entry's lines parsed as a one-entry sequence
+    fn document(&self) -> Option<cst::Doc> {
+        cst::parse(&self.lines).into_iter().next()
+    }
+
+    /// The entry's node
+    pub fn root(&self) -> Option<Node> {
+        self.document()?.root?.child(Seg::Index(0)).cloned()
+    }
+
+    /// Node at `path` below the entry, as written
+    pub fn node(&self, path: &[Seg]) -> Option<Node> {
+        self.root()?.at(path).cloned()
+    }
+
+    /// Scalar value of `key` in this entry's mapping; aliases and `<<`
+    /// merge keys defined within the entry are followed
+    pub fn get(&self, key: &str) -> Option<String> {
+        let doc = self.document()?;
+        doc.lookup(&[Seg::Index(0), Seg::Key(key)])?.as_str().map(String::from)
+    }
+
+    /// The entry's `name`
+    pub fn name(&self) -> Option<String> {
+        self.get("name")
+    }
+
+    /// Whether the entry is written as a flow collection (`- { ... }`)
+    pub fn is_flow(&self) -> bool {
+        self.root().and_then(|node| node.style()) == Some(Style::Flow)
+    }
+
+    /// Insert scalars into the sequence at `path`; see [`insert_items`]
+    pub fn insert_items(
+        &mut self,
+        path: &[Seg],
+        at: usize,
+        items: &[String],
+        wrap: impl FnOnce(&str, Vec<String>) -> Vec<String>,
+    ) -> bool {
+        match self.root() {
+            Some(root) => insert_items(&mut self.lines, &root, path, at, items, wrap),
+            None => false,
+        }
+    }
+
+    /// Drop the items of the sequence at `path` that `keep` rejects (given
+    /// their index and node); returns how many
+    pub fn retain_items(&mut self, path: &[Seg], keep: impl Fn(usize, &Node) -> bool) -> usize {
+        match self.root() {
+            Some(root) => retain_items(&mut self.lines, &root, path, keep),
+            None => 0,
+        }
+    }
+}
+
+/// Replace source spans (which must not overlap) with text that may span
+/// several lines
+fn replace_spans(lines: &mut Vec<String>, mut edits: Vec<(Span, String)>) {
+    edits.sort_by_key(|(span, _)| std::cmp::Reverse(span.start));
+    for (span, text) in edits {
+        let Span { start, end } = span;
+        let joined = format!("{}{}{}", &lines[start.line][..start.col], text, &lines[end.line][end.col..]);
+        lines.splice(start.line..=end.line, joined.split('\n').map(String::from));
+    }
+}
+
+/// Insert `items` into the sequence held by the key at the end of `path`
+/// (below `root`), before index `at` (or at the end)
+///
+/// Flow sequences get the items next to the existing ones. Block sequences
+/// get one `- item` line each at the entries' indent, passed through `wrap`
+/// with that indent; a key without a value gets a block sequence indented
+/// two spaces past the key. Returns false when the key is missing or holds
+/// something else.
+fn insert_items(
+    lines: &mut Vec<String>,
+    root: &Node,
+    path: &[Seg],
+    at: usize,
+    items: &[String],
+    wrap: impl FnOnce(&str, Vec<String>) -> Vec<String>,
+) -> bool {
+    let Some((Seg::Key(key), parent)) = path.split_last() else {
+        return false;
+    };
+    let Some(mapping) = root.at(parent) else {
+        return false;
+    };
+    let Some(pair) = mapping.pair(key) else {
+        return false;
+    };
+    let value = &pair.value;
+    let (line, indent) = match &value.kind {
+        Kind::Sequence(Style::Flow, seq) => {
+            let refs = items.join(", ");
+            let (pos, text) = if seq.is_empty() {
+                let open = Pos { col: value.span.start.col + 1, ..value.span.start };
+                let inner = &lines[open.line][open.col..];
+                (Pos { col: open.col + inner.len() - inner.trim_start().len(), ..open }, refs)
+            } else if at < seq.len() {
+                (seq[at].start, format!("{}, ", refs))
+            } else {
+                (seq[seq.len() - 1].node.span.end, format!(", {}", refs))
+            };
+            replace_spans(lines, vec![(Span { start: pos, end: pos }, text)]);
+            return true;
+        }
+        Kind::Sequence(Style::Block, seq) => {
+            let first = seq[0].start;
+            let line = match at.min(seq.len()) {
+                0 => pair.key.span.end.line + 1,
+                n => last_line(&seq[n - 1]) + 1,
+            };
+            (line, lines[first.line][..first.col].to_string())
+        }
+        Kind::Empty if mapping.style() == Some(Style::Block) => {
+            (pair.key.span.end.line + 1, " ".repeat(pair.key.start.col + 2))
+        }
+        _ => return false,
+    };
+    let new = items.iter().map(|item| format!("{}- {}", indent, item)).collect();
+    lines.splice(line..line, wrap(&indent, new));
+    true
+}
+
+/// Drop the items of the sequence at `path` (below `root`) that `keep`
+/// rejects; flow sequences keep the spacing around the other items, block
+/// items go with their lines
+fn retain_items(lines: &mut Vec<String>, root: &Node, path: &[Seg], keep: impl Fn(usize, &Node) -> bool) -> usize {
+    let Some(value) = root.at(path) else {
+        return 0;
+    };
+    let seq = value.items();
+    let kept: Vec<usize> = (0..seq.len()).filter(|&i| keep(i, &seq[i].node)).collect();
+    let dropped = seq.len() - kept.len();
+    if dropped == 0 {
+        return 0;
+    }
+
+    match value.style() {
+        Some(Style::Flow) => {
+            let text = value.text(lines);
+            let offset = |pos: Pos| offset_in(lines, value.span.start, pos);
+            let ranges: Vec<Range<usize>> = seq.iter().map(|i| offset(i.start)..offset(i.node.span.end)).collect();
+            let mut out = String::from(&text[..ranges[0].start]);
+            for (n, &i) in kept.iter().enumerate() {
+                out.push_str(&text[ranges[i].clone()]);
+                if n + 1 < kept.len() {
+                    out.push_str(&text[ranges[i].end..ranges[i + 1].start]);
+                }
+            }
+            out.push_str(&text[ranges[seq.len() - 1].end..]);
+            replace_spans(lines, vec![(value.span, out)]);
+        }
+        _ => {
+            for i in (0..seq.len()).rev().filter(|i| !kept.contains(i)) {
+                let item = &seq[i];
+                // An entry sharing its line with another (`- - a`) stays
+                if indent_of(&lines[item.start.line]).len() == item.start.col {
+                    lines.drain(item.start.line..=last_line(item));
+                }
+            }
+        }
+    }
+    dropped
+}
+
+/// Last line holding an item's content
+fn last_line(item: &Item) -> usize {
+    item.node.span.end.line.max(item.start.line)
+}
+
+/// Byte offset of `pos` in the text starting at `start` (lines joined with
+/// `\n`)
+fn offset_in(lines: &[String], start: Pos, pos: Pos) -> usize {
+    if pos.line == start.line {
+        return pos.col - start.col;
+    }
+    let between: usize = lines[start.line + 1..pos.line].iter().map(|l| l.len() + 1).sum();
+    lines[start.line].len() - start.col + 1 + between + pos.col
+}
+
+/// Leading whitespace of a line
+pub fn indent_of(line: &str) -> &str {
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_sections_of_first_document() {
+        let text = "%YAML 1.2\n---\nproxies:\n- { name: a }\n# groups below\nproxy-groups: &g\n  - name: p\n\
+                    \"rules\":   # comment\n  - MATCH,p\n---\nproxies: []\n";
+        let doc = Document::parse(text);
+        let keys: Vec<String> = doc.sections().into_iter().map(|s| s.key).collect();
+        assert_eq!(keys, vec!["proxies", "proxy-groups", "rules"]);
+
+        let proxies = doc.section("proxies").unwrap();
+        assert_eq!(doc.section_lines(&proxies), &lines("- { name: a }\n# groups below")[..]);
+        assert_eq!(doc.section("proxy-groups").unwrap().inline, "");
+        assert_eq!(doc.section("rules").unwrap().body, 8..9);
+        assert_eq!(doc.to_string(), text);
+    }
+
+    #[test]
+    fn test_sections_ignore_keys_inside_values() {
+        // Neither the block scalar line nor the continued flow list starts a
+        // section
+        let text = "script: |\n  rules:\nproxies: [a,\nrules: b]\nrules:\n- MATCH,a\n";
+        let doc = Document::parse(text);
+        let keys: Vec<String> = doc.sections().into_iter().map(|s| s.key).collect();
+        assert_eq!(keys, vec!["script", "proxies", "rules"]);
+        assert_eq!(doc.section("rules").unwrap().header, 4);
+        let rules = doc.node(&["rules".into()]).unwrap();
+        assert_eq!(rules.items().len(), 1);
+    }
+
+    #[test]
+    fn test_sequence_parse_keeps_comments_with_entries() {
+        let text = lines("  # first\n  - a: 1\n    # nested\n    b: 2\n\n  # second\n  - c\n\n# trailer");
+        let seq = Sequence::parse(&text);
+        assert_eq!(seq.entries.len(), 2);
+        assert_eq!(seq.entries[0].lines, lines("  # first\n  - a: 1\n    # nested\n    b: 2"));
+        assert_eq!(seq.entries[1].lines, lines("\n  # second\n  - c"));
+        assert_eq!(seq.trailing, lines("\n# trailer"));
+        assert_eq!(seq.into_lines(), text);
+
+        // An entry line that merely looks like a new entry inside a quoted
+        // scalar stays with its entry
+        let text = lines("- name: \"a\n- b\"\n- c");
+        let seq = Sequence::parse(&text);
+        assert_eq!(seq.entries.len(), 2);
+        assert_eq!(seq.entries[0].name().as_deref(), Some("a - b"));
+        assert_eq!(Sequence::detect_indent(&lines("# x\n\t\n  - a")).as_deref(), Some("  "));
+    }
+
+    #[test]
+    fn test_entry_keys_in_every_style() {
+        let flow = Entry { lines: lines("  - { name: 'HK, 01', type: select, proxies: [a, b] }") };
+        assert_eq!(flow.name().as_deref(), Some("HK, 01"));
+        assert!(flow.is_flow());
+        let proxies = flow.node(&["proxies".into()]).unwrap();
+        assert_eq!(proxies.text(&flow.lines), "[a, b]");
+
+        let block = Entry { lines: lines("- &hk name: \"HK\"\n  servername: x\n  proxies:\n  - a\n  - b\n  type: ss") };
+        assert_eq!(block.name().as_deref(), Some("HK"));
+        assert_eq!(block.get("type").as_deref(), Some("ss"));
+        let proxies = block.node(&["proxies".into()]).unwrap();
+        assert_eq!((proxies.items().len(), proxies.span.start.line, proxies.span.end.line), (2, 3, 4));
+
+        let nested = Entry { lines: lines("  -\n    name: JP # node\n    ws-opts:\n      name: inner") };
+        assert_eq!(nested.name().as_deref(), Some("JP"));
+        assert_eq!(nested.get("missing"), None);
+
+        let merged = Entry { lines: lines("- <<: &d { type: relay }\n  name: old") };
+        assert_eq!(merged.get("type").as_deref(), Some("relay"));
+    }
+
+    #[test]
+    fn test_insert_and_retain_items() {
+        let mut entry = Entry { lines: lines("- name: g\n  proxies: [ a, 'X', b ] # note") };
+        assert_eq!(entry.retain_items(&["proxies".into()], |_, n| n.as_str() != Some("X")), 1);
+        assert!(entry.insert_items(&["proxies".into()], 1, &["C".to_string()], |_, l| l));
+        assert_eq!(entry.lines[1], "  proxies: [ a, C, b ] # note");
+
+        let mut entry = Entry { lines: lines("- name: g\n  proxies:\n  # members\n  - a\n  - X # old\n  - b") };
+        assert_eq!(entry.retain_items(&["proxies".into()], |_, n| n.as_str() != Some("X")), 1);
+        let wrap = |indent: &str, mut l: Vec<String>| {
+            l.insert(0, format!("{}# <", indent));
+            l
+        };
+        assert!(entry.insert_items(&["proxies".into()], 0, &["C".to_string()], wrap));
+        assert!(entry.insert_items(&["proxies".into()], 9, &["D".to_string()], |_, l| l));
+        assert_eq!(entry.lines, lines("- name: g\n  proxies:\n  # <\n  - C\n  # members\n  - a\n  - b\n  - D"));
+
+        let mut entry = Entry { lines: lines("- name: g\n  proxies: # none yet\n  type: select") };
+        assert!(entry.insert_items(&["proxies".into()], 0, &["C".to_string()], |_, l| l));
+        assert_eq!(entry.lines, lines("- name: g\n  proxies: # none yet\n    - C\n  type: select"));
+        assert!(!entry.insert_items(&["type".into()], 0, &["C".to_string()], |_, l| l));
+        assert!(!entry.insert_items(&["use".into()], 0, &["C".to_string()], |_, l| l));
+
+        let mut doc = Document::parse("rules: [\n  a,\n  X, # drop\n  b\n]\n---\nrules: [X]\n");
+        assert_eq!(doc.retain_items(&["rules".into()], |i, _| i != 1), 1);
+        assert_eq!(doc.to_string(), "rules: [\n  a,\n  b\n]\n---\nrules: [X]\n");
+    }
+
+    #[test]
+    fn test_replace_node_source() {
+        let mut doc = Document::parse("rules:\n  - 'MATCH,a' # keep\n  - \"DOMAIN,\n    x.com,a\"\n");
+        let rules = doc.node(&["rules".into()]).unwrap();
+        assert_eq!(rules.items()[1].node.as_str(), Some("DOMAIN, x.com,a"));
+        doc.replace(vec![
+            (rules.items()[0].node.span, "'MATCH,b'".to_string()),
+            (rules.items()[1].node.span, "'DOMAIN,x.com,b'".to_string()),
+        ]);
+        assert_eq!(doc.to_string(), "rules:\n  - 'MATCH,b' # keep\n  - 'DOMAIN,x.com,b'\n");
+    }
+
+    #[test]
+    fn test_line_endings_and_bom_round_trip() {
+        let text = "\u{feff}proxies:\r\n  - a\r\nrules:\r\n  - MATCH,a\r\n";
+        let mut doc = Document::parse(text);
+        assert!(doc.is_crlf() && doc.has_bom());
+        assert_eq!(doc.section("proxies").unwrap().body, 1..2);
+        assert_eq!(doc.to_string(), text);
+
+        doc.splice(vec![(2..2, vec!["  - b".to_string()])]);
+        assert_eq!(doc.to_string(), "\u{feff}proxies:\r\n  - a\r\n  - b\r\nrules:\r\n  - MATCH,a\r\n");
+        assert!(!Document::parse("a: 1\r\nb: 2\nc: 3\n").is_crlf());
+    }
+
+    #[test]
+    fn test_open_block_and_splice() {
+        let mut doc = Document::parse("proxies: [] # none\nrules:\n  - MATCH,DIRECT");
+        let proxies = doc.section("proxies").unwrap();
+        assert!(doc.open_block(&proxies));
+        doc.splice(vec![
+            (1..1, vec!["  - { name: a }".to_string()]),
+            (2..3, vec!["  - MATCH,a".to_string()]),
+        ]);
+        assert_eq!(doc.to_string(), "proxies: # none\n  - { name: a }\nrules:\n  - MATCH,a");
+        let mut doc = Document::parse("proxies: ~\n# routing\nrules:\n  - MATCH,DIRECT\n");
+        assert!(doc.open_block(&doc.section("proxies").unwrap()));
+        doc.insert_section("proxy-groups", &["rules"], None);
+        doc.insert_section("dns", &["hosts"], Some("# added"));
+        assert_eq!(doc.to_string(), "proxies:\n# routing\nproxy-groups:\nrules:\n  - MATCH,DIRECT\ndns: # added\n");
+        let mut doc = Document::parse("proxies: [a]\n");
+        assert!(!doc.open_block(&doc.section("proxies").unwrap()));
+    }
+}
@@ -0,0 +1,296 @@
+//! Flow-style collections and scalars
+//!
+//! Scans `{ a: 1, b: [x, 'y, z'] }` / `[a, b]` text while respecting quotes
+//! and nesting, so single values can be located and replaced in place.
+
+use std::ops::Range;
+
+/// Structural chars of flow text outside quotes, with the bracket depth
+/// they sit at; iteration stops at a ` # comment`
+struct Structure<'a> {
+    text: &'a str,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    quote: Option<char>,
+    depth: usize,
+    comment: Option<usize>,
+}
+
+impl<'a> Structure<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { text, chars: text.char_indices().peekable(), quote: None, depth: 0, comment: None }
+    }
+
+    /// Quotes only open a scalar at its start (`it's` is a plain scalar)
+    fn opens_quote(&self, at: usize) -> bool {
+        self.text[..at]
+            .trim_end()
+            .chars()
+            .last()
+            .is_none_or(|c| matches!(c, ',' | '[' | '{' | ':' | '-' | '?'))
+    }
+}
+
+impl Iterator for Structure<'_> {
+    /// (byte offset, char, depth outside any bracket at that offset)
+    type Item = (usize, char, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (at, c) = self.chars.next()?;
+            match self.quote {
+                Some('\'') if c == '\'' => {
+                    // '' is an escaped quote inside single quotes
+                    if self.chars.peek().is_some_and(|(_, n)| *n == '\'') {
+                        self.chars.next();
+                    } else {
+                        self.quote = None;
+                    }
+                }
+                Some('"') if c == '\\' => {
+                    self.chars.next();
+                }
+                Some(q) if c == q => self.quote = None,
+                Some(_) => {}
+                None => match c {
+                    '\'' | '"' if self.opens_quote(at) => self.quote = Some(c),
+                    '#' if at == 0 || self.text[..at].ends_with(char::is_whitespace) => {
+                        self.comment = Some(at);
+                        while self.chars.next().is_some() {}
+                        return None;
+                    }
+                    '[' | '{' => {
+                        self.depth += 1;
+                        return Some((at, c, self.depth - 1));
+                    }
+                    ']' | '}' => {
+                        self.depth = self.depth.saturating_sub(1);
+                        return Some((at, c, self.depth));
+                    }
+                    _ => return Some((at, c, self.depth)),
+                },
+            }
+        }
+    }
+}
+
+/// Offset of the bracket closing the one at `open` (`[` or `{`)
+pub fn matching_close(text: &str, open: usize) -> Option<usize> {
+    Structure::new(&text[open..])
+        .skip(1)
+        .find(|(_, c, depth)| matches!(c, ']' | '}') && *depth == 0)
+        .map(|(at, _, _)| open + at)
+}
+
+/// Items of a flow collection given the text between its brackets, as
+/// trimmed byte ranges (a trailing comma is allowed)
+pub fn split_items(inner: &str) -> Vec<Range<usize>> {
+    let mut structure = Structure::new(inner);
+    let mut items = Vec::new();
+    let mut start = 0;
+    for (at, c, depth) in structure.by_ref() {
+        if c == ',' && depth == 0 {
+            items.push(trimmed(inner, start..at));
+            start = at + 1;
+        }
+    }
+    items.push(trimmed(inner, start..structure.comment.unwrap_or(inner.len())));
+    items.retain(|r| !r.is_empty());
+    items
+}
+
+/// `key: value` pairs of a flow mapping given the text between its braces:
+/// unquoted key and the trimmed byte range of the value
+pub fn mapping_entries(inner: &str) -> Vec<(String, Range<usize>)> {
+    split_items(inner)
+        .into_iter()
+        .map(|item| {
+            let text = &inner[item.clone()];
+            match find_key_colon(text, true) {
+                Some(colon) => {
+                    let key = unquote(text[..colon].trim());
+                    let value = trimmed(text, colon + 1..text.len());
+                    (key, item.start + value.start..item.start + value.end)
+                }
+                None => (unquote(text), item.end..item.end),
+            }
+        })
+        .collect()
+}
+
+/// Offset of the `:` ending a mapping key at the start of `text`
+///
+/// In flow context (`flow`) a colon may also be directly followed by a flow
+/// indicator; a quoted key may be directly followed by its colon.
+pub fn find_key_colon(text: &str, flow: bool) -> Option<usize> {
+    for (at, c, depth) in Structure::new(text) {
+        if depth > 0 || matches!(c, '[' | '{') || (flow && c == ',') {
+            return None;
+        }
+        if c != ':' {
+            continue;
+        }
+        let after_quote = text[..at].ends_with(['\'', '"']);
+        let ends_key = match text[at + 1..].chars().next() {
+            None => true,
+            Some(n) if n.is_whitespace() => true,
+            Some(',' | '[' | ']' | '{' | '}') => flow || after_quote,
+            _ => after_quote,
+        };
+        if ends_key {
+            return Some(at);
+        }
+    }
+    None
+}
+
+/// Drop the items of a flow collection (text between its brackets) that
+/// `keep` rejects, leaving the spacing around the others as it was.
+/// Returns `None` when nothing is dropped.
+pub fn retain_items(inner: &str, keep: impl Fn(&str) -> bool) -> Option<String> {
+    let items = split_items(inner);
+    if items.iter().all(|r| keep(&inner[r.clone()])) {
+        return None;
+    }
+    let kept: Vec<usize> = (0..items.len()).filter(|&i| keep(&inner[items[i].clone()])).collect();
+
+    let mut out = String::from(&inner[..items.first().map_or(0, |r| r.start)]);
+    for (n, &i) in kept.iter().enumerate() {
+        out.push_str(&inner[items[i].clone()]);
+        if n + 1 < kept.len() {
+            out.push_str(&inner[items[i].end..items[i + 1].start]);
+        }
+    }
+    out.push_str(&inner[items.last().map_or(0, |r| r.end)..]);
+    Some(out)
+}
+
+/// Byte offset of a trailing ` # comment` (outside quotes)
+pub fn comment_start(text: &str) -> Option<usize> {
+    let mut structure = Structure::new(text);
+    structure.by_ref().for_each(drop);
+    structure.comment
+}
+
+/// Text before a trailing ` # comment`, right-trimmed
+pub fn strip_comment(text: &str) -> &str {
+    text[..comment_start(text).unwrap_or(text.len())].trim_end()
+}
+
+/// Value of a quoted or plain scalar
+pub fn unquote(text: &str) -> String {
+    let text = text.trim();
+    if text.len() >= 2 && text.starts_with('\'') && text.ends_with('\'') {
+        return text[1..text.len() - 1].replace("''", "'");
+    }
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        let inner = &text[1..text.len() - 1];
+        let mut out = String::with_capacity(inner.len());
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(other) => out.push(other),
+                None => {}
+            }
+        }
+        return out;
+    }
+    text.to_string()
+}
+
+/// Quote a scalar string for flow-style YAML output if it contains characters
+/// that would break parsing or could be misinterpreted as another type.
+pub fn quote_scalar(s: &str) -> String {
+    let needs_quote = s.is_empty()
+        || s.starts_with(' ')
+        || s.ends_with(' ')
+        || s.starts_with(['!', '&', '*', '?', '|', '>', '%', '@', '`', '[', ']', '{', '}', '#', ',', '\'', '"'])
+        || s.contains([':', ',', '{', '}', '[', ']', '#', '\n', '\t', '\'', '"', '`'])
+        || matches!(s.to_ascii_lowercase().as_str(),
+            "true" | "false" | "null" | "yes" | "no" | "on" | "off" | "~")
+        || s.parse::<f64>().is_ok();
+    if needs_quote {
+        format!("'{}'", s.replace('\'', "''"))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Flow-style text of a parsed value, with strings quoted as needed
+pub fn render(value: &serde_yaml::Value) -> String {
+    use serde_yaml::Value;
+    let join = |items: Vec<String>| items.join(", ");
+    match value {
+        Value::Null => "~".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => quote_scalar(s),
+        Value::Sequence(items) => format!("[{}]", join(items.iter().map(render).collect())),
+        Value::Mapping(map) if map.is_empty() => "{}".to_string(),
+        Value::Mapping(map) => {
+            format!("{{ {} }}", join(map.iter().map(|(k, v)| format!("{}: {}", render(k), render(v))).collect()))
+        }
+        Value::Tagged(tagged) => format!("{} {}", tagged.tag, render(&tagged.value)),
+    }
+}
+
+fn trimmed(text: &str, range: Range<usize>) -> Range<usize> {
+    let slice = &text[range.clone()];
+    let start = range.start + (slice.len() - slice.trim_start().len());
+    let end = range.start + slice.trim_end().len();
+    start..end.max(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_items_respects_quotes_and_nesting() {
+        let inner = " a, 'b, c', \"d\\\"e\", [x, y], {k: v}, ";
+        let items: Vec<&str> = split_items(inner).into_iter().map(|r| &inner[r]).collect();
+        assert_eq!(items, vec!["a", "'b, c'", "\"d\\\"e\"", "[x, y]", "{k: v}"]);
+    }
+
+    #[test]
+    fn test_mapping_entries_and_close() {
+        let line = "- { name: 'HK: 01', type: ss, proxies: [a, 'b]'] } # note";
+        let open = line.find('{').unwrap();
+        let close = matching_close(line, open).unwrap();
+        assert_eq!(&line[close..close + 1], "}");
+
+        let inner = &line[open + 1..close];
+        let entries = mapping_entries(inner);
+        let get = |k: &str| entries.iter().find(|(key, _)| key == k).map(|(_, r)| &inner[r.clone()]);
+        assert_eq!(get("name"), Some("'HK: 01'"));
+        assert_eq!(get("proxies"), Some("[a, 'b]']"));
+    }
+
+    #[test]
+    fn test_retain_items_keeps_spacing() {
+        let inner = " X, a, 'X',  b ";
+        assert_eq!(retain_items(inner, |i| unquote(i) != "X").as_deref(), Some(" a, b "));
+        assert_eq!(retain_items("a, b", |_| true), None);
+        assert_eq!(retain_items("X", |_| false).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_comments_and_scalars() {
+        assert_eq!(strip_comment("MATCH,Proxy # ccp: was X"), "MATCH,Proxy");
+        assert_eq!(strip_comment("'a # b' # c"), "'a # b'");
+        assert_eq!(strip_comment("a#b"), "a#b");
+        assert_eq!(unquote("'it''s'"), "it's");
+        assert_eq!(find_key_colon("\"a:b\": 1", false), Some(5));
+        assert_eq!(find_key_colon("url: http://x", false), Some(3));
+        assert_eq!(find_key_colon("- a", false), None);
+        assert_eq!(quote_scalar("HK 01"), "HK 01");
+        assert_eq!(quote_scalar("a: b"), "'a: b'");
+        let value: serde_yaml::Value = serde_yaml::from_str("{ name: 'HK: 01', port: 443, udp: true, ws-opts: { path: /ws, headers: {} }, alpn: [h2] }").unwrap();
+        assert_eq!(render(&value), "{ name: 'HK: 01', port: 443, udp: true, ws-opts: { path: /ws, headers: {} }, alpn: [h2] }");
+    }
+}
@@ -0,0 +1,27 @@
+//! Format-preserving YAML editing
+//!
+//! serde_yaml is used to read a config; writes go through the [`Document`]
+//! model, which locates nodes by path in a concrete syntax tree ([`cst`]) and
+//! edits only their source, so comments, quoting and flow or block style
+//! survive.
+
+pub mod cst;
+pub mod document;
+pub mod flow;
+
+pub use cst::{Node, Seg};
+pub use document::{Document, Entry, Section, Sequence};
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_yaml::Value;
+
+/// Parse the first YAML document of `content` (later documents are ignored,
+/// as they are by the editors). A leading BOM is skipped.
+pub fn parse_value(content: &str) -> Result<Value> {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    match serde_yaml::Deserializer::from_str(content).next() {
+        Some(document) => Ok(Value::deserialize(document)?),
+        None => Ok(Value::Null),
+    }
+}